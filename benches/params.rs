@@ -0,0 +1,67 @@
+// Compares the old validation path, where every param was round-tripped
+// through a RawValue and re-parsed for the allowlist, with the current one that
+// validates the values parsed alongside the request body.
+//
+//     cargo bench --bench params
+
+use serde_json::Value;
+use serde_json::value::RawValue;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+#[path = "../src/allowlist.rs"]
+mod allowlist;
+#[allow(dead_code)]
+#[path = "../src/limiter.rs"]
+mod limiter;
+
+const ITERATIONS: u32 = 200_000;
+
+const REQUESTS: &[&str] = &[
+    r#"{"method":"getblock","params":["0b8a1cd5d6c0c0e8c6c1e4e0e5d8c4d3a2b1c0d9e8f7a6b5c4d3e2f1a0b9c8d7",true]}"#,
+    r#"{"method":"getaddressbalance","params":[{"addresses":["RQ3J1sfBy2Wnhz2kFcV8qLaqbLBFiLu2Zj","RWpdmQ3fZr9ncvHP1JqWzHeJ8wD7YJ1wWS"]}]}"#,
+    r#"{"method":"getidentity","params":["verus@",-1,false,0]}"#,
+    r#"{"method":"sendcurrency","params":["*",[{"currency":"VRSC","amount":1.5,"address":"alice@"}],1,0.0001,true]}"#,
+    r#"{"method":"fundrawtransaction","params":["0400008085202f89",[{"txid":"ab","vout":0}],"RQ3J1sfBy2Wnhz2kFcV8qLaqbLBFiLu2Zj",0]}"#,
+];
+
+fn split(body: &str) -> (String, Vec<Value>) {
+    let mut req: Value = serde_json::from_str(body).unwrap();
+    let method = req["method"].as_str().unwrap().to_string();
+    match req["params"].take() {
+        Value::Array(params) => (method, params),
+        _ => unreachable!(),
+    }
+}
+
+fn reparsed(body: &str) -> bool {
+    let (method, params) = split(body);
+    let raw: Vec<Box<RawValue>> = params.iter().map(|v| RawValue::from_string(v.to_string()).unwrap()).collect();
+    let values: Vec<Value> = raw.iter().map(|p| serde_json::from_str(&p.to_string()).unwrap()).collect();
+    allowlist::is_method_allowed(&method, &values)
+}
+
+fn parsed_once(body: &str) -> bool {
+    let (method, params) = split(body);
+    allowlist::is_method_allowed(&method, &params)
+}
+
+fn run(name: &str, f: fn(&str) -> bool) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for body in REQUESTS {
+            black_box(f(black_box(body)));
+        }
+    }
+    let elapsed = start.elapsed();
+    let per_sec = (ITERATIONS as f64 * REQUESTS.len() as f64) / elapsed.as_secs_f64();
+    println!("{:<12} {:>10.2?} {:>12.0} req/s", name, elapsed, per_sec);
+    elapsed
+}
+
+fn main() {
+    let before = run("reparsed", reparsed);
+    let after = run("parsed once", parsed_once);
+    println!("speedup      {:.2}x", before.as_secs_f64() / after.as_secs_f64());
+}
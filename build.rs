@@ -0,0 +1,13 @@
+fn main() {
+    // The gRPC service is generated from proto/verus.proto with a pure Rust
+    // protobuf compiler, so building it doesn't need protoc installed.
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/verus.proto");
+        let descriptors = protox::compile(["verus.proto"], ["proto"]).expect("Failed to compile proto/verus.proto");
+        tonic_build::configure()
+            .build_client(false)
+            .compile_fds(descriptors)
+            .expect("Failed to generate the gRPC service");
+    }
+}
@@ -0,0 +1,124 @@
+use jsonrpc::error::RpcError;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// Version bytes of transparent (R) and identity (i) addresses.
+const PUBKEY_ADDRESS: u8 = 60;
+const IDENTITY_ADDRESS: u8 = 102;
+
+// Characters the daemon doesn't allow in any part of an identity name.
+const INVALID_NAME_CHARS: &str = "\\/:*?\"<>|@";
+const MAX_NAME_LEN: usize = 64;
+
+fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(s.len());
+    for c in s.bytes() {
+        let mut carry = BASE58.iter().position(|b| *b == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    bytes.extend(s.bytes().take_while(|c| *c == b'1').map(|_| 0));
+    bytes.reverse();
+    Some(bytes)
+}
+
+// The version byte of a base58check encoded 20 byte hash.
+fn version(s: &str) -> Option<u8> {
+    match base58_decode(s) {
+        Some(bytes) if bytes.len() == 25 => {
+            let (payload, checksum) = bytes.split_at(21);
+            let hash = Sha256::digest(Sha256::digest(payload));
+            Some(payload[0]).filter(|_| hash[..4] == *checksum)
+        },
+        _ => None,
+    }
+}
+
+// A base58check encoded 20 byte hash with one of the address version bytes.
+fn is_address(s: &str) -> bool {
+    matches!(version(s), Some(PUBKEY_ADDRESS | IDENTITY_ADDRESS))
+}
+
+// `name@`, `sub.parent@` or the same without the trailing `@`.
+fn is_identity_name(s: &str) -> bool {
+    let name = s.strip_suffix('@').unwrap_or(s);
+    !name.is_empty() && name.split('.').all(|part| {
+        !part.is_empty()
+            && part.len() <= MAX_NAME_LEN
+            && part.trim() == part
+            && !part.chars().any(|c| INVALID_NAME_CHARS.contains(c) || c.is_control())
+    })
+}
+
+// Where an address is expected, identities can be given by name, which then
+// needs the trailing `@` to tell it apart from a mistyped address.
+pub fn is_address_or_name(s: &str) -> bool {
+    is_address(s) || (s.ends_with('@') && is_identity_name(s))
+}
+
+fn invalid(what: &str, value: &str) -> RpcError {
+    RpcError { code: -5, message: format!("Invalid {}: {}", what, value), data: None }
+}
+
+// Rejects malformed addresses and identity names before the call goes upstream.
+// Only the syntax is checked; whether an identity exists is still up to the daemon.
+pub fn check(method: &str, params: &[Value]) -> Result<(), RpcError> {
+    let first = params.first().unwrap_or(&Value::Null);
+    match method {
+        "getaddressbalance" | "getaddressdeltas" | "getaddressmempool" | "getaddresstxids" | "getaddressutxos" => {
+            let addresses = match &first["addresses"] {
+                Value::String(address) => vec![address.as_str()],
+                Value::Array(addresses) => addresses.iter().map(|a| a.as_str().unwrap_or_default()).collect(),
+                _ => return Err(invalid("address", "missing addresses")),
+            };
+            match addresses.into_iter().find(|address| !is_address_or_name(address)) {
+                Some(address) => Err(invalid("address", address)),
+                None => Ok(()),
+            }
+        },
+        "getidentitieswithaddress" => match first["address"].as_str() {
+            Some(address) if !is_address(address) => Err(invalid("address", address)),
+            _ => Ok(()),
+        },
+        "getidentity" => match first.as_str() {
+            Some(name) if !is_address(name) && !is_identity_name(name) => Err(invalid("identity name", name)),
+            _ => Ok(()),
+        },
+        // The name is reserved under the parent given, so it is a single part
+        // without the `@`, and only an R address can control the commitment.
+        // The referral and parent may be left empty.
+        "registernamecommitment" => {
+            let param = |index: usize| params.get(index).and_then(Value::as_str);
+            match param(0) {
+                Some(name) if is_identity_name(name) && !name.contains(['.', '@']) => {},
+                name => return Err(invalid("name", name.unwrap_or("missing name"))),
+            }
+            match param(1) {
+                Some(address) if version(address) == Some(PUBKEY_ADDRESS) => {},
+                address => return Err(invalid("control address", address.unwrap_or("missing control address"))),
+            }
+            match param(2) {
+                Some(referral) if !referral.is_empty() && !is_address_or_name(referral) => return Err(invalid("referral identity", referral)),
+                _ => {},
+            }
+            match param(3) {
+                Some(parent) if !parent.is_empty() && !is_address(parent) && !is_identity_name(parent) => return Err(invalid("parent", parent)),
+                _ => {},
+            }
+            match param(4) {
+                Some(source) if !is_address_or_name(source) => Err(invalid("source of funds", source)),
+                _ => Ok(()),
+            }
+        },
+        _ => Ok(()),
+    }
+}
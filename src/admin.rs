@@ -0,0 +1,157 @@
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::VerusRPC;
+use crate::listener;
+
+fn query_param<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.uri().query()?.split('&').find_map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        if parts.next() == Some(name) { parts.next() } else { None }
+    })
+}
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+// Sync state of the daemon, or why it couldn't be read.
+async fn daemon_status(rpc: &VerusRPC) -> Value {
+    match tokio::time::timeout(Duration::from_secs(5), rpc.upstream.call("getblockchaininfo", &[])).await {
+        Ok(Ok(info)) => json!({
+            "reachable": true,
+            "chain": info["chain"],
+            "blocks": info["blocks"],
+            "headers": info["headers"],
+            "verificationprogress": info["verificationprogress"],
+            "syncing": info["blocks"].as_u64() < info["headers"].as_u64(),
+        }),
+        Ok(Err(e)) => json!({ "reachable": false, "error": e.message }),
+        Err(_) => json!({ "reachable": false, "error": "getblockchaininfo timed out" }),
+    }
+}
+
+// Everything the dashboard shows, as JSON.
+async fn stats(rpc: &VerusRPC) -> Value {
+    let mut stats = rpc.stats.summary();
+    stats["daemon"] = daemon_status(rpc).await;
+    stats["tip"] = json!(rpc.tip.height());
+    stats["tip_age"] = json!(rpc.tip.age());
+    stats["tip_stalled"] = json!(rpc.tip.is_stalled());
+    stats["upstream"] = rpc.upstream.limiter.summary();
+    stats["cache"] = rpc.cache.summary();
+    stats["disk_cache"] = rpc.disk_cache.as_ref().map_or(Value::Null, |disk_cache| disk_cache.summary());
+    stats["phases"] = rpc.phases.summary();
+    stats["schemas"] = rpc.schemas.summary();
+    stats["deprecations"] = rpc.migrations.summary();
+    stats["faucet"] = rpc.faucet.as_ref().map_or(Value::Null, |faucet| faucet.summary());
+    stats["captcha"] = rpc.captcha.as_ref().map_or(Value::Null, |captcha| captcha.summary());
+    stats["listening"] = json!(*rpc.listening.lock().unwrap());
+    stats["time"] = json!(crate::indexer::unix_time());
+    stats
+}
+
+// Compares digests, in constant time, so the token can't be found a byte at
+// a time from how long a refusal takes.
+fn token_matches(given: &str, token: &str) -> bool {
+    let (given, token) = (Sha256::digest(given.as_bytes()), Sha256::digest(token.as_bytes()));
+    given.iter().zip(token.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Operator-facing endpoints, served on the separate admin listener so they are
+// never reachable through the public RPC port. Every one but the dashboard
+// page itself (which asks for the token) needs Authorization: Bearer
+// <admin_token>, since local processes can reach the listener too.
+pub async fn handle_admin(req: Request<Body>, rpc: Arc<VerusRPC>) -> Result<Response<Body>, hyper::Error> {
+    let authorized = match (&rpc.admin_token, listener::bearer(req.headers())) {
+        (Some(token), Some(given)) => token_matches(given, token),
+        _ => false,
+    };
+    let dashboard = req.method() == Method::GET && req.uri().path() == "/dashboard";
+    if !(authorized || dashboard) {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(hyper::header::WWW_AUTHENTICATE, "Bearer")
+            .body(Body::from("Unauthorized"))
+            .unwrap());
+    }
+    if matches!(req.uri().path(), "/bans" | "/keys" | "/limits" | "/maintenance") {
+        return crate::runtime::handle(req, &rpc).await;
+    }
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            let mut out = String::new();
+            rpc.pool.render_metrics(&mut out);
+            rpc.upstream.render_metrics(&mut out);
+            rpc.cache.render_metrics(&mut out);
+            rpc.subscriptions.render_metrics(&mut out);
+            rpc.sessions.render_metrics(&mut out);
+            rpc.stats.render_metrics(&mut out);
+            rpc.tip.render_metrics(&mut out);
+            rpc.phases.render_metrics(&mut out);
+            rpc.schemas.render_metrics(&mut out);
+            rpc.migrations.render_metrics(&mut out);
+            if let Some(disk_cache) = &rpc.disk_cache {
+                disk_cache.render_metrics(&mut out);
+            }
+            Ok(Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(Body::from(out))
+                .unwrap())
+        },
+        (&Method::GET, "/stats") => Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(stats(&rpc).await.to_string()))
+            .unwrap()),
+        (&Method::GET, "/usage") => Ok(crate::usage::handle(&req, &rpc)),
+        // Every caller still using each deprecated behaviour.
+        (&Method::GET, "/deprecations") => Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(rpc.migrations.callers().to_string()))
+            .unwrap()),
+        // A page polling /stats, for operators without a metrics stack.
+        (&Method::GET, "/dashboard") => Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(DASHBOARD))
+            .unwrap()),
+        // Flushes the response cache, e.g. after a daemon reindex. `?method=` limits it to one method.
+        (&Method::POST, "/cache/flush") => {
+            let method = query_param(&req, "method");
+            let mut flushed = rpc.cache.flush(method);
+            if let Some(disk_cache) = &rpc.disk_cache {
+                flushed += disk_cache.flush(method);
+            }
+            Ok(Response::builder()
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({"flushed": flushed}).to_string()))
+                .unwrap())
+        },
+        // Ends every session issued so far to `?subject=`, `?identity=` or
+        // `?api_key=`.
+        (&Method::POST, "/sessions/revoke") => {
+            let subject = match (query_param(&req, "subject"), query_param(&req, "identity"), query_param(&req, "api_key")) {
+                (Some(subject), _, _) => Some(subject.to_string()),
+                (None, Some(identity), _) => Some(format!("id:{}", identity.to_lowercase())),
+                (None, None, Some(key)) => Some(crate::session::key_subject(key)),
+                (None, None, None) => None,
+            };
+            let (status, body) = match subject {
+                Some(subject) => {
+                    rpc.sessions.revoke_subject(&subject);
+                    (StatusCode::OK, json!({"revoked": subject}))
+                },
+                None => (StatusCode::BAD_REQUEST, json!({"error": "Give subject, identity or api_key"})),
+            };
+            Ok(Response::builder()
+                .status(status)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap())
+        },
+        _ => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found"))
+            .unwrap()),
+    }
+}
@@ -0,0 +1,348 @@
+use async_trait::async_trait;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnector;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::VerusRPC;
+use crate::indexer::unix_time;
+use crate::rest;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Something an operator should hear about. Conditions that last (the daemon
+// being down, the tip stalling) are raised once when they start and again,
+// `resolved`, when they end.
+pub struct Alert {
+    pub kind: &'static str,
+    pub message: String,
+    pub resolved: bool,
+}
+
+impl Alert {
+    fn subject(&self) -> String {
+        format!("[verus proxy] {}{}", if self.resolved { "resolved: " } else { "" }, self.kind)
+    }
+}
+
+// A way of delivering alerts, configured by an `alert_notifiers` entry.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn describe(&self) -> String;
+    async fn send(&self, alert: &Alert) -> Result<(), String>;
+}
+
+type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+async fn post_json(client: &HttpsClient, url: &str, body: &Value) -> Result<(), String> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .map_err(|e| e.to_string())?;
+    match tokio::time::timeout(DELIVERY_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => Ok(()),
+        Ok(Ok(response)) => Err(format!("answered {}", response.status())),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+// POSTs `{"alert", "message", "resolved", "time"}` to a URL.
+struct WebhookNotifier {
+    client: HttpsClient,
+    url: String,
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), String> {
+        let body = json!({ "alert": alert.kind, "message": alert.message, "resolved": alert.resolved, "time": unix_time() });
+        post_json(&self.client, &self.url, &body).await
+    }
+}
+
+// Sends a message to a chat through a Telegram bot.
+struct TelegramNotifier {
+    client: HttpsClient,
+    bot_token: String,
+    chat_id: String,
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn describe(&self) -> String {
+        format!("telegram chat {}", self.chat_id)
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), String> {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+        let body = json!({ "chat_id": self.chat_id, "text": format!("{}\n{}", alert.subject(), alert.message) });
+        post_json(&self.client, &url, &body).await
+    }
+}
+
+// Mails the alert through an SMTP relay, over STARTTLS unless `tls` is
+// "none" or "wrapper" (implicit TLS, usually port 465).
+#[cfg(feature = "smtp")]
+struct SmtpNotifier {
+    transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
+    from: lettre::message::Mailbox,
+    to: Vec<lettre::message::Mailbox>,
+}
+
+#[cfg(feature = "smtp")]
+impl SmtpNotifier {
+    fn new(entry: &HashMap<String, Value>) -> Result<SmtpNotifier, String> {
+        use lettre::AsyncSmtpTransport;
+        use lettre::transport::smtp::authentication::Credentials;
+
+        let field = |name: &str| entry.get(name).and_then(Value::as_str);
+        let server = field("server").ok_or("smtp notifier without a server")?;
+        let mut builder = match field("tls").unwrap_or("starttls") {
+            "starttls" => AsyncSmtpTransport::<lettre::Tokio1Executor>::starttls_relay(server),
+            "wrapper" => AsyncSmtpTransport::<lettre::Tokio1Executor>::relay(server),
+            "none" => Ok(AsyncSmtpTransport::<lettre::Tokio1Executor>::builder_dangerous(server)),
+            tls => return Err(format!("Unknown smtp tls mode {}", tls)),
+        }
+        .map_err(|e| e.to_string())?;
+        if let Some(port) = entry.get("port").and_then(Value::as_u64) {
+            builder = builder.port(port as u16);
+        }
+        if let (Some(username), Some(password)) = (field("username"), field("password")) {
+            builder = builder.credentials(Credentials::new(username.to_string(), password.to_string()));
+        }
+        let from = field("from").ok_or("smtp notifier without a from address")?.parse().map_err(|e| format!("Invalid from address: {}", e))?;
+        let to = entry.get("to").and_then(Value::as_array).into_iter().flatten()
+            .filter_map(Value::as_str)
+            .map(|to| to.parse().map_err(|e| format!("Invalid to address {}: {}", to, e)))
+            .collect::<Result<Vec<_>, String>>()?;
+        if to.is_empty() {
+            return Err("smtp notifier without recipients".to_string());
+        }
+        Ok(SmtpNotifier { transport: builder.build(), from, to })
+    }
+}
+
+#[cfg(feature = "smtp")]
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    fn describe(&self) -> String {
+        format!("mail to {}", self.to.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), String> {
+        use lettre::AsyncTransport;
+
+        let mut message = lettre::Message::builder().from(self.from.clone()).subject(alert.subject());
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message.body(alert.message.clone()).map_err(|e| e.to_string())?;
+        self.transport.send(message).await.map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+pub fn load(entries: Vec<HashMap<String, Value>>) -> Result<Vec<Arc<dyn Notifier>>, String> {
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client: HttpsClient = Client::builder().build(https);
+    let mut notifiers: Vec<Arc<dyn Notifier>> = Vec::new();
+    for entry in entries {
+        let field = |name: &str| entry.get(name).and_then(Value::as_str).map(str::to_string);
+        match field("type").as_deref() {
+            Some("webhook") => notifiers.push(Arc::new(WebhookNotifier {
+                client: client.clone(),
+                url: field("url").ok_or("webhook notifier without a url")?,
+            })),
+            Some("telegram") => notifiers.push(Arc::new(TelegramNotifier {
+                client: client.clone(),
+                bot_token: field("bot_token").ok_or("telegram notifier without a bot_token")?,
+                chat_id: entry.get("chat_id").map(|id| id.as_str().map_or_else(|| id.to_string(), str::to_string)).ok_or("telegram notifier without a chat_id")?,
+            })),
+            #[cfg(feature = "smtp")]
+            Some("smtp") => notifiers.push(Arc::new(SmtpNotifier::new(&entry)?)),
+            #[cfg(not(feature = "smtp"))]
+            Some("smtp") => return Err("smtp notifiers need the smtp feature".to_string()),
+            other => return Err(format!("Unknown notifier type {:?}", other.unwrap_or_default())),
+        }
+    }
+    Ok(notifiers)
+}
+
+// Hands alerts to every notifier. Alerts are always logged; deliveries run in
+// the background and a failing notifier is logged without holding up the rest.
+pub struct Alerts {
+    notifiers: Vec<Arc<dyn Notifier>>,
+}
+
+impl Alerts {
+    pub fn new(notifiers: Vec<Arc<dyn Notifier>>) -> Alerts {
+        Alerts { notifiers }
+    }
+
+    pub fn has_notifiers(&self) -> bool {
+        !self.notifiers.is_empty()
+    }
+
+    pub fn raise(&self, alert: Alert) {
+        eprintln!("alert: {}: {}", alert.subject(), alert.message);
+        let alert = Arc::new(alert);
+        for notifier in &self.notifiers {
+            let (notifier, alert) = (notifier.clone(), alert.clone());
+            tokio::spawn(async move {
+                if let Err(e) = notifier.send(&alert).await {
+                    eprintln!("alert delivery to {} failed: {}", notifier.describe(), e);
+                }
+            });
+        }
+    }
+}
+
+// When the conditions `watch` checks count as an alert. A zero duration or
+// rate turns the check off. A stalled tip is judged by the tip's `max_age`.
+pub struct AlertRules {
+    pub daemon_unreachable: Duration,
+    // Share of failed calls over `error_window`, once there are at least
+    // `error_min_requests` of them.
+    pub error_rate: f64,
+    pub error_min_requests: u64,
+    pub error_window: Duration,
+    // Identities (names or i-addresses) whose updates are reported.
+    pub identities: Vec<String>,
+}
+
+// A condition that is either on or off, raised when it turns on and
+// resolved when it turns off.
+struct Condition {
+    kind: &'static str,
+    active: bool,
+}
+
+impl Condition {
+    fn new(kind: &'static str) -> Condition {
+        Condition { kind, active: false }
+    }
+
+    fn update(&mut self, alerts: &Alerts, active: bool, message: impl FnOnce() -> String) {
+        if active != self.active {
+            self.active = active;
+            alerts.raise(Alert { kind: self.kind, message: message(), resolved: !active });
+        }
+    }
+}
+
+// Resolves the watched identities to i-addresses, keeping those that can't be
+// looked up yet for the next try.
+async fn resolve_identities(rpc: &VerusRPC, pending: &mut Vec<String>, watched: &mut HashMap<String, String>) {
+    let mut unresolved = Vec::new();
+    for identity in pending.drain(..) {
+        if rest::is_id(&identity) {
+            watched.insert(identity.clone(), identity);
+            continue;
+        }
+        match rpc.upstream.call("getidentity", &[json!(identity)]).await {
+            Ok(found) => match found["identity"]["identityaddress"].as_str() {
+                Some(address) => {
+                    watched.insert(address.to_string(), identity);
+                },
+                None => unresolved.push(identity),
+            },
+            Err(e) => {
+                eprintln!("alerts: cannot look up identity {}: {}", identity, e.message);
+                unresolved.push(identity);
+            },
+        }
+    }
+    *pending = unresolved;
+}
+
+// Checks the daemon, the tip and the error rate every `interval`, and reports
+// updates of the watched identities as they are announced.
+pub async fn watch(rpc: Arc<VerusRPC>, rules: AlertRules, interval: Duration) {
+    let mut unreachable = Condition::new("daemon unreachable");
+    let mut stalled = Condition::new("chain tip stalled");
+    let mut errors = Condition::new("error rate");
+    let mut last_reply = Instant::now();
+    // When the error rate window started, with the call and error totals then.
+    let (requests, failed) = rpc.stats.totals();
+    let mut window = (Instant::now(), requests, failed);
+
+    let mut pending = rules.identities.clone();
+    let mut watched: HashMap<String, String> = HashMap::new();
+    let mut updates = rpc.events.subscribe();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            event = updates.recv(), if !rules.identities.is_empty() => {
+                match event {
+                    Ok(event) if event.kind == "identity_update" => {
+                        let address = event.data["identityaddress"].as_str().unwrap_or_default();
+                        if let Some(identity) = watched.get(address) {
+                            let name = event.data["name"].as_str().unwrap_or(identity);
+                            let txid = event.data["txid"].as_str().unwrap_or_default();
+                            rpc.alerts.raise(Alert {
+                                kind: "identity modified",
+                                message: format!("{} ({}) was updated in block {}, transaction {}", name, address, event.data["height"], txid),
+                                resolved: false,
+                            });
+                        }
+                    },
+                    Ok(_) => {},
+                    Err(RecvError::Lagged(missed)) => eprintln!("alerts: {} events missed", missed),
+                    Err(RecvError::Closed) => return,
+                }
+                continue;
+            },
+        }
+
+        if !pending.is_empty() {
+            resolve_identities(&rpc, &mut pending, &mut watched).await;
+        }
+
+        match rpc.upstream.call("getblockcount", &[]).await {
+            Ok(_) => last_reply = Instant::now(),
+            Err(e) if e.code == -32603 => {},
+            // Any answer from the daemon, even an error, means it is up.
+            Err(_) => last_reply = Instant::now(),
+        }
+        let down_for = last_reply.elapsed();
+        if !rules.daemon_unreachable.is_zero() {
+            unreachable.update(&rpc.alerts, down_for >= rules.daemon_unreachable, || match down_for >= rules.daemon_unreachable {
+                true => format!("The daemon has not answered for {}s", down_for.as_secs()),
+                false => "The daemon is answering again".to_string(),
+            });
+        }
+        if let (Some(height), Some(age)) = (rpc.tip.height(), rpc.tip.age()) {
+            let stuck = rpc.tip.is_stalled();
+            stalled.update(&rpc.alerts, stuck, || match stuck {
+                true => format!("Best block {} is {}s old", height, age),
+                false => format!("The chain moved on to block {}", height),
+            });
+        }
+        if rules.error_rate > 0.0 && window.0.elapsed() >= rules.error_window {
+            let (requests, failed) = rpc.stats.totals();
+            let (requests_in_window, failed_in_window) = (requests - window.1, failed - window.2);
+            let rate = failed_in_window as f64 / requests_in_window.max(1) as f64;
+            let spiking = requests_in_window >= rules.error_min_requests && rate >= rules.error_rate;
+            errors.update(&rpc.alerts, spiking, || match spiking {
+                true => format!("{} of {} calls failed in the last {}s", failed_in_window, requests_in_window, window.0.elapsed().as_secs()),
+                false => format!("Error rate is back to {:.0}%", rate * 100.0),
+            });
+            window = (Instant::now(), requests, failed);
+        }
+    }
+}
@@ -1,12 +1,17 @@
 use serde_json::{Value};
-use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
 
-fn check_params(params: &[Box<RawValue>], expected_types: &[&str]) -> bool {
+use crate::limiter::Priority;
+
+fn param_is_true(params: &[Value], index: usize) -> bool {
+    params.get(index).and_then(Value::as_bool).unwrap_or(false)
+}
+
+fn check_params(params: &[Value], expected_types: &[&str]) -> bool {
     if params.len() > expected_types.len() {
         return false;
     }
-    for (param, &expected_type) in params.iter().zip(expected_types) {
-        let value: Value = serde_json::from_str(&param.to_string()).unwrap();
+    for (value, &expected_type) in params.iter().zip(expected_types) {
         match expected_type {
             "obj" => if !matches!(value, Value::Object(_)) { return false; },
             "arr" => if !matches!(value, Value::Array(_)) { return false; },
@@ -20,90 +25,242 @@ fn check_params(params: &[Box<RawValue>], expected_types: &[&str]) -> bool {
     true
 }
 
-pub fn is_method_allowed(method: &str, params: &[Box<RawValue>]) -> bool {
-    match method {
-        "fundrawtransaction" => {
-            if params.len() != 4 {
-                return false;
-            }
-            match (serde_json::from_str::<Value>(&params[0].to_string()),
-                   serde_json::from_str::<Value>(&params[1].to_string()),
-                   serde_json::from_str::<Value>(&params[2].to_string()),
-                   serde_json::from_str::<Value>(&params[3].to_string())) {
-                (Ok(Value::String(_)), Ok(Value::Array(_)), Ok(Value::String(_)), Ok(Value::Number(_))) => true,
-                _ => false,
-            }
-        },
-        "recoveridentity" => params.get(1).and_then(|p| serde_json::from_str::<Value>(&p.to_string()).ok()).map_or(false, |v| v.as_bool().unwrap_or(false)) && check_params(params, &["obj", "bool", "bool", "float", "str"]),
-        "registeridentity" => params.get(1).and_then(|p| serde_json::from_str::<Value>(&p.to_string()).ok()).map_or(false, |v| v.as_bool().unwrap_or(false)) && check_params(params, &["obj", "bool", "float", "str"]),
-        "revokeidentity" => params.get(1).and_then(|p| serde_json::from_str::<Value>(&p.to_string()).ok()).map_or(false, |v| v.as_bool().unwrap_or(false)) && check_params(params, &["str", "bool", "bool", "float", "str"]),
-        "updateidentity" => params.get(1).and_then(|p| serde_json::from_str::<Value>(&p.to_string()).ok()).map_or(false, |v| v.as_bool().unwrap_or(false)) && check_params(params, &["obj", "bool", "bool", "float", "str"]),
-        "setidentitytimelock" => params.get(2).and_then(|p| serde_json::from_str::<Value>(&p.to_string()).ok()).map_or(false, |v| v.as_bool().unwrap_or(false)) && check_params(params, &["str", "obj", "bool", "float", "str"]),
-        "sendcurrency" => params.get(4).and_then(|p| serde_json::from_str::<Value>(&p.to_string()).ok()).map_or(false, |v| v.as_bool().unwrap_or(false)) && check_params(params, &["str", "arr", "int", "float", "bool"]),
-        "coinsupply" => check_params(params, &[]),
-        "convertpassphrase" => check_params(params, &["str"]),
-        "createmultisig" => check_params(params, &["int", "arr"]),
-        "createrawtransaction" => check_params(params, &["arr", "obj", "int", "int"]),
-        "decoderawtransaction" => check_params(params, &["str", "bool"]),
-        "decodescript" => check_params(params, &["str", "bool"]),
-        "estimateconversion" => check_params(params, &["obj"]),
-        "estimatefee" => check_params(params, &["int"]),
-        "estimatepriority" => check_params(params, &["int"]),
-        "getaddressmempool" => check_params(params, &["obj"]),
-        "getaddressutxos" => check_params(params, &["obj"]),
-        "getaddressbalance" => check_params(params, &["obj"]),
-        "getaddressdeltas" => check_params(params, &["obj"]),
-        "getaddresstxids" => check_params(params, &["obj"]),
-        "getbestblockhash" => check_params(params, &[]),
-        "getbestproofroot" => check_params(params, &["obj"]),
-        "getblock" => check_params(params, &["str", "bool"]),
-        "getblockchaininfo" => check_params(params, &[]),
-        "getblockcount" => check_params(params, &[]),
-        "getblockhashes" => check_params(params, &["int", "int"]),
-        "getblockhash" => check_params(params, &["int"]),
-        "getblockheader" => check_params(params, &["str"]),
-        "getblocksubsidy" => check_params(params, &["int"]),
-        "getblocktemplate" => check_params(params, &["obj"]),
-        "getchaintips" => check_params(params, &[]),
-        "getcurrency" => check_params(params, &["str"]),
-        "getcurrencyconverters" => check_params(params, &["str", "str", "str"]),
-        "getcurrencystate" => check_params(params, &["str"]),
-        "getcurrencytrust" => check_params(params, &["arr"]),
-        "getdifficulty" => check_params(params, &[]),
-        "getexports" => check_params(params, &["str", "int", "int"]),
-        "getinfo" => check_params(params, &[]),
-        "getinitialcurrencystate" => check_params(params, &["str"]),
-        "getidentitieswithaddress" => check_params(params, &["obj"]),
-        "getidentitieswithrevocation" => check_params(params, &["obj"]),
-        "getidentitieswithrecovery" => check_params(params, &["obj"]),
-        "getidentity" => check_params(params, &["str", "int", "bool", "int"]),
-        "getidentitytrust" => check_params(params, &["arr"]),
-        "getlastimportfrom" => check_params(params, &["str"]),
-        "getimports" => check_params(params, &["str","int","int"]),
-        "getlaunchinfo" => check_params(params, &["str"]),
-        "getmempoolinfo" => check_params(params, &[]),
-        "getmininginfo" => check_params(params, &[]),
-        "getnetworkinfo" => check_params(params, &[]),
-        "getnotarizationdata" => check_params(params, &["str"]),
-        "getoffers" => check_params(params, &["str", "bool", "bool"]),
-        "getpendingtransfers" => check_params(params, &["str"]),
-        "getrawmempool" => check_params(params, &[]),
-        "getrawtransaction" => check_params(params, &["str", "int"]),
-        "getreservedeposits" => check_params(params, &["str"]),
-        "getsaplingtree" => check_params(params, &["int"]),
-        "getspentinfo" => check_params(params, &["obj"]),
-        "gettxout" => check_params(params, &["str", "int", "bool"]),
-        "gettxoutsetinfo" => check_params(params, &[]),
-        "getvdxfid" => check_params(params, &["str", "obj"]),
-        "hashdata" => check_params(params, &["str", "str", "str"]),
-        "help" => check_params(params, &[]),
-        "listcurrencies" => check_params(params, &["obj", "int", "int"]),
-        "sendrawtransaction" => check_params(params, &["str"]),
-        "submitacceptednotarization" => check_params(params, &["obj", "obj"]),
-        "submitimports" => check_params(params, &["obj"]),
-        "verifymessage" => check_params(params, &["str", "str", "str", "bool"]),
-        "verifyhash" => check_params(params, &["str", "str", "str", "bool"]),
-        "verifysignature" => check_params(params, &["obj"]),
-        _ => false,
+// One allowlisted method: the types of the params it takes, in order (any may
+// be left out from the end), and the flag param that must be true, e.g.
+// returntx on identity operations.
+pub struct Rule {
+    pub method: &'static str,
+    pub params: &'static [&'static str],
+    pub flag: Option<usize>,
+}
+
+impl Rule {
+    fn allows(&self, params: &[Value]) -> bool {
+        self.flag.is_none_or(|index| param_is_true(params, index)) && check_params(params, self.params)
+    }
+}
+
+fn find(rules: &'static [Rule], method: &str) -> Option<&'static Rule> {
+    rules.iter().find(|rule| rule.method == method)
+}
+
+const STANDARD: &[Rule] = &[
+    // All four params are required and the fee may be any number; see
+    // is_method_allowed.
+    Rule { method: "fundrawtransaction", params: &["str", "arr", "str", "float"], flag: None },
+    Rule { method: "makeoffer", params: &["str", "obj", "bool", "float"], flag: Some(2) },
+    Rule { method: "recoveridentity", params: &["obj", "bool", "bool", "float", "str"], flag: Some(1) },
+    Rule { method: "registeridentity", params: &["obj", "bool", "float", "str"], flag: Some(1) },
+    Rule { method: "revokeidentity", params: &["str", "bool", "bool", "float", "str"], flag: Some(1) },
+    Rule { method: "updateidentity", params: &["obj", "bool", "bool", "float", "str"], flag: Some(1) },
+    Rule { method: "setidentitytimelock", params: &["str", "obj", "bool", "float", "str"], flag: Some(2) },
+    // Only transaction templates (returntxtemplate) are built for the client
+    // to sign; the send policy is checked on top.
+    Rule { method: "sendcurrency", params: &["str", "arr", "int", "float", "bool"], flag: Some(4) },
+    Rule { method: "coinsupply", params: &[], flag: None },
+    Rule { method: "convertpassphrase", params: &["str"], flag: None },
+    Rule { method: "createmultisig", params: &["int", "arr"], flag: None },
+    Rule { method: "createrawtransaction", params: &["arr", "obj", "int", "int"], flag: None },
+    Rule { method: "decoderawtransaction", params: &["str", "bool"], flag: None },
+    Rule { method: "decodescript", params: &["str", "bool"], flag: None },
+    Rule { method: "estimateconversion", params: &["obj"], flag: None },
+    Rule { method: "estimatefee", params: &["int"], flag: None },
+    Rule { method: "estimatepriority", params: &["int"], flag: None },
+    Rule { method: "getaddressmempool", params: &["obj"], flag: None },
+    Rule { method: "getaddressutxos", params: &["obj"], flag: None },
+    Rule { method: "getaddressbalance", params: &["obj"], flag: None },
+    Rule { method: "getaddressdeltas", params: &["obj"], flag: None },
+    Rule { method: "getaddresstxids", params: &["obj"], flag: None },
+    Rule { method: "getbestblockhash", params: &[], flag: None },
+    Rule { method: "getbestproofroot", params: &["obj"], flag: None },
+    Rule { method: "getblock", params: &["str", "bool"], flag: None },
+    Rule { method: "getblockchaininfo", params: &[], flag: None },
+    Rule { method: "getblockcount", params: &[], flag: None },
+    Rule { method: "getblockhashes", params: &["int", "int"], flag: None },
+    Rule { method: "getblockhash", params: &["int"], flag: None },
+    Rule { method: "getblockheader", params: &["str"], flag: None },
+    Rule { method: "getchaintips", params: &[], flag: None },
+    Rule { method: "getcurrency", params: &["str"], flag: None },
+    Rule { method: "getcurrencyconverters", params: &["str", "str", "str"], flag: None },
+    Rule { method: "getcurrencystate", params: &["str"], flag: None },
+    Rule { method: "getcurrencytrust", params: &["arr"], flag: None },
+    Rule { method: "getdifficulty", params: &[], flag: None },
+    Rule { method: "getexports", params: &["str", "int", "int"], flag: None },
+    Rule { method: "getinfo", params: &[], flag: None },
+    Rule { method: "getinitialcurrencystate", params: &["str"], flag: None },
+    Rule { method: "getidentitieswithaddress", params: &["obj"], flag: None },
+    Rule { method: "getidentitieswithrevocation", params: &["obj"], flag: None },
+    Rule { method: "getidentitieswithrecovery", params: &["obj"], flag: None },
+    Rule { method: "getidentity", params: &["str", "int", "bool", "int"], flag: None },
+    Rule { method: "getidentitytrust", params: &["arr"], flag: None },
+    Rule { method: "getlastimportfrom", params: &["str"], flag: None },
+    Rule { method: "getimports", params: &["str", "int", "int"], flag: None },
+    Rule { method: "getlaunchinfo", params: &["str"], flag: None },
+    Rule { method: "getmempoolinfo", params: &[], flag: None },
+    Rule { method: "getmininginfo", params: &[], flag: None },
+    Rule { method: "getnetworkinfo", params: &[], flag: None },
+    Rule { method: "getnotarizationdata", params: &["str"], flag: None },
+    Rule { method: "getoffers", params: &["str", "bool", "bool"], flag: None },
+    Rule { method: "getpendingtransfers", params: &["str"], flag: None },
+    Rule { method: "getrawmempool", params: &[], flag: None },
+    Rule { method: "getrawtransaction", params: &["str", "int"], flag: None },
+    Rule { method: "getreservedeposits", params: &["str"], flag: None },
+    Rule { method: "getsaplingtree", params: &["int"], flag: None },
+    Rule { method: "getspentinfo", params: &["obj"], flag: None },
+    Rule { method: "gettxout", params: &["str", "int", "bool"], flag: None },
+    Rule { method: "gettxoutsetinfo", params: &[], flag: None },
+    Rule { method: "getvdxfid", params: &["str", "obj"], flag: None },
+    Rule { method: "hashdata", params: &["str", "str", "str"], flag: None },
+    Rule { method: "help", params: &[], flag: None },
+    Rule { method: "listcurrencies", params: &["obj", "int", "int"], flag: None },
+    Rule { method: "sendrawtransaction", params: &["str"], flag: None },
+    Rule { method: "submitacceptednotarization", params: &["obj", "obj"], flag: None },
+    Rule { method: "submitimports", params: &["obj"], flag: None },
+    Rule { method: "verifymessage", params: &["str", "str", "str", "bool"], flag: None },
+    Rule { method: "verifyhash", params: &["str", "str", "str", "bool"], flag: None },
+    Rule { method: "verifysignature", params: &["obj"], flag: None },
+];
+
+pub fn is_method_allowed(method: &str, params: &[Value]) -> bool {
+    if method == "fundrawtransaction" {
+        if params.len() != 4 {
+            return false;
+        }
+        return matches!((&params[0], &params[1], &params[2], &params[3]),
+                        (Value::String(_), Value::Array(_), Value::String(_), Value::Number(_)));
+    }
+    find(STANDARD, method).is_some_and(|rule| rule.allows(params))
+}
+
+// Shielded viewing and operation status methods. They reveal wallet data, so
+// they are only allowed when enabled in the config, which should only be done
+// behind authentication.
+const SHIELDED: &[Rule] = &[
+    Rule { method: "z_getbalance", params: &["str", "int"], flag: None },
+    Rule { method: "z_getnotescount", params: &["int"], flag: None },
+    Rule { method: "z_getoperationresult", params: &["arr"], flag: None },
+    Rule { method: "z_getoperationstatus", params: &["arr"], flag: None },
+    Rule { method: "z_gettotalbalance", params: &["int", "bool"], flag: None },
+    Rule { method: "z_listreceivedbyaddress", params: &["str", "int"], flag: None },
+    Rule { method: "z_listunspent", params: &["int", "int", "bool", "arr"], flag: None },
+    Rule { method: "z_validateaddress", params: &["str"], flag: None },
+    Rule { method: "z_viewtransaction", params: &["str"], flag: None },
+];
+
+pub fn is_shielded_method_allowed(method: &str, params: &[Value]) -> bool {
+    find(SHIELDED, method).is_some_and(|rule| rule.allows(params))
+}
+
+// Mining methods. getblocktemplate is expensive and only miners need either,
+// so they are only allowed where mining access is granted.
+const MINING: &[Rule] = &[
+    Rule { method: "getblocksubsidy", params: &["int"], flag: None },
+    Rule { method: "getblocktemplate", params: &["obj"], flag: None },
+];
+
+fn is_mining_method_allowed(method: &str, params: &[Value]) -> bool {
+    find(MINING, method).is_some_and(|rule| rule.allows(params))
+}
+
+// Name commitments. Unlike the identity operations above there is no
+// returntx, so the daemon pays for and broadcasts the commitment from its own
+// wallet; they are only allowed where registration access is granted.
+const REGISTRATION: &[Rule] = &[
+    // The name and control address are required; see address::check.
+    Rule { method: "registernamecommitment", params: &["str", "str", "str", "str", "str"], flag: None },
+];
+
+fn is_registration_method_allowed(method: &str, params: &[Value]) -> bool {
+    find(REGISTRATION, method).is_some_and(|rule| rule.allows(params))
+}
+
+// Which methods a listener lets its clients call: the standard allowlist, the
+// same without write methods, or any daemon method for trusted local tooling.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    ReadOnly,
+    Standard,
+    Full,
+}
+
+impl Scope {
+    pub fn parse(scope: &str) -> Option<Scope> {
+        match scope {
+            "readonly" => Some(Scope::ReadOnly),
+            "standard" => Some(Scope::Standard),
+            "full" => Some(Scope::Full),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::ReadOnly => "readonly",
+            Scope::Standard => "standard",
+            Scope::Full => "full",
+        }
+    }
+}
+
+// What one request may call: its listener's scope, plus the mining methods,
+// the shielded viewing methods and the name commitments when the listener or
+// the client's key allows them, narrowed by its roles
+// (a bitmask, see `Roles`; 0 for none). Also carries the priority its daemon
+// calls queue at, whether its replies get a `_debug` field, whether it still
+// has to solve a captcha and who it authenticated as.
+#[derive(Clone, Copy)]
+pub struct Access {
+    pub scope: Scope,
+    pub mining: bool,
+    pub shielded: bool,
+    pub registration: bool,
+    pub priority: Priority,
+    pub roles: u64,
+    pub debug: bool,
+    // Set for anonymous clients that haven't solved a captcha, whose calls to
+    // the captcha_methods are refused.
+    pub needs_captcha: bool,
+    // A digest of the session or key subject the request authenticated with,
+    // so `Access` stays `Copy`; None for anonymous requests.
+    pub principal: Option<[u8; 16]>,
+}
+
+impl Access {
+    pub const STANDARD: Access = Access { scope: Scope::Standard, mining: false, shielded: false, registration: false, priority: Priority::Interactive, roles: 0, debug: false, needs_captcha: false, principal: None };
+
+    // This access for a request authenticated as `subject`.
+    pub fn authenticated(self, subject: &str) -> Access {
+        let mut principal = [0u8; 16];
+        principal.copy_from_slice(&Sha256::digest(subject.as_bytes())[..16]);
+        Access { principal: Some(principal), ..self }
+    }
+
+    // `is_write` is whether the method is annotated as a write.
+    pub fn permits(self, method: &str, params: &[Value], is_write: bool) -> bool {
+        let allowed = || {
+            is_method_allowed(method, params)
+                || (self.shielded && is_shielded_method_allowed(method, params))
+                || (self.mining && is_mining_method_allowed(method, params))
+                || (self.registration && is_registration_method_allowed(method, params))
+        };
+        match self.scope {
+            Scope::Full => true,
+            Scope::Standard => allowed(),
+            Scope::ReadOnly => !is_write && allowed(),
+        }
+    }
+
+    // The rules of every method this access allows, for documentation; None
+    // under full access, which allows any daemon method.
+    pub fn rules(self, is_write: impl Fn(&str) -> bool) -> Option<Vec<&'static Rule>> {
+        if self.scope == Scope::Full {
+            return None;
+        }
+        let shielded = if self.shielded { SHIELDED } else { &[] };
+        let mining = if self.mining { MINING } else { &[] };
+        let registration = if self.registration { REGISTRATION } else { &[] };
+        Some(STANDARD.iter().chain(shielded).chain(mining).chain(registration)
+            .filter(|rule| self.scope != Scope::ReadOnly || !is_write(rule.method))
+            .collect())
     }
 }
@@ -0,0 +1,109 @@
+use jsonrpc::error::RpcError;
+use serde_json::Value;
+
+// Smallest unit is 1e-8 of a coin.
+const MAX_DECIMALS: usize = 8;
+
+// Object fields that hold an amount, wherever they appear in the params.
+const AMOUNT_FIELDS: &[&str] = &["amount", "feeamount", "fee", "feeoffer"];
+
+// Positional fee params of the methods that take one.
+fn fee_position(method: &str) -> Option<usize> {
+    match method {
+        "registeridentity" => Some(2),
+        "fundrawtransaction" | "recoveridentity" | "revokeidentity" | "setidentitytimelock" | "sendcurrency" | "updateidentity" => Some(3),
+        _ => None,
+    }
+}
+
+// Decimal places of a plain (`0.001`) or exponent (`1e-8`) number.
+fn decimals(repr: &str) -> Option<usize> {
+    let (mantissa, exponent) = match repr.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+        None => (repr, 0),
+    };
+    let mantissa = mantissa.strip_prefix('-').unwrap_or(mantissa);
+    let fraction = match mantissa.split_once('.') {
+        Some((whole, fraction)) if whole.chars().all(|c| c.is_ascii_digit()) => fraction,
+        Some(_) => return None,
+        None => "",
+    };
+    if !mantissa.chars().any(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((fraction.trim_end_matches('0').len() as i64 - exponent).max(0) as usize)
+}
+
+fn check_amount(value: &Value, require_strings: bool) -> Result<(), String> {
+    let repr = match value {
+        Value::Number(n) if require_strings => return Err(format!("{} must be given as a string", n)),
+        Value::Number(n) if n.as_f64().is_some_and(|f| !f.is_finite()) => return Err(format!("{} is not finite", n)),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        // Anything else is left for the daemon to reject.
+        _ => return Ok(()),
+    };
+    match decimals(&repr) {
+        Some(places) if places <= MAX_DECIMALS => Ok(()),
+        Some(_) => Err(format!("{} has more than {} decimal places", repr, MAX_DECIMALS)),
+        None => Err(format!("{} is not a number", repr)),
+    }
+}
+
+fn check_fields(value: &Value, require_strings: bool) -> Result<(), String> {
+    match value {
+        Value::Object(map) => map.iter().try_for_each(|(key, value)| {
+            if AMOUNT_FIELDS.contains(&key.as_str()) {
+                check_amount(value, require_strings)
+            } else {
+                check_fields(value, require_strings)
+            }
+        }),
+        Value::Array(items) => items.iter().try_for_each(|item| check_fields(item, require_strings)),
+        _ => Ok(()),
+    }
+}
+
+pub struct AmountRules {
+    // Amounts in objects (such as sendcurrency outputs) must be strings, so a
+    // client's float formatting can't change the value.
+    pub require_strings: bool,
+    // Highest fee accepted for updateidentity, revokeidentity and recoveridentity.
+    pub max_identity_fee: f64,
+    // Highest fee offer accepted for registeridentity.
+    pub max_registration_fee: f64,
+}
+
+impl AmountRules {
+    // Fee ceiling for the identity operations; 0 means no ceiling.
+    fn fee_ceiling(&self, method: &str) -> f64 {
+        match method {
+            "registeridentity" => self.max_registration_fee,
+            "updateidentity" | "revokeidentity" | "recoveridentity" => self.max_identity_fee,
+            _ => 0.0,
+        }
+    }
+
+    // Rejects amounts the daemon would round or misread: non-finite numbers and
+    // more than eight decimal places, plus identity fees above their ceiling,
+    // which would otherwise be paid without question. Positional fees are
+    // numbers in the allowlist, so only their value is checked.
+    pub fn check(&self, method: &str, params: &[Value]) -> Result<(), RpcError> {
+        let fee = match fee_position(method).and_then(|i| params.get(i)) {
+            Some(fee) => check_amount(fee, false),
+            None => Ok(()),
+        };
+        fee.and_then(|_| params.iter().try_for_each(|param| check_fields(param, self.require_strings)))
+            .map_err(|reason| RpcError { code: -3, message: format!("Invalid amount: {}", reason), data: None })?;
+
+        let ceiling = self.fee_ceiling(method);
+        match fee_position(method).and_then(|i| params.get(i)).and_then(Value::as_f64) {
+            Some(fee) if ceiling > 0.0 && fee > ceiling => Err(RpcError {
+                code: -3,
+                message: format!("Fee {} exceeds the maximum of {} for {}", fee, ceiling, method),
+                data: None,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::limiter::Priority;
+
+// Properties of a method that the cache, the limits and the write checks go
+// by, declared once per method in `BUILT_IN` (or the [annotations] table)
+// rather than in lists of their own.
+#[derive(Clone, Default)]
+pub struct Annotations {
+    // cacheable(ttl): cached for ttl seconds unless [cache] says otherwise.
+    pub cache_ttl: Option<u64>,
+    // invalidate-on-block: the answer can change with every block, so cached
+    // results are keyed to the tip height.
+    pub invalidate_on_block: bool,
+    // never-cache: never answered from or stored in a cache, whatever [cache]
+    // or negative caching say.
+    pub never_cache: bool,
+    // heavy(concurrency=N): expensive for the daemon, so at most N calls run at once.
+    pub heavy: Option<usize>,
+    // priority(class): queues for the daemon at no higher than this priority
+    // (background or analytics), whoever calls it.
+    pub priority: Priority,
+    // group(name): belongs to a named group of methods that roles can be
+    // given, such as send, identity, offers or mining.
+    pub groups: Vec<String>,
+    // write: changes wallet or chain state. Batches run these on their own,
+    // read-only listeners refuse them and enforce_origin applies to them.
+    pub write: bool,
+}
+
+const WRITE: &[&str] = &["write", "never-cache"];
+const SEND: &[&str] = &["write", "never-cache", "group(send)"];
+const IDENTITY: &[&str] = &["write", "never-cache", "group(identity)"];
+const TIP: &[&str] = &["invalidate-on-block"];
+
+const BUILT_IN: &[(&str, &[&str])] = &[
+    ("sendcurrency", SEND),
+    ("sendrawtransaction", SEND),
+    ("confirmsend", SEND),
+    ("registernamecommitment", IDENTITY),
+    ("registeridentity", IDENTITY),
+    ("updateidentity", IDENTITY),
+    ("revokeidentity", IDENTITY),
+    ("recoveridentity", IDENTITY),
+    ("setidentitytimelock", IDENTITY),
+    ("makeoffer", &["write", "never-cache", "group(offers)"]),
+    ("submitacceptednotarization", WRITE),
+    ("submitimports", WRITE),
+    ("getbestblockhash", TIP),
+    ("getblockchaininfo", TIP),
+    ("getblockcount", TIP),
+    ("getinfo", TIP),
+    ("getmininginfo", TIP),
+    ("getblocktemplate", &["never-cache", "heavy(1)", "group(mining)"]),
+    ("getblocksubsidy", &["heavy(1)", "group(mining)"]),
+    ("gettxoutsetinfo", &["heavy(1)", "priority(analytics)"]),
+    // A VDXF id only depends on its name.
+    ("getvdxfid", &["cacheable(86400)"]),
+    // Converter lists and currency states change with each block; the
+    // converter endpoint looks up many at once.
+    ("getcurrencyconverters", &["cacheable(60)", "invalidate-on-block"]),
+    ("getcurrencystate", &["cacheable(60)", "invalidate-on-block"]),
+    // Pages of listcurrencies are cut from one cached full list.
+    ("listcurrencies", &["cacheable(60)"]),
+    // Signature checks are deterministic for a given signature and height, so
+    // repeated login checks are answered from the cache.
+    ("verifymessage", &["cacheable(3600)", "invalidate-on-block"]),
+    ("verifyhash", &["cacheable(3600)", "invalidate-on-block"]),
+    ("verifysignature", &["cacheable(3600)", "invalidate-on-block"]),
+];
+
+impl Annotations {
+    // Parses annotations such as ["cacheable(60)", "heavy(concurrency=2)"].
+    pub fn parse(annotations: &[impl AsRef<str>]) -> Result<Annotations, String> {
+        let mut parsed = Annotations::default();
+        for annotation in annotations {
+            let annotation = annotation.as_ref().trim();
+            let (name, argument) = match annotation.strip_suffix(')').and_then(|rest| rest.split_once('(')) {
+                Some((name, argument)) => (name, Some(argument.trim())),
+                None => (annotation, None),
+            };
+            let number = |key: &str| {
+                argument
+                    .map(|argument| argument.strip_prefix(key).map_or(argument, |value| value.trim_start_matches([' ', '='])))
+                    .and_then(|value| value.parse::<u64>().ok())
+                    .ok_or_else(|| format!("{} needs a number", name))
+            };
+            match name {
+                "cacheable" => parsed.cache_ttl = Some(number("ttl")?),
+                "heavy" => parsed.heavy = Some(number("concurrency")?.max(1) as usize),
+                "priority" => {
+                    parsed.priority = argument.and_then(Priority::parse).ok_or_else(|| format!("Unknown priority in {}", annotation))?
+                },
+                "group" => match argument.filter(|group| !group.is_empty()) {
+                    Some(group) => parsed.groups.push(group.to_string()),
+                    None => return Err(format!("{} needs a group name", annotation)),
+                },
+                "invalidate-on-block" => parsed.invalidate_on_block = true,
+                "never-cache" => parsed.never_cache = true,
+                "write" => parsed.write = true,
+                _ => return Err(format!("Unknown annotation {}", annotation)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+static UNANNOTATED: Annotations = Annotations {
+    cache_ttl: None,
+    invalidate_on_block: false,
+    never_cache: false,
+    heavy: None,
+    priority: Priority::Interactive,
+    groups: Vec::new(),
+    write: false,
+};
+
+// The annotations of every method, with a concurrency limit for each heavy one.
+pub struct MethodTable {
+    methods: HashMap<String, Annotations>,
+    heavy: HashMap<String, Semaphore>,
+}
+
+impl MethodTable {
+    // The built-in annotations, with methods listed in `overrides` replacing
+    // theirs, except that a built-in write stays a write and never cached,
+    // whatever its override says.
+    pub fn new(overrides: HashMap<String, Vec<String>>) -> Result<MethodTable, String> {
+        let mut methods: HashMap<String, Annotations> = BUILT_IN
+            .iter()
+            .map(|(method, annotations)| (method.to_string(), Annotations::parse(annotations).unwrap()))
+            .collect();
+        for (method, annotations) in overrides {
+            let mut annotations = Annotations::parse(&annotations).map_err(|e| format!("{}: {}", method, e))?;
+            if methods.get(&method).is_some_and(|built_in| built_in.write) {
+                annotations.write = true;
+                annotations.never_cache = true;
+            }
+            methods.insert(method, annotations);
+        }
+        let heavy = methods.iter()
+            .filter_map(|(method, annotations)| annotations.heavy.map(|limit| (method.clone(), Semaphore::new(limit))))
+            .collect();
+        Ok(MethodTable { methods, heavy })
+    }
+
+    pub fn get(&self, method: &str) -> &Annotations {
+        self.methods.get(method).unwrap_or(&UNANNOTATED)
+    }
+
+    pub fn is_write(&self, method: &str) -> bool {
+        self.get(method).write
+    }
+
+    // Default cache lifetimes of the cacheable methods.
+    pub fn cache_ttls(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.methods.iter().filter_map(|(method, annotations)| annotations.cache_ttl.map(|ttl| (method.as_str(), ttl)))
+    }
+
+    // Waits for a slot if the method is heavy; the slot is held until the
+    // returned permit is dropped.
+    pub async fn throttle(&self, method: &str) -> Option<SemaphorePermit<'_>> {
+        match self.heavy.get(method) {
+            Some(limit) => limit.acquire().await.ok(),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(overrides: &[(&str, &[&str])]) -> MethodTable {
+        MethodTable::new(overrides.iter()
+            .map(|(method, annotations)| (method.to_string(), annotations.iter().map(|a| a.to_string()).collect()))
+            .collect()).unwrap()
+    }
+
+    #[test]
+    fn overriding_a_built_in_write_keeps_it_a_never_cached_write() {
+        let methods = table(&[("sendcurrency", &["priority(background)"]), ("registeridentity", &["heavy(2)"])]);
+        for method in ["sendcurrency", "registeridentity"] {
+            assert!(methods.get(method).write);
+            assert!(methods.get(method).never_cache);
+        }
+        assert_eq!(methods.get("sendcurrency").priority, Priority::Background);
+        assert_eq!(methods.get("registeridentity").heavy, Some(2));
+    }
+
+    #[test]
+    fn overrides_still_replace_the_other_annotations() {
+        let methods = table(&[("sendcurrency", &["group(payments)"]), ("getblocktemplate", &["heavy(4)"])]);
+        assert_eq!(methods.get("sendcurrency").groups, vec!["payments".to_string()]);
+        assert!(!methods.get("getblocktemplate").never_cache);
+        assert!(methods.get("getblocktemplate").groups.is_empty());
+        assert!(!methods.is_write("getblocktemplate"));
+    }
+}
@@ -0,0 +1,132 @@
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+use crate::annotations::MethodTable;
+use crate::indexer::unix_time;
+use crate::redact;
+
+// The "prev" of the first entry.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// The hash of an entry: SHA-256 of its JSON without the "hash" field. Keys
+// serialize sorted, so a parsed entry hashes the same as when it was written.
+fn entry_hash(entry: &Map<String, Value>) -> String {
+    hex::encode(Sha256::digest(Value::Object(entry.clone()).to_string()))
+}
+
+// Checks one line against the entry before it, returning its seq and hash.
+fn check_line(line: &str, seq: u64, prev: &str) -> Result<(u64, String), String> {
+    let mut entry = match serde_json::from_str(line) {
+        Ok(Value::Object(entry)) => entry,
+        _ => return Err("not a JSON object".to_string()),
+    };
+    let hash = match entry.remove("hash") {
+        Some(Value::String(hash)) => hash,
+        _ => return Err("no hash".to_string()),
+    };
+    if entry.get("seq").and_then(Value::as_u64) != Some(seq) {
+        return Err(format!("expected seq {}", seq));
+    }
+    if entry.get("prev").and_then(Value::as_str) != Some(prev) {
+        return Err("prev does not match the hash of the entry before".to_string());
+    }
+    if entry_hash(&entry) != hash {
+        return Err("hash does not match the entry".to_string());
+    }
+    Ok((seq, hash))
+}
+
+// Reads a log from the start, checking every link. Returns the number of
+// entries and the last hash.
+pub fn verify(path: &str) -> Result<(u64, String), String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let (mut count, mut prev) = (0, GENESIS.to_string());
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let (_, hash) = check_line(&line, count + 1, &prev).map_err(|e| format!("entry {}: {}", count + 1, e))?;
+        count += 1;
+        prev = hash;
+    }
+    Ok((count, prev))
+}
+
+// Append-only JSON lines log of write calls, one entry per call with who made
+// it and how it ended. Each entry carries a sequence number, the hash of the
+// entry before it and its own hash, so removing, reordering or editing any
+// entry breaks the chain from there on (see `audit verify`). Publishing the
+// last hash now and then pins the log up to that point. With `redact`,
+// params and results are logged as `redact` masks them.
+pub struct AuditLog {
+    // The file, and the seq and hash of the last entry.
+    state: Mutex<(File, u64, String)>,
+    redact: bool,
+}
+
+impl AuditLog {
+    // Opens the log to append to, checking the existing entries so a damaged
+    // log isn't extended.
+    pub fn open(path: &str, redact: bool) -> Result<AuditLog, String> {
+        let (seq, prev) = match std::path::Path::new(path).exists() {
+            true => verify(path)?,
+            false => (0, GENESIS.to_string()),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        Ok(AuditLog { state: Mutex::new((file, seq, prev)), redact })
+    }
+
+    fn append(&self, fields: Value) {
+        let mut state = self.state.lock().unwrap();
+        let (file, seq, prev) = &mut *state;
+        let mut entry = match fields {
+            Value::Object(entry) => entry,
+            _ => return,
+        };
+        entry.insert("seq".to_string(), json!(*seq + 1));
+        entry.insert("time".to_string(), json!(unix_time()));
+        entry.insert("prev".to_string(), json!(prev));
+        let hash = entry_hash(&entry);
+        entry.insert("hash".to_string(), json!(hash));
+        if let Err(e) = writeln!(file, "{}", Value::Object(entry)).and_then(|_| file.sync_data()) {
+            eprintln!("audit log: failed to write: {}", e);
+            return;
+        }
+        *seq += 1;
+        *prev = hash;
+    }
+
+    // Logs the write calls among `requests` (one call or a batch) with the
+    // replies they got, in the same shape.
+    pub fn record(&self, principal: &str, client: &str, requests: &Value, replies: &Value, methods: &MethodTable) {
+        let calls: Vec<(&Value, &Value)> = match (requests, replies) {
+            (Value::Array(requests), Value::Array(replies)) => requests.iter().zip(replies).collect(),
+            (Value::Array(_), _) => return,
+            (request, reply) => vec![(request, reply)],
+        };
+        for (request, reply) in calls {
+            match request["method"].as_str() {
+                Some(method) if methods.is_write(method) => self.record_one(principal, client, method, &request["params"], reply),
+                _ => continue,
+            }
+        }
+    }
+
+    // Logs one call, or something the proxy did for a client (like a faucet
+    // send), with its reply.
+    pub fn record_one(&self, principal: &str, client: &str, method: &str, params: &Value, reply: &Value) {
+        let (params, result, error) = match self.redact {
+            true => (redact::params(method, params), redact::result(method, &reply["result"]), redact::value(&reply["error"])),
+            false => (params.clone(), reply["result"].clone(), reply["error"].clone()),
+        };
+        self.append(json!({
+            "principal": principal,
+            "client": client,
+            "method": method,
+            "params": params,
+            "result": result,
+            "error": error,
+        }));
+    }
+}
@@ -0,0 +1,101 @@
+use serde_json::{Value, json};
+use jsonrpc::error::RpcError;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use crate::{VerusRPC, reply, with_warning};
+use crate::allowlist::Access;
+
+pub struct BatchLimits {
+    pub max_size: usize,
+    pub concurrency: usize,
+}
+
+impl BatchLimits {
+    // What a request counts as against the rate limits: a call per batch
+    // entry, up to the most a batch may have.
+    pub fn calls(&self, body: &Value) -> u64 {
+        match body {
+            Value::Array(entries) => entries.len().clamp(1, self.max_size.max(1)) as u64,
+            _ => 1,
+        }
+    }
+}
+
+// Runs a JSON-RPC batch. Consecutive read-only entries are executed together,
+// at most `concurrency` at a time, while a write method waits for everything
+// before it and runs on its own, so a read that follows a write in the batch
+// still observes it. Replies keep the order (and ids) of the request entries.
+pub async fn handle_batch(entries: Vec<Value>, rpc: Arc<VerusRPC>, access: Access, caller: &str) -> Value {
+    if entries.is_empty() {
+        return reply(Err(RpcError { code: -32600, message: "Invalid Request".into(), data: None }));
+    }
+    if entries.len() > rpc.batch.max_size {
+        return reply(Err(RpcError { code: -32600, message: format!("Batch too large, max {} entries", rpc.batch.max_size), data: None }));
+    }
+
+    let limit = Arc::new(Semaphore::new(rpc.batch.concurrency.max(1)));
+    let mut replies = Vec::with_capacity(entries.len());
+    let mut reads: Vec<JoinHandle<Value>> = Vec::new();
+
+    for mut entry in entries {
+        let warning = rpc.migrations.apply(&mut entry, caller);
+        let is_write = entry["method"].as_str().is_some_and(|method| rpc.methods.is_write(method));
+        if is_write {
+            for read in reads.drain(..) {
+                replies.push(read.await.unwrap());
+            }
+        }
+        let permit = limit.clone().acquire_owned().await.unwrap();
+        let rpc = rpc.clone();
+        let call = tokio::spawn(async move {
+            let id = entry.get("id").cloned().unwrap_or(Value::Null);
+            let mut reply = with_warning(rpc.answer(entry, access).await, warning);
+            reply["id"] = id;
+            drop(permit);
+            reply
+        });
+        if is_write {
+            replies.push(call.await.unwrap());
+        } else {
+            reads.push(call);
+        }
+    }
+    for read in reads {
+        replies.push(read.await.unwrap());
+    }
+    json!(replies)
+}
+
+// Batched getvdxfid: takes an array of names, or [name, options] pairs, and
+// resolves each as its own getvdxfid call, so every key is validated and cached
+// individually, with the caller's `access`. Replies are in key order, without ids.
+pub async fn getvdxfids(params: Vec<Value>, rpc: Arc<VerusRPC>, access: Access) -> Result<Value, RpcError> {
+    let keys = match params.as_slice() {
+        [Value::Array(keys)] => keys,
+        _ => return Err(RpcError { code: -32602, message: "Invalid params parameter".into(), data: None }),
+    };
+    if keys.len() > rpc.batch.max_size {
+        return Err(RpcError { code: -32600, message: format!("Batch too large, max {} entries", rpc.batch.max_size), data: None });
+    }
+
+    let limit = Arc::new(Semaphore::new(rpc.batch.concurrency.max(1)));
+    let calls: Vec<JoinHandle<Value>> = keys.iter().map(|key| {
+        let params = match key {
+            Value::Array(params) => params.clone(),
+            key => vec![key.clone()],
+        };
+        let limit = limit.clone();
+        let rpc = rpc.clone();
+        tokio::spawn(async move {
+            let _permit = limit.acquire_owned().await.unwrap();
+            reply(rpc.handle_call_as("getvdxfid".to_string(), params, access).await)
+        })
+    }).collect();
+    let mut replies = Vec::with_capacity(calls.len());
+    for call in calls {
+        replies.push(call.await.unwrap());
+    }
+    Ok(json!(replies))
+}
@@ -0,0 +1,52 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::annotations::MethodTable;
+
+// The largest request body a call may send, in bytes: `max_body_size` for
+// everything, replaced in [body_limits] per method, per group (send,
+// identity, ...) or for all reads or all writes, so raw transactions can be
+// several MB while read-only calls stay at a few KB.
+pub struct BodyLimits {
+    default: u64,
+    limits: HashMap<String, u64>,
+}
+
+impl BodyLimits {
+    pub fn new(default: u64, limits: HashMap<String, u64>) -> BodyLimits {
+        BodyLimits { default, limits }
+    }
+
+    // No body is read past this, whatever its calls turn out to be.
+    pub fn ceiling(&self) -> u64 {
+        self.limits.values().copied().fold(self.default, u64::max)
+    }
+
+    // The limit for one method: its own, else the largest of its groups', else
+    // the one for reads or writes, else max_body_size.
+    pub fn limit(&self, method: &str, methods: &MethodTable) -> u64 {
+        let annotations = methods.get(method);
+        self.limits.get(method).copied()
+            .or_else(|| annotations.groups.iter().filter_map(|group| self.limits.get(group).copied()).max())
+            .or_else(|| self.limits.get(if annotations.write { "write" } else { "read" }).copied())
+            .unwrap_or(self.default)
+    }
+
+    // Checks a parsed body of `size` bytes against the limits of the calls in
+    // it; each entry of a batch is held to its own method's limit. Returns the
+    // method over its limit and that limit.
+    pub fn check(&self, body: &Value, size: usize, methods: &MethodTable) -> Result<(), (String, u64)> {
+        let over = |entry: &Value, size: usize| {
+            let method = entry["method"].as_str().unwrap_or_default();
+            let limit = self.limit(method, methods);
+            match size as u64 > limit {
+                true => Err((method.to_string(), limit)),
+                false => Ok(()),
+            }
+        };
+        match body {
+            Value::Array(entries) => entries.iter().try_for_each(|entry| over(entry, entry.to_string().len())),
+            entry => over(entry, size),
+        }
+    }
+}
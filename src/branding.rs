@@ -0,0 +1,92 @@
+use hyper::{Body, Response};
+use hyper::header::{HeaderName, HeaderValue};
+use serde_json::{Value, json};
+
+// What a public deployment says about itself, so clients of a community
+// endpoint can tell what they are talking to: a name, the chain, the proxy's
+// version and the terms of service. Sent as headers on every response, as a
+// `deployment` field next to the result of JSON-RPC replies, or both.
+pub struct Branding {
+    name: Option<String>,
+    chain: Option<String>,
+    terms_url: Option<String>,
+    headers: bool,
+    body: bool,
+}
+
+impl Branding {
+    pub fn from_settings(settings: &config::Config) -> Result<Branding, String> {
+        let (headers, body) = match settings.get_str("deployment_metadata").unwrap_or_else(|_| "headers".to_string()).as_str() {
+            "headers" => (true, false),
+            "body" => (false, true),
+            "both" => (true, true),
+            other => return Err(format!("Unknown deployment_metadata {}, expected headers, body or both", other)),
+        };
+        Ok(Branding {
+            name: settings.get_str("deployment_name").ok(),
+            chain: settings.get_str("deployment_chain").or_else(|_| settings.get_str("expected_chain_name")).ok(),
+            terms_url: settings.get_str("terms_url").ok(),
+            headers,
+            body,
+        })
+    }
+
+    // Nothing is added until a deployment name or terms URL is configured.
+    fn is_set(&self) -> bool {
+        self.name.is_some() || self.terms_url.is_some()
+    }
+
+    fn metadata(&self) -> Value {
+        json!({
+            "name": self.name,
+            "chain": self.chain,
+            "version": env!("CARGO_PKG_VERSION"),
+            "terms": self.terms_url,
+        })
+    }
+
+    // The info object of the OpenAPI document.
+    pub fn openapi_info(&self) -> Value {
+        let mut info = json!({
+            "title": self.name.as_deref().unwrap_or("Verus RPC proxy"),
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        if let Some(chain) = &self.chain {
+            info["description"] = json!(format!("JSON-RPC and REST access to {}", chain));
+        }
+        if let Some(terms) = &self.terms_url {
+            info["termsOfService"] = json!(terms);
+        }
+        info
+    }
+
+    pub fn add_headers(&self, response: &mut Response<Body>) {
+        if !self.headers || !self.is_set() {
+            return;
+        }
+        let headers = [
+            ("x-deployment-name", self.name.clone()),
+            ("x-deployment-chain", self.chain.clone()),
+            ("x-proxy-version", Some(env!("CARGO_PKG_VERSION").to_string())),
+            ("link", self.terms_url.as_ref().map(|url| format!("<{}>; rel=\"terms-of-service\"", url))),
+        ];
+        for (name, value) in headers {
+            if let Some(value) = value.and_then(|value| HeaderValue::from_str(&value).ok()) {
+                response.headers_mut().append(HeaderName::from_static(name), value);
+            }
+        }
+    }
+
+    // Whether JSON-RPC replies get a `deployment` field.
+    pub fn in_body(&self) -> bool {
+        self.body && self.is_set()
+    }
+
+    // Adds the `deployment` field to a JSON-RPC reply.
+    pub fn with_metadata(&self, mut reply: Value) -> Value {
+        if self.in_body() && reply.is_object() {
+            reply["deployment"] = self.metadata();
+        }
+        reply
+    }
+}
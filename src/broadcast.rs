@@ -0,0 +1,143 @@
+use base64::Engine;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnector;
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::hash;
+
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Somewhere else a raw transaction is sent.
+enum Target {
+    // Another daemon, called with sendrawtransaction.
+    Daemon { url: String, auth: Option<String> },
+    // An explorer's submit API, POSTed {"<field>": hex}, such as Insight's
+    // /api/tx/send with "rawtx".
+    Explorer { url: String, field: String },
+}
+
+impl Target {
+    fn url(&self) -> &str {
+        match self {
+            Target::Daemon { url, .. } | Target::Explorer { url, .. } => url,
+        }
+    }
+
+    fn parse(entry: &HashMap<String, Value>) -> Result<Target, String> {
+        let field = |name: &str| entry.get(name).and_then(Value::as_str).map(str::to_string);
+        let url = field("url").ok_or("Broadcast target without a url")?;
+        match field("type").as_deref().unwrap_or("daemon") {
+            "daemon" => {
+                let auth = field("user").map(|user| {
+                    let credentials = format!("{}:{}", user, field("password").unwrap_or_default());
+                    format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+                });
+                Ok(Target::Daemon { url, auth })
+            },
+            "explorer" => Ok(Target::Explorer { url, field: field("field").unwrap_or_else(|| "rawtx".to_string()) }),
+            other => Err(format!("Unknown broadcast target type {}", other)),
+        }
+    }
+}
+
+// Extra daemons and explorers that every sendrawtransaction is also sent to,
+// so a transaction still propagates when the proxy's own daemon is poorly
+// connected. Sends run in parallel with the daemon's own and finish in the
+// background; the first to accept answers the client.
+pub struct Broadcaster {
+    targets: Vec<Target>,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl Broadcaster {
+    pub fn load(entries: Vec<HashMap<String, Value>>) -> Result<Broadcaster, String> {
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Broadcaster {
+            targets: entries.iter().map(Target::parse).collect::<Result<_, _>>()?,
+            client: Client::builder().build(https),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    // Sends `hex` to one target, returning the txid it accepted.
+    async fn submit(&self, target: &Target, hex: &str) -> Result<Value, String> {
+        let (body, auth) = match target {
+            Target::Daemon { auth, .. } => (json!({"jsonrpc": "1.0", "id": "broadcast", "method": "sendrawtransaction", "params": [hex]}), auth.as_deref()),
+            Target::Explorer { field, .. } => (json!({ field.as_str(): hex }), None),
+        };
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(target.url())
+            .header(hyper::header::CONTENT_TYPE, "application/json");
+        if let Some(auth) = auth {
+            request = request.header(hyper::header::AUTHORIZATION, auth);
+        }
+        let request = request.body(Body::from(body.to_string())).map_err(|e| e.to_string())?;
+        let response = tokio::time::timeout(SUBMIT_TIMEOUT, async {
+            let response = self.client.request(request).await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+            Ok::<_, String>((status, body))
+        }).await.map_err(|_| "timed out".to_string())??;
+        let (status, body) = response;
+        let reply: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        match target {
+            Target::Daemon { .. } if reply["error"].is_null() && reply["result"].is_string() => Ok(reply["result"].clone()),
+            Target::Daemon { .. } => Err(reply["error"]["message"].as_str().map_or_else(|| format!("answered {}", status), str::to_string)),
+            // Explorers that don't echo the txid get it worked out from the hex.
+            Target::Explorer { .. } if status.is_success() => Ok(match &reply["txid"] {
+                Value::String(txid) => json!(txid),
+                _ => json!(hex::decode(hex).map(|raw| hash::txid(&raw)).unwrap_or_default()),
+            }),
+            Target::Explorer { .. } => Err(format!("answered {}: {}", status, String::from_utf8_lossy(&body))),
+        }
+    }
+
+    // Sends `hex` to every target alongside `local` (the daemon's own
+    // sendrawtransaction) and answers with the first txid any of them
+    // returns. If none accepts, the daemon's error is returned.
+    pub async fn broadcast<F>(self: &Arc<Self>, hex: String, local: F) -> Result<Value, RpcError>
+    where
+        F: std::future::Future<Output = Result<Value, RpcError>> + Send + 'static,
+    {
+        let (sender, mut outcomes) = tokio::sync::mpsc::channel(self.targets.len() + 1);
+        let local_sender = sender.clone();
+        tokio::spawn(async move {
+            let _ = local_sender.send(local.await.map_err(Some)).await;
+        });
+        let hex = Arc::new(hex);
+        for index in 0..self.targets.len() {
+            let (broadcaster, hex, sender) = (self.clone(), hex.clone(), sender.clone());
+            tokio::spawn(async move {
+                let target = &broadcaster.targets[index];
+                let outcome = broadcaster.submit(target, &hex).await;
+                if let Err(e) = &outcome {
+                    eprintln!("broadcast to {} failed: {}", target.url(), e);
+                }
+                let _ = sender.send(outcome.map_err(|_| None)).await;
+            });
+        }
+        drop(sender);
+        let mut local_error = None;
+        while let Some(outcome) = outcomes.recv().await {
+            match outcome {
+                Ok(txid) => return Ok(txid),
+                Err(Some(e)) => local_error = Some(e),
+                Err(None) => {},
+            }
+        }
+        Err(local_error.unwrap_or(RpcError { code: -26, message: "No broadcast target accepted the transaction".into(), data: None }))
+    }
+}
@@ -0,0 +1,200 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    method: String,
+    value: Result<Value, RpcError>,
+    expires: Instant,
+    // Past `expires` but before this, the entry is served stale while it is refreshed.
+    stale_until: Instant,
+}
+
+pub struct Cached {
+    pub value: Result<Value, RpcError>,
+    pub stale: bool,
+}
+
+#[derive(Default)]
+struct MethodStats {
+    hits: u64,
+    stale_hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+fn write_counter(out: &mut String, name: &str, methods: &[(&String, &MethodStats)], counter: fn(&MethodStats) -> u64) {
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+    for (method, stats) in methods {
+        writeln!(out, "{}{{method=\"{}\"}} {}", name, method, counter(stats)).unwrap();
+    }
+}
+
+// Short-lived caching of "not found" style daemon errors, for any method, so
+// probes for nonexistent txids or identities don't all reach the daemon.
+pub struct NegativeCaching {
+    pub ttl: Duration,
+    pub codes: Vec<i32>,
+}
+
+// In-memory cache of successful daemon results for the methods that have a TTL
+// configured, plus negative results. Methods with a max staleness keep being
+// served for that long after expiry while a single background refresh runs.
+// When full, dead entries are purged first and then the entry closest to
+// expiry is evicted.
+pub struct ResponseCache {
+    ttls: HashMap<String, Duration>,
+    max_stale: HashMap<String, Duration>,
+    negative: NegativeCaching,
+    refreshing: Mutex<HashSet<String>>,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+    stats: Mutex<HashMap<String, MethodStats>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttls: HashMap<String, Duration>, max_stale: HashMap<String, Duration>, negative: NegativeCaching, max_entries: usize) -> ResponseCache {
+        ResponseCache {
+            ttls,
+            max_stale,
+            negative,
+            refreshing: Mutex::new(HashSet::new()),
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            stats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_cacheable(&self, method: &str) -> bool {
+        self.ttls.contains_key(method) || (!self.negative.ttl.is_zero() && !self.negative.codes.is_empty())
+    }
+
+    fn record(&self, method: &str, f: impl FnOnce(&mut MethodStats)) {
+        let mut stats = self.stats.lock().unwrap();
+        match stats.get_mut(method) {
+            Some(entry) => f(entry),
+            None => f(stats.entry(method.to_string()).or_default()),
+        }
+    }
+
+    pub fn get(&self, method: &str, key: &str) -> Option<Cached> {
+        let now = Instant::now();
+        let found = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(key) {
+                Some(entry) if entry.expires > now => Some(Cached { value: entry.value.clone(), stale: false }),
+                Some(entry) if entry.stale_until > now => Some(Cached { value: entry.value.clone(), stale: true }),
+                Some(_) => {
+                    entries.remove(key);
+                    None
+                },
+                None => None,
+            }
+        };
+        match &found {
+            Some(Cached { stale: false, .. }) => self.record(method, |s| s.hits += 1),
+            Some(Cached { stale: true, .. }) => self.record(method, |s| s.stale_hits += 1),
+            None => self.record(method, |s| s.misses += 1),
+        }
+        found
+    }
+
+    // Claims the refresh of a stale key. Returns false if one is already running.
+    pub fn begin_refresh(&self, key: &str) -> bool {
+        self.refreshing.lock().unwrap().insert(key.to_string())
+    }
+
+    pub fn end_refresh(&self, key: &str) {
+        self.refreshing.lock().unwrap().remove(key);
+    }
+
+    pub fn insert(&self, method: &str, key: String, value: Value) {
+        if let Some(ttl) = self.ttls.get(method) {
+            let max_stale = self.max_stale.get(method).copied().unwrap_or_default();
+            self.store(method, key, Ok(value), *ttl, max_stale);
+        }
+    }
+
+    pub fn insert_negative(&self, method: &str, key: String, err: &RpcError) {
+        if self.negative.codes.contains(&err.code) {
+            self.store(method, key, Err(err.clone()), self.negative.ttl, Duration::default());
+        }
+    }
+
+    fn store(&self, method: &str, key: String, value: Result<Value, RpcError>, ttl: Duration, max_stale: Duration) {
+        if self.max_entries == 0 || ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut evicted = None;
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+                entries.retain(|_, entry| entry.stale_until > now);
+                if entries.len() >= self.max_entries {
+                    let oldest = entries.iter().min_by_key(|(_, entry)| entry.expires).map(|(key, _)| key.clone());
+                    evicted = oldest.and_then(|oldest| entries.remove(&oldest));
+                }
+            }
+            let expires = now + ttl;
+            entries.insert(key, Entry { method: method.to_string(), value, expires, stale_until: expires + max_stale });
+        }
+        if let Some(evicted) = evicted {
+            self.record(&evicted.method, |s| s.evictions += 1);
+        }
+    }
+
+    // Drops every entry, or only those of one method. Returns how many were removed.
+    pub fn flush(&self, method: Option<&str>) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        match method {
+            Some(method) => entries.retain(|_, entry| entry.method != method),
+            None => entries.clear(),
+        }
+        before - entries.len()
+    }
+
+    // Drops what a reorg back to below `height` may have changed: block
+    // lookups, which name blocks by height or hash, and entries cached for a
+    // tip at or above it. Returns how many were removed.
+    pub fn flush_from(&self, height: u64) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|key, entry| {
+            let tip = key.rsplit_once('@').and_then(|(_, tip)| tip.parse::<u64>().ok());
+            !crate::disk_cache::is_candidate(&entry.method) && tip.is_none_or(|tip| tip < height)
+        });
+        before - entries.len()
+    }
+
+    // Entry count, and hits and misses per method with the share served from the cache.
+    pub fn summary(&self) -> Value {
+        let stats = self.stats.lock().unwrap();
+        let methods: serde_json::Map<String, Value> = stats
+            .iter()
+            .map(|(method, s)| {
+                let served = s.hits + s.stale_hits;
+                let hit_rate = served as f64 / (served + s.misses).max(1) as f64;
+                (method.clone(), json!({ "hits": s.hits, "stale_hits": s.stale_hits, "misses": s.misses, "evictions": s.evictions, "hit_rate": hit_rate }))
+            })
+            .collect();
+        json!({ "entries": self.entries.lock().unwrap().len(), "methods": methods })
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        writeln!(out, "# TYPE cache_entries gauge").unwrap();
+        writeln!(out, "cache_entries {}", self.entries.lock().unwrap().len()).unwrap();
+
+        let stats = self.stats.lock().unwrap();
+        let mut methods: Vec<_> = stats.iter().collect();
+        methods.sort_by(|a, b| a.0.cmp(b.0));
+        write_counter(out, "cache_hits_total", &methods, |s| s.hits);
+        write_counter(out, "cache_stale_hits_total", &methods, |s| s.stale_hits);
+        write_counter(out, "cache_misses_total", &methods, |s| s.misses);
+        write_counter(out, "cache_evictions_total", &methods, |s| s.evictions);
+    }
+}
@@ -0,0 +1,165 @@
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnector;
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+// Asks anonymous clients (those without a session or any key the proxy
+// knows) calling one of `methods` to solve an hCaptcha or Turnstile challenge
+// first. The widget in the dApp hands the client a token, sent as an
+// X-Captcha-Token header or a "captcha" field of the request, which is
+// checked with the provider's siteverify endpoint before any call is made.
+// Without one, each call to a guarded method is refused as it is dispatched,
+// after aliases are resolved, so batch entries and the calls composites make
+// are held to it too (see `Access::needs_captcha`).
+// "faucet" among the methods guards POST /faucet the same way.
+pub struct Captcha {
+    provider: String,
+    secret: String,
+    verify_url: String,
+    site_key: Option<String>,
+    methods: HashSet<String>,
+    client: HttpsClient,
+    passed: AtomicU64,
+    failed: AtomicU64,
+}
+
+// Form-encodes a value for the siteverify request.
+fn form_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+pub fn header(headers: &hyper::HeaderMap) -> Option<&str> {
+    headers.get("x-captcha-token").and_then(|value| value.to_str().ok())
+}
+
+// The token a request carries: its X-Captcha-Token header, or else the
+// "captcha" field of the request (or of the first batch entry with one).
+pub fn token<'a>(header: Option<&'a str>, body: &'a Value) -> Option<&'a str> {
+    if header.is_some() {
+        return header;
+    }
+    match body {
+        Value::Array(entries) => entries.iter().find_map(|entry| entry["captcha"].as_str()),
+        body => body["captcha"].as_str(),
+    }
+}
+
+impl Captcha {
+    // None unless captcha_provider is set.
+    pub fn from_settings(settings: &config::Config) -> Result<Option<Captcha>, String> {
+        let provider = match settings.get_str("captcha_provider") {
+            Ok(provider) => provider,
+            Err(_) => return Ok(None),
+        };
+        let default_url = match provider.as_str() {
+            "hcaptcha" => "https://api.hcaptcha.com/siteverify",
+            "turnstile" => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+            _ => return Err(format!("Unknown captcha_provider {}: use hcaptcha or turnstile", provider)),
+        };
+        let secret = settings.get_str("captcha_secret").map_err(|_| "captcha_provider needs a captcha_secret")?;
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Some(Captcha {
+            provider,
+            secret,
+            verify_url: settings.get_str("captcha_verify_url").unwrap_or_else(|_| default_url.to_string()),
+            site_key: settings.get_str("captcha_site_key").ok(),
+            methods: settings.get::<Vec<String>>("captcha_methods").unwrap_or_default().into_iter().collect(),
+            client: Client::builder().build(https),
+            passed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn guards(&self, method: &str) -> bool {
+        self.methods.contains(method)
+    }
+
+    // What a client needs to show the widget, sent along with a refusal.
+    pub fn describe(&self) -> Value {
+        json!({ "provider": self.provider, "site_key": self.site_key })
+    }
+
+    pub fn summary(&self) -> Value {
+        json!({
+            "provider": self.provider,
+            "methods": self.methods,
+            "passed": self.passed.load(Ordering::Relaxed),
+            "failed": self.failed.load(Ordering::Relaxed),
+        })
+    }
+
+    async fn siteverify(&self, token: &str, client: &str) -> Result<bool, String> {
+        let mut form = format!("secret={}&response={}", form_value(&self.secret), form_value(token));
+        if !client.is_empty() {
+            form.push_str(&format!("&remoteip={}", form_value(client)));
+        }
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.verify_url)
+            .header(hyper::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form))
+            .map_err(|e| e.to_string())?;
+        let response = match tokio::time::timeout(VERIFY_TIMEOUT, self.client.request(request)).await {
+            Ok(Ok(response)) if response.status().is_success() => response,
+            Ok(Ok(response)) => return Err(format!("answered {}", response.status())),
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => return Err("timed out".to_string()),
+        };
+        let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+        let reply: Value = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+        Ok(reply["success"] == json!(true))
+    }
+
+    fn refusal(&self, message: &str) -> RpcError {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        RpcError {
+            code: -32008,
+            message: message.to_string(),
+            data: Some(serde_json::value::to_raw_value(&self.describe()).unwrap()),
+        }
+    }
+
+    // The refusal of a guarded call from a client that sent no token.
+    pub fn required(&self) -> RpcError {
+        self.refusal("A captcha is required")
+    }
+
+    // Checks a client's token with the provider. A provider that can't be
+    // reached fails the check, so guarded methods close rather than open.
+    pub async fn verify(&self, token: Option<&str>, client: &str) -> Result<(), RpcError> {
+        let token = match token {
+            Some(token) if !token.is_empty() => token,
+            _ => return Err(self.required()),
+        };
+        match self.siteverify(token, client).await {
+            Ok(true) => {
+                self.passed.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            },
+            Ok(false) => Err(self.refusal("The captcha was not solved")),
+            Err(e) => {
+                eprintln!("captcha verification with {} failed: {}", self.provider, e);
+                Err(self.refusal("The captcha could not be checked"))
+            },
+        }
+    }
+}
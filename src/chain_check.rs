@@ -0,0 +1,47 @@
+use serde_json::Value;
+
+// What the daemon must report about its chain before anything is served from
+// it, so a mainnet frontend can't end up talking to a testnet node. Unset
+// fields aren't checked.
+#[derive(Default)]
+pub struct ChainExpectation {
+    // getinfo's name, e.g. VRSC or VRSCTEST.
+    pub name: Option<String>,
+    // getinfo's chainid, the chain's i-address.
+    pub chain_id: Option<String>,
+    pub p2p_port: Option<u64>,
+    // Hash of block 0.
+    pub genesis_hash: Option<String>,
+}
+
+impl ChainExpectation {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.chain_id.is_none() && self.p2p_port.is_none() && self.genesis_hash.is_none()
+    }
+
+    pub fn needs_genesis(&self) -> bool {
+        self.genesis_hash.is_some()
+    }
+
+    // Compares getinfo's answer (and the genesis block hash, if it was asked
+    // for) with the expectation, describing the first difference.
+    pub fn check(&self, info: &Value, genesis_hash: Option<&Value>) -> Result<(), String> {
+        let expect = |what: &str, expected: Value, actual: &Value| match &expected == actual {
+            true => Ok(()),
+            false => Err(format!("expected {} {}, the daemon reports {}", what, expected, actual)),
+        };
+        if let Some(name) = &self.name {
+            expect("chain", Value::from(name.as_str()), &info["name"])?;
+        }
+        if let Some(chain_id) = &self.chain_id {
+            expect("chain id", Value::from(chain_id.as_str()), &info["chainid"])?;
+        }
+        if let Some(port) = self.p2p_port {
+            expect("p2p port", Value::from(port), &info["p2pport"])?;
+        }
+        if let (Some(expected), Some(actual)) = (&self.genesis_hash, genesis_hash) {
+            expect("genesis hash", Value::from(expected.as_str()), actual)?;
+        }
+        Ok(())
+    }
+}
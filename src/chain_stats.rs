@@ -0,0 +1,273 @@
+use hyper::{Body, Response, StatusCode};
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex};
+
+use crate::VerusRPC;
+use crate::limiter::Priority;
+use crate::rest::json_response;
+
+// The chain's target block interval.
+const BLOCK_SECS: u64 = 60;
+// Reward changes looked ahead for the emission schedule.
+const SCHEDULE_ERAS: usize = 3;
+// How far ahead a reward change is searched for.
+const SCHEDULE_HORIZON: u64 = 20_000_000;
+// Holders summed for the concentration figure.
+const TOP_HOLDERS: u64 = 100;
+// Forks this many blocks below the tip or less are reported.
+const RECENT_FORK_DEPTH: u64 = 1440;
+// Most forks listed.
+const MAX_FORKS: usize = 10;
+// How far back network trends look.
+const TREND_WINDOW_SECS: i64 = 86_400;
+// Samples read for a trend.
+const TREND_SAMPLES: u64 = 1000;
+
+// The proxy-local method a schedule entry names to sample the network summary
+// into the history, which /stats/network then reads its trends from.
+pub const NETWORK_SUMMARY: &str = "networksummary";
+
+// Chain-wide figures for status pages, worked out at most once per block.
+pub struct ChainStats {
+    supply: Mutex<Option<(u64, Value)>>,
+    network: Mutex<Option<(u64, Value)>>,
+    // The upcoming reward changes as (height, reward).
+    schedule: Mutex<Option<Vec<(u64, f64)>>>,
+}
+
+impl ChainStats {
+    pub fn new() -> ChainStats {
+        ChainStats { supply: Mutex::new(None), network: Mutex::new(None), schedule: Mutex::new(None) }
+    }
+}
+
+fn cached(cache: &Mutex<Option<(u64, Value)>>, height: u64) -> Option<Value> {
+    cache.lock().unwrap().as_ref().filter(|(at, _)| *at == height).map(|(_, value)| value.clone())
+}
+
+async fn daemon(rpc: &VerusRPC, method: &str, params: &[Value]) -> Result<Value, String> {
+    rpc.upstream.call_as(method, params, Priority::Background).await.map_err(|e| format!("{}: {}", method, e.message))
+}
+
+async fn subsidy(rpc: &VerusRPC, height: u64) -> Result<f64, String> {
+    let result = daemon(rpc, "getblocksubsidy", &[json!(height)]).await?;
+    result["miner"].as_f64().or_else(|| result.as_f64()).ok_or_else(|| "getblocksubsidy: no reward in the reply".to_string())
+}
+
+// The heights at which the block reward next changes, found by bisecting
+// getblocksubsidy (the reward only ever steps down), with the reward from
+// each. Kept until the tip reaches the first change.
+async fn schedule(rpc: &VerusRPC, tip: u64) -> Result<Vec<(u64, f64)>, String> {
+    if let Some(changes) = rpc.chain_stats.schedule.lock().unwrap().as_ref().filter(|changes| changes.first().is_none_or(|(height, _)| *height > tip)) {
+        return Ok(changes.clone());
+    }
+    let mut changes = Vec::new();
+    let (mut from, mut reward) = (tip, subsidy(rpc, tip).await?);
+    while changes.len() < SCHEDULE_ERAS && reward > 0.0 {
+        let mut high = from + SCHEDULE_HORIZON;
+        if subsidy(rpc, high).await? == reward {
+            break;
+        }
+        // subsidy(from) == reward and subsidy(high) != reward.
+        while high - from > 1 {
+            let middle = from + (high - from) / 2;
+            match subsidy(rpc, middle).await? == reward {
+                true => from = middle,
+                false => high = middle,
+            }
+        }
+        reward = subsidy(rpc, high).await?;
+        changes.push((high, reward));
+        from = high;
+    }
+    *rpc.chain_stats.schedule.lock().unwrap() = Some(changes.clone());
+    Ok(changes)
+}
+
+async fn supply(rpc: &Arc<VerusRPC>, tip: u64) -> Result<Value, String> {
+    let coins = daemon(rpc, "coinsupply", &[]).await?;
+    let mining = daemon(rpc, "getmininginfo", &[]).await?;
+    let reward = subsidy(rpc, tip).await?;
+    let changes = schedule(rpc, tip).await?;
+
+    let total = coins["total"].as_f64().unwrap_or(0.0);
+    let staking = mining["stakingsupply"].as_f64();
+    let blocks_per_day = 86_400 / BLOCK_SECS;
+    let mut projected = total;
+    let (mut from, mut era_reward) = (tip, reward);
+    let schedule: Vec<Value> = changes.iter().map(|(height, new_reward)| {
+        projected += (height - from) as f64 * era_reward;
+        from = *height;
+        era_reward = *new_reward;
+        json!({
+            "height": height,
+            "block_reward": new_reward,
+            "eta_days": (height - tip) as f64 / blocks_per_day as f64,
+            "projected_supply": projected,
+        })
+    }).collect();
+    let mut stats = json!({
+        "height": tip,
+        "supply": {
+            "transparent": coins["supply"],
+            "shielded": coins["zfunds"],
+            "total": coins["total"],
+        },
+        "staking": {
+            "supply": staking,
+            "ratio": staking.filter(|_| total > 0.0).map(|staking| staking / total),
+        },
+        "emission": {
+            "block_reward": reward,
+            "daily": reward * blocks_per_day as f64,
+            "annual_inflation": if total > 0.0 { Some(reward * (365 * blocks_per_day) as f64 / total) } else { None },
+            "schedule": schedule,
+        },
+    });
+    if let Some(indexer) = &rpc.indexer {
+        let top = indexer.store.rich_list("native", 0, TOP_HOLDERS).await?;
+        let held: f64 = top.iter().filter_map(|holder| holder["balance"].as_f64()).sum();
+        let complete = indexer.store.start_height().await?.is_some_and(|start| start <= 1);
+        stats["indexed"] = json!({
+            "height": indexer.store.height().await?,
+            "complete": complete,
+            "top_holders": TOP_HOLDERS,
+            "top_holders_share": if complete && total > 0.0 { Some(held / total) } else { None },
+        });
+    }
+    Ok(stats)
+}
+
+// GET /stats/supply: the coin supply (transparent, shielded and total, from
+// coinsupply), the share of it staking (getmininginfo's stakingsupply), the
+// current block reward with daily emission and annual inflation, and the
+// coming reward changes with the supply projected at each. With a complete
+// index it also gives the share held by the largest addresses.
+pub async fn handle_supply(rpc: &Arc<VerusRPC>) -> Response<Body> {
+    let tip = match rpc.tip.height() {
+        Some(tip) => tip,
+        None => return json_response(StatusCode::SERVICE_UNAVAILABLE, json!({"error": "The chain tip is not known yet"})),
+    };
+    if let Some(stats) = cached(&rpc.chain_stats.supply, tip) {
+        return json_response(StatusCode::OK, stats);
+    }
+    match supply(rpc, tip).await {
+        Ok(stats) => {
+            *rpc.chain_stats.supply.lock().unwrap() = Some((tip, stats.clone()));
+            json_response(StatusCode::OK, stats)
+        },
+        Err(message) => json_response(StatusCode::BAD_GATEWAY, json!({"error": message})),
+    }
+}
+
+// The state of the network from getmininginfo, getnetworkinfo and
+// getchaintips: difficulty and hash rate, peers and node version, and the
+// forks seen near the tip.
+pub async fn network_summary(rpc: &VerusRPC) -> Result<Value, String> {
+    let mining = daemon(rpc, "getmininginfo", &[]).await?;
+    let network = daemon(rpc, "getnetworkinfo", &[]).await?;
+    let tips = daemon(rpc, "getchaintips", &[]).await?;
+
+    let tips = tips.as_array().cloned().unwrap_or_default();
+    let active = tips.iter().find(|tip| tip["status"] == "active");
+    let height = active.and_then(|tip| tip["height"].as_u64()).or_else(|| mining["blocks"].as_u64());
+    let mut forks: Vec<&Value> = tips.iter()
+        .filter(|tip| tip["status"] != "active")
+        .filter(|tip| match (height, tip["height"].as_u64()) {
+            (Some(height), Some(fork)) => fork + RECENT_FORK_DEPTH >= height,
+            _ => false,
+        })
+        .collect();
+    forks.sort_by_key(|fork| std::cmp::Reverse(fork["height"].as_u64()));
+    let longest = forks.iter().filter_map(|fork| fork["branchlen"].as_u64()).max();
+    let forks: Vec<Value> = forks.iter().take(MAX_FORKS).map(|fork| json!({
+        "height": fork["height"],
+        "hash": fork["hash"],
+        "branchlen": fork["branchlen"],
+        "status": fork["status"],
+    })).collect();
+    Ok(json!({
+        "height": height,
+        "tip": active.map(|tip| &tip["hash"]),
+        "mining": {
+            "difficulty": mining["difficulty"],
+            "networkhashps": mining["networkhashps"],
+            "stakingsupply": mining["stakingsupply"],
+            "pooledtx": mining["pooledtx"],
+        },
+        "peers": {
+            "connections": network["connections"],
+            "version": network["version"],
+            "subversion": network["subversion"],
+            "protocolversion": network["protocolversion"],
+            "relayfee": network["relayfee"],
+            "warnings": network["warnings"],
+        },
+        "forks": {
+            "recent": forks.len(),
+            "longest_branch": longest,
+            "tips": forks,
+        },
+    }))
+}
+
+// How a figure of the sampled summaries moved over the trend window.
+fn trend(samples: &[Value], pointer: &str) -> Value {
+    let values: Vec<f64> = samples.iter().filter_map(|sample| sample["value"].pointer(pointer).and_then(Value::as_f64)).collect();
+    let (first, last) = match (values.first(), values.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Value::Null,
+    };
+    json!({
+        "first": first,
+        "last": last,
+        "min": values.iter().copied().fold(f64::INFINITY, f64::min),
+        "max": values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        "change": if first != 0.0 { Some((last - first) / first) } else { None },
+        "samples": values.len(),
+    })
+}
+
+// Peer, difficulty and hash rate trends over the last day, from the samples
+// of the schedule entry running networksummary, if there is one.
+fn trends(rpc: &VerusRPC) -> Result<Value, String> {
+    let (history, job) = match rpc.history.as_ref().and_then(|history| Some((history, history.job_for(NETWORK_SUMMARY)?))) {
+        Some(found) => found,
+        None => return Ok(Value::Null),
+    };
+    let since = crate::indexer::unix_time() - TREND_WINDOW_SECS;
+    let samples = history.samples(job, Some(since), None, TREND_SAMPLES).map_err(|e| e.to_string())?;
+    Ok(json!({
+        "job": job,
+        "since": since,
+        "connections": trend(&samples, "/peers/connections"),
+        "difficulty": trend(&samples, "/mining/difficulty"),
+        "networkhashps": trend(&samples, "/mining/networkhashps"),
+    }))
+}
+
+// GET /stats/network: one response for status pages with the network summary
+// (worked out once per block) and, when a schedule entry samples
+// networksummary into the history, how peers, difficulty and hash rate moved
+// over the last day.
+pub async fn handle_network(rpc: &Arc<VerusRPC>) -> Response<Body> {
+    let tip = match rpc.tip.height() {
+        Some(tip) => tip,
+        None => return json_response(StatusCode::SERVICE_UNAVAILABLE, json!({"error": "The chain tip is not known yet"})),
+    };
+    let mut summary = match cached(&rpc.chain_stats.network, tip) {
+        Some(summary) => summary,
+        None => match network_summary(rpc).await {
+            Ok(summary) => {
+                *rpc.chain_stats.network.lock().unwrap() = Some((tip, summary.clone()));
+                summary
+            },
+            Err(message) => return json_response(StatusCode::BAD_GATEWAY, json!({"error": message})),
+        },
+    };
+    match trends(rpc) {
+        Ok(trends) => summary["trends"] = trends,
+        Err(message) => return json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": message})),
+    }
+    json_response(StatusCode::OK, summary)
+}
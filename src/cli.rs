@@ -0,0 +1,175 @@
+use crate::audit;
+use crate::disk_cache::DiskCache;
+use crate::indexer::{self, Indexer};
+use crate::snapshot;
+use crate::upstream::Upstream;
+
+const USAGE: &str = "usage:
+  rust_verusd_rpc_server                      run the proxy
+  rust_verusd_rpc_server index backfill [--from <height>] [--to <height>] [--rate <blocks/s>]
+  rust_verusd_rpc_server index resync [--from <height>] [--rate <blocks/s>]
+  rust_verusd_rpc_server snapshot export <file>
+  rust_verusd_rpc_server snapshot import <file>
+  rust_verusd_rpc_server audit verify [<file>]";
+
+struct Options {
+    from: Option<u64>,
+    to: Option<u64>,
+    rate: f64,
+}
+
+fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { from: None, to: None, rate: 20.0 };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+        let invalid = || format!("Invalid value for {}: {}", flag, value);
+        match flag.as_str() {
+            "--from" => options.from = Some(value.parse().map_err(|_| invalid())?),
+            "--to" => options.to = Some(value.parse().map_err(|_| invalid())?),
+            "--rate" => options.rate = value.parse().map_err(|_| invalid())?,
+            _ => return Err(format!("Unknown option {}", flag)),
+        }
+    }
+    Ok(options)
+}
+
+// Runs a maintenance command given on the command line and returns the exit
+// code.
+pub async fn run(args: &[String], settings: &config::Config, upstream: Upstream) -> i32 {
+    match args {
+        [index, command, rest @ ..] if index == "index" => match parse(rest) {
+            Ok(options) => run_index(command, options, settings, upstream).await,
+            Err(message) => {
+                eprintln!("{}\n{}", message, USAGE);
+                2
+            },
+        },
+        [snapshot, command, path] if snapshot == "snapshot" => run_snapshot(command, path, settings, upstream).await,
+        [audit, command, path @ ..] if audit == "audit" && command == "verify" && path.len() <= 1 => verify_audit(path.first().cloned(), settings),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        },
+    }
+}
+
+async fn open_index(settings: &config::Config) -> Result<Option<Indexer>, i32> {
+    Indexer::open(settings).await.map_err(|message| {
+        eprintln!("{}", message);
+        1
+    })
+}
+
+// `index backfill` fills the index from --from (default: genesis) up to the
+// index depth below the tip; `index resync` drops the index and rebuilds it
+// from --from (default: where it started). Both pace themselves with --rate
+// blocks per second (0 for no limit) and can run while the proxy serves
+// traffic from the same index.
+async fn run_index(command: &str, options: Options, settings: &config::Config, upstream: Upstream) -> i32 {
+    let indexer = match open_index(settings).await {
+        Ok(Some(indexer)) => indexer,
+        Ok(None) => {
+            eprintln!("No index is configured in Conf.toml");
+            return 1;
+        },
+        Err(code) => return code,
+    };
+
+    let from = match command {
+        "backfill" => options.from.unwrap_or(0),
+        "resync" => {
+            let start = indexer.store.start_height().await.unwrap_or(None);
+            let from = options.from.or(start).unwrap_or(0);
+            if let Err(e) = indexer.store.reset(from).await {
+                eprintln!("Failed to reset the index: {}", e);
+                return 1;
+            }
+            println!("index cleared, rebuilding from block {}", from);
+            from
+        },
+        _ => {
+            eprintln!("Unknown index command {}\n{}", command, USAGE);
+            return 2;
+        },
+    };
+    match indexer::backfill(&upstream, &indexer, from, options.to, options.rate).await {
+        Ok(blocks) => {
+            println!("done, {} blocks indexed", blocks);
+            0
+        },
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        },
+    }
+}
+
+// `snapshot export` writes the index and disk cache to a file that
+// `snapshot import` loads on another instance, replacing its index, so a new
+// proxy doesn't have to backfill from its daemon.
+async fn run_snapshot(command: &str, path: &str, settings: &config::Config, upstream: Upstream) -> i32 {
+    let indexer = match open_index(settings).await {
+        Ok(indexer) => indexer,
+        Err(code) => return code,
+    };
+    let disk_cache = match DiskCache::from_settings(settings) {
+        Ok(disk_cache) => disk_cache,
+        Err(e) => {
+            eprintln!("Failed to open disk cache: {}", e);
+            return 1;
+        },
+    };
+    if indexer.is_none() && disk_cache.is_none() {
+        eprintln!("Neither an index nor a disk cache is configured in Conf.toml");
+        return 1;
+    }
+    let result = match command {
+        "export" => snapshot::export(path, &upstream, indexer.as_ref(), disk_cache.as_ref()).await,
+        "import" => snapshot::import(path, &upstream, indexer.as_ref(), disk_cache.as_ref()).await,
+        _ => {
+            eprintln!("Unknown snapshot command {}\n{}", command, USAGE);
+            return 2;
+        },
+    };
+    match result {
+        Ok(counts) => {
+            for (table, rows) in counts {
+                println!("{}: {} rows", table, rows);
+            }
+            if let (true, Some(indexer)) = (command == "import", &indexer) {
+                if let Ok(Some(height)) = indexer.store.height().await {
+                    println!("index now at block {}", height);
+                }
+            }
+            0
+        },
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        },
+    }
+}
+
+// `audit verify` checks every link of the audit log (audit_log, or the file
+// given) and prints the entry count and last hash, which operators can publish
+// to pin the log up to that point.
+fn verify_audit(path: Option<String>, settings: &config::Config) -> i32 {
+    let path = match path.or_else(|| settings.get_str("audit_log").ok()) {
+        Some(path) => path,
+        None => {
+            eprintln!("No audit log is configured in Conf.toml");
+            return 1;
+        },
+    };
+    match audit::verify(&path) {
+        Ok((entries, hash)) => {
+            println!("ok, {} entries, last hash {}", entries, hash);
+            0
+        },
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        },
+    }
+}
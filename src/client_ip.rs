@@ -0,0 +1,129 @@
+use hyper::HeaderMap;
+use std::net::IpAddr;
+
+// The address a request came from, after looking through trusted proxies.
+// Stored in the request extensions for anything keyed by client.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+// An address block such as 10.0.0.0/8 or 2400:cb00::/32.
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    pub fn parse(cidr: &str) -> Option<Cidr> {
+        let (network, prefix) = match cidr.split_once('/') {
+            Some((network, prefix)) => (network.parse::<IpAddr>().ok()?, prefix.parse::<u32>().ok()?),
+            None => {
+                let network = cidr.parse::<IpAddr>().ok()?;
+                (network, if network.is_ipv4() { 32 } else { 128 })
+            },
+        };
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        (prefix <= bits).then_some(Cidr { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+// IPv4 clients of a dual-stack listener show up as ::ffff:a.b.c.d.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+// Proxies and load balancers whose X-Forwarded-For and Forwarded headers are
+// believed. Requests from anywhere else are attributed to the connecting
+// address, whatever headers they carry.
+pub struct TrustedProxies {
+    networks: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    pub fn parse(cidrs: &[String]) -> Result<TrustedProxies, String> {
+        let networks = cidrs.iter()
+            .map(|cidr| Cidr::parse(cidr.trim()).ok_or_else(|| format!("Invalid trusted proxy {}", cidr)))
+            .collect::<Result<_, _>>()?;
+        Ok(TrustedProxies { networks })
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    // The client behind `peer`. Forwarding headers list the hops oldest first,
+    // each proxy appending the address it was connected from, so the client is
+    // the last address not belonging to a trusted proxy. Anything before it
+    // could have been sent by the client and isn't looked at.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> ClientIp {
+        let peer = canonical(peer);
+        if !self.is_trusted(peer) {
+            return ClientIp(peer);
+        }
+        let mut client = peer;
+        for hop in forwarded_hops(headers).iter().rev() {
+            match hop {
+                Some(ip) => {
+                    client = canonical(*ip);
+                    if !self.is_trusted(client) {
+                        break;
+                    }
+                },
+                // An obfuscated or unparsable hop can't be looked past.
+                None => break,
+            }
+        }
+        ClientIp(client)
+    }
+}
+
+// The addresses in the Forwarded header (RFC 7239) if there is one, otherwise
+// in X-Forwarded-For, oldest first. `None` stands for an entry that isn't an
+// address.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<&str> = headers.get_all(hyper::header::FORWARDED).iter().filter_map(|value| value.to_str().ok()).collect();
+    if !forwarded.is_empty() {
+        return forwarded.iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element.split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim_matches('"')))
+            })
+            .collect();
+    }
+    headers.get_all("x-forwarded-for").iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| parse_node(hop.trim()))
+        .collect()
+}
+
+// An address, possibly with a port: 192.0.2.1, 192.0.2.1:4711,
+// [2001:db8::1] or [2001:db8::1]:4711.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.rsplit_once(':')?.0.parse().ok()
+}
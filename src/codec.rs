@@ -0,0 +1,158 @@
+use hyper::HeaderMap;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use serde_json::Value;
+
+// Wire formats for JSON-RPC bodies. JSON is the default; MessagePack is used for
+// a request body when its Content-Type says so, and for the reply when Accept
+// asks for it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    MessagePack,
+}
+
+fn is_msgpack(media_type: &str) -> bool {
+    let media_type = media_type.split(';').next().unwrap_or_default().trim();
+    media_type.eq_ignore_ascii_case("application/msgpack") || media_type.eq_ignore_ascii_case("application/x-msgpack")
+}
+
+// application/json and its relatives, e.g. application/json-rpc or application/vnd.api+json.
+fn is_json(media_type: &str) -> bool {
+    let media_type = media_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    media_type == "application/json" || media_type == "application/json-rpc" || (media_type.starts_with("application/") && media_type.ends_with("+json"))
+}
+
+// The q of an Accept entry: 1 unless it gives one, 0 if it is unreadable.
+fn quality(media_range: &str) -> f32 {
+    media_range.split(';').skip(1)
+        .find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim().eq_ignore_ascii_case("q").then(|| value.trim().parse::<f32>().map_or(0.0, |q| q.clamp(0.0, 1.0)))
+        })
+        .unwrap_or(1.0)
+}
+
+// How much an Accept header takes `format`: the q of the most specific entry
+// covering it (the format itself, then application/*, then */*), with how
+// specific that entry is. None if no entry covers it.
+fn preference(accept: &str, format: Format) -> Option<(f32, u8)> {
+    accept.split(',')
+        .filter_map(|media_range| {
+            let media_type = media_range.split(';').next().unwrap_or_default().trim();
+            let specificity = match format {
+                Format::Json if is_json(media_type) => 2,
+                Format::MessagePack if is_msgpack(media_type) => 2,
+                _ if media_type.eq_ignore_ascii_case("application/*") => 1,
+                _ if media_type == "*/*" => 0,
+                _ => return None,
+            };
+            Some((specificity, quality(media_range)))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .map(|(specificity, q)| (q, specificity))
+}
+
+impl Format {
+    pub fn of_body(headers: &HeaderMap) -> Format {
+        match headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()) {
+            Some(content_type) if is_msgpack(content_type) => Format::MessagePack,
+            _ => Format::Json,
+        }
+    }
+
+    // The declared format of a request body, if it is one the proxy reads.
+    pub fn declared(headers: &HeaderMap) -> Option<Format> {
+        let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+        if is_msgpack(content_type) {
+            Some(Format::MessagePack)
+        } else if is_json(content_type) {
+            Some(Format::Json)
+        } else {
+            None
+        }
+    }
+
+    // The reply format Accept prefers by q, or `None` when it rules out both
+    // formats. At equal q the more specific entry wins, and JSON unless
+    // MessagePack is named.
+    pub fn negotiate(headers: &HeaderMap) -> Option<Format> {
+        let accept = match headers.get(ACCEPT).and_then(|value| value.to_str().ok()) {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Some(Format::Json),
+        };
+        let json = preference(accept, Format::Json).filter(|(q, _)| *q > 0.0);
+        let msgpack = preference(accept, Format::MessagePack).filter(|(q, _)| *q > 0.0);
+        match (json, msgpack) {
+            (None, None) => None,
+            (Some(_), None) => Some(Format::Json),
+            (None, Some(_)) => Some(Format::MessagePack),
+            (Some(json), Some(msgpack)) if json > msgpack || (json == msgpack && msgpack.1 < 2) => Some(Format::Json),
+            (Some(_), Some(_)) => Some(Format::MessagePack),
+        }
+    }
+
+    // The reply format for clients that aren't held to Accept: JSON unless
+    // they prefer MessagePack.
+    pub fn accepted(headers: &HeaderMap) -> Format {
+        Format::negotiate(headers).unwrap_or(Format::Json)
+    }
+
+    pub fn parse(self, bytes: &[u8]) -> Option<Value> {
+        match self {
+            Format::Json => crate::json::from_slice(bytes),
+            Format::MessagePack => rmp_serde::from_slice(bytes).ok(),
+        }
+    }
+
+    pub fn write(self, out: &mut Vec<u8>, value: &Value) {
+        match self {
+            Format::Json => serde_json::to_writer(out, value).unwrap(),
+            Format::MessagePack => rmp_serde::encode::write_named(out, value).unwrap(),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(accept: &str) -> Option<Format> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, accept.parse().unwrap());
+        Format::negotiate(&headers)
+    }
+
+    #[test]
+    fn refused_formats_are_not_chosen() {
+        assert_eq!(negotiate("application/msgpack;q=0, application/json"), Some(Format::Json));
+        assert_eq!(negotiate("application/msgpack;q=0, */*"), Some(Format::Json));
+        assert_eq!(negotiate("application/json;q=0, */*"), Some(Format::MessagePack));
+        assert_eq!(negotiate("application/json;q=0"), None);
+        assert_eq!(negotiate("application/json; q=0.0, application/msgpack;Q=0"), None);
+        assert_eq!(negotiate("text/html"), None);
+    }
+
+    #[test]
+    fn the_highest_q_wins() {
+        assert_eq!(negotiate("application/msgpack;q=0.2, application/json;q=0.9"), Some(Format::Json));
+        assert_eq!(negotiate("application/json;q=0.5, application/msgpack;q=0.8"), Some(Format::MessagePack));
+        assert_eq!(negotiate("*/*;q=0.1, application/msgpack"), Some(Format::MessagePack));
+        assert_eq!(negotiate("application/msgpack;q=0.5, */*"), Some(Format::Json));
+    }
+
+    #[test]
+    fn ties_go_to_the_named_format_then_json() {
+        assert_eq!(negotiate("application/msgpack, application/json"), Some(Format::MessagePack));
+        assert_eq!(negotiate("application/json, application/msgpack"), Some(Format::MessagePack));
+        assert_eq!(negotiate("application/json, */*"), Some(Format::Json));
+        assert_eq!(negotiate("*/*"), Some(Format::Json));
+        assert_eq!(Format::negotiate(&HeaderMap::new()), Some(Format::Json));
+    }
+}
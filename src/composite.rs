@@ -0,0 +1,170 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+
+// One call of a composite method. Its params are a template: `{{path}}` refers
+// to the composite's own params (`params.name`) or to the result of an earlier
+// step (`identity.identity.primaryaddresses.0`).
+struct Step {
+    name: String,
+    method: String,
+    params: Value,
+    depends_on: Vec<String>,
+}
+
+// A virtual method defined in the config as a set of allowlisted calls. Steps
+// run as soon as the steps they refer to are done, independent ones
+// concurrently, and each goes through the same validation as a client call.
+// The reply is the `result` template, or every step's result by name.
+pub struct Composite {
+    params: Vec<String>,
+    steps: Vec<Step>,
+    result: Option<Value>,
+}
+
+fn placeholders(template: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    let mut from = 0;
+    std::iter::from_fn(move || {
+        let start = from + template[from..].find("{{")?;
+        let end = start + template[start..].find("}}")? + 2;
+        from = end;
+        Some((start, end, template[start + 2..end - 2].trim()))
+    })
+}
+
+fn references(template: &Value, out: &mut Vec<String>) {
+    match template {
+        Value::String(s) => out.extend(placeholders(s).map(|(_, _, path)| path.split('.').next().unwrap_or_default().to_string())),
+        Value::Array(items) => items.iter().for_each(|item| references(item, out)),
+        Value::Object(map) => map.values().for_each(|value| references(value, out)),
+        _ => {},
+    }
+}
+
+fn lookup<'a>(scope: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    let mut segments = path.split('.');
+    let mut value = scope.get(segments.next()?)?;
+    for segment in segments {
+        value = match value {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            value => value.get(segment)?,
+        };
+    }
+    Some(value)
+}
+
+// A string that is a single placeholder takes the referenced value as is, so
+// objects and numbers keep their type; otherwise placeholders are spliced in as text.
+fn render(template: &Value, scope: &Map<String, Value>) -> Value {
+    match template {
+        Value::String(s) => {
+            let found: Vec<_> = placeholders(s).collect();
+            if let [(0, end, path)] = found.as_slice() {
+                if *end == s.len() {
+                    return lookup(scope, path).cloned().unwrap_or(Value::Null);
+                }
+            }
+            let mut out = String::with_capacity(s.len());
+            let mut last = 0;
+            for (start, end, path) in found {
+                out.push_str(&s[last..start]);
+                match lookup(scope, path) {
+                    Some(Value::String(value)) => out.push_str(value),
+                    Some(value) => out.push_str(&value.to_string()),
+                    None => {},
+                }
+                last = end;
+            }
+            out.push_str(&s[last..]);
+            Value::String(out)
+        },
+        Value::Array(items) => Value::Array(items.iter().map(|item| render(item, scope)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(key, value)| (key.clone(), render(value, scope))).collect()),
+        other => other.clone(),
+    }
+}
+
+impl Composite {
+    fn parse(name: &str, definition: &Value) -> Result<Composite, String> {
+        let params = definition["params"].as_array().map(|names| {
+            names.iter().filter_map(Value::as_str).map(str::to_string).collect()
+        }).unwrap_or_default();
+        let mut steps: Vec<Step> = Vec::new();
+        for step in definition["steps"].as_array().ok_or_else(|| format!("{}: steps must be an array", name))? {
+            let step_name = step["name"].as_str().ok_or_else(|| format!("{}: every step needs a name", name))?;
+            let method = step["method"].as_str().ok_or_else(|| format!("{}.{}: method missing", name, step_name))?;
+            if step_name == "params" || steps.iter().any(|s| s.name == step_name) {
+                return Err(format!("{}.{}: step names must be unique and not \"params\"", name, step_name));
+            }
+            let params = match &step["params"] {
+                Value::Null => Value::Array(vec![]),
+                params @ Value::Array(_) => params.clone(),
+                _ => return Err(format!("{}.{}: params must be an array", name, step_name)),
+            };
+            let mut depends_on = Vec::new();
+            references(&params, &mut depends_on);
+            depends_on.retain(|dep| dep != "params");
+            // Only earlier steps can be referred to, which keeps the graph acyclic.
+            if let Some(unknown) = depends_on.iter().find(|dep| !steps.iter().any(|s| s.name == **dep)) {
+                return Err(format!("{}.{}: refers to {}, which is not an earlier step", name, step_name, unknown));
+            }
+            steps.push(Step { name: step_name.to_string(), method: method.to_string(), params, depends_on });
+        }
+        Ok(Composite { params, steps, result: definition.get("result").cloned() })
+    }
+
+    // Runs the steps with the caller's `access`, each checked as if the
+    // caller had made it.
+    pub async fn run(&self, rpc: &Arc<VerusRPC>, params: Vec<Value>, access: Access) -> Result<Value, RpcError> {
+        if params.len() > self.params.len() {
+            return Err(RpcError { code: -32602, message: "Invalid params parameter".into(), data: None });
+        }
+        let mut named = Map::new();
+        for (i, name) in self.params.iter().enumerate() {
+            named.insert(name.clone(), params.get(i).cloned().unwrap_or(Value::Null));
+        }
+        let mut scope = Map::new();
+        scope.insert("params".to_string(), Value::Object(named));
+
+        let mut pending: Vec<&Step> = self.steps.iter().collect();
+        while !pending.is_empty() {
+            let (ready, waiting): (Vec<&Step>, Vec<&Step>) = pending.into_iter()
+                .partition(|step| step.depends_on.iter().all(|dep| scope.contains_key(dep)));
+            let mut calls = JoinSet::new();
+            for step in ready {
+                let params = match render(&step.params, &scope) {
+                    Value::Array(params) => params,
+                    _ => vec![],
+                };
+                let (rpc, name, method) = (rpc.clone(), step.name.clone(), step.method.clone());
+                calls.spawn(async move { (name, rpc.handle_call_as(method, params, access).await) });
+            }
+            while let Some(call) = calls.join_next().await {
+                match call.unwrap() {
+                    (name, Ok(result)) => { scope.insert(name, result); },
+                    (name, Err(err)) => {
+                        return Err(RpcError { code: err.code, message: format!("{} failed: {}", name, err.message), data: None });
+                    },
+                }
+            }
+            pending = waiting;
+        }
+
+        match &self.result {
+            Some(template) => Ok(render(template, &scope)),
+            None => {
+                scope.remove("params");
+                Ok(Value::Object(scope))
+            },
+        }
+    }
+}
+
+pub fn load(definitions: HashMap<String, Value>) -> Result<HashMap<String, Composite>, String> {
+    definitions.iter().map(|(name, definition)| Ok((name.clone(), Composite::parse(name, definition)?))).collect()
+}
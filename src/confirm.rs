@@ -0,0 +1,131 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::allowlist::Access;
+use crate::policy::output_totals;
+use crate::session::random_hex;
+
+struct Pending {
+    params: Vec<Value>,
+    expires: Instant,
+    // Who made the send, None if it was made anonymously.
+    principal: Option<[u8; 16]>,
+}
+
+// Two-step sendcurrency for large amounts: a send moving more than
+// `threshold` of any currency from the daemon's wallet isn't forwarded, but
+// held under a random id that a `confirmsend` call must name within
+// `window`. The confirming caller must be allowed to send and have
+// authenticated as a different principal than the one that made the send, so
+// the id alone confirms nothing. Template requests are never held, since the
+// client still has to sign and broadcast those itself.
+pub struct PendingSends {
+    threshold: f64,
+    window: Duration,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl PendingSends {
+    // A threshold of 0 turns confirmations off.
+    pub fn new(threshold: f64, window: Duration) -> PendingSends {
+        PendingSends { threshold, window, pending: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn needs_confirmation(&self, method: &str, params: &[Value]) -> bool {
+        self.threshold > 0.0
+            && method == "sendcurrency"
+            && params.get(4).and_then(Value::as_bool) != Some(true)
+            && output_totals(params).values().any(|total| *total > self.threshold)
+    }
+
+    // Holds a send made with `access` and returns the error telling the
+    // client how to confirm it.
+    pub fn hold(&self, params: Vec<Value>, access: Access) -> RpcError {
+        let id = random_hex(16);
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, held| held.expires > now);
+        pending.insert(id.clone(), Pending { params, expires: now + self.window, principal: access.principal });
+        let data = json!({ "pending": id, "expires_in": self.window.as_secs(), "confirm_with": "confirmsend" });
+        RpcError {
+            code: -32004,
+            message: "Confirmation required".into(),
+            data: Some(serde_json::value::to_raw_value(&data).unwrap()),
+        }
+    }
+
+    // The params of the held send `confirmsend` names, which is then no
+    // longer pending. A confirmation from the wrong principal leaves it held.
+    pub fn take(&self, params: &[Value], access: Access) -> Result<Vec<Value>, RpcError> {
+        let id = match params {
+            [Value::String(id)] => id,
+            _ => return Err(RpcError { code: -32602, message: "Invalid params parameter".into(), data: None }),
+        };
+        let mut pending = self.pending.lock().unwrap();
+        let held = match pending.get(id) {
+            Some(held) if held.expires > Instant::now() => held,
+            _ => {
+                pending.remove(id);
+                return Err(RpcError { code: -8, message: "Unknown or expired pending send".into(), data: None });
+            },
+        };
+        match access.principal {
+            None => Err(RpcError { code: -8, message: "Confirming a send needs a session or an API key".into(), data: None }),
+            Some(principal) if held.principal == Some(principal) => {
+                Err(RpcError { code: -8, message: "A send must be confirmed by someone other than who made it".into(), data: None })
+            },
+            Some(_) => Ok(pending.remove(id).unwrap().params),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held_id(error: &RpcError) -> String {
+        let data: Value = serde_json::from_str(error.data.as_ref().unwrap().get()).unwrap();
+        data["pending"].as_str().unwrap().to_string()
+    }
+
+    fn send() -> Vec<Value> {
+        vec![json!("*"), json!([{ "address": "alice@", "amount": 500 }])]
+    }
+
+    #[test]
+    fn confirmation_needs_another_authenticated_principal() {
+        let sends = PendingSends::new(100.0, Duration::from_secs(60));
+        let alice = Access::STANDARD.authenticated("key:alice");
+        let id = held_id(&sends.hold(send(), alice));
+
+        let own = sends.take(&[json!(id)], alice).unwrap_err();
+        assert_eq!(own.message, "A send must be confirmed by someone other than who made it");
+        let anonymous = sends.take(&[json!(id)], Access::STANDARD).unwrap_err();
+        assert_eq!(anonymous.message, "Confirming a send needs a session or an API key");
+
+        let bob = Access::STANDARD.authenticated("id:bob@");
+        assert_eq!(sends.take(&[json!(id)], bob).unwrap(), send());
+        assert!(sends.take(&[json!(id)], bob).is_err());
+    }
+
+    #[test]
+    fn anonymous_sends_are_confirmed_by_an_authenticated_principal() {
+        let sends = PendingSends::new(100.0, Duration::from_secs(60));
+        let id = held_id(&sends.hold(send(), Access::STANDARD));
+        assert!(sends.take(&[json!(id)], Access::STANDARD).is_err());
+        assert!(sends.take(&[json!(id)], Access::STANDARD.authenticated("key:bob")).is_ok());
+    }
+
+    #[test]
+    fn expired_sends_cannot_be_confirmed() {
+        let sends = PendingSends::new(100.0, Duration::from_millis(10));
+        let id = held_id(&sends.hold(send(), Access::STANDARD.authenticated("key:alice")));
+        std::thread::sleep(Duration::from_millis(20));
+        let expired = sends.take(&[json!(id)], Access::STANDARD.authenticated("key:bob")).unwrap_err();
+        assert_eq!(expired.message, "Unknown or expired pending send");
+        assert!(sends.pending.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+use hyper::{Body, Response, StatusCode};
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+use crate::rest::{self, json_response};
+
+// One reserve of a basket: how much it holds and the price of a basket unit
+// in it.
+pub struct Reserve {
+    pub reserves: f64,
+    pub price: f64,
+    pub weight: f64,
+}
+
+// A fractional basket that converts between its reserves and itself.
+pub struct Basket {
+    pub id: String,
+    pub name: Value,
+    pub supply: f64,
+    pub reserves: Vec<(String, Reserve)>,
+}
+
+impl Basket {
+    pub fn reserve(&self, currency: &str) -> Option<&Reserve> {
+        self.reserves.iter().find(|(id, _)| id == currency).map(|(_, reserve)| reserve)
+    }
+
+    pub fn converts(&self, currency: &str) -> bool {
+        currency == self.id || self.reserve(currency).is_some()
+    }
+
+    // How many `to` one `from` buys at the current prices, before fees and
+    // slippage.
+    pub fn rate(&self, from: &str, to: &str) -> Option<f64> {
+        let price = |currency: &str| match currency == self.id {
+            true => Some(1.0),
+            false => self.reserve(currency).map(|reserve| reserve.price).filter(|price| *price > 0.0),
+        };
+        Some(price(to)? / price(from)?)
+    }
+
+    // How deep the basket is on one side, in basket units: the supply for the
+    // basket itself, otherwise what the reserve is worth.
+    fn depth(&self, currency: &str) -> f64 {
+        match (currency == self.id, self.reserve(currency)) {
+            (true, _) => self.supply,
+            (false, Some(reserve)) if reserve.price > 0.0 => reserve.reserves / reserve.price,
+            _ => 0.0,
+        }
+    }
+
+    // The depth of the thinner side of a conversion, which bounds how much it
+    // can take without moving the price far.
+    pub fn liquidity(&self, from: &str, to: &str) -> f64 {
+        self.depth(from).min(self.depth(to))
+    }
+
+    pub fn from_state(id: &str, name: Value, state: &Value) -> Basket {
+        let reserves = state["reservecurrencies"].as_array().into_iter().flatten()
+            .filter_map(|reserve| Some((reserve["currencyid"].as_str()?.to_string(), Reserve {
+                reserves: reserve["reserves"].as_f64().unwrap_or(0.0),
+                price: reserve["priceinreserve"].as_f64().unwrap_or(0.0),
+                weight: reserve["weight"].as_f64().unwrap_or(0.0),
+            })))
+            .collect();
+        Basket { id: id.to_string(), name, supply: state["supply"].as_f64().unwrap_or(0.0), reserves }
+    }
+
+    fn side_json(&self, currency: &str) -> Value {
+        match self.reserve(currency) {
+            Some(reserve) => json!({ "reserves": reserve.reserves, "priceinreserve": reserve.price, "weight": reserve.weight }),
+            None => json!({ "supply": self.supply }),
+        }
+    }
+}
+
+// The current state of a basket: the latest getcurrencystate, which is cached
+// per block, or the state of its last notarization.
+pub async fn current_state(rpc: &Arc<VerusRPC>, id: &str, notarized: &Value, access: Access) -> Value {
+    match rpc.handle_call_as("getcurrencystate".to_string(), vec![json!(id)], access).await {
+        Ok(Value::Array(states)) if !states.is_empty() => states[states.len() - 1]["currencystate"].clone(),
+        _ => notarized.clone(),
+    }
+}
+
+// The basket a getcurrencyconverters entry describes: its definition sits
+// under its own i-address.
+fn converter_id(entry: &Value) -> Option<(String, Value)> {
+    entry.as_object()?.iter()
+        .find(|(key, definition)| rest::is_id(key) && definition.is_object())
+        .map(|(id, definition)| (id.clone(), definition["fullyqualifiedname"].clone()))
+        .map(|(id, name)| (id, if name.is_null() { entry["fullyqualifiedname"].clone() } else { name }))
+}
+
+// The baskets that convert between `from` and `to`, as i-addresses, with
+// their current state.
+pub async fn converters(rpc: &Arc<VerusRPC>, from: &str, to: &str, access: Access) -> Result<Vec<Basket>, String> {
+    let listed = rpc.handle_call_as("getcurrencyconverters".to_string(), vec![json!(from), json!(to)], access).await.map_err(|e| e.message)?;
+    let mut baskets = Vec::new();
+    for entry in listed.as_array().into_iter().flatten() {
+        if let Some((id, name)) = converter_id(entry) {
+            let state = current_state(rpc, &id, &entry["lastnotarization"]["currencystate"], access).await;
+            let basket = Basket::from_state(&id, name, &state);
+            if basket.converts(from) && basket.converts(to) {
+                baskets.push(basket);
+            }
+        }
+    }
+    Ok(baskets)
+}
+
+// GET /converters/<from>/<to> lists the baskets that convert between two
+// currencies (names or i-addresses), each with the reserves and prices of
+// both sides from its current state, the rate before fees and a liquidity
+// figure (the depth of its thinner side, in basket units), deepest first.
+pub async fn handle(path: &str, rpc: &Arc<VerusRPC>, access: Access) -> Response<Body> {
+    let (from, to) = match path.trim_start_matches("/converters/").split_once('/') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() && !to.contains('/') => (from, to),
+        _ => return json_response(StatusCode::NOT_FOUND, json!({"error": "Use /converters/<from>/<to>"})),
+    };
+    let (from, to) = match (rest::resolve(rpc, "getcurrency", from, access).await, rest::resolve(rpc, "getcurrency", to, access).await) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(message), _) | (_, Err(message)) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+    };
+    let mut baskets = match converters(rpc, &from, &to, access).await {
+        Ok(baskets) => baskets,
+        Err(message) => return json_response(StatusCode::BAD_GATEWAY, json!({"error": message})),
+    };
+    baskets.sort_by(|a, b| b.liquidity(&from, &to).total_cmp(&a.liquidity(&from, &to)));
+    let listed: Vec<Value> = baskets.iter().map(|basket| json!({
+        "currencyid": basket.id,
+        "name": basket.name,
+        "rate": basket.rate(&from, &to),
+        "liquidity": basket.liquidity(&from, &to),
+        "supply": basket.supply,
+        "from": basket.side_json(&from),
+        "to": basket.side_json(&to),
+    })).collect();
+    json_response(StatusCode::OK, json!({"from": from, "to": to, "height": rpc.tip.height(), "converters": listed}))
+}
@@ -0,0 +1,92 @@
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::VerusRPC;
+use crate::limiter::Priority;
+
+// Figures of a currency state that are watched for changes: supply and, per
+// reserve currency, reserves and price.
+fn figures(state: &Value) -> HashMap<String, f64> {
+    let mut figures = HashMap::new();
+    if let Some(supply) = state["supply"].as_f64() {
+        figures.insert("supply".to_string(), supply);
+    }
+    for reserve in state["reservecurrencies"].as_array().into_iter().flatten() {
+        let currency = reserve["currencyid"].as_str().unwrap_or_default();
+        if let Some(reserves) = reserve["reserves"].as_f64() {
+            figures.insert(format!("reserves:{}", currency), reserves);
+        }
+        if let Some(price) = reserve["priceinreserve"].as_f64() {
+            figures.insert(format!("price:{}", currency), price);
+        }
+    }
+    figures
+}
+
+fn relative_change(old: f64, new: f64) -> f64 {
+    if old == 0.0 {
+        if new == 0.0 { 0.0 } else { f64::INFINITY }
+    } else {
+        ((new - old) / old).abs()
+    }
+}
+
+// Checks the state of each watched currency whenever the tip moves and
+// publishes a `currency_state` event for every figure that moved by at least
+// `threshold` (relative) since the last event for that currency. The first
+// state seen is only taken as the baseline.
+pub async fn watch(rpc: Arc<VerusRPC>, currencies: Vec<String>, threshold: f64, interval: Duration) {
+    let mut baselines: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    let mut last_height = None;
+    loop {
+        tokio::time::sleep(interval).await;
+        let height = rpc.tip.height();
+        if height.is_none() || height == last_height {
+            continue;
+        }
+        last_height = height;
+
+        for currency in &currencies {
+            let states = match rpc.upstream.call_as("getcurrencystate", &[json!(currency)], Priority::Background).await {
+                Ok(states) => states,
+                Err(e) => {
+                    eprintln!("currency watch: getcurrencystate {} failed: {}", currency, e.message);
+                    continue;
+                },
+            };
+            let latest = match states.as_array().and_then(|states| states.last()) {
+                Some(latest) => latest,
+                None => continue,
+            };
+            let state = &latest["currencystate"];
+            let current = figures(state);
+            let baseline = match baselines.get_mut(currency) {
+                Some(baseline) => baseline,
+                None => {
+                    baselines.insert(currency.clone(), current);
+                    continue;
+                },
+            };
+
+            let mut changes = Map::new();
+            for (figure, new) in &current {
+                let old = baseline.get(figure).copied().unwrap_or(0.0);
+                let change = relative_change(old, *new);
+                if change >= threshold {
+                    changes.insert(figure.clone(), json!({ "old": old, "new": new, "change": if change.is_finite() { json!(change) } else { Value::Null } }));
+                }
+            }
+            if !changes.is_empty() {
+                *baseline = current;
+                rpc.events.publish("currency_state", json!({
+                    "currency": currency,
+                    "height": latest["height"].as_u64().or(height),
+                    "changes": changes,
+                    "state": state,
+                }));
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use serde_json::{Map, Value, json};
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::upstream::Trace;
+
+// What the proxy did to answer one call: the steps it went through (which
+// checks passed, whether the proxy answered it itself), how the cache tiers
+// answered and how long each part took. Collected only for clients that ask
+// with X-Debug and hold one of the debug_api_keys.
+#[derive(Default)]
+pub struct Record {
+    path: Vec<String>,
+    cache: Option<&'static str>,
+    timings: Vec<(&'static str, Duration)>,
+}
+
+impl Record {
+    // The `_debug` field of a reply, with the daemon calls made for it.
+    pub fn to_json(&self, traces: &[Trace], total: Duration) -> Value {
+        let mut timings: Map<String, Value> = self.timings.iter()
+            .map(|(name, elapsed)| (name.to_string(), json!(elapsed.as_secs_f64() * 1000.0)))
+            .collect();
+        timings.insert("total".to_string(), json!(total.as_secs_f64() * 1000.0));
+        json!({
+            "path": self.path,
+            "cache": self.cache,
+            "upstream": traces.iter().map(Trace::to_json).collect::<Vec<_>>(),
+            "timings_ms": timings,
+        })
+    }
+}
+
+tokio::task_local! {
+    static RECORD: RefCell<Record>;
+}
+
+fn note(f: impl FnOnce(&mut Record)) {
+    let _ = RECORD.try_with(|record| f(&mut record.borrow_mut()));
+}
+
+// Notes a step of the call's path, if it is being debugged.
+pub fn step(step: impl Into<String>) {
+    note(|record| record.path.push(step.into()));
+}
+
+// Notes how the cache tiers answered: memory, memory (stale), disk, miss or
+// uncached.
+pub fn cache(outcome: &'static str) {
+    note(|record| record.cache = Some(outcome));
+}
+
+pub fn timing(name: &'static str, elapsed: Duration) {
+    note(|record| record.timings.push((name, elapsed)));
+}
+
+// Runs `f`, collecting its record if `enabled`.
+pub async fn recorded<F: Future>(enabled: bool, f: F) -> (F::Output, Option<Record>) {
+    if !enabled {
+        return (f.await, None);
+    }
+    RECORD.scope(RefCell::new(Record::default()), async {
+        let output = f.await;
+        (output, Some(RECORD.with(RefCell::take)))
+    }).await
+}
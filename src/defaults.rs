@@ -0,0 +1,29 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+// Operator-configured values for params that clients leave off the end of a
+// call, by method and param position. Filling stops at the first omitted
+// position without a default, since params can't be skipped.
+pub struct ParamDefaults {
+    defaults: HashMap<String, HashMap<usize, Value>>,
+}
+
+impl ParamDefaults {
+    pub fn new(defaults: HashMap<String, HashMap<String, Value>>) -> ParamDefaults {
+        let defaults = defaults.into_iter().map(|(method, positions)| {
+            let positions = positions.into_iter()
+                .map(|(position, value)| (position.parse().expect("Param default positions must be numbers"), value))
+                .collect();
+            (method, positions)
+        }).collect();
+        ParamDefaults { defaults }
+    }
+
+    pub fn fill(&self, method: &str, params: &mut Vec<Value>) {
+        if let Some(positions) = self.defaults.get(method) {
+            while let Some(value) = positions.get(&params.len()) {
+                params.push(value.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_json::{Value, json};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Methods whose results can become immutable once deep enough in the chain.
+pub fn is_candidate(method: &str) -> bool {
+    matches!(method, "getblock" | "getblockheader" | "getblockhash" | "getrawtransaction" | "getcurrencystate")
+}
+
+fn is_deep(height: Option<u64>, tip: u64, depth: u64) -> bool {
+    height.is_some_and(|height| height + depth <= tip)
+}
+
+// Whether a result refers only to blocks at least `depth` below the tip, where
+// a reorg is no longer a practical concern. Results without a height (raw hex
+// blocks and transactions, mempool transactions) are never considered final.
+pub fn is_final(method: &str, params: &[Value], result: &Value, tip: u64, depth: u64) -> bool {
+    match method {
+        "getblock" | "getblockheader" | "getrawtransaction" => is_deep(result["height"].as_u64(), tip, depth),
+        "getblockhash" => is_deep(params.first().and_then(Value::as_u64), tip, depth),
+        // The second param is "height", "start,end" or "start,end,step".
+        "getcurrencystate" => match params.get(1).and_then(Value::as_str) {
+            Some(heights) => {
+                let heights: Option<Vec<u64>> = heights.split(',').map(|h| h.trim().parse().ok()).collect();
+                is_deep(heights.and_then(|heights| heights.into_iter().max()), tip, depth)
+            },
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+// Stored blocks and transactions carry the confirmation count from when they
+// were cached; bring it up to date before serving them.
+pub fn refresh(mut result: Value, tip: u64) -> Value {
+    if let Some(height) = result["height"].as_u64() {
+        if let Some(confirmations) = result.get_mut("confirmations") {
+            *confirmations = json!(tip.saturating_sub(height) + 1);
+        }
+    }
+    result
+}
+
+// SQLite-backed tier for final results, so a restart doesn't send every block
+// and transaction lookup back to the daemon. Once the stored values exceed
+// `max_bytes` the oldest entries are dropped down to 90% of the limit and the
+// freed pages are vacuumed.
+pub struct DiskCache {
+    conn: Mutex<Connection>,
+    max_bytes: u64,
+    pub depth: u64,
+    bytes: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl DiskCache {
+    // The disk cache configured by disk_cache_path, if any.
+    pub fn from_settings(settings: &config::Config) -> rusqlite::Result<Option<DiskCache>> {
+        let path = match settings.get_str("disk_cache_path") {
+            Ok(path) => path,
+            Err(_) => return Ok(None),
+        };
+        let max_bytes = settings.get::<u64>("disk_cache_max_mb").unwrap_or(1024) * 1024 * 1024;
+        let depth = settings.get::<u64>("disk_cache_min_confirmations").unwrap_or(10);
+        DiskCache::open(&path, max_bytes, depth).map(Some)
+    }
+
+    pub fn open(path: &str, max_bytes: u64, depth: u64) -> rusqlite::Result<DiskCache> {
+        let conn = Connection::open(path)?;
+        // auto_vacuum only takes effect when set before the first table is created.
+        conn.execute_batch(
+            "PRAGMA auto_vacuum = INCREMENTAL;
+             PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS entries (
+                 key TEXT PRIMARY KEY,
+                 method TEXT NOT NULL,
+                 value TEXT NOT NULL,
+                 size INTEGER NOT NULL
+             );",
+        )?;
+        let bytes: i64 = conn.query_row("SELECT COALESCE(SUM(size), 0) FROM entries", [], |row| row.get(0))?;
+        Ok(DiskCache {
+            conn: Mutex::new(conn),
+            max_bytes,
+            depth,
+            bytes: AtomicU64::new(bytes as u64),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let conn = self.conn.lock().unwrap();
+        let stored: Option<String> = conn
+            .query_row("SELECT value FROM entries WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()
+            .unwrap_or(None);
+        match stored.and_then(|value| crate::json::from_slice(value.as_bytes())) {
+            Some(value) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value)
+            },
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+        }
+    }
+
+    pub fn insert(&self, method: &str, key: &str, value: &Value) {
+        let value = value.to_string();
+        let size = value.len() as u64;
+        let conn = self.conn.lock().unwrap();
+        let inserted = conn
+            .execute("INSERT OR IGNORE INTO entries (key, method, value, size) VALUES (?1, ?2, ?3, ?4)", params![key, method, value, size as i64])
+            .unwrap_or(0);
+        if inserted == 0 {
+            return;
+        }
+        if self.bytes.fetch_add(size, Ordering::Relaxed) + size > self.max_bytes {
+            if let Err(e) = self.compact(&conn) {
+                eprintln!("disk cache compaction failed: {}", e);
+            }
+        }
+    }
+
+    fn compact(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let excess = self.bytes.load(Ordering::Relaxed).saturating_sub(self.max_bytes / 10 * 9);
+        let mut victims = Vec::new();
+        let mut freed = 0u64;
+        {
+            let mut oldest = conn.prepare("SELECT rowid, size FROM entries ORDER BY rowid")?;
+            let mut rows = oldest.query([])?;
+            while freed < excess {
+                let row = match rows.next()? {
+                    Some(row) => row,
+                    None => break,
+                };
+                let (rowid, size): (i64, i64) = (row.get(0)?, row.get(1)?);
+                victims.push(rowid);
+                freed += size as u64;
+            }
+        }
+        conn.execute_batch("BEGIN")?;
+        for rowid in &victims {
+            conn.execute("DELETE FROM entries WHERE rowid = ?1", params![rowid])?;
+        }
+        conn.execute_batch("COMMIT; PRAGMA incremental_vacuum;")?;
+        self.bytes.fetch_sub(freed, Ordering::Relaxed);
+        self.evictions.fetch_add(victims.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn flush(&self, method: Option<&str>) -> usize {
+        let conn = self.conn.lock().unwrap();
+        let removed = match method {
+            Some(method) => conn.execute("DELETE FROM entries WHERE method = ?1", params![method]),
+            None => conn.execute("DELETE FROM entries", []),
+        }
+        .unwrap_or(0);
+        let bytes: i64 = conn.query_row("SELECT COALESCE(SUM(size), 0) FROM entries", [], |row| row.get(0)).unwrap_or(0);
+        self.bytes.store(bytes as u64, Ordering::Relaxed);
+        let _ = conn.execute_batch("PRAGMA incremental_vacuum;");
+        removed
+    }
+
+    // Drops the entries a reorg back to below `height` may have changed:
+    // blocks and transactions at or above it, and the lookups that don't
+    // carry their height (block hashes by height, currency states).
+    pub fn flush_from(&self, height: u64) -> usize {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            "DELETE FROM entries WHERE method IN ('getblockhash', 'getcurrencystate') OR json_extract(value, '$.height') >= ?1",
+            params![height as i64],
+        )
+        .unwrap_or(0);
+        let bytes: i64 = conn.query_row("SELECT COALESCE(SUM(size), 0) FROM entries", [], |row| row.get(0)).unwrap_or(0);
+        self.bytes.store(bytes as u64, Ordering::Relaxed);
+        let _ = conn.execute_batch("PRAGMA incremental_vacuum;");
+        removed
+    }
+
+    // Passes every entry to `out` as [key, method, value], oldest first.
+    pub fn export(&self, out: &mut dyn FnMut(Vec<Value>) -> Result<(), String>) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare("SELECT key, method, value FROM entries ORDER BY rowid").map_err(|e| e.to_string())?;
+        let mut rows = query.query([]).map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            let entry: rusqlite::Result<Vec<Value>> = (0..3).map(|i| row.get::<_, String>(i).map(Value::String)).collect();
+            out(entry.map_err(|e| e.to_string())?)?;
+        }
+        Ok(())
+    }
+
+    // Adds exported entries, keeping any already stored, then compacts if
+    // that took the cache over its size limit.
+    pub fn import(&self, entries: &[Vec<Value>]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let mut added = 0u64;
+        {
+            let db = conn.transaction()?;
+            {
+                let mut insert = db.prepare("INSERT OR IGNORE INTO entries (key, method, value, size) VALUES (?1, ?2, ?3, ?4)")?;
+                for entry in entries {
+                    if let [Value::String(key), Value::String(method), Value::String(value)] = entry.as_slice() {
+                        if insert.execute(params![key, method, value, value.len() as i64])? > 0 {
+                            added += value.len() as u64;
+                        }
+                    }
+                }
+            }
+            db.commit()?;
+        }
+        if self.bytes.fetch_add(added, Ordering::Relaxed) + added > self.max_bytes {
+            self.compact(&conn)?;
+        }
+        Ok(())
+    }
+
+    pub fn summary(&self) -> Value {
+        let (hits, misses) = (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed));
+        json!({
+            "bytes": self.bytes.load(Ordering::Relaxed),
+            "hits": hits,
+            "misses": misses,
+            "evictions": self.evictions.load(Ordering::Relaxed),
+            "hit_rate": hits as f64 / (hits + misses).max(1) as f64,
+        })
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        writeln!(out, "# TYPE disk_cache_bytes gauge").unwrap();
+        writeln!(out, "disk_cache_bytes {}", self.bytes.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "# TYPE disk_cache_hits_total counter").unwrap();
+        writeln!(out, "disk_cache_hits_total {}", self.hits.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "# TYPE disk_cache_misses_total counter").unwrap();
+        writeln!(out, "disk_cache_misses_total {}", self.misses.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "# TYPE disk_cache_evictions_total counter").unwrap();
+        writeln!(out, "disk_cache_evictions_total {}", self.evictions.load(Ordering::Relaxed)).unwrap();
+    }
+}
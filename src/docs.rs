@@ -0,0 +1,210 @@
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::VerusRPC;
+use crate::allowlist::{Access, Rule};
+use crate::events;
+use crate::indexer::MAX_PAGE;
+use crate::limiter::Priority;
+use crate::rest::json_response;
+
+// How long a method's help is kept before it is asked for again, so a daemon
+// upgrade shows up without a restart.
+const DOCS_TTL: Duration = Duration::from_secs(3600);
+// Methods listed per page of the index by default.
+const DEFAULT_PAGE: u64 = 100;
+
+// The daemon's help for each method, parsed, and its list of methods.
+pub struct MethodDocs {
+    docs: Mutex<HashMap<String, (Instant, Value)>>,
+}
+
+impl MethodDocs {
+    pub fn new() -> MethodDocs {
+        MethodDocs { docs: Mutex::new(HashMap::new()) }
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        self.docs.lock().unwrap().get(key).filter(|(at, _)| at.elapsed() < DOCS_TTL).map(|(_, doc)| doc.clone())
+    }
+
+    fn insert(&self, key: &str, doc: Value) {
+        self.docs.lock().unwrap().insert(key.to_string(), (Instant::now(), doc));
+    }
+}
+
+// One argument line of a help text, e.g.
+// `2. "verbosity"   (numeric, optional, default=1) 0 for hex encoded data, ...`:
+// its position, name, type, whether it is required, its default and what it is.
+fn argument(line: &str) -> Option<Value> {
+    let (position, rest) = line.split_once(". ")?;
+    let position: u64 = position.trim().parse().ok()?;
+    let rest = rest.trim_start();
+    let name_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_end);
+    let rest = rest.trim_start();
+    let (details, description) = match rest.strip_prefix('(').and_then(|rest| rest.split_once(')')) {
+        Some((details, description)) => (details, description.trim()),
+        None => ("", rest),
+    };
+    let details: Vec<&str> = details.split(',').map(str::trim).filter(|detail| !detail.is_empty()).collect();
+    let default = details.iter().find_map(|detail| detail.strip_prefix("default=").or_else(|| detail.strip_prefix("default ")));
+    Some(json!({
+        "position": position,
+        "name": name.trim_matches('"'),
+        "type": details.first().filter(|kind| !kind.starts_with("default")),
+        "required": details.contains(&"required"),
+        "default": default,
+        "description": description,
+    }))
+}
+
+// Splits a help text into its usage line, description, arguments, result and
+// examples. Sections start at unindented lines ending in a colon
+// ("Arguments:", "Result:", "Result (for verbose = true):", "Examples:").
+fn parse(method: &str, text: &str) -> Value {
+    let mut lines = text.lines();
+    let usage = lines.next().unwrap_or_default().trim().to_string();
+    let mut section = String::new();
+    let mut description: Vec<&str> = Vec::new();
+    let mut arguments: Vec<Value> = Vec::new();
+    let mut results: Vec<(String, Vec<&str>)> = Vec::new();
+    let mut examples: Vec<Value> = Vec::new();
+    let mut example_note: Vec<&str> = Vec::new();
+    for line in lines {
+        let trimmed = line.trim();
+        if !line.starts_with(char::is_whitespace) && trimmed.ends_with(':') && !trimmed.starts_with('>') {
+            section = trimmed.trim_end_matches(':').to_string();
+            if section.starts_with("Result") {
+                results.push((section.clone(), Vec::new()));
+            }
+            continue;
+        }
+        match section.as_str() {
+            "" => description.push(trimmed),
+            "Arguments" => match argument(trimmed) {
+                Some(parsed) => arguments.push(parsed),
+                // Wrapped descriptions and the fields of object arguments
+                // belong to the argument above.
+                None if !trimmed.is_empty() => match arguments.last_mut() {
+                    Some(last) => last["description"] = json!(format!("{}\n{}", last["description"].as_str().unwrap_or_default(), trimmed).trim()),
+                    None => description.push(trimmed),
+                },
+                None => {},
+            },
+            "Examples" => match trimmed.strip_prefix('>') {
+                Some(command) => examples.push(json!({ "description": example_note.join(" "), "command": command.trim() })),
+                None if trimmed.is_empty() => example_note.clear(),
+                None => example_note.push(trimmed),
+            },
+            _ if section.starts_with("Result") => {
+                if let Some((_, result)) = results.last_mut() {
+                    result.push(line);
+                }
+            },
+            _ => description.push(trimmed),
+        }
+    }
+    let results: Vec<Value> = results.into_iter()
+        .map(|(title, lines)| json!({ "title": title, "text": lines.join("\n").trim_matches('\n') }))
+        .collect();
+    json!({
+        "method": method,
+        "usage": usage,
+        "description": description.join("\n").trim(),
+        "arguments": arguments,
+        "results": results,
+        "examples": examples,
+    })
+}
+
+// The method names in the daemon's full help, which lists one usage line per
+// method under "== Category ==" headings.
+fn method_names(text: &str) -> Vec<String> {
+    text.lines()
+        .filter(|line| !line.starts_with("==") && !line.trim().is_empty())
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+async fn help(rpc: &VerusRPC, method: Option<&str>) -> Result<String, String> {
+    let params: Vec<Value> = method.into_iter().map(|method| json!(method)).collect();
+    let text = rpc.upstream.call_as("help", &params, Priority::Background).await.map_err(|e| e.message)?;
+    text.as_str().map(str::to_string).ok_or_else(|| "Unexpected help reply".to_string())
+}
+
+fn rule_json(rule: &Rule) -> Value {
+    json!({ "method": rule.method, "params": rule.params, "must_be_true": rule.flag, "docs": format!("/docs/{}", rule.method) })
+}
+
+// GET /docs lists the methods this endpoint allows, a page at a time
+// (?start=&count=), with the param types the allowlist takes and where the
+// daemon's documentation of each is; under full access that is every method
+// in the daemon's help.
+async fn index(req: &Request<Body>, rpc: &Arc<VerusRPC>, access: Access) -> Response<Body> {
+    let query = events::query(req.uri());
+    let start = query.get("start").and_then(|start| start.parse().ok()).unwrap_or(0u64);
+    let count = query.get("count").and_then(|count| count.parse().ok()).unwrap_or(DEFAULT_PAGE).min(MAX_PAGE);
+    let methods: Vec<Value> = match access.rules(|method| rpc.methods.is_write(method)) {
+        Some(rules) => rules.into_iter().map(rule_json).collect(),
+        None => {
+            let names = match rpc.docs.get("") {
+                Some(names) => names,
+                None => match help(rpc, None).await {
+                    Ok(text) => {
+                        let names = json!(method_names(&text));
+                        rpc.docs.insert("", names.clone());
+                        names
+                    },
+                    Err(message) => return json_response(StatusCode::BAD_GATEWAY, json!({"error": message})),
+                },
+            };
+            names.as_array().into_iter().flatten()
+                .filter_map(Value::as_str)
+                .map(|method| json!({ "method": method, "docs": format!("/docs/{}", method) }))
+                .collect()
+        },
+    };
+    let total = methods.len();
+    let page: Vec<Value> = methods.into_iter().skip(start as usize).take(count as usize).collect();
+    json_response(StatusCode::OK, json!({ "access": access.scope.name(), "total": total, "start": start, "methods": page }))
+}
+
+// GET /docs/<method> is the daemon's help for a method this endpoint allows,
+// parsed into its usage, description, arguments (name, type, required,
+// default), result and examples, with the raw text and the params the
+// allowlist takes. Help is cached for an hour.
+pub async fn handle(path: &str, req: &Request<Body>, rpc: &Arc<VerusRPC>, access: Access) -> Response<Body> {
+    let method = path.trim_start_matches("/docs").trim_matches('/');
+    if method.is_empty() {
+        return index(req, rpc, access).await;
+    }
+    if !method.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return json_response(StatusCode::BAD_REQUEST, json!({"error": "Invalid method name"}));
+    }
+    let rules = access.rules(|method| rpc.methods.is_write(method));
+    let rule = match rules.as_ref().map(|rules| rules.iter().find(|rule| rule.method == method)) {
+        Some(None) => return json_response(StatusCode::NOT_FOUND, json!({"error": format!("{} is not available on this endpoint", method), "allowlist": "/docs"})),
+        Some(Some(rule)) => Some(rule_json(rule)),
+        None => None,
+    };
+    let mut doc = match rpc.docs.get(method) {
+        Some(doc) => doc,
+        None => match help(rpc, Some(method)).await {
+            Ok(text) => {
+                let mut doc = parse(method, &text);
+                doc["text"] = json!(text);
+                rpc.docs.insert(method, doc.clone());
+                doc
+            },
+            Err(message) => return json_response(StatusCode::BAD_GATEWAY, json!({"error": message})),
+        },
+    };
+    doc["allowlist"] = rule.unwrap_or_else(|| json!({ "method": method, "params": "any" }));
+    doc["links"] = json!({ "allowlist": "/docs" });
+    json_response(StatusCode::OK, doc)
+}
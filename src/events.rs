@@ -0,0 +1,187 @@
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+// Events buffered per subscriber before a slow one starts missing them.
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub struct Event {
+    // Increases by one per event, so a client can tell what it missed.
+    pub seq: u64,
+    pub kind: &'static str,
+    pub data: Value,
+}
+
+impl Event {
+    pub fn to_json(&self) -> Value {
+        json!({ "seq": self.seq, "event": self.kind, "data": self.data })
+    }
+}
+
+struct History {
+    next_seq: u64,
+    events: VecDeque<Arc<Event>>,
+}
+
+// What a reconnecting subscriber gets: the buffered events after the sequence
+// number it last saw, and how many it missed that are no longer buffered.
+pub struct Replay {
+    pub events: Vec<Arc<Event>>,
+    pub missed: u64,
+}
+
+// Fan-out of chain events to streaming clients. The last `replay_size` events
+// are kept so clients that reconnect can pick up where they left off.
+pub struct EventHub {
+    sender: broadcast::Sender<Arc<Event>>,
+    history: Mutex<History>,
+    replay_size: usize,
+}
+
+impl EventHub {
+    pub fn new(replay_size: usize) -> EventHub {
+        EventHub {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            history: Mutex::new(History { next_seq: 1, events: VecDeque::with_capacity(replay_size) }),
+            replay_size,
+        }
+    }
+
+    // Producers skip work that only matters to subscribers when there are none.
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn publish(&self, kind: &'static str, data: Value) {
+        // Sending under the lock keeps the buffer and the channel in the same
+        // order, which `subscribe_since` relies on.
+        let mut history = self.history.lock().unwrap();
+        let event = Arc::new(Event { seq: history.next_seq, kind, data });
+        history.next_seq += 1;
+        if self.replay_size > 0 {
+            if history.events.len() == self.replay_size {
+                history.events.pop_front();
+            }
+            history.events.push_back(event.clone());
+        }
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.sender.subscribe()
+    }
+
+    // Subscribes and returns the buffered events after `since`, with nothing
+    // lost or repeated between the two. A `since` ahead of the hub (from before
+    // a restart) replays nothing.
+    pub fn subscribe_since(&self, since: u64) -> (Replay, broadcast::Receiver<Arc<Event>>) {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let events: Vec<_> = history.events.iter().filter(|event| event.seq > since).cloned().collect();
+        let first_available = events.first().map_or(history.next_seq, |event| event.seq);
+        let missed = first_available.saturating_sub(since + 1);
+        (Replay { events, missed }, receiver)
+    }
+}
+
+// The sequence number a reconnecting client last saw, from `?since=N` or an
+// SSE `Last-Event-ID` header.
+pub fn since(query: &HashMap<String, String>, headers: &hyper::HeaderMap) -> Option<u64> {
+    query.get("since").map(String::as_str)
+        .or_else(|| headers.get("last-event-id").and_then(|value| value.to_str().ok()))
+        .and_then(|value| value.parse().ok())
+}
+
+pub fn query(uri: &hyper::Uri) -> HashMap<String, String> {
+    uri.query().unwrap_or_default().split('&').filter_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        Some((key.to_string(), value.to_string()))
+    }).collect()
+}
+
+fn list(value: Option<&String>) -> Vec<String> {
+    value.map(|value| value.split(',').filter(|item| !item.is_empty()).map(str::to_string).collect()).unwrap_or_default()
+}
+
+// What a streaming client subscribed to, from the stream name in its path and
+// the filter in its query string. Empty lists match everything.
+pub enum Filter {
+    // New mempool transactions touching any of the addresses, any of the
+    // currencies ("native" for the chain's own coin) and at least min_value of
+    // one of those currencies, e.g. `?address=R...,alice@&currency=native&min_value=10`.
+    Mempool { addresses: Vec<String>, currencies: Vec<String>, min_value: f64 },
+    // State changes of any of the currencies, e.g. `?currency=bridge.veth`.
+    Currencies { currencies: Vec<String> },
+    // Watched identities becoming spendable, by name or i-address, e.g.
+    // `?address=vault@`.
+    Timelocks { identities: Vec<String> },
+    // Chain reorganizations.
+    Reorgs,
+}
+
+impl Filter {
+    pub fn parse(stream: &str, query: &HashMap<String, String>) -> Result<Filter, String> {
+        match stream {
+            "mempool" => {
+                let min_value = match query.get("min_value") {
+                    Some(value) => value.parse().map_err(|_| format!("Invalid min_value: {}", value))?,
+                    None => 0.0,
+                };
+                Ok(Filter::Mempool { addresses: list(query.get("address")), currencies: list(query.get("currency")), min_value })
+            },
+            "currencies" => Ok(Filter::Currencies { currencies: list(query.get("currency")) }),
+            "timelocks" => Ok(Filter::Timelocks { identities: list(query.get("address")) }),
+            "reorgs" => Ok(Filter::Reorgs),
+            _ => Err(format!("Unknown stream: {}", stream)),
+        }
+    }
+
+    // How many addresses and currencies the filter lists.
+    pub fn terms(&self) -> usize {
+        match self {
+            Filter::Mempool { addresses, currencies, .. } => addresses.len() + currencies.len(),
+            Filter::Currencies { currencies } => currencies.len(),
+            Filter::Timelocks { identities } => identities.len(),
+            Filter::Reorgs => 0,
+        }
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        let tx = &event.data;
+        match self {
+            Filter::Mempool { addresses, currencies, min_value } => {
+                if event.kind != "mempool_tx" {
+                    return false;
+                }
+                let has = |field: &str, wanted: &[String]| {
+                    wanted.is_empty() || tx[field].as_array().into_iter().flatten().any(|item| wanted.iter().any(|w| item == w.as_str()))
+                };
+                if !has("addresses", addresses) || !has("currencies", currencies) {
+                    return false;
+                }
+                let largest = tx["totals"].as_object().into_iter().flatten()
+                    .filter(|(currency, _)| currencies.is_empty() || currencies.contains(currency))
+                    .filter_map(|(_, total)| total.as_f64())
+                    .fold(0.0, f64::max);
+                largest >= *min_value
+            },
+            Filter::Currencies { currencies } => {
+                event.kind == "currency_state" && (currencies.is_empty() || {
+                    let currency = event.data["currency"].as_str().unwrap_or_default();
+                    currencies.iter().any(|wanted| wanted.eq_ignore_ascii_case(currency))
+                })
+            },
+            Filter::Timelocks { identities } => {
+                event.kind == "identity_spendable" && (identities.is_empty() || identities.iter().any(|wanted| {
+                    let wanted = wanted.strip_suffix('@').unwrap_or(wanted);
+                    ["identity", "name"].iter().any(|field| event.data[field].as_str().is_some_and(|value| {
+                        value.strip_suffix('@').unwrap_or(value).eq_ignore_ascii_case(wanted)
+                    }))
+                }))
+            },
+            Filter::Reorgs => event.kind == "reorg",
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::VerusRPC;
+use crate::events::Event;
+
+// Where the event bus is exported to. Each event goes to the topic or subject
+// `<prefix>.<kind>`, e.g. `verus.block`, as `{"seq", "event", "data"}` JSON.
+pub struct ExportOptions {
+    pub prefix: String,
+    // Event kinds to export; empty exports all.
+    pub events: Vec<String>,
+}
+
+impl ExportOptions {
+    fn wants(&self, event: &Event) -> bool {
+        self.events.is_empty() || self.events.iter().any(|kind| kind == event.kind)
+    }
+
+    fn destination(&self, event: &Event) -> String {
+        format!("{}.{}", self.prefix, event.kind)
+    }
+}
+
+// Next event to export. Events dropped because the exporter fell behind are
+// logged rather than stopping the export.
+async fn next(events: &mut tokio::sync::broadcast::Receiver<Arc<Event>>, sink: &str) -> Option<Arc<Event>> {
+    loop {
+        match events.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(missed)) => eprintln!("{} export fell behind, {} events dropped", sink, missed),
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+fn now_millis() -> i64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+#[cfg(feature = "kafka")]
+pub async fn kafka(rpc: Arc<VerusRPC>, brokers: Vec<String>, options: ExportOptions) {
+    use rskafka::chrono::{TimeZone, Utc};
+    use rskafka::client::ClientBuilder;
+    use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+    use rskafka::record::Record;
+    use std::collections::HashMap;
+
+    let mut events = rpc.events.subscribe();
+    // rskafka retries with backoff internally, so a failed connect means the
+    // brokers are unreachable or misconfigured.
+    let client = match ClientBuilder::new(brokers).build().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("kafka export disabled, cannot connect: {}", e);
+            return;
+        },
+    };
+    let mut partitions: HashMap<String, PartitionClient> = HashMap::new();
+    while let Some(event) = next(&mut events, "kafka").await {
+        if !options.wants(&event) {
+            continue;
+        }
+        let topic = options.destination(&event);
+        if !partitions.contains_key(&topic) {
+            match client.partition_client(topic.as_str(), 0, UnknownTopicHandling::Retry).await {
+                Ok(partition) => {
+                    partitions.insert(topic.clone(), partition);
+                },
+                Err(e) => {
+                    eprintln!("kafka export to {} failed: {}", topic, e);
+                    continue;
+                },
+            }
+        }
+        let record = Record {
+            key: Some(event.seq.to_string().into_bytes()),
+            value: Some(event.to_json().to_string().into_bytes()),
+            headers: Default::default(),
+            timestamp: Utc.timestamp_millis_opt(now_millis()).unwrap(),
+        };
+        if let Err(e) = partitions[&topic].produce(vec![record], Compression::NoCompression).await {
+            eprintln!("kafka export to {} failed: {}", topic, e);
+        }
+    }
+}
+
+#[cfg(feature = "nats")]
+pub async fn nats(rpc: Arc<VerusRPC>, url: String, options: ExportOptions) {
+    use hyper::body::Bytes;
+
+    let mut events = rpc.events.subscribe();
+    // The client reconnects on its own and buffers publishes meanwhile.
+    let client = match async_nats::connect(url.as_str()).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("nats export disabled, cannot connect to {}: {}", url, e);
+            return;
+        },
+    };
+    while let Some(event) = next(&mut events, "nats").await {
+        if !options.wants(&event) {
+            continue;
+        }
+        let subject = options.destination(&event);
+        if let Err(e) = client.publish(subject.clone(), Bytes::from(event.to_json().to_string())).await {
+            eprintln!("nats export to {} failed: {}", subject, e);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::VerusRPC;
+use crate::{address, captcha};
+use crate::rest::json_response;
+use crate::session::random_hex;
+
+// How long a proof-of-work challenge can be solved for.
+const CHALLENGE_TTL: Duration = Duration::from_secs(600);
+// Challenges handed out and not yet used, at most.
+const MAX_CHALLENGES: usize = 10_000;
+// Unused challenges one client may hold; asking for more drops its oldest, so
+// no client can use up MAX_CHALLENGES for everyone else.
+const MAX_CLIENT_CHALLENGES: usize = 5;
+
+// Hands out a small amount of coins to whoever asks, for testnet deployments
+// where dApp developers and testers need some to try things with. Each client
+// address and each recipient gets coins once per cooldown, and when pow_bits
+// is set a request must carry a proof of work over a challenge from
+// GET /faucet, so filling a wallet takes more than a loop of requests. Every
+// send goes to the audit log when there is one.
+pub struct Faucet {
+    amount: f64,
+    currency: Option<String>,
+    from: String,
+    ip_cooldown: Duration,
+    address_cooldown: Duration,
+    pow_bits: u32,
+    allow_mainnet: bool,
+    // Unused challenges, with the client each went to and when.
+    challenges: Mutex<HashMap<String, (String, Instant)>>,
+    // When each client address, and each recipient, was last sent coins.
+    by_client: Mutex<HashMap<String, Instant>>,
+    by_address: Mutex<HashMap<String, Instant>>,
+    sent: AtomicU64,
+    refused: AtomicU64,
+}
+
+// The leading zero bits of a hash.
+fn zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+// The key a client is counted under: its address, or for IPv6 its /64, which
+// a single host usually has all of to pick addresses from.
+fn client_key(client: &str) -> String {
+    match client.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => {
+            let network = Ipv6Addr::from(u128::from(ip) & !((1u128 << 64) - 1));
+            format!("{}/64", network)
+        },
+        _ => client.to_string(),
+    }
+}
+
+// Notes `key` as sent to now, unless it was sent to within `cooldown`, in
+// which case it says how many seconds are left.
+fn claim(sent: &Mutex<HashMap<String, Instant>>, key: &str, cooldown: Duration) -> Result<(), u64> {
+    let mut sent = sent.lock().unwrap();
+    let now = Instant::now();
+    sent.retain(|_, at| now.duration_since(*at) < cooldown);
+    if let Some(at) = sent.get(key) {
+        return Err((cooldown - now.duration_since(*at)).as_secs().max(1));
+    }
+    sent.insert(key.to_string(), now);
+    Ok(())
+}
+
+fn refused(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({ "error": message }))
+}
+
+impl Faucet {
+    // None unless faucet_amount is set.
+    pub fn from_settings(settings: &config::Config) -> Result<Option<Faucet>, String> {
+        let amount = match settings.get::<f64>("faucet_amount") {
+            Ok(amount) if amount > 0.0 => amount,
+            Ok(_) => return Err("faucet_amount must be positive".to_string()),
+            Err(_) => return Ok(None),
+        };
+        let pow_bits = settings.get::<u32>("faucet_pow_bits").unwrap_or(0);
+        if pow_bits > 32 {
+            return Err("faucet_pow_bits can be at most 32".to_string());
+        }
+        Ok(Some(Faucet {
+            amount,
+            currency: settings.get_str("faucet_currency").ok(),
+            from: settings.get_str("faucet_from").unwrap_or_else(|_| "*".to_string()),
+            ip_cooldown: Duration::from_secs(settings.get::<u64>("faucet_ip_cooldown_secs").unwrap_or(86_400)),
+            address_cooldown: Duration::from_secs(settings.get::<u64>("faucet_address_cooldown_secs").unwrap_or(86_400)),
+            pow_bits,
+            allow_mainnet: settings.get::<bool>("faucet_allow_mainnet").unwrap_or(false),
+            challenges: Mutex::new(HashMap::new()),
+            by_client: Mutex::new(HashMap::new()),
+            by_address: Mutex::new(HashMap::new()),
+            sent: AtomicU64::new(0),
+            refused: AtomicU64::new(0),
+        }))
+    }
+
+    fn challenge(&self, client: &str) -> Option<String> {
+        if self.pow_bits == 0 {
+            return None;
+        }
+        let mut challenges = self.challenges.lock().unwrap();
+        challenges.retain(|_, (_, issued)| issued.elapsed() < CHALLENGE_TTL);
+        let mut held: Vec<(&String, Instant)> = challenges.iter()
+            .filter(|(_, (owner, _))| owner == client)
+            .map(|(challenge, (_, issued))| (challenge, *issued))
+            .collect();
+        if held.len() >= MAX_CLIENT_CHALLENGES {
+            held.sort_by_key(|(_, issued)| *issued);
+            let oldest = held[0].0.clone();
+            challenges.remove(&oldest);
+        } else if challenges.len() >= MAX_CHALLENGES {
+            return None;
+        }
+        let challenge = random_hex(16);
+        challenges.insert(challenge.clone(), (client.to_string(), Instant::now()));
+        Some(challenge)
+    }
+
+    // A challenge is good for one request, whether its proof holds or not.
+    fn check_work(&self, body: &Value, address: &str) -> Result<(), &'static str> {
+        if self.pow_bits == 0 {
+            return Ok(());
+        }
+        let (challenge, nonce) = match (body["challenge"].as_str(), body["nonce"].as_str()) {
+            (Some(challenge), Some(nonce)) => (challenge, nonce),
+            _ => return Err("A proof of work is required: challenge and nonce"),
+        };
+        match self.challenges.lock().unwrap().remove(challenge) {
+            Some((_, issued)) if issued.elapsed() < CHALLENGE_TTL => {},
+            _ => return Err("Unknown or expired challenge"),
+        }
+        match zero_bits(&Sha256::digest(format!("{}:{}:{}", challenge, address, nonce))) >= self.pow_bits {
+            true => Ok(()),
+            false => Err("The proof of work doesn't meet the difficulty"),
+        }
+    }
+
+    fn info(&self, client: &str) -> Value {
+        json!({
+            "amount": self.amount,
+            "currency": self.currency,
+            "ip_cooldown_secs": self.ip_cooldown.as_secs(),
+            "address_cooldown_secs": self.address_cooldown.as_secs(),
+            "pow": match self.challenge(client) {
+                Some(challenge) => json!({
+                    "challenge": challenge,
+                    "bits": self.pow_bits,
+                    "algorithm": "sha256(challenge:address:nonce) with at least bits leading zero bits",
+                }),
+                None => Value::Null,
+            },
+        })
+    }
+
+    pub fn summary(&self) -> Value {
+        json!({
+            "amount": self.amount,
+            "currency": self.currency,
+            "sent": self.sent.load(Ordering::Relaxed),
+            "refused": self.refused.load(Ordering::Relaxed),
+        })
+    }
+
+    // Takes back a client's and a recipient's claims after a send failed.
+    fn release(&self, client: &str, address: &str) {
+        self.by_client.lock().unwrap().remove(client);
+        self.by_address.lock().unwrap().remove(address);
+    }
+
+    async fn send(&self, rpc: &VerusRPC, body: &Value, client: &str, principal: &str) -> Response<Body> {
+        let address = match body["address"].as_str() {
+            Some(address) if address::is_address_or_name(address) => address.to_string(),
+            _ => return refused(StatusCode::BAD_REQUEST, "A valid address or identity@ is required"),
+        };
+        if let Err(message) = self.check_work(body, &address) {
+            return refused(StatusCode::FORBIDDEN, message);
+        }
+        if !self.allow_mainnet {
+            match rpc.upstream.call("getinfo", &[]).await {
+                Ok(info) if info["testnet"] == json!(true) => {},
+                Ok(_) => return refused(StatusCode::SERVICE_UNAVAILABLE, "The faucet only runs on testnet"),
+                Err(e) => return json_response(StatusCode::BAD_GATEWAY, json!({ "error": e.message })),
+            }
+        }
+        let client_key = client_key(client);
+        if let Err(retry_after) = claim(&self.by_client, &client_key, self.ip_cooldown) {
+            let mut response = refused(StatusCode::TOO_MANY_REQUESTS, "This client was sent coins recently");
+            response.headers_mut().insert(hyper::header::RETRY_AFTER, retry_after.into());
+            return response;
+        }
+        if let Err(retry_after) = claim(&self.by_address, &address, self.address_cooldown) {
+            self.by_client.lock().unwrap().remove(&client_key);
+            let mut response = refused(StatusCode::TOO_MANY_REQUESTS, "This address was sent coins recently");
+            response.headers_mut().insert(hyper::header::RETRY_AFTER, retry_after.into());
+            return response;
+        }
+
+        let mut output = json!({ "address": address, "amount": self.amount });
+        if let Some(currency) = &self.currency {
+            output["currency"] = json!(currency);
+        }
+        let result = rpc.upstream.call("sendcurrency", &[json!(self.from), json!([output])]).await;
+        if let Some(audit) = &rpc.audit {
+            let reply = match &result {
+                Ok(opid) => json!({ "result": opid }),
+                Err(e) => json!({ "error": { "code": e.code, "message": e.message } }),
+            };
+            audit.record_one(principal, client, "faucet", &json!([output]), &reply);
+        }
+        match result {
+            Ok(opid) => {
+                self.sent.fetch_add(1, Ordering::Relaxed);
+                json_response(StatusCode::OK, json!({ "address": address, "amount": self.amount, "currency": self.currency, "opid": opid }))
+            },
+            Err(e) => {
+                self.release(&client_key, &address);
+                json_response(StatusCode::BAD_GATEWAY, json!({ "error": e.message }))
+            },
+        }
+    }
+}
+
+// An anonymous client must solve a captcha for a send when "faucet" is among
+// the captcha_methods.
+async fn solved(rpc: &VerusRPC, anonymous: bool, token: Option<&str>, client: &str) -> Result<(), Response<Body>> {
+    match rpc.captcha.as_ref().filter(|captcha| anonymous && captcha.guards("faucet")) {
+        Some(captcha) => captcha.verify(token, client).await
+            .map_err(|e| json_response(StatusCode::FORBIDDEN, json!({ "error": e.message, "captcha": captcha.describe() }))),
+        None => Ok(()),
+    }
+}
+
+// GET /faucet says what the faucet gives and, with proof of work on, hands out
+// a challenge; POST /faucet with {"address", "challenge", "nonce"} sends the
+// configured amount to the address, answering with the daemon's operation id.
+// The body is read like a JSON-RPC one, held to the body limits and
+// body_read_timeout.
+pub async fn handle(req: Request<Body>, rpc: &Arc<VerusRPC>, client: &str, principal: &str, anonymous: bool) -> Result<Response<Body>, hyper::Error> {
+    let faucet = match &rpc.faucet {
+        Some(faucet) => faucet,
+        None => return Ok(refused(StatusCode::NOT_FOUND, "There is no faucet here")),
+    };
+    match *req.method() {
+        Method::GET => Ok(json_response(StatusCode::OK, faucet.info(&client_key(client)))),
+        Method::POST => {
+            let captcha_header = captcha::header(req.headers()).map(str::to_string);
+            let (parts, body) = req.into_parts();
+            let mut whole_body = Vec::new();
+            if let Err(response) = crate::read_body(rpc, &parts.headers, body, &mut whole_body).await? {
+                faucet.refused.fetch_add(1, Ordering::Relaxed);
+                return Ok(response);
+            }
+            let response = match crate::json::from_slice(&whole_body) {
+                Some(body @ Value::Object(_)) => match solved(rpc, anonymous, captcha::token(captcha_header.as_deref(), &body), client).await {
+                    Ok(()) => faucet.send(rpc, &body, client, principal).await,
+                    Err(response) => response,
+                },
+                _ => refused(StatusCode::BAD_REQUEST, "Body must be a JSON object"),
+            };
+            if response.status() != StatusCode::OK {
+                faucet.refused.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(response)
+        },
+        _ => Ok(refused(StatusCode::METHOD_NOT_ALLOWED, "Use GET or POST")),
+    }
+}
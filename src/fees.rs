@@ -0,0 +1,58 @@
+use serde_json::{Value, json};
+
+const SATS_PER_COIN: f64 = 100_000_000.0;
+
+// Verus conversion fees, as a fraction of the amount converted.
+pub const CONVERSION_FEE_RATE: f64 = 0.00025;
+pub const RESERVE_TO_RESERVE_FEE_RATE: f64 = 0.0005;
+
+pub struct FeeRules {
+    // Lowest fee per kB worth recommending, the wallet default for standard transactions.
+    pub min_fee_per_kb: f64,
+    // Flat fee charged for exports to other systems, if the operator wants it advertised.
+    pub export_fee: Option<f64>,
+}
+
+fn per_kb(sats_per_byte: f64) -> f64 {
+    sats_per_byte * 1000.0 / SATS_PER_COIN
+}
+
+fn median(sorted: &[f64]) -> Option<f64> {
+    sorted.get(sorted.len() / 2).copied()
+}
+
+// Slow/normal/fast fees per kB from three sources: the daemon's estimatefee for
+// 25, 6 and 2 blocks, the rates of transactions recently mined out of the
+// mempool, and what is waiting in the mempool now. Each tier takes the highest
+// of the sources that apply to it, never going below the minimum fee; fast is
+// raised by half again when the mempool is congested.
+pub fn recommend(estimates: [Option<f64>; 3], mined_rates: &[f64], mempool: &Value, rules: &FeeRules) -> Value {
+    let [slow_estimate, normal_estimate, fast_estimate] = estimates;
+    let mut mined = mined_rates.to_vec();
+    mined.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mined_median = median(&mined).map(per_kb);
+    let waiting_median = mempool["median_rate"].as_f64().map(per_kb);
+    let congested = mempool["congestion"]["level"] == "high";
+
+    let highest = |fees: &[Option<f64>]| fees.iter().flatten().fold(rules.min_fee_per_kb, |a, b| a.max(*b));
+    let slow = highest(&[slow_estimate]);
+    let normal = highest(&[normal_estimate, mined_median]).max(slow);
+    let fast = highest(&[fast_estimate, waiting_median]).max(normal) * if congested { 1.5 } else { 1.0 };
+
+    json!({
+        "unit": "coins per kB",
+        "slow": slow,
+        "normal": normal,
+        "fast": fast,
+        "sources": {
+            "estimatefee": { "25": slow_estimate, "6": normal_estimate, "2": fast_estimate },
+            "recently_mined": { "count": mined.len(), "median_per_kb": mined_median },
+            "mempool": { "median_per_kb": waiting_median, "congestion": mempool["congestion"]["level"] },
+        },
+        "conversion": {
+            "fee_rate": CONVERSION_FEE_RATE,
+            "reserve_to_reserve_fee_rate": RESERVE_TO_RESERVE_FEE_RATE,
+        },
+        "export_fee": rules.export_fee,
+    })
+}
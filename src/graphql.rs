@@ -0,0 +1,217 @@
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Error, ErrorExtensions, Json, Object, Result, Schema};
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+
+// Most transactions resolved for one block.
+const MAX_BLOCK_TRANSACTIONS: usize = 100;
+
+pub type ChainSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+// The schema holds no state; the rpc and the caller's access are attached to
+// each request, so every field resolves through the same validation and cache
+// tiers as JSON-RPC.
+pub fn schema() -> ChainSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(10)
+        .limit_complexity(500)
+        .finish()
+}
+
+async fn call(ctx: &Context<'_>, method: &str, params: Vec<Value>) -> Result<Value> {
+    let (rpc, access) = (ctx.data_unchecked::<Arc<VerusRPC>>(), ctx.data_unchecked::<Access>());
+    rpc.handle_call_as(method.to_string(), params, *access).await
+        .map_err(|err| {
+            let code = err.code;
+            Error::new(err.message).extend_with(|_, e| e.set("code", code))
+        })
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    value.as_array().into_iter().flatten().filter_map(string).collect()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn block_count(&self, ctx: &Context<'_>) -> Result<u64> {
+        Ok(call(ctx, "getblockcount", vec![]).await?.as_u64().unwrap_or_default())
+    }
+
+    async fn block(&self, ctx: &Context<'_>, hash_or_height: String) -> Result<Block> {
+        call(ctx, "getblock", vec![json!(hash_or_height)]).await.map(Block)
+    }
+
+    async fn transaction(&self, ctx: &Context<'_>, txid: String) -> Result<Transaction> {
+        call(ctx, "getrawtransaction", vec![json!(txid), json!(1)]).await.map(Transaction)
+    }
+
+    async fn identity(&self, ctx: &Context<'_>, name: String) -> Result<Identity> {
+        call(ctx, "getidentity", vec![json!(name)]).await.map(Identity)
+    }
+
+    async fn currency(&self, ctx: &Context<'_>, name: String) -> Result<Currency> {
+        call(ctx, "getcurrency", vec![json!(name)]).await.map(Currency)
+    }
+
+    async fn address(&self, address: String) -> Address {
+        Address(address)
+    }
+}
+
+pub struct Block(Value);
+
+#[Object]
+impl Block {
+    async fn hash(&self) -> Option<String> {
+        string(&self.0["hash"])
+    }
+
+    async fn height(&self) -> Option<u64> {
+        self.0["height"].as_u64()
+    }
+
+    async fn confirmations(&self) -> Option<i64> {
+        self.0["confirmations"].as_i64()
+    }
+
+    async fn time(&self) -> Option<u64> {
+        self.0["time"].as_u64()
+    }
+
+    async fn previous_block_hash(&self) -> Option<String> {
+        string(&self.0["previousblockhash"])
+    }
+
+    async fn txids(&self) -> Vec<String> {
+        strings(&self.0["tx"])
+    }
+
+    async fn transactions(&self, ctx: &Context<'_>, #[graphql(default = 25)] limit: usize) -> Result<Vec<Transaction>> {
+        let mut transactions = Vec::new();
+        for txid in strings(&self.0["tx"]).into_iter().take(limit.min(MAX_BLOCK_TRANSACTIONS)) {
+            transactions.push(Transaction(call(ctx, "getrawtransaction", vec![json!(txid), json!(1)]).await?));
+        }
+        Ok(transactions)
+    }
+
+    async fn raw(&self) -> Json<Value> {
+        Json(self.0.clone())
+    }
+}
+
+pub struct Transaction(Value);
+
+#[Object]
+impl Transaction {
+    async fn txid(&self) -> Option<String> {
+        string(&self.0["txid"])
+    }
+
+    async fn confirmations(&self) -> Option<i64> {
+        self.0["confirmations"].as_i64()
+    }
+
+    async fn height(&self) -> Option<u64> {
+        self.0["height"].as_u64()
+    }
+
+    async fn block(&self, ctx: &Context<'_>) -> Result<Option<Block>> {
+        match string(&self.0["blockhash"]) {
+            Some(hash) => call(ctx, "getblock", vec![json!(hash)]).await.map(|block| Some(Block(block))),
+            None => Ok(None),
+        }
+    }
+
+    async fn raw(&self) -> Json<Value> {
+        Json(self.0.clone())
+    }
+}
+
+pub struct Identity(Value);
+
+#[Object]
+impl Identity {
+    async fn name(&self) -> Option<String> {
+        string(&self.0["fullyqualifiedname"]).or_else(|| string(&self.0["identity"]["name"]))
+    }
+
+    async fn identity_address(&self) -> Option<String> {
+        string(&self.0["identity"]["identityaddress"])
+    }
+
+    async fn status(&self) -> Option<String> {
+        string(&self.0["status"])
+    }
+
+    async fn primary_addresses(&self) -> Vec<Address> {
+        strings(&self.0["identity"]["primaryaddresses"]).into_iter().map(Address).collect()
+    }
+
+    async fn raw(&self) -> Json<Value> {
+        Json(self.0.clone())
+    }
+}
+
+pub struct Currency(Value);
+
+#[Object]
+impl Currency {
+    async fn name(&self) -> Option<String> {
+        string(&self.0["fullyqualifiedname"]).or_else(|| string(&self.0["name"]))
+    }
+
+    async fn currency_id(&self) -> Option<String> {
+        string(&self.0["currencyid"])
+    }
+
+    async fn options(&self) -> Option<u64> {
+        self.0["options"].as_u64()
+    }
+
+    async fn reserve_currencies(&self) -> Vec<String> {
+        strings(&self.0["currencies"])
+    }
+
+    async fn raw(&self) -> Json<Value> {
+        Json(self.0.clone())
+    }
+}
+
+pub struct Address(String);
+
+#[Object]
+impl Address {
+    async fn address(&self) -> &str {
+        &self.0
+    }
+
+    async fn balance(&self, ctx: &Context<'_>) -> Result<Json<Value>> {
+        call(ctx, "getaddressbalance", vec![json!({ "addresses": [self.0] })]).await.map(Json)
+    }
+
+    async fn txids(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        call(ctx, "getaddresstxids", vec![json!({ "addresses": [self.0] })]).await.map(|txids| strings(&txids))
+    }
+
+    async fn utxos(&self, ctx: &Context<'_>) -> Result<Json<Value>> {
+        call(ctx, "getaddressutxos", vec![json!({ "addresses": [self.0] })]).await.map(Json)
+    }
+}
+
+// Runs a GraphQL request body ({"query", "variables", "operationName"}).
+pub async fn execute(rpc: &Arc<VerusRPC>, body: &[u8], access: Access) -> Value {
+    let request: async_graphql::Request = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return json!({ "errors": [{ "message": format!("Invalid GraphQL request: {}", e) }] }),
+    };
+    let response = rpc.graphql.execute(request.data(rpc.clone()).data(access)).await;
+    serde_json::to_value(response).unwrap_or(Value::Null)
+}
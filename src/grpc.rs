@@ -0,0 +1,270 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+use crate::{VerusRPC, reply};
+use crate::allowlist::Access;
+use crate::listener::Profile;
+use crate::session;
+
+pub mod proto {
+    tonic::include_proto!("verus.v1");
+}
+
+use proto::call_reply::Reply;
+use proto::verus_server::{Verus, VerusServer};
+
+// Replies that are still buffered for a CallStream client before its requests
+// stop being read.
+const STREAM_BUFFER: usize = 16;
+
+fn status(err: RpcError) -> Status {
+    let code = match err.code {
+        -32601 => Code::Unimplemented,
+        -32600 | -32602 | -8 | -3 => Code::InvalidArgument,
+        -5 => Code::NotFound,
+        -32000 => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    Status::new(code, err.message)
+}
+
+fn string(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    value.as_array().into_iter().flatten().map(string).collect()
+}
+
+// Why a request isn't let in, kept small until it becomes a Status.
+type Refusal = (Code, &'static str);
+
+fn refused((code, message): Refusal) -> Status {
+    Status::new(code, message)
+}
+
+// Who a gRPC request is from, worked out as handle_req does for HTTP.
+struct Caller {
+    access: Access,
+    principal: String,
+    subject: Option<String>,
+    client: String,
+}
+
+// The JSON-RPC API as a gRPC service. Requests are handled exactly like
+// JSON-RPC requests, so the allowlist, validation and caches all apply, with
+// the access of the gRPC listener's own profile (grpc_access, grpc_mining
+// and grpc_api_keys). Bans, role and key rate limits, captchas and the audit
+// log apply as on the public listeners.
+pub struct Service {
+    rpc: Arc<VerusRPC>,
+    profile: Profile,
+}
+
+impl Service {
+    // Lets in a request showing one of grpc_api_keys (any request, without
+    // them), a key holding a role or one created through the admin listener,
+    // as Authorization: Bearer <key> metadata.
+    fn admit<T>(&self, request: &Request<T>) -> Result<Caller, Refusal> {
+        let rpc = &self.rpc;
+        let ip = request.remote_addr().map(|addr| addr.ip());
+        if ip.is_some_and(|ip| rpc.runtime.is_banned(ip)) {
+            return Err((Code::PermissionDenied, "Banned"));
+        }
+        let key = request.metadata().get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let managed = key.and_then(|key| rpc.runtime.key(key));
+        let key_roles = key.map_or(0, |key| rpc.roles.of_key(key))
+            | managed.as_ref().map_or(0, |managed| rpc.roles.named(&managed.roles));
+        let listed = key.is_some_and(|key| self.profile.api_keys.contains(key));
+        if !self.profile.api_keys.is_empty() && !listed && key_roles == 0 && managed.is_none() {
+            return Err((Code::Unauthenticated, match key {
+                Some(_) => "Invalid API key",
+                None => "This listener needs an API key",
+            }));
+        }
+        let mut access = self.profile.access;
+        access.mining |= key.is_some_and(|key| rpc.mining_api_keys.contains(key)) || managed.as_ref().is_some_and(|managed| managed.mining);
+        access.shielded |= key.is_some_and(|key| rpc.shielded_api_keys.contains(key));
+        access.registration |= key.is_some_and(|key| rpc.registration_api_keys.contains(key));
+        if let Some(priority) = key.and_then(|key| rpc.priority_api_keys.get(key)) {
+            access.priority = access.priority.max(*priority);
+        }
+        access.roles = match key_roles {
+            0 => rpc.roles.default(),
+            roles => roles,
+        };
+        if let Some(priority) = rpc.roles.priority(access.roles) {
+            access.priority = access.priority.max(priority);
+        }
+        let client = ip.map(|ip| ip.to_string()).unwrap_or_default();
+        let subject = key.map(session::key_subject);
+        let principal = match key_roles {
+            0 => client.clone(),
+            _ => subject.clone().unwrap_or_default(),
+        };
+        let anonymous = key_roles == 0 && managed.is_none() && !listed;
+        if let (false, Some(subject)) = (anonymous, &subject) {
+            access = access.authenticated(subject);
+        }
+        // gRPC clients can't show a captcha widget, so anonymous ones can't
+        // call the methods it guards.
+        access.needs_captcha = anonymous && rpc.captcha.is_some();
+        let caller = Caller { access, principal, subject, client };
+        self.limit(&caller)?;
+        Ok(caller)
+    }
+
+    fn limit(&self, caller: &Caller) -> Result<(), Refusal> {
+        if self.rpc.roles.admit(caller.access.roles, &caller.principal, 1).is_err() {
+            return Err((Code::ResourceExhausted, "Rate limit for your role exceeded"));
+        }
+        if caller.subject.as_deref().map_or(Ok(()), |subject| self.rpc.runtime.admit(subject, 1)).is_err() {
+            return Err((Code::ResourceExhausted, "Rate limit for your key exceeded"));
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, caller: &Caller, method: &str, params: Value) -> Result<Value, RpcError> {
+        let rpc = &self.rpc;
+        let result = rpc.handle_as(json!({ "method": method, "params": params.clone() }), caller.access).await;
+        if let (Some(audit), true) = (&rpc.audit, rpc.methods.is_write(method)) {
+            audit.record_one(caller.subject.as_deref().unwrap_or_default(), &caller.client, method, &params, &reply(result.clone()));
+        }
+        result
+    }
+
+    async fn call_reply(&self, caller: &Caller, request: proto::CallRequest) -> proto::CallReply {
+        let params = if request.params_json.is_empty() {
+            Ok(json!([]))
+        } else {
+            crate::json::from_slice(request.params_json.as_bytes())
+                .ok_or_else(|| RpcError { code: -32700, message: "Parse error".into(), data: None })
+        };
+        let reply = match params {
+            Ok(params) => self.handle(caller, &request.method, params).await,
+            Err(err) => Err(err),
+        };
+        proto::CallReply {
+            reply: Some(match reply {
+                Ok(result) => Reply::ResultJson(result.to_string()),
+                Err(err) => Reply::Error(proto::RpcError { code: err.code, message: err.message }),
+            }),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Verus for Arc<Service> {
+    async fn call(&self, request: Request<proto::CallRequest>) -> Result<Response<proto::CallReply>, Status> {
+        let caller = self.admit(&request).map_err(refused)?;
+        Ok(Response::new(self.call_reply(&caller, request.into_inner()).await))
+    }
+
+    type CallStreamStream = ReceiverStream<Result<proto::CallReply, Status>>;
+
+    // Requests on a stream are answered one at a time, in order, each
+    // counted against the caller's rate limits.
+    async fn call_stream(&self, request: Request<Streaming<proto::CallRequest>>) -> Result<Response<Self::CallStreamStream>, Status> {
+        let caller = self.admit(&request).map_err(refused)?;
+        let mut requests = request.into_inner();
+        let (replies, stream) = mpsc::channel(STREAM_BUFFER);
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let reply = match requests.message().await {
+                    Ok(Some(request)) => match service.limit(&caller) {
+                        Ok(()) => Ok(service.call_reply(&caller, request).await),
+                        Err(refusal) => Err(refused(refusal)),
+                    },
+                    Ok(None) => break,
+                    Err(status) => Err(status),
+                };
+                let failed = reply.is_err();
+                if replies.send(reply).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+
+    async fn get_block_count(&self, request: Request<proto::Empty>) -> Result<Response<proto::BlockCount>, Status> {
+        let caller = self.admit(&request).map_err(refused)?;
+        let count = self.handle(&caller, "getblockcount", json!([])).await.map_err(status)?;
+        Ok(Response::new(proto::BlockCount { height: count.as_u64().unwrap_or_default() }))
+    }
+
+    async fn get_block(&self, request: Request<proto::GetBlockRequest>) -> Result<Response<proto::Block>, Status> {
+        let caller = self.admit(&request).map_err(refused)?;
+        let block = self.handle(&caller, "getblock", json!([request.into_inner().hash_or_height])).await.map_err(status)?;
+        Ok(Response::new(proto::Block {
+            hash: string(&block["hash"]),
+            height: block["height"].as_u64().unwrap_or_default(),
+            confirmations: block["confirmations"].as_i64().unwrap_or_default(),
+            time: block["time"].as_u64().unwrap_or_default(),
+            previous_block_hash: string(&block["previousblockhash"]),
+            txids: strings(&block["tx"]),
+            json: block.to_string(),
+        }))
+    }
+
+    async fn get_raw_transaction(&self, request: Request<proto::GetRawTransactionRequest>) -> Result<Response<proto::Transaction>, Status> {
+        let caller = self.admit(&request).map_err(refused)?;
+        let tx = self.handle(&caller, "getrawtransaction", json!([request.into_inner().txid, 1])).await.map_err(status)?;
+        Ok(Response::new(proto::Transaction {
+            txid: string(&tx["txid"]),
+            block_hash: string(&tx["blockhash"]),
+            height: tx["height"].as_u64().unwrap_or_default(),
+            confirmations: tx["confirmations"].as_i64().unwrap_or_default(),
+            json: tx.to_string(),
+        }))
+    }
+
+    async fn get_identity(&self, request: Request<proto::GetIdentityRequest>) -> Result<Response<proto::Identity>, Status> {
+        let caller = self.admit(&request).map_err(refused)?;
+        let identity = self.handle(&caller, "getidentity", json!([request.into_inner().name])).await.map_err(status)?;
+        Ok(Response::new(proto::Identity {
+            name: identity["fullyqualifiedname"].as_str().map(str::to_string).unwrap_or_else(|| string(&identity["identity"]["name"])),
+            identity_address: string(&identity["identity"]["identityaddress"]),
+            status: string(&identity["status"]),
+            primary_addresses: strings(&identity["identity"]["primaryaddresses"]),
+            json: identity.to_string(),
+        }))
+    }
+
+    async fn get_currency(&self, request: Request<proto::GetCurrencyRequest>) -> Result<Response<proto::Currency>, Status> {
+        let caller = self.admit(&request).map_err(refused)?;
+        let currency = self.handle(&caller, "getcurrency", json!([request.into_inner().name])).await.map_err(status)?;
+        Ok(Response::new(proto::Currency {
+            name: currency["fullyqualifiedname"].as_str().map(str::to_string).unwrap_or_else(|| string(&currency["name"])),
+            currency_id: string(&currency["currencyid"]),
+            options: currency["options"].as_u64().unwrap_or_default(),
+            reserve_currencies: strings(&currency["currencies"]),
+            json: currency.to_string(),
+        }))
+    }
+
+    async fn get_address_balance(&self, request: Request<proto::AddressRequest>) -> Result<Response<proto::AddressBalance>, Status> {
+        let caller = self.admit(&request).map_err(refused)?;
+        let addresses = request.into_inner().addresses;
+        let balance = self.handle(&caller, "getaddressbalance", json!([{ "addresses": addresses }])).await.map_err(status)?;
+        Ok(Response::new(proto::AddressBalance {
+            balance: balance["balance"].as_i64().unwrap_or_default(),
+            received: balance["received"].as_i64().unwrap_or_default(),
+            json: balance.to_string(),
+        }))
+    }
+}
+
+pub async fn serve(rpc: Arc<VerusRPC>, addr: std::net::SocketAddr, profile: Profile) {
+    let service = VerusServer::new(Arc::new(Service { rpc, profile }));
+    if let Err(e) = tonic::transport::Server::builder().add_service(service).serve(addr).await {
+        eprintln!("gRPC server error: {}", e);
+    }
+}
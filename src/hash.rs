@@ -0,0 +1,48 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+const DEFAULT_PERSONAL: &[u8; 16] = b"VerusDefaultHash";
+
+// The daemon keeps hash results in a uint256 and prints it with GetHex, which
+// writes the bytes in reverse order.
+fn uint256_hex(mut hash: Vec<u8>) -> String {
+    hash.reverse();
+    hex::encode(hash)
+}
+
+// The txid of a serialized transaction: its double SHA-256, as the daemon
+// prints it.
+pub fn txid(raw: &[u8]) -> String {
+    uint256_hex(Sha256::digest(Sha256::digest(raw)).to_vec())
+}
+
+// Local hashdata for the hash types that are plain digests, with the daemon's
+// defaults and byte order. Returns None for anything it can't answer exactly
+// (the VerusHash variants, unusual personal strings, malformed hex) so the call
+// goes to the daemon instead.
+pub fn hashdata(params: &[Value]) -> Option<Value> {
+    let data = hex::decode(params.first()?.as_str()?).ok()?;
+    let hash_type = match params.get(1) {
+        Some(hash_type) => hash_type.as_str()?,
+        None => "sha256",
+    };
+    let personal = match params.get(2) {
+        Some(personal) => personal.as_str()?.as_bytes(),
+        None => DEFAULT_PERSONAL,
+    };
+    let hash = match hash_type {
+        "sha256" => Sha256::digest(&data).to_vec(),
+        "sha256rev" => Sha256::digest(&data).iter().rev().copied().collect(),
+        "sha256D" => Sha256::digest(Sha256::digest(&data)).to_vec(),
+        "keccak256" => Keccak256::digest(&data).to_vec(),
+        "blake2b" if personal.len() == 16 => blake2b_simd::Params::new()
+            .hash_length(32)
+            .personal(personal)
+            .hash(&data)
+            .as_bytes()
+            .to_vec(),
+        _ => return None,
+    };
+    Some(Value::String(uint256_hex(hash)))
+}
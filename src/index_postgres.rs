@@ -0,0 +1,420 @@
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use tokio::sync::Mutex;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::{Client, IsolationLevel, NoTls, Transaction};
+
+use crate::indexer::{self, IndexStore, RowSink, BACKFILL_HEARTBEAT_SECS, unix_time};
+
+// Index in a PostgreSQL database, which several proxies can share.
+pub struct PostgresIndex {
+    client: Mutex<Client>,
+}
+
+type PgResult<T> = Result<T, tokio_postgres::Error>;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS index_state (
+        name TEXT PRIMARY KEY,
+        height BIGINT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS identities (
+        identity TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        parent TEXT NOT NULL,
+        height BIGINT NOT NULL,
+        txid TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS identity_content (
+        identity TEXT NOT NULL,
+        vdxfkey TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        value TEXT NOT NULL,
+        height BIGINT NOT NULL,
+        txid TEXT NOT NULL,
+        PRIMARY KEY (identity, vdxfkey, kind)
+    );
+    CREATE INDEX IF NOT EXISTS identity_content_key ON identity_content (vdxfkey, identity);
+    CREATE TABLE IF NOT EXISTS identity_content_history (
+        id BIGSERIAL PRIMARY KEY,
+        identity TEXT NOT NULL,
+        vdxfkey TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        value TEXT,
+        height BIGINT NOT NULL,
+        txid TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS identity_content_history_identity ON identity_content_history (identity, vdxfkey, height);
+    CREATE TABLE IF NOT EXISTS identity_updates (
+        id BIGSERIAL PRIMARY KEY,
+        identity TEXT NOT NULL,
+        height BIGINT NOT NULL,
+        txid TEXT NOT NULL,
+        state TEXT NOT NULL,
+        changes TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS identity_updates_identity ON identity_updates (identity, height);
+    CREATE TABLE IF NOT EXISTS outputs (
+        txid TEXT NOT NULL,
+        n BIGINT NOT NULL,
+        address TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        amount BIGINT NOT NULL,
+        PRIMARY KEY (txid, n, address, currency)
+    );
+    CREATE TABLE IF NOT EXISTS balances (
+        address TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        balance BIGINT NOT NULL,
+        received BIGINT NOT NULL,
+        PRIMARY KEY (address, currency)
+    );
+    CREATE INDEX IF NOT EXISTS balances_rich ON balances (currency, balance DESC);
+    CREATE TABLE IF NOT EXISTS conversion_volume (
+        day BIGINT NOT NULL,
+        source TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        volume BIGINT NOT NULL,
+        count BIGINT NOT NULL,
+        PRIMARY KEY (day, source, destination)
+    );";
+
+async fn state(client: &impl tokio_postgres::GenericClient, name: &str) -> PgResult<Option<i64>> {
+    Ok(client.query_opt("SELECT height FROM index_state WHERE name = $1", &[&name]).await?.map(|row| row.get(0)))
+}
+
+async fn set_state(db: &Transaction<'_>, name: &str, height: i64) -> PgResult<()> {
+    db.execute(
+        "INSERT INTO index_state (name, height) VALUES ($1, $2) ON CONFLICT (name) DO UPDATE SET height = EXCLUDED.height",
+        &[&name, &height],
+    ).await?;
+    Ok(())
+}
+
+// Records a new state of an identity: appends it with the fields it changed
+// to the updates, replaces its current content and appends the content
+// changes to the history.
+async fn index_identity(db: &Transaction<'_>, identity: &Value, height: i64, txid: &str) -> PgResult<()> {
+    let address = match identity["identityaddress"].as_str() {
+        Some(address) => address,
+        None => return Ok(()),
+    };
+    db.execute(
+        "INSERT INTO identities (identity, name, parent, height, txid) VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (identity) DO UPDATE SET name = EXCLUDED.name, parent = EXCLUDED.parent, height = EXCLUDED.height, txid = EXCLUDED.txid",
+        &[&address, &identity["name"].as_str().unwrap_or_default(), &identity["parent"].as_str().unwrap_or_default(), &height, &txid],
+    ).await?;
+
+    let state: Option<String> = db
+        .query_opt("SELECT state FROM identity_updates WHERE identity = $1 ORDER BY height DESC, id DESC LIMIT 1", &[&address]).await?
+        .map(|row| row.get(0));
+    let changes = indexer::identity_changes(state.as_deref(), identity);
+    if changes.as_object().is_some_and(|changes| !changes.is_empty()) {
+        db.execute(
+            "INSERT INTO identity_updates (identity, height, txid, state, changes) VALUES ($1, $2, $3, $4, $5)",
+            &[&address, &height, &txid, &identity.to_string(), &changes.to_string()],
+        ).await?;
+    }
+
+    let entries = indexer::content_entries(identity);
+    let previous: Vec<(String, String, String)> = db
+        .query("SELECT vdxfkey, kind, value FROM identity_content WHERE identity = $1", &[&address]).await?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    for (key, kind, value) in indexer::content_changes(&previous, &entries) {
+        db.execute(
+            "INSERT INTO identity_content_history (identity, vdxfkey, kind, value, height, txid) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&address, &key, &kind, &value, &height, &txid],
+        ).await?;
+    }
+
+    db.execute("DELETE FROM identity_content WHERE identity = $1", &[&address]).await?;
+    for (key, kind, value) in &entries {
+        db.execute(
+            "INSERT INTO identity_content (identity, vdxfkey, kind, value, height, txid) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&address, key, kind, value, &height, &txid],
+        ).await?;
+    }
+    Ok(())
+}
+
+// Debits the output an input spends.
+async fn spend(db: &Transaction<'_>, input: &Value) -> PgResult<()> {
+    let (txid, n, fallback) = match indexer::spent_output(input) {
+        Some(spent) => spent,
+        None => return Ok(()),
+    };
+    let mut spent: Vec<(String, String, i64)> = db
+        .query("DELETE FROM outputs WHERE txid = $1 AND n = $2 RETURNING address, currency, amount", &[&txid, &n]).await?
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    if spent.is_empty() {
+        spent.extend(fallback);
+    }
+    for (address, currency, amount) in spent {
+        db.execute("UPDATE balances SET balance = balance - $3 WHERE address = $1 AND currency = $2", &[&address, &currency, &amount]).await?;
+    }
+    Ok(())
+}
+
+async fn receive(db: &Transaction<'_>, txid: &str, n: i64, output: &Value) -> PgResult<()> {
+    for (address, currency, amount) in indexer::output_amounts(output) {
+        db.execute(
+            "INSERT INTO outputs (txid, n, address, currency, amount) VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+            &[&txid, &n, &address, &currency, &amount],
+        ).await?;
+        db.execute(
+            "INSERT INTO balances (address, currency, balance, received) VALUES ($1, $2, $3, $3)
+             ON CONFLICT (address, currency) DO UPDATE SET balance = balances.balance + $3, received = balances.received + $3",
+            &[&address, &currency, &amount],
+        ).await?;
+    }
+    Ok(())
+}
+
+impl PostgresIndex {
+    pub async fn connect(url: &str) -> PgResult<PostgresIndex> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("index database connection failed: {}", e);
+            }
+        });
+        client.batch_execute(SCHEMA).await?;
+        Ok(PostgresIndex { client: Mutex::new(client) })
+    }
+
+    async fn index_block(&self, height: u64, block: &Value) -> PgResult<bool> {
+        let height = height as i64;
+        let mut client = self.client.lock().await;
+        let db = client.transaction().await?;
+        // Serializes writers sharing the database, like SQLite's write lock.
+        db.batch_execute("LOCK TABLE index_state IN EXCLUSIVE MODE").await?;
+        if state(&db, "blocks").await?.is_some_and(|last| last + 1 != height) {
+            return Ok(false);
+        }
+        let day = indexer::block_day(block);
+        for tx in block["tx"].as_array().into_iter().flatten() {
+            let txid = tx["txid"].as_str().unwrap_or_default();
+            for input in tx["vin"].as_array().into_iter().flatten() {
+                spend(&db, input).await?;
+            }
+            for (source, destination, amount) in indexer::conversions(tx) {
+                db.execute(
+                    "INSERT INTO conversion_volume (day, source, destination, volume, count) VALUES ($1, $2, $3, $4, 1)
+                     ON CONFLICT (day, source, destination) DO UPDATE SET volume = conversion_volume.volume + $4, count = conversion_volume.count + 1",
+                    &[&day, &source, &destination, &amount],
+                ).await?;
+            }
+            for (n, output) in tx["vout"].as_array().into_iter().flatten().enumerate() {
+                let identity = &output["scriptPubKey"]["identityprimary"];
+                if identity.is_object() {
+                    index_identity(&db, identity, height, txid).await?;
+                }
+                receive(&db, txid, output["n"].as_i64().unwrap_or(n as i64), output).await?;
+            }
+        }
+        db.execute("INSERT INTO index_state (name, height) VALUES ('start', $1) ON CONFLICT DO NOTHING", &[&height]).await?;
+        set_state(&db, "blocks", height).await?;
+        db.commit().await?;
+        Ok(true)
+    }
+
+    async fn reset(&self, start: u64) -> PgResult<()> {
+        let mut client = self.client.lock().await;
+        let db = client.transaction().await?;
+        db.batch_execute(
+            "LOCK TABLE index_state IN EXCLUSIVE MODE;
+             TRUNCATE identities, identity_content, identity_content_history, identity_updates, outputs, balances, conversion_volume;
+             DELETE FROM index_state WHERE name <> 'backfill';",
+        ).await?;
+        set_state(&db, "start", start as i64).await?;
+        set_state(&db, "blocks", start as i64 - 1).await?;
+        db.commit().await
+    }
+
+    async fn import(&self, table: &str, columns: &[&str], rows: &[Vec<Value>]) -> PgResult<()> {
+        let mut client = self.client.lock().await;
+        let db = client.transaction().await?;
+        let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("${}", i)).collect();
+        let conflict = match table {
+            "index_state" => " ON CONFLICT (name) DO UPDATE SET height = EXCLUDED.height",
+            _ => "",
+        };
+        let insert = db.prepare(&format!("INSERT INTO {} ({}) VALUES ({}){}", table, columns.join(", "), placeholders.join(", "), conflict)).await?;
+        for row in rows {
+            let values: Vec<Box<dyn ToSql + Sync + Send>> = row
+                .iter()
+                .map(|value| -> Box<dyn ToSql + Sync + Send> {
+                    match value {
+                        Value::Number(n) => Box::new(n.as_i64()),
+                        Value::String(text) => Box::new(text.clone()),
+                        _ => Box::new(None::<String>),
+                    }
+                })
+                .collect();
+            db.execute_raw(&insert, values.iter().map(|value| value.as_ref() as &(dyn ToSql + Sync))).await?;
+        }
+        db.commit().await
+    }
+}
+
+fn text(e: tokio_postgres::Error) -> String {
+    e.to_string()
+}
+
+#[async_trait]
+impl IndexStore for PostgresIndex {
+    async fn start_height(&self) -> Result<Option<u64>, String> {
+        let client = self.client.lock().await;
+        Ok(state(&*client, "start").await.map_err(text)?.map(|height| height as u64))
+    }
+
+    async fn height(&self) -> Result<Option<u64>, String> {
+        let client = self.client.lock().await;
+        Ok(state(&*client, "blocks").await.map_err(text)?.filter(|height| *height >= 0).map(|height| height as u64))
+    }
+
+    async fn reset(&self, start: u64) -> Result<(), String> {
+        PostgresIndex::reset(self, start).await.map_err(text)
+    }
+
+    async fn set_backfilling(&self, active: bool) -> Result<(), String> {
+        let client = self.client.lock().await;
+        match active {
+            true => client.execute(
+                "INSERT INTO index_state (name, height) VALUES ('backfill', $1) ON CONFLICT (name) DO UPDATE SET height = EXCLUDED.height",
+                &[&unix_time()],
+            ).await,
+            false => client.execute("DELETE FROM index_state WHERE name = 'backfill'", &[]).await,
+        }
+        .map(|_| ())
+        .map_err(text)
+    }
+
+    async fn is_backfilling(&self) -> Result<bool, String> {
+        let client = self.client.lock().await;
+        let reported = state(&*client, "backfill").await.map_err(text)?;
+        Ok(reported.is_some_and(|reported| unix_time() - reported < BACKFILL_HEARTBEAT_SECS))
+    }
+
+    async fn index_block(&self, height: u64, block: &Value) -> Result<bool, String> {
+        PostgresIndex::index_block(self, height, block).await.map_err(text)
+    }
+
+    async fn identities_with_key(&self, vdxfkey: &str, start: u64, count: u64) -> Result<Vec<Value>, String> {
+        let client = self.client.lock().await;
+        let rows = client.query(
+            "SELECT c.identity, i.name, i.parent, c.kind, c.value, c.height, c.txid
+             FROM identity_content c JOIN identities i ON i.identity = c.identity
+             WHERE c.vdxfkey = $1 ORDER BY c.identity LIMIT $2 OFFSET $3",
+            &[&vdxfkey, &(count as i64), &(start as i64)],
+        ).await.map_err(text)?;
+        Ok(rows.iter().map(|row| json!({
+            "identity": row.get::<_, String>(0),
+            "name": row.get::<_, String>(1),
+            "parent": row.get::<_, String>(2),
+            "kind": row.get::<_, String>(3),
+            "value": serde_json::from_str::<Value>(row.get(4)).unwrap_or(Value::Null),
+            "height": row.get::<_, i64>(5),
+            "txid": row.get::<_, String>(6),
+        })).collect())
+    }
+
+    async fn content_history(&self, identity: &str, vdxfkey: Option<&str>, start: u64, count: u64) -> Result<Vec<Value>, String> {
+        let client = self.client.lock().await;
+        let rows = client.query(
+            "SELECT vdxfkey, kind, value, height, txid FROM identity_content_history
+             WHERE identity = $1 AND ($2::TEXT IS NULL OR vdxfkey = $2)
+             ORDER BY height DESC, id DESC LIMIT $3 OFFSET $4",
+            &[&identity, &vdxfkey, &(count as i64), &(start as i64)],
+        ).await.map_err(text)?;
+        Ok(rows.iter().map(|row| json!({
+            "vdxfkey": row.get::<_, String>(0),
+            "kind": row.get::<_, String>(1),
+            "value": row.get::<_, Option<&str>>(2).and_then(|value| serde_json::from_str::<Value>(value).ok()),
+            "height": row.get::<_, i64>(3),
+            "txid": row.get::<_, String>(4),
+        })).collect())
+    }
+
+    async fn identity_history(&self, identity: &str, start: u64, count: u64) -> Result<Vec<Value>, String> {
+        let client = self.client.lock().await;
+        let rows = client.query(
+            "SELECT height, txid, changes FROM identity_updates WHERE identity = $1
+             ORDER BY height DESC, id DESC LIMIT $2 OFFSET $3",
+            &[&identity, &(count as i64), &(start as i64)],
+        ).await.map_err(text)?;
+        Ok(rows.iter().map(|row| indexer::identity_update_json(row.get(0), row.get(1), row.get(2))).collect())
+    }
+
+    async fn balance(&self, address: &str) -> Result<serde_json::Map<String, Value>, String> {
+        let client = self.client.lock().await;
+        let rows = client.query(
+            "SELECT currency, balance, received FROM balances WHERE address = $1 ORDER BY currency",
+            &[&address],
+        ).await.map_err(text)?;
+        Ok(rows.iter().map(|row| (row.get(0), indexer::balance_json(row.get(1), row.get(2)))).collect())
+    }
+
+    async fn rich_list(&self, currency: &str, start: u64, count: u64) -> Result<Vec<Value>, String> {
+        let client = self.client.lock().await;
+        let rows = client.query(
+            "SELECT address, balance, received FROM balances WHERE currency = $1 AND balance > 0
+             ORDER BY balance DESC LIMIT $2 OFFSET $3",
+            &[&currency, &(count as i64), &(start as i64)],
+        ).await.map_err(text)?;
+        Ok(rows.iter().map(|row| {
+            let mut holder = indexer::balance_json(row.get(1), row.get(2));
+            holder["address"] = json!(row.get::<_, String>(0));
+            holder
+        }).collect())
+    }
+
+    async fn conversion_volume(&self, source: Option<&str>, destination: Option<&str>, from_day: i64, to_day: i64) -> Result<Vec<Value>, String> {
+        let client = self.client.lock().await;
+        let rows = client.query(
+            "SELECT day, source, destination, volume, count FROM conversion_volume
+             WHERE day BETWEEN $1 AND $2 AND ($3::TEXT IS NULL OR source = $3) AND ($4::TEXT IS NULL OR destination = $4)
+             ORDER BY day, source, destination LIMIT $5",
+            &[&from_day, &to_day, &source, &destination, &(indexer::MAX_PAGE as i64)],
+        ).await.map_err(text)?;
+        Ok(rows.iter().map(|row| indexer::volume_json(row.get(0), row.get(1), row.get(2), row.get(3), row.get(4))).collect())
+    }
+
+    async fn export(&self, out: &mut RowSink<'_>) -> Result<(), String> {
+        let mut client = self.client.lock().await;
+        let db = client.build_transaction().isolation_level(IsolationLevel::RepeatableRead).read_only(true).start().await.map_err(text)?;
+        for (table, columns) in indexer::SNAPSHOT_TABLES {
+            let query = match *table {
+                "index_state" => format!("SELECT {} FROM index_state WHERE name <> 'backfill'", columns.join(", ")),
+                "identity_content_history" | "identity_updates" => format!("SELECT {} FROM {} ORDER BY id", columns.join(", "), table),
+                _ => format!("SELECT {} FROM {}", columns.join(", "), table),
+            };
+            let portal = db.bind(query.as_str(), &[]).await.map_err(text)?;
+            loop {
+                let rows = db.query_portal(&portal, 1000).await.map_err(text)?;
+                if rows.is_empty() {
+                    break;
+                }
+                for row in rows {
+                    let values = (0..columns.len())
+                        .map(|i| match *row.columns()[i].type_() {
+                            Type::INT8 => json!(row.get::<_, Option<i64>>(i)),
+                            _ => json!(row.get::<_, Option<String>>(i)),
+                        })
+                        .collect();
+                    out(table, values)?;
+                }
+            }
+        }
+        db.commit().await.map_err(text)
+    }
+
+    async fn import(&self, table: &str, rows: &[Vec<Value>]) -> Result<(), String> {
+        let columns = indexer::snapshot_columns(table)?;
+        PostgresIndex::import(self, table, columns, rows).await.map_err(text)
+    }
+}
@@ -0,0 +1,405 @@
+use async_trait::async_trait;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior, params};
+use serde_json::{Value, json};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::indexer::{self, IndexStore, RowSink, BACKFILL_HEARTBEAT_SECS, unix_time};
+
+// Index in a SQLite file next to the proxy.
+pub struct SqliteIndex {
+    conn: Mutex<Connection>,
+}
+
+fn state(conn: &Connection, name: &str) -> rusqlite::Result<Option<i64>> {
+    conn.query_row("SELECT height FROM index_state WHERE name = ?1", params![name], |row| row.get(0)).optional()
+}
+
+// Records a new state of an identity: appends it with the fields it changed
+// to the updates, replaces its current content and appends the content
+// changes to the history.
+fn index_identity(db: &Transaction, identity: &Value, height: u64, txid: &str) -> rusqlite::Result<()> {
+    let address = match identity["identityaddress"].as_str() {
+        Some(address) => address,
+        None => return Ok(()),
+    };
+    db.execute(
+        "INSERT OR REPLACE INTO identities (identity, name, parent, height, txid) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![address, identity["name"].as_str().unwrap_or_default(), identity["parent"].as_str().unwrap_or_default(), height as i64, txid],
+    )?;
+
+    let state: Option<String> = db
+        .query_row("SELECT state FROM identity_updates WHERE identity = ?1 ORDER BY height DESC, rowid DESC LIMIT 1", params![address], |row| row.get(0))
+        .optional()?;
+    let changes = indexer::identity_changes(state.as_deref(), identity);
+    if changes.as_object().is_some_and(|changes| !changes.is_empty()) {
+        db.execute(
+            "INSERT INTO identity_updates (identity, height, txid, state, changes) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![address, height as i64, txid, identity.to_string(), changes.to_string()],
+        )?;
+    }
+
+    let entries = indexer::content_entries(identity);
+    let previous: Vec<(String, String, String)> = db
+        .prepare("SELECT vdxfkey, kind, value FROM identity_content WHERE identity = ?1")?
+        .query_map(params![address], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut history = db.prepare("INSERT INTO identity_content_history (identity, vdxfkey, kind, value, height, txid) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+    for (key, kind, value) in indexer::content_changes(&previous, &entries) {
+        history.execute(params![address, key, kind, value, height as i64, txid])?;
+    }
+
+    db.execute("DELETE FROM identity_content WHERE identity = ?1", params![address])?;
+    let mut current = db.prepare("INSERT INTO identity_content (identity, vdxfkey, kind, value, height, txid) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+    for (key, kind, value) in &entries {
+        current.execute(params![address, key, kind, value, height as i64, txid])?;
+    }
+    Ok(())
+}
+
+// Debits the output an input spends.
+fn spend(db: &Transaction, input: &Value) -> rusqlite::Result<()> {
+    let (txid, n, fallback) = match indexer::spent_output(input) {
+        Some(spent) => spent,
+        None => return Ok(()),
+    };
+    let mut spent: Vec<(String, String, i64)> = db
+        .prepare("SELECT address, currency, amount FROM outputs WHERE txid = ?1 AND n = ?2")?
+        .query_map(params![txid, n], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    if spent.is_empty() {
+        spent.extend(fallback);
+    }
+    db.execute("DELETE FROM outputs WHERE txid = ?1 AND n = ?2", params![txid, n])?;
+    let mut debit = db.prepare("UPDATE balances SET balance = balance - ?3 WHERE address = ?1 AND currency = ?2")?;
+    for (address, currency, amount) in spent {
+        debit.execute(params![address, currency, amount])?;
+    }
+    Ok(())
+}
+
+fn receive(db: &Transaction, txid: &str, n: i64, output: &Value) -> rusqlite::Result<()> {
+    let mut insert = db.prepare("INSERT OR IGNORE INTO outputs (txid, n, address, currency, amount) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    let mut credit = db.prepare(
+        "INSERT INTO balances (address, currency, balance, received) VALUES (?1, ?2, ?3, ?3)
+         ON CONFLICT (address, currency) DO UPDATE SET balance = balance + ?3, received = received + ?3",
+    )?;
+    for (address, currency, amount) in indexer::output_amounts(output) {
+        insert.execute(params![txid, n, address, currency, amount])?;
+        credit.execute(params![address, currency, amount])?;
+    }
+    Ok(())
+}
+
+impl SqliteIndex {
+    pub fn open(path: &str) -> rusqlite::Result<SqliteIndex> {
+        let conn = Connection::open(path)?;
+        // The proxy and a backfill run from the command line share the file.
+        conn.busy_timeout(Duration::from_secs(30))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS index_state (
+                 name TEXT PRIMARY KEY,
+                 height INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS identities (
+                 identity TEXT PRIMARY KEY,
+                 name TEXT NOT NULL,
+                 parent TEXT NOT NULL,
+                 height INTEGER NOT NULL,
+                 txid TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS identity_content (
+                 identity TEXT NOT NULL,
+                 vdxfkey TEXT NOT NULL,
+                 kind TEXT NOT NULL,
+                 value TEXT NOT NULL,
+                 height INTEGER NOT NULL,
+                 txid TEXT NOT NULL,
+                 PRIMARY KEY (identity, vdxfkey, kind)
+             );
+             CREATE INDEX IF NOT EXISTS identity_content_key ON identity_content (vdxfkey, identity);
+             CREATE TABLE IF NOT EXISTS identity_content_history (
+                 identity TEXT NOT NULL,
+                 vdxfkey TEXT NOT NULL,
+                 kind TEXT NOT NULL,
+                 value TEXT,
+                 height INTEGER NOT NULL,
+                 txid TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS identity_content_history_identity ON identity_content_history (identity, vdxfkey, height);
+             CREATE TABLE IF NOT EXISTS identity_updates (
+                 identity TEXT NOT NULL,
+                 height INTEGER NOT NULL,
+                 txid TEXT NOT NULL,
+                 state TEXT NOT NULL,
+                 changes TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS identity_updates_identity ON identity_updates (identity, height);
+             CREATE TABLE IF NOT EXISTS outputs (
+                 txid TEXT NOT NULL,
+                 n INTEGER NOT NULL,
+                 address TEXT NOT NULL,
+                 currency TEXT NOT NULL,
+                 amount INTEGER NOT NULL,
+                 PRIMARY KEY (txid, n, address, currency)
+             );
+             CREATE TABLE IF NOT EXISTS balances (
+                 address TEXT NOT NULL,
+                 currency TEXT NOT NULL,
+                 balance INTEGER NOT NULL,
+                 received INTEGER NOT NULL,
+                 PRIMARY KEY (address, currency)
+             );
+             CREATE INDEX IF NOT EXISTS balances_rich ON balances (currency, balance DESC);
+             CREATE TABLE IF NOT EXISTS conversion_volume (
+                 day INTEGER NOT NULL,
+                 source TEXT NOT NULL,
+                 destination TEXT NOT NULL,
+                 volume INTEGER NOT NULL,
+                 count INTEGER NOT NULL,
+                 PRIMARY KEY (day, source, destination)
+             );",
+        )?;
+        Ok(SqliteIndex { conn: Mutex::new(conn) })
+    }
+
+    fn index_block(&self, height: u64, block: &Value) -> rusqlite::Result<bool> {
+        let mut conn = self.conn.lock().unwrap();
+        let db = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if state(&db, "blocks")?.is_some_and(|last| last + 1 != height as i64) {
+            return Ok(false);
+        }
+        let day = indexer::block_day(block);
+        for tx in block["tx"].as_array().into_iter().flatten() {
+            let txid = tx["txid"].as_str().unwrap_or_default();
+            for input in tx["vin"].as_array().into_iter().flatten() {
+                spend(&db, input)?;
+            }
+            for (source, destination, amount) in indexer::conversions(tx) {
+                db.execute(
+                    "INSERT INTO conversion_volume (day, source, destination, volume, count) VALUES (?1, ?2, ?3, ?4, 1)
+                     ON CONFLICT (day, source, destination) DO UPDATE SET volume = volume + ?4, count = count + 1",
+                    params![day, source, destination, amount],
+                )?;
+            }
+            for (n, output) in tx["vout"].as_array().into_iter().flatten().enumerate() {
+                let identity = &output["scriptPubKey"]["identityprimary"];
+                if identity.is_object() {
+                    index_identity(&db, identity, height, txid)?;
+                }
+                receive(&db, txid, output["n"].as_i64().unwrap_or(n as i64), output)?;
+            }
+        }
+        db.execute("INSERT OR IGNORE INTO index_state (name, height) VALUES ('start', ?1)", params![height as i64])?;
+        db.execute("INSERT OR REPLACE INTO index_state (name, height) VALUES ('blocks', ?1)", params![height as i64])?;
+        db.commit()?;
+        Ok(true)
+    }
+
+    fn reset(&self, start: u64) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let db = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        db.execute_batch(
+            "DELETE FROM identities; DELETE FROM identity_content; DELETE FROM identity_content_history; DELETE FROM identity_updates;
+             DELETE FROM outputs; DELETE FROM balances; DELETE FROM conversion_volume; DELETE FROM index_state WHERE name <> 'backfill';",
+        )?;
+        db.execute("INSERT INTO index_state (name, height) VALUES ('start', ?1)", params![start as i64])?;
+        db.execute("INSERT INTO index_state (name, height) VALUES ('blocks', ?1)", params![start as i64 - 1])?;
+        db.commit()
+    }
+
+    fn identities_with_key(&self, vdxfkey: &str, start: u64, count: u64) -> rusqlite::Result<Vec<Value>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT c.identity, i.name, i.parent, c.kind, c.value, c.height, c.txid
+             FROM identity_content c JOIN identities i ON i.identity = c.identity
+             WHERE c.vdxfkey = ?1 ORDER BY c.identity LIMIT ?2 OFFSET ?3",
+        )?;
+        let rows = query.query_map(params![vdxfkey, count as i64, start as i64], |row| {
+            Ok(json!({
+                "identity": row.get::<_, String>(0)?,
+                "name": row.get::<_, String>(1)?,
+                "parent": row.get::<_, String>(2)?,
+                "kind": row.get::<_, String>(3)?,
+                "value": serde_json::from_str::<Value>(&row.get::<_, String>(4)?).unwrap_or(Value::Null),
+                "height": row.get::<_, i64>(5)?,
+                "txid": row.get::<_, String>(6)?,
+            }))
+        })?;
+        rows.collect()
+    }
+
+    fn content_history(&self, identity: &str, vdxfkey: Option<&str>, start: u64, count: u64) -> rusqlite::Result<Vec<Value>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT vdxfkey, kind, value, height, txid FROM identity_content_history
+             WHERE identity = ?1 AND (?2 IS NULL OR vdxfkey = ?2)
+             ORDER BY height DESC, rowid DESC LIMIT ?3 OFFSET ?4",
+        )?;
+        let rows = query.query_map(params![identity, vdxfkey, count as i64, start as i64], |row| {
+            Ok(json!({
+                "vdxfkey": row.get::<_, String>(0)?,
+                "kind": row.get::<_, String>(1)?,
+                "value": row.get::<_, Option<String>>(2)?.and_then(|value| serde_json::from_str::<Value>(&value).ok()),
+                "height": row.get::<_, i64>(3)?,
+                "txid": row.get::<_, String>(4)?,
+            }))
+        })?;
+        rows.collect()
+    }
+
+    fn identity_history(&self, identity: &str, start: u64, count: u64) -> rusqlite::Result<Vec<Value>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT height, txid, changes FROM identity_updates WHERE identity = ?1
+             ORDER BY height DESC, rowid DESC LIMIT ?2 OFFSET ?3",
+        )?;
+        let rows = query.query_map(params![identity, count as i64, start as i64], |row| {
+            Ok(indexer::identity_update_json(row.get(0)?, row.get(1)?, &row.get::<_, String>(2)?))
+        })?;
+        rows.collect()
+    }
+
+    fn balance(&self, address: &str) -> rusqlite::Result<serde_json::Map<String, Value>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare("SELECT currency, balance, received FROM balances WHERE address = ?1 ORDER BY currency")?;
+        let rows = query.query_map(params![address], |row| Ok((row.get(0)?, indexer::balance_json(row.get(1)?, row.get(2)?))))?;
+        rows.collect()
+    }
+
+    fn rich_list(&self, currency: &str, start: u64, count: u64) -> rusqlite::Result<Vec<Value>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT address, balance, received FROM balances WHERE currency = ?1 AND balance > 0
+             ORDER BY balance DESC LIMIT ?2 OFFSET ?3",
+        )?;
+        let rows = query.query_map(params![currency, count as i64, start as i64], |row| {
+            let mut holder = indexer::balance_json(row.get(1)?, row.get(2)?);
+            holder["address"] = json!(row.get::<_, String>(0)?);
+            Ok(holder)
+        })?;
+        rows.collect()
+    }
+
+    fn conversion_volume(&self, source: Option<&str>, destination: Option<&str>, from_day: i64, to_day: i64) -> rusqlite::Result<Vec<Value>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT day, source, destination, volume, count FROM conversion_volume
+             WHERE day BETWEEN ?1 AND ?2 AND (?3 IS NULL OR source = ?3) AND (?4 IS NULL OR destination = ?4)
+             ORDER BY day, source, destination LIMIT ?5",
+        )?;
+        let rows = query.query_map(params![from_day, to_day, source, destination, indexer::MAX_PAGE as i64], |row| {
+            Ok(indexer::volume_json(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+        })?;
+        rows.collect()
+    }
+
+    fn import(&self, table: &str, columns: &[&str], rows: &[Vec<Value>]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let db = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        {
+            let placeholders = vec!["?"; columns.len()].join(", ");
+            let mut insert = db.prepare(&format!("INSERT OR REPLACE INTO {} ({}) VALUES ({})", table, columns.join(", "), placeholders))?;
+            for row in rows {
+                let values = row.iter().map(|value| match value {
+                    Value::Number(n) => SqlValue::Integer(n.as_i64().unwrap_or_default()),
+                    Value::String(text) => SqlValue::Text(text.clone()),
+                    _ => SqlValue::Null,
+                });
+                insert.execute(rusqlite::params_from_iter(values))?;
+            }
+        }
+        db.commit()
+    }
+}
+
+// SQLite calls are quick local file access, so they run inline like the disk cache's.
+#[async_trait]
+impl IndexStore for SqliteIndex {
+    async fn start_height(&self) -> Result<Option<u64>, String> {
+        let conn = self.conn.lock().unwrap();
+        Ok(state(&conn, "start").map_err(|e| e.to_string())?.map(|height| height as u64))
+    }
+
+    async fn height(&self) -> Result<Option<u64>, String> {
+        let conn = self.conn.lock().unwrap();
+        Ok(state(&conn, "blocks").map_err(|e| e.to_string())?.filter(|height| *height >= 0).map(|height| height as u64))
+    }
+
+    async fn reset(&self, start: u64) -> Result<(), String> {
+        SqliteIndex::reset(self, start).map_err(|e| e.to_string())
+    }
+
+    async fn set_backfilling(&self, active: bool) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        match active {
+            true => conn.execute("INSERT OR REPLACE INTO index_state (name, height) VALUES ('backfill', ?1)", params![unix_time()]),
+            false => conn.execute("DELETE FROM index_state WHERE name = 'backfill'", []),
+        }
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    }
+
+    async fn is_backfilling(&self) -> Result<bool, String> {
+        let conn = self.conn.lock().unwrap();
+        let reported = state(&conn, "backfill").map_err(|e| e.to_string())?;
+        Ok(reported.is_some_and(|reported| unix_time() - reported < BACKFILL_HEARTBEAT_SECS))
+    }
+
+    async fn index_block(&self, height: u64, block: &Value) -> Result<bool, String> {
+        SqliteIndex::index_block(self, height, block).map_err(|e| e.to_string())
+    }
+
+    async fn identities_with_key(&self, vdxfkey: &str, start: u64, count: u64) -> Result<Vec<Value>, String> {
+        SqliteIndex::identities_with_key(self, vdxfkey, start, count).map_err(|e| e.to_string())
+    }
+
+    async fn content_history(&self, identity: &str, vdxfkey: Option<&str>, start: u64, count: u64) -> Result<Vec<Value>, String> {
+        SqliteIndex::content_history(self, identity, vdxfkey, start, count).map_err(|e| e.to_string())
+    }
+
+    async fn identity_history(&self, identity: &str, start: u64, count: u64) -> Result<Vec<Value>, String> {
+        SqliteIndex::identity_history(self, identity, start, count).map_err(|e| e.to_string())
+    }
+
+    async fn balance(&self, address: &str) -> Result<serde_json::Map<String, Value>, String> {
+        SqliteIndex::balance(self, address).map_err(|e| e.to_string())
+    }
+
+    async fn rich_list(&self, currency: &str, start: u64, count: u64) -> Result<Vec<Value>, String> {
+        SqliteIndex::rich_list(self, currency, start, count).map_err(|e| e.to_string())
+    }
+
+    async fn conversion_volume(&self, source: Option<&str>, destination: Option<&str>, from_day: i64, to_day: i64) -> Result<Vec<Value>, String> {
+        SqliteIndex::conversion_volume(self, source, destination, from_day, to_day).map_err(|e| e.to_string())
+    }
+
+    async fn export(&self, out: &mut RowSink<'_>) -> Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let db = conn.transaction().map_err(|e| e.to_string())?;
+        for (table, columns) in indexer::SNAPSHOT_TABLES {
+            let filter = if *table == "index_state" { " WHERE name <> 'backfill'" } else { "" };
+            let mut query = db.prepare(&format!("SELECT {} FROM {}{} ORDER BY rowid", columns.join(", "), table, filter)).map_err(|e| e.to_string())?;
+            let mut rows = query.query([]).map_err(|e| e.to_string())?;
+            while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+                let mut values = Vec::with_capacity(columns.len());
+                for i in 0..columns.len() {
+                    values.push(match row.get::<_, SqlValue>(i).map_err(|e| e.to_string())? {
+                        SqlValue::Integer(n) => json!(n),
+                        SqlValue::Text(text) => json!(text),
+                        _ => Value::Null,
+                    });
+                }
+                out(table, values)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn import(&self, table: &str, rows: &[Vec<Value>]) -> Result<(), String> {
+        let columns = indexer::snapshot_columns(table)?;
+        SqliteIndex::import(self, table, columns, rows).map_err(|e| e.to_string())
+    }
+}
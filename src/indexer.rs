@@ -0,0 +1,374 @@
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::VerusRPC;
+#[cfg(feature = "postgres")]
+use crate::index_postgres::PostgresIndex;
+use crate::index_sqlite::SqliteIndex;
+use crate::limiter::Priority;
+use crate::upstream::Upstream;
+
+// Most rows a listing endpoint returns at once.
+pub const MAX_PAGE: u64 = 1000;
+
+// Satoshis per coin, for every currency.
+pub const COIN: f64 = 100_000_000.0;
+
+// A backfill that hasn't reported in this long is assumed to have died.
+pub const BACKFILL_HEARTBEAT_SECS: i64 = 60;
+
+pub fn unix_time() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Storage behind the index: SQLite embedded in the proxy, or PostgreSQL (with
+// the postgres feature) for explorers that share a database. A store may be
+// shared by several processes, e.g. the proxy and a backfill.
+//
+// It holds identity content (the current contentmap/contentmultimap entries
+// of every identity by VDXF key, and the history of each entry's value), the
+// balance of every address per currency, kept from the unspent outputs, and
+// the daily volume of conversions requested per currency pair.
+#[async_trait]
+pub trait IndexStore: Send + Sync {
+    // First block indexed. Balances are complete only when that is genesis.
+    async fn start_height(&self) -> Result<Option<u64>, String>;
+    // Last block indexed, if any.
+    async fn height(&self) -> Result<Option<u64>, String>;
+    // Drops everything indexed so indexing starts over at `start`.
+    async fn reset(&self, start: u64) -> Result<(), String>;
+    // A running backfill marks itself, refreshing the mark as it goes, so
+    // the proxy leaves the indexing to it.
+    async fn set_backfilling(&self, active: bool) -> Result<(), String>;
+    async fn is_backfilling(&self) -> Result<bool, String>;
+    // Indexes a block fetched with verbosity 2, all or nothing. Returns false
+    // without doing anything when it isn't the next block to index, which
+    // happens when another process sharing the store got there first.
+    async fn index_block(&self, height: u64, block: &Value) -> Result<bool, String>;
+    // Identities whose current content has `vdxfkey`, with its value.
+    async fn identities_with_key(&self, vdxfkey: &str, start: u64, count: u64) -> Result<Vec<Value>, String>;
+    // How the content of an identity changed, newest first, optionally for one key.
+    async fn content_history(&self, identity: &str, vdxfkey: Option<&str>, start: u64, count: u64) -> Result<Vec<Value>, String>;
+    // Every update of an identity seen by the index, newest first.
+    async fn identity_history(&self, identity: &str, start: u64, count: u64) -> Result<Vec<Value>, String>;
+    // Balance and total received of an address per currency.
+    async fn balance(&self, address: &str) -> Result<serde_json::Map<String, Value>, String>;
+    // Addresses holding the most of `currency`.
+    async fn rich_list(&self, currency: &str, start: u64, count: u64) -> Result<Vec<Value>, String>;
+    // Conversion volume per day and currency pair between two days (unix
+    // days), oldest first, optionally for one source and/or destination.
+    async fn conversion_volume(&self, source: Option<&str>, destination: Option<&str>, from_day: i64, to_day: i64) -> Result<Vec<Value>, String>;
+    // Passes every row of every table in SNAPSHOT_TABLES to `out`, all read
+    // from one consistent view of the index.
+    async fn export(&self, out: &mut RowSink<'_>) -> Result<(), String>;
+    // Adds rows exported from a store of either kind to `table`, replacing
+    // index_state entries of the same name.
+    async fn import(&self, table: &str, rows: &[Vec<Value>]) -> Result<(), String>;
+}
+
+// Receives the rows of an export as (table, row).
+pub type RowSink<'a> = dyn FnMut(&str, Vec<Value>) -> Result<(), String> + Send + 'a;
+
+// What a snapshot holds of the index: each table with its columns, in the
+// order they are written. index_state goes last so a store that is being
+// imported into doesn't claim to be further along than its contents.
+pub const SNAPSHOT_TABLES: &[(&str, &[&str])] = &[
+    ("identities", &["identity", "name", "parent", "height", "txid"]),
+    ("identity_content", &["identity", "vdxfkey", "kind", "value", "height", "txid"]),
+    ("identity_content_history", &["identity", "vdxfkey", "kind", "value", "height", "txid"]),
+    ("identity_updates", &["identity", "height", "txid", "state", "changes"]),
+    ("outputs", &["txid", "n", "address", "currency", "amount"]),
+    ("balances", &["address", "currency", "balance", "received"]),
+    ("conversion_volume", &["day", "source", "destination", "volume", "count"]),
+    ("index_state", &["name", "height"]),
+];
+
+pub fn snapshot_columns(table: &str) -> Result<&'static [&'static str], String> {
+    SNAPSHOT_TABLES.iter().find(|(name, _)| *name == table).map(|(_, columns)| *columns).ok_or_else(|| format!("Unknown index table {}", table))
+}
+
+// Local index of chain data the daemon can only answer with scans, built by
+// walking blocks once they are `depth` deep so reorgs rarely reach it.
+pub struct Indexer {
+    pub store: Box<dyn IndexStore>,
+    pub depth: u64,
+}
+
+impl Indexer {
+    // Opens the store named by index_backend: "sqlite" (index_path) or
+    // "postgres" (index_postgres_url).
+    pub async fn open(settings: &config::Config) -> Result<Option<Indexer>, String> {
+        let depth = settings.get::<u64>("index_min_confirmations").unwrap_or(10);
+        let store: Box<dyn IndexStore> = match settings.get_str("index_backend").unwrap_or_else(|_| "sqlite".to_string()).as_str() {
+            "sqlite" => match settings.get_str("index_path") {
+                Ok(path) => Box::new(SqliteIndex::open(&path).map_err(|e| format!("Failed to open index {}: {}", path, e))?),
+                Err(_) => return Ok(None),
+            },
+            #[cfg(feature = "postgres")]
+            "postgres" => {
+                let url = settings.get_str("index_postgres_url").map_err(|_| "index_postgres_url is not set".to_string())?;
+                Box::new(PostgresIndex::connect(&url).await.map_err(|e| format!("Failed to open the PostgreSQL index: {}", e))?)
+            },
+            backend => return Err(format!("Unsupported index_backend: {}", backend)),
+        };
+        Ok(Some(Indexer { store, depth }))
+    }
+}
+
+// The content entries of an identity as they stand after one update, keyed by
+// (vdxf key, "contentmap" or "contentmultimap").
+pub fn content_entries(identity: &Value) -> Vec<(String, &'static str, String)> {
+    let mut entries = Vec::new();
+    for kind in ["contentmap", "contentmultimap"] {
+        for (key, value) in identity[kind].as_object().into_iter().flatten() {
+            entries.push((key.clone(), kind, value.to_string()));
+        }
+    }
+    entries
+}
+
+// The history rows an identity update adds: every entry that changed, and a
+// null value for every entry it removed.
+pub fn content_changes(previous: &[(String, String, String)], entries: &[(String, &'static str, String)]) -> Vec<(String, String, Option<String>)> {
+    let mut changes = Vec::new();
+    for (key, kind, value) in entries {
+        if !previous.iter().any(|(k, d, v)| k == key && d == kind && v == value) {
+            changes.push((key.clone(), kind.to_string(), Some(value.clone())));
+        }
+    }
+    for (key, kind, _) in previous {
+        if !entries.iter().any(|(k, d, _)| k == key && d == kind) {
+            changes.push((key.clone(), kind.clone(), None));
+        }
+    }
+    changes
+}
+
+// Identity fields that decide who controls an identity.
+const AUTHORITY_FIELDS: &[&str] = &[
+    "primaryaddresses", "minimumsignatures", "revocationauthority", "recoveryauthority", "privateaddress", "flags", "timelock",
+];
+
+// The fields an identity update changed, as {field: {"from", "to"}}. The
+// first update the index sees has every field coming from null.
+pub fn identity_changes(previous: Option<&str>, identity: &Value) -> Value {
+    let previous: Value = previous.and_then(|state| serde_json::from_str(state).ok()).unwrap_or(Value::Null);
+    let mut changes = serde_json::Map::new();
+    let fields = identity.as_object().into_iter().flatten().map(|(field, _)| field);
+    let removed = previous.as_object().into_iter().flatten().map(|(field, _)| field).filter(|field| identity.get(field.as_str()).is_none());
+    for field in fields.chain(removed) {
+        let (from, to) = (&previous[field.as_str()], &identity[field.as_str()]);
+        if from != to {
+            changes.insert(field.clone(), json!({ "from": from, "to": to }));
+        }
+    }
+    Value::Object(changes)
+}
+
+// An identity_history entry from a stored update.
+pub fn identity_update_json(height: i64, txid: String, changes: &str) -> Value {
+    let changes: Value = serde_json::from_str(changes).unwrap_or_else(|_| json!({}));
+    let authority: Vec<&str> = AUTHORITY_FIELDS.iter().copied().filter(|field| changes.get(*field).is_some()).collect();
+    json!({
+        "height": height,
+        "txid": txid,
+        "changes": changes,
+        "authority_changes": authority,
+    })
+}
+
+fn sats(value: &Value) -> i64 {
+    (value.as_f64().unwrap_or(0.0) * COIN).round() as i64
+}
+
+// An amount paid to an address: (address, currency, satoshis), the native
+// coin under "native".
+pub type Amount = (String, String, i64);
+
+// What an output pays, for every address it names.
+pub fn output_amounts(output: &Value) -> Vec<Amount> {
+    let script = &output["scriptPubKey"];
+    let mut amounts = Vec::new();
+    let native = output["valueSat"].as_i64().unwrap_or_else(|| sats(&output["value"]));
+    let reserves = script["reserveoutput"]["currencyvalues"].as_object();
+    for address in script["addresses"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+        if native > 0 {
+            amounts.push((address.to_string(), "native".to_string(), native));
+        }
+        for (currency, value) in reserves.into_iter().flatten() {
+            let amount = sats(value);
+            if amount > 0 {
+                amounts.push((address.to_string(), currency.clone(), amount));
+            }
+        }
+    }
+    amounts
+}
+
+// The output an input spends, and what the daemon's spent index says it paid
+// (address and valueSat) in case the output predates the index.
+pub fn spent_output(input: &Value) -> Option<(&str, i64, Option<Amount>)> {
+    let (txid, n) = (input["txid"].as_str()?, input["vout"].as_i64()?);
+    let fallback = match (input["address"].as_str(), input["valueSat"].as_i64()) {
+        (Some(address), Some(amount)) => Some((address.to_string(), "native".to_string(), amount)),
+        _ => None,
+    };
+    Some((txid, n, fallback))
+}
+
+// Reserve transfer flags marking a conversion.
+const TRANSFER_CONVERT: u64 = 0x02;
+const TRANSFER_PRECONVERT: u64 = 0x04;
+
+// The conversions a transaction requests, as (source currency, destination
+// currency, satoshis of the source): its reserve transfer outputs that convert
+// what they carry into their destination currency.
+pub fn conversions(tx: &Value) -> Vec<Amount> {
+    let mut conversions = Vec::new();
+    for output in tx["vout"].as_array().into_iter().flatten() {
+        let transfer = &output["scriptPubKey"]["reservetransfer"];
+        let flags = transfer["flags"].as_u64().unwrap_or(0);
+        let converts = flags & (TRANSFER_CONVERT | TRANSFER_PRECONVERT) != 0 || transfer["convert"] == true || transfer["preconvert"] == true;
+        let destination = match transfer["destinationcurrencyid"].as_str() {
+            Some(destination) if converts => destination,
+            _ => continue,
+        };
+        for (source, value) in transfer["currencyvalues"].as_object().into_iter().flatten() {
+            let amount = sats(value);
+            if amount > 0 && source != destination {
+                conversions.push((source.clone(), destination.to_string(), amount));
+            }
+        }
+    }
+    conversions
+}
+
+// The unix day a block was mined on.
+pub fn block_day(block: &Value) -> i64 {
+    block["time"].as_i64().unwrap_or_else(unix_time) / 86_400
+}
+
+pub fn volume_json(day: i64, source: String, destination: String, volume: i64, count: i64) -> Value {
+    json!({
+        "day": day * 86_400,
+        "source": source,
+        "destination": destination,
+        "volume": volume as f64 / COIN,
+        "count": count,
+    })
+}
+
+pub fn balance_json(balance: i64, received: i64) -> Value {
+    json!({
+        "balance": balance as f64 / COIN,
+        "received": received as f64 / COIN,
+    })
+}
+
+// Indexes blocks as they reach the index depth. A new index starts at
+// `start_height`, or at the current tip when that isn't set.
+pub async fn follow(rpc: Arc<VerusRPC>, start_height: Option<u64>, interval: Duration) {
+    let indexer = match &rpc.indexer {
+        Some(indexer) => indexer,
+        None => return,
+    };
+    loop {
+        let backfilling = indexer.store.is_backfilling().await.unwrap_or(false);
+        if let Some(tip) = rpc.tip.height().filter(|_| !backfilling) {
+            let target = tip.saturating_sub(indexer.depth);
+            let mut next = match indexer.store.height().await {
+                Ok(height) => height.map(|height| height + 1).unwrap_or_else(|| start_height.unwrap_or(target)),
+                Err(e) => {
+                    eprintln!("indexer: {}", e);
+                    target + 1
+                },
+            };
+            while next <= target {
+                let block = match rpc.upstream.call_as("getblock", &[json!(next.to_string()), json!(2)], Priority::Background).await {
+                    Ok(block) => block,
+                    Err(e) => {
+                        eprintln!("indexer: failed to fetch block {}: {}", next, e.message);
+                        break;
+                    },
+                };
+                match indexer.store.index_block(next, &block).await {
+                    Ok(true) => {
+                        rpc.offers.index_block(next, &block);
+                        next += 1;
+                    },
+                    Ok(false) => break,
+                    Err(e) => {
+                        eprintln!("indexer: failed to index block {}: {}", next, e);
+                        break;
+                    },
+                }
+            }
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+// Indexes blocks from `from` (or wherever the index is) up to `to`, or up to
+// the index depth below the tip, at no more than `rate` blocks a second
+// (0 for no limit), printing progress. It can run next to a proxy using the
+// same index, which pauses its own indexing meanwhile.
+pub async fn backfill(upstream: &Upstream, indexer: &Indexer, from: u64, to: Option<u64>, rate: f64) -> Result<u64, String> {
+    let store = &indexer.store;
+    if let Some(start) = store.start_height().await?.filter(|start| *start > from) {
+        return Err(format!("The index starts at block {}; use `index resync --from {}` to rebuild it from there", start, from));
+    }
+    let target = match to {
+        Some(to) => to,
+        None => {
+            let tip = upstream.call("getblockcount", &[]).await.map_err(|e| e.message)?;
+            tip.as_u64().ok_or("Unexpected getblockcount reply")?.saturating_sub(indexer.depth)
+        },
+    };
+    let mut next = store.height().await?.map_or(from, |height| height + 1);
+    let first = next;
+    let started = Instant::now();
+    let mut reported = Instant::now();
+    let result = loop {
+        if next > target {
+            break Ok(next - first);
+        }
+        if let Err(e) = store.set_backfilling(true).await {
+            break Err(e);
+        }
+        let block = match upstream.call("getblock", &[json!(next.to_string()), json!(2)]).await {
+            Ok(block) => block,
+            Err(e) => break Err(format!("Failed to fetch block {}: {}", next, e.message)),
+        };
+        match store.index_block(next, &block).await {
+            Ok(true) => next += 1,
+            // Somebody else moved the index on; carry on from there.
+            Ok(false) => match store.height().await {
+                Ok(height) => next = height.map_or(next, |height| height + 1),
+                Err(e) => break Err(e),
+            },
+            Err(e) => break Err(format!("Failed to index block {}: {}", next, e)),
+        }
+
+        let done = next - first;
+        let elapsed = started.elapsed().as_secs_f64();
+        if reported.elapsed() >= Duration::from_secs(1) || next > target {
+            reported = Instant::now();
+            let speed = done as f64 / elapsed.max(0.001);
+            let left = (target + 1).saturating_sub(next);
+            println!(
+                "indexed block {} of {} ({:.1}%), {:.1} blocks/s, {:.0}s left",
+                next - 1, target, (next - first) as f64 * 100.0 / (target + 1 - first).max(1) as f64, speed, left as f64 / speed.max(0.001),
+            );
+        }
+        if rate > 0.0 {
+            let due = Duration::from_secs_f64(done as f64 / rate);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+    };
+    let _ = store.set_backfilling(false).await;
+    result
+}
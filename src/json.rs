@@ -0,0 +1,17 @@
+use serde_json::Value;
+
+// Single entry point for turning the request body into a `Value`. The params
+// parsed here are what both the allowlist and the upstream call work from.
+// Building with the `simd-json` feature swaps serde_json for simd-json's serde
+// deserializer.
+#[cfg(not(feature = "simd-json"))]
+pub fn from_slice(bytes: &[u8]) -> Option<Value> {
+    serde_json::from_slice(bytes).ok()
+}
+
+#[cfg(feature = "simd-json")]
+pub fn from_slice(bytes: &[u8]) -> Option<Value> {
+    // simd-json parses in place, so it needs its own mutable copy.
+    let mut buf = bytes.to_vec();
+    simd_json::serde::from_slice(&mut buf).ok()
+}
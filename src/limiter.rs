@@ -0,0 +1,194 @@
+use serde_json::json;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+// How urgently a daemon call is needed. Waiting calls are let through in
+// this order, so background refreshes and analytics queue behind what users
+// are waiting on rather than alongside it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    // Wallet and dapp requests with someone waiting on the answer.
+    #[default]
+    Interactive,
+    // Refreshes, subscriptions, watchers and scheduled jobs.
+    Background,
+    // Heavy queries nobody is waiting on interactively.
+    Analytics,
+}
+
+const PRIORITIES: [Priority; 3] = [Priority::Interactive, Priority::Background, Priority::Analytics];
+
+impl Priority {
+    pub fn parse(priority: &str) -> Option<Priority> {
+        PRIORITIES.iter().copied().find(|p| p.name() == priority)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Background => "background",
+            Priority::Analytics => "analytics",
+        }
+    }
+}
+
+pub struct LimiterOptions {
+    pub min: usize,
+    pub max: usize,
+    pub target_latency: Duration,
+    pub queue_timeout: Duration,
+}
+
+struct LimiterState {
+    limit: f64,
+    in_flight: usize,
+    // Calls waiting for a slot, by priority.
+    waiting: [usize; 3],
+}
+
+impl LimiterState {
+    // Whether a call of `priority` may take a slot: one must be free and no
+    // more urgent call be waiting for it.
+    fn admits(&self, priority: Priority) -> bool {
+        (self.in_flight as f64) < self.limit.floor() && self.waiting[..priority as usize].iter().all(|waiting| *waiting == 0)
+    }
+}
+
+// AIMD limit on concurrent daemon calls. Every call that comes back within the
+// target latency grows the limit by roughly one per window of calls; a
+// transport failure or a slow reply shrinks it by 10%. Calls over the limit
+// wait for a slot, up to `queue_timeout`, and are let through by priority.
+pub struct AdaptiveLimiter {
+    state: Mutex<LimiterState>,
+    notify: Notify,
+    opts: LimiterOptions,
+    rejected: AtomicU64,
+}
+
+// A slot, held until dropped. It owns a handle on its limiter, so a streamed
+// reply can hold it for as long as the daemon is still sending.
+pub struct Permit {
+    limiter: Arc<AdaptiveLimiter>,
+    start: Instant,
+    overloaded: Option<bool>,
+}
+
+impl Permit {
+    // Marks whether the call failed in a way that suggests the daemon is
+    // struggling. A permit dropped without a verdict (e.g. the client went away)
+    // frees its slot without moving the limit.
+    pub fn record(&mut self, failed: bool) {
+        self.overloaded = Some(failed || self.start.elapsed() > self.limiter.opts.target_latency);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        state.in_flight -= 1;
+        match self.overloaded {
+            Some(true) => state.limit = (state.limit * 0.9).max(self.limiter.opts.min as f64),
+            Some(false) => state.limit = (state.limit + 1.0 / state.limit).min(self.limiter.opts.max as f64),
+            None => {},
+        }
+        drop(state);
+        self.limiter.notify.notify_waiters();
+    }
+}
+
+// A call counted as waiting, holding back less urgent ones, until it gets a
+// slot or gives up.
+struct Queued<'a> {
+    limiter: &'a AdaptiveLimiter,
+    priority: Priority,
+}
+
+impl<'a> Queued<'a> {
+    fn new(limiter: &'a AdaptiveLimiter, priority: Priority) -> Queued<'a> {
+        limiter.state.lock().unwrap().waiting[priority as usize] += 1;
+        Queued { limiter, priority }
+    }
+}
+
+impl<'a> Drop for Queued<'a> {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().waiting[self.priority as usize] -= 1;
+        self.limiter.notify.notify_waiters();
+    }
+}
+
+impl AdaptiveLimiter {
+    pub fn new(opts: LimiterOptions) -> Arc<AdaptiveLimiter> {
+        let min = opts.min.max(1);
+        let max = opts.max.max(min);
+        let opts = LimiterOptions { min, max, ..opts };
+        Arc::new(AdaptiveLimiter {
+            state: Mutex::new(LimiterState { limit: max as f64, in_flight: 0, waiting: [0; 3] }),
+            notify: Notify::new(),
+            opts,
+            rejected: AtomicU64::new(0),
+        })
+    }
+
+    fn try_acquire(self: &Arc<Self>, priority: Priority) -> Option<Permit> {
+        let mut state = self.state.lock().unwrap();
+        if state.admits(priority) {
+            state.in_flight += 1;
+            Some(Permit { limiter: self.clone(), start: Instant::now(), overloaded: None })
+        } else {
+            None
+        }
+    }
+
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> Option<Permit> {
+        if let Some(permit) = self.try_acquire(priority) {
+            return Some(permit);
+        }
+        let deadline = tokio::time::Instant::now() + self.opts.queue_timeout;
+        let _queued = Queued::new(self, priority);
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if let Some(permit) = self.try_acquire(priority) {
+                return Some(permit);
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        }
+    }
+
+    pub fn summary(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        json!({
+            "limit": state.limit.floor(),
+            "min": self.opts.min,
+            "max": self.opts.max,
+            "in_flight": state.in_flight,
+            "waiting": PRIORITIES.iter().map(|p| (p.name().to_string(), json!(state.waiting[*p as usize]))).collect::<serde_json::Map<_, _>>(),
+            "rejected": self.rejected.load(Ordering::Relaxed),
+        })
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        let (limit, in_flight, waiting) = {
+            let state = self.state.lock().unwrap();
+            (state.limit.floor(), state.in_flight, state.waiting)
+        };
+        writeln!(out, "# TYPE upstream_concurrency_limit gauge").unwrap();
+        writeln!(out, "upstream_concurrency_limit {}", limit).unwrap();
+        writeln!(out, "# TYPE upstream_in_flight gauge").unwrap();
+        writeln!(out, "upstream_in_flight {}", in_flight).unwrap();
+        writeln!(out, "# TYPE upstream_queue_waiting gauge").unwrap();
+        for priority in PRIORITIES {
+            writeln!(out, "upstream_queue_waiting{{priority=\"{}\"}} {}", priority.name(), waiting[priority as usize]).unwrap();
+        }
+        writeln!(out, "# TYPE upstream_queue_rejected_total counter").unwrap();
+        writeln!(out, "upstream_queue_rejected_total {}", self.rejected.load(Ordering::Relaxed)).unwrap();
+    }
+}
@@ -0,0 +1,473 @@
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::allowlist::{Access, Scope};
+use crate::client_ip::TrustedProxies;
+use crate::limiter::Priority;
+use crate::phases::{Phase, Phases};
+use crate::proxy_protocol;
+
+pub struct ConnOptions {
+    pub tcp_nodelay: bool,
+    pub tcp_keepalive: Option<Duration>,
+    pub http_keepalive: bool,
+    // How long a keep-alive connection may sit without a request before it is closed.
+    pub keepalive_timeout: Option<Duration>,
+    pub max_header_size: Option<usize>,
+    // How long a client may take to send a request's headers, however slowly
+    // it trickles them in.
+    pub header_read_timeout: Option<Duration>,
+    // When set, every connection must come from one of these load balancers
+    // and start with a PROXY protocol header naming the client it was opened
+    // for; connections from anywhere else are dropped unread.
+    pub proxy_protocol: Option<Arc<TrustedProxies>>,
+    pub phases: Arc<Phases>,
+}
+
+// The key in an Authorization: Bearer <key> header.
+pub fn bearer(headers: &hyper::HeaderMap) -> Option<&str> {
+    headers.get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+// What clients of one listener may call, and whether they must show one of
+// `api_keys` (as Authorization: Bearer <key>) for anything at all. Session
+// tokens carry the `name` of the listener that issued them.
+pub struct Profile {
+    pub name: String,
+    pub access: Access,
+    pub api_keys: HashSet<String>,
+}
+
+impl Profile {
+    pub fn authorize(&self, headers: &hyper::HeaderMap) -> Result<(), String> {
+        if self.api_keys.is_empty() {
+            return Ok(());
+        }
+        match bearer(headers) {
+            Some(key) if self.api_keys.contains(key) => Ok(()),
+            Some(_) => Err("Invalid API key".to_string()),
+            None => Err("This listener needs an API key".to_string()),
+        }
+    }
+}
+
+// A listener's profile from its config entry (`name`, `access`, `mining`,
+// `shielded`, `registration`, `priority` and `api_keys`), named "<addr>:<port>"
+// unless it has a name.
+fn profile(entry: &HashMap<String, Value>, addr: &str, port: u16) -> Result<Profile, String> {
+    let name = match entry.get("name") {
+        Some(name) => name.as_str().ok_or("name must be a string")?.to_string(),
+        None => format!("{}:{}", addr, port),
+    };
+    let scope = match entry.get("access") {
+        Some(access) => access.as_str().and_then(Scope::parse).ok_or_else(|| format!("Unknown listener access {}", access))?,
+        None => Scope::Standard,
+    };
+    let mining = match entry.get("mining") {
+        Some(mining) => mining.as_bool().ok_or("mining must be true or false")?,
+        None => false,
+    };
+    let shielded = match entry.get("shielded") {
+        Some(shielded) => shielded.as_bool().ok_or("shielded must be true or false")?,
+        None => false,
+    };
+    let registration = match entry.get("registration") {
+        Some(registration) => registration.as_bool().ok_or("registration must be true or false")?,
+        None => false,
+    };
+    let priority = match entry.get("priority") {
+        Some(priority) => priority.as_str().and_then(Priority::parse).ok_or_else(|| format!("Unknown listener priority {}", priority))?,
+        None => Priority::Interactive,
+    };
+    let access = Access { scope, mining, shielded, registration, priority, roles: 0, debug: false, needs_captcha: false, principal: None };
+    let api_keys = match entry.get("api_keys") {
+        Some(Value::Array(keys)) => keys.iter().map(|key| key.as_str().map(str::to_string).ok_or("api_keys must be strings")).collect::<Result<_, _>>()?,
+        Some(_) => return Err("api_keys must be an array".to_string()),
+        None => HashSet::new(),
+    };
+    Ok(Profile { name, access, api_keys })
+}
+
+// The addresses to bind for a configured addr and port. The addr is an IP
+// address, optionally in brackets ("[::]"), or a hostname, which is bound on
+// every address it resolves to (e.g. "localhost" on both 127.0.0.1 and ::1).
+pub fn addresses(addr: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let host = addr.trim().trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    let mut resolved: Vec<SocketAddr> = Vec::new();
+    for address in (host, port).to_socket_addrs().map_err(|e| format!("Cannot resolve {}: {}", host, e))? {
+        if !resolved.contains(&address) {
+            resolved.push(address);
+        }
+    }
+    match resolved.is_empty() {
+        true => Err(format!("{} resolves to no addresses", host)),
+        false => Ok(resolved),
+    }
+}
+
+// Binds a listening socket. `::` takes IPv4 clients too (as IPv4-mapped
+// addresses) wherever the OS allows dual-stack sockets, and port 0 binds an
+// ephemeral port, which `local_addr` then tells.
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM, Some(socket2::Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if let SocketAddr::V6(v6) = addr {
+        socket.set_only_v6(!v6.ip().is_unspecified())?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+// The extra `listeners`, each with an addr, a port and a profile.
+pub fn load(entries: Vec<HashMap<String, Value>>) -> Result<Vec<(Vec<SocketAddr>, Profile)>, String> {
+    entries.into_iter()
+        .map(|entry| {
+            let addr = entry.get("addr").and_then(Value::as_str).ok_or("Listener without an addr")?;
+            let port = entry.get("port").and_then(Value::as_u64).and_then(|port| u16::try_from(port).ok()).ok_or("Listener without a valid port")?;
+            Ok((addresses(addr, port)?, profile(&entry, addr, port)?))
+        })
+        .collect()
+}
+
+// How long a connection may take to send its PROXY protocol header.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// When the connection last moved bytes and how many requests on it are still
+// being answered, so an idle keep-alive connection can be closed without
+// cutting off a slow upstream call, and since when it has been waiting for a
+// request's headers, so a client can't hold it open by withholding them.
+struct Activity {
+    last: Mutex<Instant>,
+    in_flight: AtomicUsize,
+    // From the connection's start for its first request, and from the first
+    // byte of each later one; None from when hyper has a request's headers
+    // until the next request starts arriving.
+    head_since: Mutex<Option<Instant>>,
+}
+
+impl Activity {
+    fn touch(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last.lock().unwrap().elapsed()
+    }
+
+    // Bytes arrived: the start of the next request's headers unless a request
+    // is being answered.
+    fn received(&self) {
+        if self.in_flight.load(Ordering::SeqCst) == 0 {
+            self.head_since.lock().unwrap().get_or_insert_with(Instant::now);
+        }
+    }
+
+    fn head_received(&self) {
+        *self.head_since.lock().unwrap() = None;
+    }
+
+    fn head_for(&self) -> Option<Duration> {
+        self.head_since.lock().unwrap().map(|since| since.elapsed())
+    }
+}
+
+struct TrackedStream {
+    inner: TcpStream,
+    activity: Arc<Activity>,
+}
+
+impl AsyncRead for TrackedStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            self.activity.touch();
+            if buf.filled().len() > filled {
+                self.activity.received();
+            }
+        }
+        res
+    }
+}
+
+impl AsyncWrite for TrackedStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(_)) = res {
+            self.activity.touch();
+        }
+        res
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(_)) = res {
+            self.activity.touch();
+        }
+        res
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+fn configure(stream: &TcpStream, opts: &ConnOptions) -> io::Result<()> {
+    stream.set_nodelay(opts.tcp_nodelay)?;
+    if let Some(keepalive) = opts.tcp_keepalive {
+        socket2::SockRef::from(stream).set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(keepalive))?;
+    }
+    Ok(())
+}
+
+// Accept loop for the public listener. Unlike `hyper::Server` this applies the
+// socket options per connection and enforces the keep-alive idle timeout and
+// the header read timeout, which hyper would enforce without saying so.
+pub async fn serve<H, F>(listener: TcpListener, opts: Arc<ConnOptions>, handler: H)
+where
+    H: Fn(Request<Body>, SocketAddr) -> F + Clone + Send + 'static,
+    F: Future<Output = Result<Response<Body>, hyper::Error>> + Send + 'static,
+{
+    let mut http = Http::new();
+    http.http1_keep_alive(opts.http_keepalive);
+    if let Some(max_header_size) = opts.max_header_size {
+        http.max_buf_size(max_header_size);
+    }
+
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Usually fd exhaustion; back off instead of spinning on accept.
+                eprintln!("accept error: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            },
+        };
+        // IPv4 clients of a dual-stack listener arrive as ::ffff:a.b.c.d; bans,
+        // limits and trusted proxies are written with the plain IPv4 address.
+        let remote = SocketAddr::new(remote.ip().to_canonical(), remote.port());
+        if let Err(e) = configure(&stream, &opts) {
+            eprintln!("failed to configure connection from {}: {}", remote, e);
+        }
+
+        let handler = handler.clone();
+        let http = http.clone();
+        let keepalive_timeout = opts.keepalive_timeout;
+        let header_read_timeout = opts.header_read_timeout;
+        let proxy_protocol = opts.proxy_protocol.clone();
+        let phases = opts.phases.clone();
+
+        tokio::spawn(async move {
+            let (mut stream, mut remote) = (stream, remote);
+            // Read in the connection's own task so a slow load balancer can't
+            // hold up accepting others.
+            if let Some(load_balancers) = proxy_protocol {
+                // A header from anyone else would let them claim any address.
+                if !load_balancers.is_trusted(remote.ip()) {
+                    eprintln!("dropping connection from {}: not a trusted proxy", remote);
+                    return;
+                }
+                match tokio::time::timeout(PROXY_HEADER_TIMEOUT, proxy_protocol::read_header(&mut stream)).await {
+                    Ok(Ok(Some(client))) => remote = client,
+                    Ok(Ok(None)) => {},
+                    Ok(Err(e)) => {
+                        eprintln!("bad PROXY protocol header from {}: {}", remote, e);
+                        return;
+                    },
+                    Err(_) => {
+                        eprintln!("no PROXY protocol header from {}", remote);
+                        return;
+                    },
+                }
+            }
+
+            let activity = Arc::new(Activity {
+                last: Mutex::new(Instant::now()),
+                in_flight: AtomicUsize::new(0),
+                head_since: Mutex::new(Some(Instant::now())),
+            });
+            let stream = TrackedStream { inner: stream, activity: activity.clone() };
+            let tracked = activity.clone();
+            let (connected, first_request) = (Instant::now(), AtomicBool::new(true));
+            let timed = phases.clone();
+            let service = service_fn(move |req| {
+                if first_request.swap(false, Ordering::Relaxed) {
+                    timed.record(Phase::HeaderRead, connected.elapsed());
+                }
+                tracked.in_flight.fetch_add(1, Ordering::SeqCst);
+                tracked.head_received();
+                let tracked = tracked.clone();
+                let res = handler(req, remote);
+                async move {
+                    let res = res.await;
+                    tracked.in_flight.fetch_sub(1, Ordering::SeqCst);
+                    tracked.touch();
+                    res
+                }
+            });
+            // Once a connection is upgraded (to a WebSocket) it leaves hyper, and
+            // this task, which ends with it.
+            let conn = http.serve_connection(stream, service).with_upgrades();
+            tokio::pin!(conn);
+            if keepalive_timeout.is_none() && header_read_timeout.is_none() {
+                let _ = conn.await;
+                return;
+            }
+            loop {
+                let idle_wait = keepalive_timeout.map(|timeout| match activity.in_flight.load(Ordering::SeqCst) {
+                    0 => timeout.saturating_sub(activity.idle_for()),
+                    _ => timeout,
+                });
+                let head_wait = header_read_timeout.and_then(|timeout| activity.head_for().map(|waited| timeout.saturating_sub(waited)));
+                // With neither running out, the connection is checked again
+                // as often as the shorter timeout.
+                let wait = idle_wait.into_iter().chain(head_wait)
+                    .min()
+                    .or(header_read_timeout)
+                    .unwrap_or_default();
+                tokio::select! {
+                    _ = conn.as_mut() => return,
+                    _ = tokio::time::sleep(wait) => {
+                        // Dropping the connection closes it; hyper's graceful
+                        // shutdown never completes on a connection that hasn't
+                        // sent its first request yet.
+                        if header_read_timeout.is_some_and(|timeout| activity.head_for().is_some_and(|waited| waited >= timeout)) {
+                            phases.timed_out(Phase::HeaderRead);
+                            return;
+                        }
+                        // Nothing is in flight, so the connection closes cleanly.
+                        if keepalive_timeout.is_some_and(|timeout| activity.in_flight.load(Ordering::SeqCst) == 0 && activity.idle_for() >= timeout) {
+                            return;
+                        }
+                    },
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const HEADER_READ_TIMEOUT: Duration = Duration::from_millis(300);
+
+    // Serves "ok" on an ephemeral port with a short header_read_timeout.
+    async fn start(keepalive_timeout: Option<Duration>) -> (SocketAddr, Arc<Phases>) {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let phases = Arc::new(Phases::default());
+        let opts = Arc::new(ConnOptions {
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            http_keepalive: true,
+            keepalive_timeout,
+            max_header_size: None,
+            header_read_timeout: Some(HEADER_READ_TIMEOUT),
+            proxy_protocol: None,
+            phases: phases.clone(),
+        });
+        tokio::spawn(serve(listener, opts, |_req, _remote| async { Ok(Response::new(Body::from("ok"))) }));
+        (addr, phases)
+    }
+
+    // Waits for the server to close the connection, and says how long that took.
+    async fn closed_after(stream: &mut TcpStream) -> Duration {
+        let started = Instant::now();
+        let mut buf = [0u8; 64];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))), "connection still open");
+        started.elapsed()
+    }
+
+    async fn answered(addr: SocketAddr) -> bool {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").await.unwrap();
+        let mut buf = [0u8; 64];
+        let read = stream.read(&mut buf).await.unwrap();
+        buf[..read].starts_with(b"HTTP/1.1 200")
+    }
+
+    fn header_timeouts(phases: &Phases) -> u64 {
+        phases.summary()["header_read"]["timeouts"].as_u64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn withheld_headers_close_the_connection() {
+        let (addr, phases) = start(None).await;
+        let mut silent = TcpStream::connect(addr).await.unwrap();
+        assert!(answered(addr).await);
+        let waited = closed_after(&mut silent).await;
+        assert!(waited < HEADER_READ_TIMEOUT * 3, "closed after {:?}", waited);
+        assert_eq!(header_timeouts(&phases), 1);
+        assert!(answered(addr).await);
+    }
+
+    #[tokio::test]
+    async fn dribbled_headers_close_the_connection() {
+        let (addr, phases) = start(Some(Duration::from_secs(60))).await;
+        let mut slow = TcpStream::connect(addr).await.unwrap();
+        let dribble = async {
+            for byte in b"GET / HTTP/1.1\r\nHost: test\r\nX-Slow: aaaaaaaaaaaaaaaaaaaa" {
+                if slow.write_all(&[*byte]).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        let started = Instant::now();
+        let (_, others) = tokio::join!(dribble, async {
+            tokio::time::sleep(HEADER_READ_TIMEOUT / 2).await;
+            answered(addr).await
+        });
+        assert!(others);
+        closed_after(&mut slow).await;
+        assert!(started.elapsed() < HEADER_READ_TIMEOUT * 3 + Duration::from_secs(1), "closed after {:?}", started.elapsed());
+        assert_eq!(header_timeouts(&phases), 1);
+    }
+
+    #[tokio::test]
+    async fn idle_keepalive_connections_are_not_header_timeouts() {
+        let (addr, phases) = start(Some(Duration::from_secs(60))).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").await.unwrap();
+        let mut buf = [0u8; 256];
+        assert!(stream.read(&mut buf).await.unwrap() > 0);
+        tokio::time::sleep(HEADER_READ_TIMEOUT * 2).await;
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").await.unwrap();
+        let read = stream.read(&mut buf).await.unwrap();
+        assert!(buf[..read].starts_with(b"HTTP/1.1 200"));
+        assert_eq!(header_timeouts(&phases), 0);
+    }
+}
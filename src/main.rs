@@ -1,118 +1,936 @@
 use hyper::{Body, Request, Response, Server, service::{make_service_fn, service_fn}};
+use hyper::body::HttpBody;
 use serde_json::{Value, json};
-use jsonrpc::{Client, error::RpcError};
-use jsonrpc::simple_http::{self, SimpleHttpTransport};
-use serde_json::value::RawValue;
+use jsonrpc::error::RpcError;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+mod address;
+mod alerts;
+mod admin;
+mod amount;
+mod annotations;
 mod allowlist;
+mod audit;
+mod batch;
+mod body_limits;
+mod branding;
+mod broadcast;
+mod cache;
+mod captcha;
+mod chain_check;
+mod chain_stats;
+mod cli;
+mod client_ip;
+mod codec;
+mod composite;
+mod confirm;
+mod converters;
+mod currency_watch;
+mod debug;
+mod defaults;
+mod disk_cache;
+mod docs;
+mod events;
+#[cfg(any(feature = "kafka", feature = "nats"))]
+mod export;
+mod faucet;
+mod fees;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod hash;
+#[cfg(feature = "postgres")]
+mod index_postgres;
+mod index_sqlite;
+mod indexer;
+mod json;
+mod limiter;
+mod listener;
+mod migrate;
+mod mempool;
+mod normalize;
+mod notarization;
+mod offers;
+mod openapi;
+mod origin;
+mod paginate;
+mod phases;
+mod policy;
+mod pool;
+mod proofroots;
+mod proxy_protocol;
+mod range;
+mod redact;
+mod registrations;
+mod reorg;
+mod rest;
+mod runtime;
+mod roles;
+mod routes;
+mod sampler;
+mod sapling;
+mod scheduler;
+mod schema;
+mod session;
+mod snapshot;
+mod sse;
+mod static_json;
+mod stats;
+mod subscriptions;
+mod timelocks;
+mod tip;
+mod trust;
+mod txbuilder;
+mod upstream;
+mod usage;
+mod warm;
+mod webhook;
+mod ws;
+
+use alerts::{AlertRules, Alerts};
+use allowlist::{Access, Scope};
+use amount::AmountRules;
+use annotations::MethodTable;
+use audit::AuditLog;
+use batch::BatchLimits;
+use body_limits::BodyLimits;
+use branding::Branding;
+use broadcast::Broadcaster;
+use confirm::PendingSends;
+use cache::{NegativeCaching, ResponseCache};
+use captcha::Captcha;
+use client_ip::{ClientIp, TrustedProxies};
+use chain_check::ChainExpectation;
+use chain_stats::ChainStats;
+use codec::Format;
+use composite::Composite;
+use defaults::ParamDefaults;
+use disk_cache::DiskCache;
+use docs::MethodDocs;
+use indexer::Indexer;
+use events::EventHub;
+use faucet::Faucet;
+use fees::FeeRules;
+use limiter::{LimiterOptions, Priority};
+use listener::ConnOptions;
+use mempool::MempoolMonitor;
+use migrate::Migrations;
+use notarization::NotarizationMonitor;
+use offers::OfferTracker;
+use origin::OriginPolicy;
+use phases::{Phase, Phases};
+use policy::SendPolicy;
+use pool::BufferPool;
+use range::RangeLimits;
+use scheduler::History;
+use schema::SchemaCheck;
+use registrations::RegistrationTracker;
+use reorg::ReorgDetector;
+use roles::Roles;
+use runtime::RuntimeAccess;
+use sampler::Sampler;
+use sapling::SaplingTrees;
+use session::{SessionOptions, Sessions};
+use static_json::StaticDocs;
+use stats::RequestStats;
+use subscriptions::{SubscriptionLimits, Subscriptions};
+use timelocks::TimelockMonitor;
+use tip::ChainTip;
+use trust::TrustRatings;
+use txbuilder::{Strategy, TxBuilder};
+use upstream::{Upstream, UpstreamOptions};
+use usage::UsageLog;
+use webhook::Webhook;
 
 struct VerusRPC {
-    client: Arc<Mutex<Client>>,
+    upstream: Upstream,
+    cache: ResponseCache,
+    disk_cache: Option<DiskCache>,
+    indexer: Option<Indexer>,
+    history: Option<History>,
+    tip: ChainTip,
+    // Whether read replies carry the block they were answered at.
+    consistency_tokens: bool,
+    mempool: MempoolMonitor,
+    notarizations: NotarizationMonitor,
+    pool: Arc<BufferPool>,
+    batch: BatchLimits,
+    amounts: AmountRules,
+    send_policy: SendPolicy,
+    pending_sends: PendingSends,
+    txbuilder: TxBuilder,
+    sapling_trees: SaplingTrees,
+    route_max_hops: usize,
+    chain_stats: ChainStats,
+    reorgs: ReorgDetector,
+    docs: MethodDocs,
+    branding: Branding,
+    body_limits: BodyLimits,
+    static_docs: StaticDocs,
+    faucet: Option<Faucet>,
+    captcha: Option<Captcha>,
+    // How long a client may take to send a request body, and how long checking
+    // a call may take.
+    body_read_timeout: Option<Duration>,
+    validation_timeout: Option<Duration>,
+    phases: Arc<Phases>,
+    // The addresses the listeners were bound to, with the ports the OS picked
+    // for any configured as 0.
+    listening: Mutex<Vec<SocketAddr>>,
+    registrations: RegistrationTracker,
+    offers: OfferTracker,
+    timelocks: TimelockMonitor,
+    trust: TrustRatings,
+    broadcaster: Arc<Broadcaster>,
+    origins: OriginPolicy,
+    audit: Option<AuditLog>,
+    sampler: Option<Sampler>,
+    usage: Option<UsageLog>,
+    schemas: SchemaCheck,
+    runtime: RuntimeAccess,
+    // What admin listener requests must show as Authorization: Bearer.
+    admin_token: Option<String>,
+    strict_content_type: bool,
+    // Whether replies say which daemon calls answered them, and how long
+    // those took.
+    debug_upstream: bool,
+    stream_methods: HashSet<String>,
+    // Keys that unlock the mining methods on any listener.
+    mining_api_keys: HashSet<String>,
+    // Keys that unlock the shielded viewing methods on any listener.
+    shielded_api_keys: HashSet<String>,
+    // Keys that unlock registernamecommitment on any listener.
+    registration_api_keys: HashSet<String>,
+    // Keys whose requests may ask for a `_debug` field with X-Debug.
+    debug_api_keys: HashSet<String>,
+    // Keys whose requests queue for the daemon at a lower priority, such as
+    // those of dashboards and analytics jobs.
+    priority_api_keys: HashMap<String, Priority>,
+    methods: MethodTable,
+    roles: Roles,
+    migrations: Migrations,
+    defaults: ParamDefaults,
+    ranges: RangeLimits,
+    currency_page_size: usize,
+    composites: HashMap<String, Composite>,
+    events: EventHub,
+    subscriptions: Subscriptions,
+    sessions: Sessions,
+    stats: RequestStats,
+    alerts: Alerts,
+    #[cfg(feature = "graphql")]
+    graphql: graphql::ChainSchema,
 }
 
 impl VerusRPC {
-    fn new(url: &str, user: &str, pass: &str) -> Result<VerusRPC, simple_http::Error> {
-        let transport = SimpleHttpTransport::builder()
-            .url(url)?
-            .auth(user, Some(pass))
-            .build();
-        Ok(VerusRPC { client: Arc::new(Mutex::new(Client::with_transport(transport))) })
+    // Whether the config gives `key` anything beyond a listener's api_keys.
+    fn knows_key(&self, key: &str) -> bool {
+        self.mining_api_keys.contains(key)
+            || self.shielded_api_keys.contains(key)
+            || self.registration_api_keys.contains(key)
+            || self.debug_api_keys.contains(key)
+            || self.priority_api_keys.contains_key(key)
+            || self.origins.api_keys.contains(key)
+    }
+
+    async fn handle(self: &Arc<Self>, req_body: Value) -> Result<Value, RpcError> {
+        self.handle_as(req_body, Access::STANDARD).await
+    }
+
+    // Answers a request with what `access` allows, as set by the listener it
+    // arrived on.
+    async fn handle_as(self: &Arc<Self>, req_body: Value, access: Access) -> Result<Value, RpcError> {
+        let started = Instant::now();
+        let method = req_body["method"].as_str().map(str::to_string);
+        let result = match self.catch_up(&req_body).await {
+            Ok(()) => self.dispatch(req_body, access).await,
+            Err(e) => Err(e),
+        };
+        if let Some(method) = method {
+            self.stats.record(&method, started.elapsed(), &result);
+        }
+        result
     }
 
-    fn handle(&self, req_body: Value) -> Result<Value, RpcError> {
+    // Answers a request as its reply object, with the block it was answered
+    // at in a `tip` field when consistency tokens are on and it was a read.
+    // The daemon calls made for it go in an `upstream` field in debug mode,
+    // and what the proxy did in a `_debug` field when the client asked.
+    async fn answer(self: &Arc<Self>, req_body: Value, access: Access) -> Value {
+        let started = Instant::now();
+        let is_read = req_body["method"].as_str().is_some_and(|method| !self.methods.is_write(method));
+        let answering = debug::recorded(access.debug, self.handle_as(req_body, access));
+        let ((result, record), traces) = upstream::traced(self.debug_upstream || access.debug, answering).await;
+        let mut reply = reply(result);
+        if let Some(tip) = self.tip.token().filter(|_| self.consistency_tokens && is_read && reply.get("result").is_some()) {
+            reply["tip"] = tip;
+        }
+        if let Some(record) = record {
+            reply["_debug"] = record.to_json(&traces, started.elapsed());
+        }
+        match self.debug_upstream {
+            true => with_traces(reply, traces),
+            false => reply,
+        }
+    }
+
+    // Holds a request with a `min_height` until the daemon has reached it, or
+    // fails it if it doesn't in time.
+    async fn catch_up(&self, req_body: &Value) -> Result<(), RpcError> {
+        match &req_body["min_height"] {
+            Value::Null => Ok(()),
+            min_height => match min_height.as_u64() {
+                Some(min_height) => {
+                    let started = Instant::now();
+                    let reached = tip::reach(self, min_height).await;
+                    debug::step(format!("waited for min_height {}", min_height));
+                    debug::timing("min_height_wait", started.elapsed());
+                    reached
+                },
+                None => Err(RpcError { code: -32602, message: "Invalid min_height parameter".into(), data: None }),
+            },
+        }
+    }
+
+    async fn dispatch(self: &Arc<Self>, mut req_body: Value, access: Access) -> Result<Value, RpcError> {
         let method = match req_body["method"].as_str() {
-            Some(method) => method,
+            Some(method) => method.to_string(),
             None => return Err(RpcError { code: -32602, message: "Invalid method parameter".into(), data: None }),
         };
-        let params: Vec<Box<RawValue>> = match req_body["params"].as_array() {
-            Some(params) => {
-                params.iter().enumerate().map(|(i, v)| {
-                    if method == "getblock" && i == 0 {
-                        if let Ok(num) = v.to_string().parse::<i64>() {
-                            // Legacy hack because getblock in JS used to allow 
-                            // strings to be passed in clientside and the former JS rpc server
-                            // wouldn't care. This will be deprecated in the future and shouldn't
-                            // be relied upon.
-                            RawValue::from_string(format!("\"{}\"", num)).unwrap()
-                        } else {
-                            RawValue::from_string(v.to_string()).unwrap()
-                        }
-                    } else {
-                        RawValue::from_string(v.to_string()).unwrap()
-                    }
-                }).collect()
-            },
-            None => return Err(RpcError { code: -32602, message: "Invalid params parameter".into(), data: None }),
+        // Params are parsed exactly once, with the body. Validation inspects these
+        // values directly and they are only serialized again for the upstream call.
+        let params: Vec<Value> = match req_body["params"].take() {
+            Value::Array(params) => params,
+            _ => return Err(RpcError { code: -32602, message: "Invalid params parameter".into(), data: None }),
         };
-    
-        if !allowlist::is_method_allowed(method, &params) {
+
+        // Methods answered by the proxy itself. They aren't in the allowlist,
+        // but the caller's roles and maintenance windows still apply to them,
+        // and the calls they make are checked with the caller's access.
+        if matches!(method.as_str(), "recommend_fees" | "buildtransaction" | "verifyproofroots" | "getvdxfids") || self.composites.contains_key(&method) {
+            debug::step(format!("{} is answered by the proxy", method));
+            self.check_roles(&method, access)?;
+        }
+        if method == "recommend_fees" {
+            if !params.is_empty() {
+                return Err(RpcError { code: -32602, message: "Invalid params parameter".into(), data: None });
+            }
+            return self.mempool.recommendation()
+                .ok_or_else(|| RpcError { code: -32000, message: "Fee data not available yet".into(), data: None });
+        }
+        if method == "buildtransaction" {
+            return txbuilder::build(self, params, access).await;
+        }
+        if method == "verifyproofroots" {
+            return proofroots::verify(self, params, access).await;
+        }
+        if method == "getvdxfids" {
+            return batch::getvdxfids(params, self.clone(), access).await;
+        }
+        if let Some(composite) = self.composites.get(&method) {
+            return composite.run(self, params, access).await;
+        }
+        // The held send goes through the checks again, with the confirming
+        // request's access.
+        if method == "confirmsend" {
+            debug::step("confirming a held sendcurrency");
+            self.check_roles(&method, access)?;
+            let mut params = self.pending_sends.take(&params, access)?;
+            self.validate("sendcurrency", &mut params, access)?;
+            return self.forward("sendcurrency".to_string(), params, access).await;
+        }
+
+        self.handle_call_as(method, params, access).await
+    }
+
+    // Validates a call to a daemon method and answers it, as `access` may.
+    async fn handle_call_as(self: &Arc<Self>, method: String, mut params: Vec<Value>, access: Access) -> Result<Value, RpcError> {
+        self.validate(&method, &mut params, access)?;
+        if self.pending_sends.needs_confirmation(&method, &params) {
+            debug::step("held for confirmation");
+            return Err(self.pending_sends.hold(params, access));
+        }
+        if self.registrations.tracks(&method) {
+            debug::step("tracked as an identity registration");
+            if method == "registeridentity" {
+                self.registrations.check(&params)?;
+            }
+            let tracked = params.clone();
+            let result = self.forward(method.clone(), params, access).await;
+            self.registrations.observe(&method, &tracked, &result, self.tip.height());
+            return result;
+        }
+        if self.offers.tracks(&method) {
+            debug::step("tracked as an offer");
+            let tracked = params.clone();
+            let result = self.forward(method, params, access).await;
+            self.offers.observe(&tracked, &result, self.tip.height());
+            return result;
+        }
+        if self.timelocks.tracks(&method) {
+            debug::step("tracked as an identity timelock");
+            let tracked = params.clone();
+            let result = self.forward(method, params, access).await;
+            self.timelocks.observe(&tracked, &result);
+            return result;
+        }
+        self.forward(method, params, access).await
+    }
+
+    // Answers a validated call.
+    async fn forward(self: &Arc<Self>, method: String, mut params: Vec<Value>, access: Access) -> Result<Value, RpcError> {
+        let _heavy = self.methods.throttle(&method).await;
+        let priority = access.priority.max(self.methods.get(&method).priority);
+
+        if method == "hashdata" {
+            if let Some(hash) = hash::hashdata(&params) {
+                debug::step("hashed by the proxy");
+                return Ok(hash);
+            }
+        }
+
+        if method == "sendrawtransaction" && !self.broadcaster.is_empty() {
+            if let Some(hex) = params.first().and_then(Value::as_str).map(str::to_string) {
+                debug::step("broadcast to the extra nodes too");
+                let rpc = self.clone();
+                return self.broadcaster.broadcast(hex, async move { rpc.call(method, params, priority).await }).await;
+            }
+        }
+
+        if method == "listcurrencies" && self.currency_page_size > 0 {
+            let page = paginate::take_page(&mut params, self.currency_page_size)?;
+            debug::step("paged by the proxy");
+            return self.call(method, params, priority).await.map(|list| paginate::slice(list, &page));
+        }
+
+        self.call(method, params, priority).await
+    }
+
+    // Fills in default params and checks a daemon call against the allowlist
+    // and policies, failing it if that took longer than validation_timeout.
+    fn validate(&self, method: &str, params: &mut Vec<Value>, access: Access) -> Result<(), RpcError> {
+        let started = Instant::now();
+        self.check_call(method, params, access)?;
+        let elapsed = started.elapsed();
+        self.phases.record(Phase::Validation, elapsed);
+        debug::timing("validation", elapsed);
+        if self.validation_timeout.is_some_and(|timeout| elapsed > timeout) {
+            self.phases.timed_out(Phase::Validation);
+            return Err(RpcError { code: -32603, message: "Validation timed out".into(), data: None });
+        }
+        Ok(())
+    }
+
+    fn check_call(&self, method: &str, params: &mut Vec<Value>, access: Access) -> Result<(), RpcError> {
+        let given = params.len();
+        self.defaults.fill(method, params);
+        if params.len() > given {
+            debug::step(format!("{} default params filled in", params.len() - given));
+        }
+        if !access.permits(method, params, self.methods.is_write(method)) {
             return Err(RpcError { code: -32601, message: "Method not found".into(), data: None });
         }
-    
-        let client = self.client.lock().unwrap();
-        let request = client.build_request(method, &params);
-
-        let response = client.send_request(request).map_err(|e| match e {
-            jsonrpc::Error::Rpc(rpc_error) => rpc_error,
-            _ => RpcError { code: -32603, message: "Internal error".into(), data: None },
-        })?;
-        
-        let result: Value = response.result().map_err(|e| match e {
-            jsonrpc::Error::Rpc(rpc_error) => rpc_error,
-            _ => RpcError { code: -32603, message: "Internal error".into(), data: None },
-        })?;
-        Ok(result)
+        debug::step(format!("allowed under {} access", access.scope.name()));
+        self.check_roles(method, access)?;
+        address::check(method, params)?;
+        self.amounts.check(method, params)?;
+        self.send_policy.check(method, params)?;
+        self.ranges.check(method, params, self.tip.height())?;
+        debug::step("passed maintenance, address, amount, send policy and range checks");
+        Ok(())
+    }
+
+    // Checks a method, a daemon one or one the proxy answers itself, against
+    // the caller's roles, the captcha and the open maintenance windows.
+    fn check_roles(&self, method: &str, access: Access) -> Result<(), RpcError> {
+        if let Some(captcha) = self.captcha.as_ref().filter(|captcha| access.needs_captcha && captcha.guards(method)) {
+            return Err(captcha.required());
+        }
+        if !self.roles.permits(access.roles, method, self.methods.get(method)) {
+            return Err(RpcError {
+                code: -32003,
+                message: "Method not allowed for your role".into(),
+                data: Some(serde_json::value::to_raw_value(&json!({ "roles": self.roles.names(access.roles) })).unwrap()),
+            });
+        }
+        debug::step("allowed for the caller's roles");
+        self.runtime.check_maintenance(method, self.methods.get(method))
+    }
+
+    // Whether a single request is answered by streaming the daemon's reply
+    // through, rather than going through the cache. Replies that have to be
+    // read whole anyway, to be checked against a schema or to get a
+    // `deployment` field, aren't.
+    fn is_streamed(&self, req_body: &Value) -> bool {
+        req_body["method"].as_str().is_some_and(|method| {
+            self.stream_methods.contains(method)
+                && !self.composites.contains_key(method)
+                && !self.schemas.covers(method)
+                && !self.branding.in_body()
+        })
+    }
+
+    // Validates a call and passes the daemon's reply body through as it
+    // arrives, in the daemon's own {"result", "error", "id"} form with the
+    // request's id. The client reading it sets the pace.
+    async fn stream(self: &Arc<Self>, mut req_body: Value, access: Access) -> Result<Body, RpcError> {
+        let started = Instant::now();
+        let method = req_body["method"].as_str().unwrap_or_default().to_string();
+        let mut params = match req_body["params"].take() {
+            Value::Array(params) => params,
+            _ => return Err(RpcError { code: -32602, message: "Invalid params parameter".into(), data: None }),
+        };
+        let result = match self.catch_up(&req_body).await.and_then(|()| self.validate(&method, &mut params, access)) {
+            Ok(()) if self.pending_sends.needs_confirmation(&method, &params) => Err(self.pending_sends.hold(params, access)),
+            Ok(()) => {
+                let priority = access.priority.max(self.methods.get(&method).priority);
+                self.upstream.stream(&method, &params, &req_body["id"], priority).await
+            },
+            Err(e) => Err(e),
+        };
+        let recorded = match &result {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(e.clone()),
+        };
+        self.stats.record(&method, started.elapsed(), &recorded);
+        result
+    }
+
+    // Answers an allowed call from the cache tiers or the daemon, queueing for
+    // the daemon at `priority`.
+    async fn call(self: &Arc<Self>, method: String, params: Vec<Value>, priority: Priority) -> Result<Value, RpcError> {
+        // The disk tier is only consulted once the tip is known, since stored
+        // results need their confirmations brought up to date.
+        let tip = self.tip.height();
+        let annotations = self.methods.get(&method);
+        let disk_cache = self.disk_cache.as_ref().filter(|_| tip.is_some() && disk_cache::is_candidate(&method));
+        let cache_key = if annotations.never_cache {
+            None
+        } else if self.cache.is_cacheable(&method) || disk_cache.is_some() {
+            // Results that can change with the chain are keyed to the current
+            // height, and not cached at all until it is known.
+            match (annotations.invalidate_on_block && normalize::is_tip_dependent(&method, &params), tip) {
+                (false, _) => Some(normalize::cache_key(&method, &params)),
+                (true, Some(tip)) => Some(format!("{}@{}", normalize::cache_key(&method, &params), tip)),
+                (true, None) => None,
+            }
+        } else {
+            None
+        };
+        if let Some(key) = &cache_key {
+            if self.cache.is_cacheable(&method) {
+                if let Some(cached) = self.cache.get(&method, key) {
+                    debug::cache(if cached.stale { "memory (stale)" } else { "memory" });
+                    if cached.stale && self.cache.begin_refresh(key) {
+                        let rpc = self.clone();
+                        let key = key.clone();
+                        tokio::spawn(async move {
+                            // Held to the same schema check as a miss, so a background refresh
+                            // can't slip a divergent reply into the cache.
+                            let result = rpc.upstream.call_as(&method, &params, priority.max(Priority::Background)).await
+                                .and_then(|result| rpc.schemas.check(&method, result));
+                            if let Ok(result) = result {
+                                rpc.cache.insert(&method, key.clone(), result);
+                            }
+                            rpc.cache.end_refresh(&key);
+                        });
+                    }
+                    return cached.value;
+                }
+            }
+            if let (Some(disk_cache), Some(tip)) = (disk_cache, tip) {
+                if let Some(stored) = disk_cache.get(key) {
+                    debug::cache("disk");
+                    let stored = disk_cache::refresh(stored, tip);
+                    self.cache.insert(&method, key.clone(), stored.clone());
+                    return Ok(stored);
+                }
+            }
+        }
+
+        debug::cache(if cache_key.is_some() { "miss" } else { "uncached" });
+        let result = self.upstream.call_as(&method, &params, priority).await.and_then(|result| self.schemas.check(&method, result));
+        if let Some(key) = cache_key {
+            match &result {
+                Ok(result) => {
+                    if let (Some(disk_cache), Some(tip)) = (disk_cache, tip) {
+                        if disk_cache::is_final(&method, &params, result, tip, disk_cache.depth) {
+                            disk_cache.insert(&method, &key, result);
+                        }
+                    }
+                    self.cache.insert(&method, key, result.clone());
+                },
+                Err(err) => self.cache.insert_negative(&method, key, err),
+            }
+        }
+        result
     }
 }
 
-async fn handle_req(req: Request<Body>, rpc: Arc<VerusRPC>) -> Result<Response<Body>, hyper::Error> {
+fn reply(result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(res) => json!({"result": res}),
+        Err(err) => match err.data {
+            Some(data) => json!({"error": { "code": err.code, "message": err.message, "data": data }}),
+            None => json!({"error": { "code": err.code, "message": err.message }}),
+        },
+    }
+}
+
+// Deprecation warnings from request migration travel in a `warning` field
+// next to the result or error.
+fn with_warning(mut reply: Value, warning: Option<String>) -> Value {
+    if let Some(warning) = warning {
+        reply["warning"] = Value::String(warning);
+    }
+    reply
+}
+
+// In debug mode, the daemon calls made for a reply travel in an `upstream`
+// field next to the result or error.
+fn with_traces(mut reply: Value, traces: Vec<upstream::Trace>) -> Value {
+    if !traces.is_empty() {
+        reply["upstream"] = traces.iter().map(upstream::Trace::to_json).collect();
+    }
+    reply
+}
+
+fn add_cors_headers(response: &mut Response<Body>) {
+    response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*".parse().unwrap());
+    response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, PUT, OPTIONS, POST".parse().unwrap());
+    response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization, Accept, X-Captcha-Token".parse().unwrap());
+    response.headers_mut().insert(hyper::header::ACCESS_CONTROL_MAX_AGE, "3600".parse().unwrap());
+
+    // Set the Referrer Policy header
+    response.headers_mut().insert(hyper::header::REFERRER_POLICY, "origin-when-cross-origin".parse().unwrap());
+}
+
+// Counts `calls` calls against the caller's role and key rate limits, or
+// gives the 429 (with Retry-After) refusing them when they would go over.
+fn admit(rpc: &VerusRPC, roles: u64, principal: &str, subject: Option<&str>, calls: u64) -> Option<Response<Body>> {
+    let refused = rpc.roles.admit(roles, principal, calls).map_err(|retry_after| ("Rate limit for your role exceeded", retry_after))
+        .and_then(|()| subject.map_or(Ok(()), |subject| rpc.runtime.admit(subject, calls)).map_err(|retry_after| ("Rate limit for your key exceeded", retry_after)));
+    let (message, retry_after) = match refused {
+        Ok(()) => return None,
+        Err(refused) => refused,
+    };
+    let mut response = rest::json_response(hyper::StatusCode::TOO_MANY_REQUESTS, json!({"error": message}));
+    response.headers_mut().insert(hyper::header::RETRY_AFTER, retry_after.into());
+    add_cors_headers(&mut response);
+    Some(response)
+}
+
+// Reads a request body into `whole_body`. The method isn't known until the
+// body is parsed, so bodies are first held to the largest limit any method
+// has, by Content-Length and again while reading for chunked bodies, and
+// then to their own methods' limits; the read is also held to
+// body_read_timeout. Err is the response refusing the body.
+async fn read_body(rpc: &VerusRPC, headers: &hyper::HeaderMap, mut body: Body, whole_body: &mut Vec<u8>) -> Result<Result<(), Response<Body>>, hyper::Error> {
+    let max_body_size = rpc.body_limits.ceiling();
+    let too_large = || Response::builder()
+        .status(hyper::StatusCode::PAYLOAD_TOO_LARGE)
+        .body(Body::from("Payload too large"))
+        .unwrap();
+    if let Some(content_length) = headers.get(hyper::header::CONTENT_LENGTH) {
+        if let Ok(content_length) = content_length.to_str().unwrap_or("").parse::<u64>() {
+            if content_length > max_body_size {
+                return Ok(Err(too_large()));
+            }
+        }
+    }
+    let started = Instant::now();
+    let read = async {
+        while let Some(chunk) = body.data().await {
+            whole_body.extend_from_slice(&chunk?);
+            if whole_body.len() as u64 > max_body_size {
+                return Ok(false);
+            }
+        }
+        Ok::<_, hyper::Error>(true)
+    };
+    let read = match rpc.body_read_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, read).await,
+        None => Ok(read.await),
+    };
+    rpc.phases.record(Phase::BodyRead, started.elapsed());
+    match read {
+        Ok(Ok(true)) => Ok(Ok(())),
+        Ok(Ok(false)) => Ok(Err(too_large())),
+        Ok(Err(e)) => Err(e),
+        Err(_) => {
+            rpc.phases.timed_out(Phase::BodyRead);
+            Ok(Err(Response::builder()
+                .status(hyper::StatusCode::REQUEST_TIMEOUT)
+                .body(Body::from("Request body timed out"))
+                .unwrap()))
+        },
+    }
+}
+
+async fn handle_req(req: Request<Body>, rpc: Arc<VerusRPC>, profile: Arc<listener::Profile>) -> Result<Response<Body>, hyper::Error> {
+    if req.extensions().get::<ClientIp>().is_some_and(|client| rpc.runtime.is_banned(client.0)) {
+        return Ok(rest::json_response(hyper::StatusCode::FORBIDDEN, json!({"error": "Banned"})));
+    }
 
     // Handle CORS preflight (OPTIONS) request
     if req.method() == hyper::Method::OPTIONS {
         let mut response = Response::new(Body::empty());
         response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*".parse().unwrap());
         response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST".parse().unwrap());
-        response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization, Accept".parse().unwrap());
+        response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization, Accept, X-Captcha-Token".parse().unwrap());
         response.headers_mut().insert(hyper::header::ACCESS_CONTROL_MAX_AGE, "3600".parse().unwrap());
         return Ok(response);
     }
 
-    // Maximum allowed content length (in bytes)
-    const MAX_CONTENT_LENGTH: u64 = 1024 * 1024 * 10; // 1 MiB, adjust as needed
+    // Signing in is how clients without the listener's API key get a session.
+    if req.uri().path().starts_with("/auth/") {
+        let mut response = Sessions::handle(req, &rpc, &profile).await?;
+        add_cors_headers(&mut response);
+        return Ok(response);
+    }
+    let session = match rpc.sessions.authenticate(req.headers(), &profile) {
+        Some(Ok(session)) => Some(session),
+        Some(Err(reason)) => {
+            let mut response = rest::json_response(hyper::StatusCode::UNAUTHORIZED, json!({"error": reason}));
+            add_cors_headers(&mut response);
+            return Ok(response);
+        },
+        None => None,
+    };
+    // Keys holding a role, and keys created through the admin listener, are
+    // let in wherever their grants allow.
+    let managed = listener::bearer(req.headers()).and_then(|key| rpc.runtime.key(key));
+    let key_roles = listener::bearer(req.headers()).map_or(0, |key| rpc.roles.of_key(key))
+        | managed.as_ref().map_or(0, |managed| rpc.roles.named(&managed.roles));
+    if let (None, 0, None, Err(reason)) = (&session, key_roles, &managed, profile.authorize(req.headers())) {
+        let mut response = rest::json_response(hyper::StatusCode::UNAUTHORIZED, json!({"error": reason}));
+        add_cors_headers(&mut response);
+        return Ok(response);
+    }
+    let mut access = match &session {
+        Some(session) => session.restrict(profile.access),
+        None => profile.access,
+    };
+    access.mining |= listener::bearer(req.headers()).is_some_and(|key| rpc.mining_api_keys.contains(key));
+    access.shielded |= listener::bearer(req.headers()).is_some_and(|key| rpc.shielded_api_keys.contains(key));
+    access.registration |= listener::bearer(req.headers()).is_some_and(|key| rpc.registration_api_keys.contains(key));
+    access.debug = req.headers().get("x-debug").is_some_and(|value| value != "0" && value != "false")
+        && listener::bearer(req.headers()).is_some_and(|key| rpc.debug_api_keys.contains(key));
+    access.mining |= session.is_none() && managed.as_ref().is_some_and(|managed| managed.mining);
+    if let Some(priority) = listener::bearer(req.headers()).and_then(|key| rpc.priority_api_keys.get(key)) {
+        access.priority = access.priority.max(*priority);
+    }
+    access.roles = match &session {
+        Some(session) => rpc.roles.named(&session.roles),
+        None => key_roles,
+    };
+    if access.roles == 0 {
+        access.roles = rpc.roles.default();
+    }
+    if let Some(priority) = rpc.roles.priority(access.roles) {
+        access.priority = access.priority.max(priority);
+    }
+    let client = req.extensions().get::<ClientIp>().map(|client| client.0.to_string()).unwrap_or_default();
+    let subject = match (&session, listener::bearer(req.headers())) {
+        (Some(session), _) => Some(session.subject.clone()),
+        (None, key) => key.map(session::key_subject),
+    };
+    // Rates are counted per client address unless the request proved who it
+    // is, with a session or a key holding a role.
+    let principal = match (&session, key_roles) {
+        (None, 0) => client.clone(),
+        _ => subject.clone().unwrap_or_default(),
+    };
+    // Clients showing nothing the proxy knows them by are asked to solve a
+    // captcha for the methods listed in captcha_methods.
+    let anonymous = session.is_none() && key_roles == 0 && managed.is_none()
+        && !listener::bearer(req.headers()).is_some_and(|key| profile.api_keys.contains(key));
+    if let (false, Some(subject)) = (anonymous, &subject) {
+        access = access.authenticated(subject);
+    }
+    access.needs_captcha = anonymous && rpc.captcha.is_some();
+    if let Some(response) = admit(&rpc, access.roles, &principal, subject.as_deref(), 1) {
+        return Ok(response);
+    }
 
-    if let Some(content_length) = req.headers().get(hyper::header::CONTENT_LENGTH) {
-        if let Ok(content_length) = content_length.to_str().unwrap_or("").parse::<u64>() {
-            if content_length > MAX_CONTENT_LENGTH {
-                return Ok(Response::builder()
-                    .status(hyper::StatusCode::PAYLOAD_TOO_LARGE)
-                    .body(Body::from("Payload too large"))
-                    .unwrap());
-            }
+    let origin = match &session {
+        Some(session) if session.grant.write => Ok(()),
+        Some(_) => Err("The session has no write scope".to_string()),
+        None if managed.as_ref().is_some_and(|managed| managed.write) && !req.headers().contains_key(hyper::header::ORIGIN) => Ok(()),
+        None => rpc.origins.check(req.headers()),
+    };
+    let request_origin = req.headers().get(hyper::header::ORIGIN).and_then(|origin| origin.to_str().ok()).map(str::to_string);
+
+    if req.method() == hyper::Method::GET && ws::is_upgrade(&req) {
+        let token = listener::bearer(req.headers()).map(str::to_string);
+        let caller = ws::Caller {
+            access,
+            principal,
+            subject,
+            client,
+            origin: request_origin,
+            writes: origin,
+            expires: session.as_ref().map(|session| session.expires),
+            session: session.as_ref().and(token.clone()),
+            managed_key: managed.as_ref().and(token),
+            profile: profile.clone(),
+        };
+        return Ok(ws::handle(req, rpc, caller).await);
+    }
+
+    if req.uri().path() == "/faucet" && rpc.faucet.is_some() {
+        let mut response = faucet::handle(req, &rpc, &client, &principal, anonymous).await?;
+        add_cors_headers(&mut response);
+        return Ok(response);
+    }
+
+    // REST and GraphQL calls can only carry a captcha token as a header, so
+    // one sent that way is checked before they are routed.
+    if let (Some(captcha), true, Some(token)) = (&rpc.captcha, access.needs_captcha, captcha::header(req.headers())) {
+        if let Err(e) = captcha.verify(Some(token), &client).await {
+            let mut response = rest::json_response(hyper::StatusCode::FORBIDDEN, reply(Err(e)));
+            add_cors_headers(&mut response);
+            return Ok(response);
         }
+        access.needs_captcha = false;
     }
-    
-    let whole_body = hyper::body::to_bytes(req.into_body()).await?;
-    let str_body = String::from_utf8(whole_body.to_vec()).unwrap();
-    let json_body: Result<Value, _> = serde_json::from_str(&str_body);
-    let result = match json_body {
-        Ok(req_body) => rpc.handle(req_body),
-        Err(_) => Err(RpcError { code: -32700, message: "Parse error".into(), data: None }),
+
+    if let Some(mut response) = rest::route(&req, &rpc, access, &profile).await {
+        add_cors_headers(&mut response);
+        return Ok(response);
+    }
+
+    #[cfg(feature = "graphql")]
+    let is_graphql = req.uri().path() == "/graphql";
+    // Bodies are parsed by their declared format, falling back to JSON for
+    // legacy clients that send text/plain or no Content-Type at all. With
+    // strict_content_type on, only declared JSON (or MessagePack) is parsed and
+    // the reply must be in a format the client accepts.
+    let (parts, body) = req.into_parts();
+    let headers = &parts.headers;
+    let (body_format, reply_format) = match (rpc.strict_content_type, Format::declared(headers), Format::negotiate(headers)) {
+        (false, _, _) => (Format::of_body(headers), Format::accepted(headers)),
+        (true, None, _) => {
+            let mut response = rest::json_response(hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE, json!({"error": "Request body must be application/json"}));
+            add_cors_headers(&mut response);
+            return Ok(response);
+        },
+        (true, _, None) => {
+            let mut response = rest::json_response(hyper::StatusCode::NOT_ACCEPTABLE, json!({"error": "Replies are application/json or application/msgpack"}));
+            add_cors_headers(&mut response);
+            return Ok(response);
+        },
+        (true, Some(body_format), Some(reply_format)) => (body_format, reply_format),
     };
-    // Process the CORS headers
-    let mut response = match result {
-        Ok(res) => Response::new(Body::from(json!({"result": res}).to_string())),
-        Err(err) => Response::new(Body::from(json!({"error": { "code": err.code, "message": err.message }}).to_string())),
+    let captcha_header = captcha::header(headers).map(str::to_string);
+    let mut whole_body = rpc.pool.get();
+    if let Err(response) = read_body(&rpc, headers, body, &mut whole_body).await? {
+        rpc.pool.put(whole_body);
+        return Ok(response);
+    }
+    #[cfg(feature = "graphql")]
+    if is_graphql {
+        let reply = graphql::execute(&rpc, &whole_body, access).await;
+        rpc.pool.put(whole_body);
+        let mut response = Response::new(Body::from(reply.to_string()));
+        add_cors_headers(&mut response);
+        return Ok(response);
+    }
+    let json_body = body_format.parse(&whole_body);
+    if let Some(sampler) = &rpc.sampler {
+        sampler.offer(json_body.as_ref(), &whole_body);
+    }
+    let body_size = whole_body.len();
+    rpc.pool.put(whole_body);
+    if let Some(Err((method, limit))) = json_body.as_ref().map(|body| rpc.body_limits.check(body, body_size, &rpc.methods)) {
+        let reply = reply(Err(RpcError { code: -32600, message: format!("Request too large: {} takes at most {} bytes", method, limit), data: None }));
+        let mut response = rest::json_response(hyper::StatusCode::PAYLOAD_TOO_LARGE, reply);
+        add_cors_headers(&mut response);
+        return Ok(response);
+    }
+    // The request was counted as one call on arrival; a batch is charged for
+    // the rest of its entries.
+    let calls = json_body.as_ref().map_or(1, |body| rpc.batch.calls(body));
+    if calls > 1 {
+        if let Some(response) = admit(&rpc, access.roles, &principal, subject.as_deref(), calls - 1) {
+            return Ok(response);
+        }
+    }
+    let called = rpc.usage.as_ref().and(json_body.as_ref()).map(usage::methods);
+    let audited = rpc.audit.as_ref().and(json_body.as_ref()).filter(|body| origin::has_write_method(body, &rpc.methods)).cloned();
+    if let (Err(reason), Some(true)) = (&origin, json_body.as_ref().map(|body| origin::has_write_method(body, &rpc.methods))) {
+        let reply = reply(Err(RpcError { code: -8, message: format!("Rejected by policy: {}", reason), data: None }));
+        let mut response = rest::json_response(hyper::StatusCode::FORBIDDEN, reply);
+        add_cors_headers(&mut response);
+        return Ok(response);
+    }
+    // A token sent along in the body is checked up front; without one, calls
+    // to the guarded methods are refused as they are dispatched.
+    let token = json_body.as_ref().and_then(|body| captcha::token(captcha_header.as_deref(), body));
+    if let (Some(captcha), true, Some(token)) = (&rpc.captcha, access.needs_captcha, token) {
+        if let Err(e) = captcha.verify(Some(token), &client).await {
+            let mut response = rest::json_response(hyper::StatusCode::FORBIDDEN, reply(Err(e)));
+            add_cors_headers(&mut response);
+            return Ok(response);
+        }
+        access.needs_captcha = false;
+    }
+
+    let mut deprecation = None;
+    let mut streamed = None;
+    let mut stream_traces = Vec::new();
+    let reply = match json_body {
+        Some(Value::Array(entries)) => batch::handle_batch(entries, rpc.clone(), access, &principal).await,
+        Some(mut req_body) => {
+            deprecation = rpc.migrations.apply(&mut req_body, &principal);
+            if matches!(reply_format, Format::Json) && rpc.is_streamed(&req_body) {
+                match upstream::traced(rpc.debug_upstream, rpc.stream(req_body, access)).await {
+                    (Ok(body), traces) => {
+                        streamed = Some(body);
+                        stream_traces = traces;
+                        Value::Null
+                    },
+                    (Err(e), traces) => with_traces(with_warning(reply(Err(e)), deprecation.clone()), traces),
+                }
+            } else {
+                with_warning(rpc.answer(req_body, access).await, deprecation.clone())
+            }
+        },
+        None => reply(Err(RpcError { code: -32700, message: "Parse error".into(), data: None })),
+    };
+    if let (Some(audit), Some(requests)) = (&rpc.audit, &audited) {
+        audit.record(subject.as_deref().unwrap_or_default(), &client, requests, &reply, &rpc.methods);
+    }
+    if let (Some(usage), Some(called)) = (&rpc.usage, &called) {
+        usage.record(subject.as_deref(), request_origin.as_deref(), called, &reply);
+    }
+    let reply = rpc.branding.with_metadata(reply);
+    let mut response = match streamed {
+        Some(body) => Response::new(body),
+        None => {
+            // Serialize into a pooled scratch buffer so responses don't regrow a fresh Vec each time.
+            let mut out = rpc.pool.get();
+            reply_format.write(&mut out, &reply);
+            Response::new(Body::from(rpc.pool.into_bytes(out)))
+        },
     };
+    response.headers_mut().insert(hyper::header::CONTENT_TYPE, reply_format.content_type().parse().unwrap());
 
     // Add CORS headers
-    response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*".parse().unwrap());
-    response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, PUT, OPTIONS, POST".parse().unwrap());
-    response.headers_mut().insert(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, Authorization, Accept".parse().unwrap());
-    response.headers_mut().insert(hyper::header::ACCESS_CONTROL_MAX_AGE, "3600".parse().unwrap());
-
-    // Set the Referrer Policy header
-    response.headers_mut().insert(hyper::header::REFERRER_POLICY, "origin-when-cross-origin".parse().unwrap());
+    add_cors_headers(&mut response);
+    if let Some(value) = deprecation.and_then(|warning| format!("299 - \"{}\"", warning).parse().ok()) {
+        response.headers_mut().insert(hyper::header::WARNING, value);
+    }
+    // A streamed reply is the daemon's own body, so its call goes in a header.
+    for trace in stream_traces {
+        if let Ok(value) = trace.to_json().to_string().parse() {
+            response.headers_mut().append("x-upstream", value);
+        }
+    }
 
     Ok(response)
 
@@ -131,18 +949,496 @@ async fn main() {
     let port = settings.get::<u16>("server_port").expect("Failed to read 'server_port' from configuration");
     let server_addr = settings.get_str("server_addr").expect("Failed to read 'server_addr' from configuration");
 
-    let addr = (server_addr.parse::<std::net::IpAddr>().unwrap(), port).into();
+    let addrs = listener::addresses(&server_addr, port).expect("Invalid server_addr");
 
-    let make_svc = make_service_fn(|_conn| {
-        let rpc = Arc::new(VerusRPC::new(&url, &user, &password).unwrap());
-        async {
-            Ok::<_, hyper::Error>(service_fn(move |req| handle_req(req, rpc.clone())))
+    let pool_size = settings.get::<usize>("buffer_pool_size").unwrap_or(64);
+    let pool_max_buffer = settings.get::<usize>("buffer_pool_max_buffer").unwrap_or(64 * 1024);
+    let pool = Arc::new(BufferPool::new(pool_size, pool_max_buffer));
+
+    let batch = BatchLimits {
+        max_size: settings.get::<usize>("max_batch_size").unwrap_or(50),
+        concurrency: settings.get::<usize>("batch_concurrency").unwrap_or(4),
+    };
+    let phases = Arc::new(Phases::default());
+    let upstream_opts = UpstreamOptions {
+        keepalive: settings.get::<bool>("upstream_keepalive").unwrap_or(true),
+        pool_idle_timeout: Duration::from_secs(settings.get::<u64>("upstream_pool_idle_timeout").unwrap_or(90)),
+        pool_max_idle: settings.get::<usize>("upstream_pool_max_idle").unwrap_or(32),
+        limits: LimiterOptions {
+            min: settings.get::<usize>("upstream_min_concurrency").unwrap_or(1),
+            max: settings.get::<usize>("upstream_max_concurrency").unwrap_or(64),
+            target_latency: Duration::from_millis(settings.get::<u64>("upstream_target_latency_ms").unwrap_or(2000)),
+            queue_timeout: Duration::from_millis(settings.get::<u64>("upstream_queue_timeout_ms").unwrap_or(5000)),
+        },
+        max_response: settings.get::<usize>("max_response_mb").unwrap_or(256) * 1024 * 1024,
+        method_max_response: settings.get::<HashMap<String, usize>>("response_limits").unwrap_or_default()
+            .into_iter()
+            .map(|(method, mb)| (method, mb * 1024 * 1024))
+            .collect(),
+        chain: ChainExpectation {
+            name: settings.get_str("expected_chain_name").ok(),
+            chain_id: settings.get_str("expected_chain_id").ok(),
+            p2p_port: settings.get::<u64>("expected_p2p_port").ok(),
+            genesis_hash: settings.get_str("expected_genesis_hash").ok(),
+        },
+        timeout: Duration::from_secs(settings.get::<u64>("upstream_timeout").unwrap_or(15)),
+        phases: phases.clone(),
+        warm_connections: settings.get::<usize>("upstream_warm_connections").unwrap_or(0),
+    };
+    let upstream = Upstream::new(&url, &user, &password, upstream_opts).unwrap();
+    // A daemon on the wrong chain is fatal at startup. One that can't be
+    // reached yet is checked when it first answers.
+    if let Err(e) = upstream.verify_chain().await {
+        match upstream.chain_mismatch() {
+            Some(reason) => {
+                eprintln!("Refusing to serve: {}", reason);
+                std::process::exit(1);
+            },
+            None => eprintln!("Could not verify the daemon's chain yet: {}", e.message),
         }
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args, &settings, upstream).await);
+    }
+
+    let methods = MethodTable::new(settings.get::<HashMap<String, Vec<String>>>("annotations").unwrap_or_default())
+        .expect("Invalid annotations");
+    let mut cache_ttls: HashMap<String, Duration> = settings.get::<HashMap<String, u64>>("cache").unwrap_or_default()
+        .into_iter()
+        .map(|(method, ttl)| (method, Duration::from_secs(ttl)))
+        .collect();
+    for (method, ttl) in methods.cache_ttls() {
+        cache_ttls.entry(method.to_string()).or_insert(Duration::from_secs(ttl));
+    }
+    cache_ttls.retain(|method, _| !methods.get(method).never_cache);
+    // Cached results keyed to the tip need it followed.
+    let tip_cached = cache_ttls.iter().any(|(method, ttl)| !ttl.is_zero() && methods.get(method).invalidate_on_block);
+    let max_stale = settings.get::<HashMap<String, u64>>("stale").unwrap_or_default()
+        .into_iter()
+        .map(|(method, secs)| (method, Duration::from_secs(secs)))
+        .collect();
+    let negative = NegativeCaching {
+        ttl: Duration::from_secs(settings.get::<u64>("negative_cache_ttl").unwrap_or(10)),
+        codes: settings.get::<Vec<i32>>("negative_cache_codes").unwrap_or_else(|_| vec![-5]),
+    };
+    let cache = ResponseCache::new(cache_ttls, max_stale, negative, settings.get::<usize>("cache_max_entries").unwrap_or(10_000));
+    let disk_cache = DiskCache::from_settings(&settings).expect("Failed to open disk cache");
+    let runtime = RuntimeAccess::open(settings.get_str("runtime_state").unwrap_or_else(|_| "runtime.json".to_string()))
+        .unwrap_or_else(|e| panic!("Failed to load runtime state: {}", e));
+    let redact_logs = settings.get::<bool>("redact_logs").unwrap_or(true);
+    let audit = match settings.get_str("audit_log") {
+        Ok(path) => Some(AuditLog::open(&path, redact_logs).unwrap_or_else(|e| panic!("Failed to open audit log: {}", e))),
+        Err(_) => None,
+    };
+    let sampler = match settings.get_str("sample_path") {
+        Ok(path) => Some(Sampler::open(
+            &path,
+            settings.get::<f64>("sample_percent").unwrap_or(1.0),
+            settings.get::<HashMap<String, f64>>("sample_rates").unwrap_or_default(),
+            settings.get::<u64>("sample_max_mb").unwrap_or(100) * 1024 * 1024,
+        ).unwrap_or_else(|e| panic!("Failed to open sample file: {}", e))),
+        Err(_) => None,
+    };
+    let indexer = Indexer::open(&settings).await.expect("Failed to open index");
+    let jobs = scheduler::load(settings.get::<Vec<HashMap<String, Value>>>("schedule").unwrap_or_default())
+        .expect("Invalid schedule entry");
+    let history = match (jobs.is_empty(), settings.get_str("history_path")) {
+        (true, _) => None,
+        (false, Ok(path)) => {
+            let retention = Duration::from_secs(settings.get::<u64>("history_retention_days").unwrap_or(30) * 86_400);
+            Some(History::open(&path, retention, &jobs).expect("Failed to open history"))
+        },
+        (false, Err(_)) => panic!("schedule needs history_path to store its samples"),
+    };
+    let fee_rules = FeeRules {
+        min_fee_per_kb: settings.get::<f64>("min_fee_per_kb").unwrap_or(0.0001),
+        export_fee: settings.get::<f64>("export_fee").ok(),
+    };
+    let mempool = MempoolMonitor::new(settings.get::<u64>("block_max_bytes").unwrap_or(2_000_000), fee_rules);
+    let amounts = AmountRules {
+        require_strings: settings.get::<bool>("amounts_as_strings").unwrap_or(false),
+        max_identity_fee: settings.get::<f64>("max_identity_fee").unwrap_or(0.01),
+        max_registration_fee: settings.get::<f64>("max_registration_fee").unwrap_or(1000.0),
+    };
+    let send_policy = SendPolicy {
+        max_amount: settings.get::<f64>("sendcurrency_max_amount").unwrap_or(0.0),
+        currencies: settings.get::<Vec<String>>("sendcurrency_currencies").unwrap_or_default()
+            .into_iter()
+            .map(|currency| currency.to_lowercase())
+            .collect(),
+        denied_addresses: settings.get::<Vec<String>>("sendcurrency_denied_addresses").unwrap_or_default(),
+        require_template: settings.get::<bool>("sendcurrency_require_template").unwrap_or(true),
+        registration_funding: settings.get::<Vec<String>>("registration_funding").unwrap_or_default(),
+    };
+    let broadcaster = Broadcaster::load(settings.get::<Vec<HashMap<String, Value>>>("broadcast_targets").unwrap_or_default())
+        .expect("Invalid broadcast target");
+    let pending_sends = PendingSends::new(
+        settings.get::<f64>("sendcurrency_confirm_above").unwrap_or(0.0),
+        Duration::from_secs(settings.get::<u64>("sendcurrency_confirm_window").unwrap_or(300)),
+    );
+    let txbuilder = TxBuilder {
+        strategy: Strategy::parse(&settings.get_str("coin_selection").unwrap_or_else(|_| "largest-first".to_string()))
+            .expect("Invalid coin_selection"),
+        min_fee_per_kb: settings.get::<f64>("min_fee_per_kb").unwrap_or(0.0001),
+        max_inputs: settings.get::<usize>("build_max_inputs").unwrap_or(500),
+    };
+    let origins = OriginPolicy {
+        enforce: settings.get::<bool>("enforce_origin").unwrap_or(false),
+        allowed: settings.get::<Vec<String>>("allowed_origins").unwrap_or_default()
+            .into_iter()
+            .map(|origin| origin.trim_end_matches('/').to_string())
+            .collect(),
+        api_keys: settings.get::<Vec<String>>("write_api_keys").unwrap_or_default().into_iter().collect(),
+    };
+    let migrations = Migrations::new(
+        settings.get::<HashMap<String, String>>("aliases").unwrap_or_default(),
+        settings.get::<bool>("deprecation_warnings").unwrap_or(true),
+    );
+    let defaults = ParamDefaults::new(settings.get::<HashMap<String, HashMap<String, Value>>>("defaults").unwrap_or_default());
+    let composites = composite::load(settings.get::<HashMap<String, Value>>("composite").unwrap_or_default())
+        .expect("Invalid composite method definition");
+    let currency_page_size = settings.get::<usize>("listcurrencies_page_size").unwrap_or(0);
+    let ranges = RangeLimits {
+        max_blocks: settings.get::<u64>("max_block_range").unwrap_or(0),
+        max_seconds: settings.get::<u64>("max_time_range").unwrap_or(0),
+    };
+    let subscription_limits = SubscriptionLimits {
+        free: settings.get::<usize>("free_subscriptions").unwrap_or(2),
+        max_per_client: settings.get::<usize>("max_subscriptions_per_client").unwrap_or(20),
+        max_filter_terms: settings.get::<usize>("max_filter_terms").unwrap_or(50),
+        api_keys: settings.get::<Vec<String>>("subscription_api_keys").unwrap_or_default().into_iter().collect(),
+        identities: settings.get::<Vec<String>>("subscription_identities").unwrap_or_default()
+            .into_iter()
+            .map(|identity| identity.to_lowercase())
+            .collect(),
+    };
+    let notifiers = alerts::load(settings.get::<Vec<HashMap<String, Value>>>("alert_notifiers").unwrap_or_default())
+        .expect("Invalid alert notifier");
+    let rpc = Arc::new(VerusRPC {
+        upstream,
+        cache,
+        disk_cache,
+        indexer,
+        history,
+        tip: ChainTip::new(
+            Duration::from_secs(settings.get::<u64>("max_tip_age_secs").unwrap_or(1800)),
+            Duration::from_millis(settings.get::<u64>("min_height_wait_ms").unwrap_or(2000)),
+        ),
+        consistency_tokens: settings.get::<bool>("consistency_tokens").unwrap_or(false),
+        mempool,
+        notarizations: NotarizationMonitor::new(settings.get::<u64>("notarization_stall_blocks").unwrap_or(120)),
+        pool,
+        batch,
+        amounts,
+        send_policy,
+        pending_sends,
+        txbuilder,
+        sapling_trees: SaplingTrees::new(settings.get::<usize>("sapling_cache_size").unwrap_or(1000)),
+        route_max_hops: settings.get::<usize>("route_max_hops").unwrap_or(3).max(1),
+        chain_stats: ChainStats::new(),
+        reorgs: ReorgDetector::new(settings.get::<u64>("reorg_window").unwrap_or(100)),
+        docs: MethodDocs::new(),
+        branding: Branding::from_settings(&settings).expect("Invalid deployment metadata"),
+        faucet: Faucet::from_settings(&settings).expect("Invalid faucet settings"),
+        captcha: Captcha::from_settings(&settings).expect("Invalid captcha settings"),
+        listening: Mutex::new(Vec::new()),
+        body_limits: BodyLimits::new(
+            settings.get::<u64>("max_body_size").unwrap_or(10 * 1024 * 1024),
+            settings.get::<HashMap<String, u64>>("body_limits").unwrap_or_default(),
+        ),
+        static_docs: StaticDocs::new(settings.get::<HashMap<String, Value>>("static_json").unwrap_or_default()).expect("Invalid static_json"),
+        body_read_timeout: Some(Duration::from_secs(settings.get::<u64>("body_read_timeout").unwrap_or(30))).filter(|t| !t.is_zero()),
+        validation_timeout: Some(Duration::from_millis(settings.get::<u64>("validation_timeout_ms").unwrap_or(1000))).filter(|t| !t.is_zero()),
+        phases: phases.clone(),
+        registrations: RegistrationTracker::new(
+            settings.get::<bool>("track_registrations").unwrap_or(false),
+            settings.get::<u64>("registration_expiry_blocks").unwrap_or(100),
+        ),
+        offers: OfferTracker::new(settings.get::<bool>("track_offers").unwrap_or(false)),
+        timelocks: TimelockMonitor::new(
+            settings.get::<Vec<String>>("timelock_identities").unwrap_or_default(),
+            settings.get::<bool>("track_timelocks").unwrap_or(false),
+        ),
+        trust: TrustRatings::new(
+            settings.get::<Vec<String>>("trust_raters").unwrap_or_default(),
+            settings.get_str("trust_ratings_key").unwrap_or_else(|_| "vrsc::system.trust.ratings".to_string()),
+        ),
+        broadcaster: Arc::new(broadcaster),
+        origins,
+        audit,
+        sampler,
+        usage: settings.get_str("usage_path").ok()
+            .map(|path| UsageLog::open(&path, settings.get::<u64>("usage_retention_days").unwrap_or(90)).expect("Failed to open usage log")),
+        schemas: SchemaCheck::new(
+            &settings.get_str("schema_check").unwrap_or_else(|_| "off".to_string()),
+            settings.get::<HashMap<String, HashMap<String, String>>>("schemas").unwrap_or_default(),
+        ).expect("Invalid schema_check"),
+        runtime,
+        admin_token: settings.get_str("admin_token").ok().filter(|token| !token.is_empty()),
+        strict_content_type: settings.get::<bool>("strict_content_type").unwrap_or(false),
+        debug_upstream: settings.get::<bool>("debug_upstream").unwrap_or(false),
+        mining_api_keys: settings.get::<Vec<String>>("mining_api_keys").unwrap_or_default().into_iter().collect(),
+        shielded_api_keys: settings.get::<Vec<String>>("shielded_api_keys").unwrap_or_default().into_iter().collect(),
+        registration_api_keys: settings.get::<Vec<String>>("registration_api_keys").unwrap_or_default().into_iter().collect(),
+        debug_api_keys: settings.get::<Vec<String>>("debug_api_keys").unwrap_or_default().into_iter().collect(),
+        priority_api_keys: [("background_api_keys", Priority::Background), ("analytics_api_keys", Priority::Analytics)]
+            .iter()
+            .flat_map(|&(setting, priority)| {
+                settings.get::<Vec<String>>(setting).unwrap_or_default().into_iter().map(move |key| (key, priority))
+            })
+            .collect(),
+        roles: Roles::load(settings.get::<HashMap<String, HashMap<String, Value>>>("roles").unwrap_or_default(), settings.get_str("default_role").ok())
+            .expect("Invalid roles"),
+        methods,
+        stream_methods: settings.get::<Vec<String>>("stream_methods").unwrap_or_else(|_| vec!["getsaplingtree".to_string()]).into_iter().collect(),
+        migrations,
+        defaults,
+        ranges,
+        currency_page_size,
+        composites,
+        events: EventHub::new(settings.get::<usize>("event_replay_size").unwrap_or(1000)),
+        subscriptions: Subscriptions::new(subscription_limits),
+        sessions: Sessions::new(SessionOptions {
+            enabled: settings.get::<bool>("sessions").unwrap_or(false),
+            secret: settings.get_str("session_secret").ok(),
+            ttl: Duration::from_secs(settings.get::<u64>("session_ttl").unwrap_or(900)),
+            refresh_ttl: Duration::from_secs(settings.get::<u64>("session_refresh_ttl").unwrap_or(86_400)),
+            identities: settings.get::<Vec<String>>("session_identities").unwrap_or_default()
+                .into_iter()
+                .map(|identity| identity.to_lowercase())
+                .collect(),
+        }),
+        stats: RequestStats::new(redact_logs),
+        alerts: Alerts::new(notifiers),
+        #[cfg(feature = "graphql")]
+        graphql: graphql::schema(),
     });
 
-    let server = Server::bind(&addr).serve(make_svc);
 
-    if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
+    let webhooks: Vec<Webhook> = settings.get::<Vec<HashMap<String, Value>>>("webhooks").unwrap_or_default()
+        .into_iter()
+        .map(|webhook| Webhook {
+            url: webhook.get("url").and_then(Value::as_str).expect("Webhook without a url").to_string(),
+            events: webhook.get("events").and_then(Value::as_array).into_iter().flatten()
+                .filter_map(|kind| kind.as_str().map(str::to_string))
+                .collect(),
+        })
+        .collect();
+    // Block and identity events come from following the tip, so exporting
+    // any events keeps it followed.
+    let exporting = !webhooks.is_empty()
+        || (cfg!(feature = "kafka") && settings.get::<Vec<String>>("export_kafka_brokers").is_ok())
+        || (cfg!(feature = "nats") && settings.get_str("export_nats_url").is_ok());
+    if !webhooks.is_empty() {
+        tokio::spawn(webhook::forward(rpc.clone(), webhooks));
+    }
+    #[cfg(any(feature = "kafka", feature = "nats"))]
+    let export_options = || export::ExportOptions {
+        prefix: settings.get_str("export_prefix").unwrap_or_else(|_| "verus".to_string()),
+        events: settings.get::<Vec<String>>("export_events").unwrap_or_default(),
+    };
+    #[cfg(feature = "kafka")]
+    if let Ok(brokers) = settings.get::<Vec<String>>("export_kafka_brokers") {
+        tokio::spawn(export::kafka(rpc.clone(), brokers, export_options()));
+    }
+    #[cfg(feature = "nats")]
+    if let Ok(url) = settings.get_str("export_nats_url") {
+        tokio::spawn(export::nats(rpc.clone(), url, export_options()));
+    }
+
+    let alert_rules = AlertRules {
+        daemon_unreachable: Duration::from_secs(settings.get::<u64>("alert_daemon_unreachable_secs").unwrap_or(60)),
+        error_rate: settings.get::<f64>("alert_error_rate").unwrap_or(0.5),
+        error_min_requests: settings.get::<u64>("alert_error_min_requests").unwrap_or(20),
+        error_window: Duration::from_secs(settings.get::<u64>("alert_error_window_secs").unwrap_or(60)),
+        identities: settings.get::<Vec<String>>("alert_identities").unwrap_or_default(),
+    };
+    let tip_interval = Duration::from_secs(settings.get::<u64>("tip_poll_interval").unwrap_or(5));
+    let watch_currencies = settings.get::<Vec<String>>("watch_currencies").unwrap_or_default();
+    let watch_notarizations = settings.get::<Vec<String>>("watch_notarizations").unwrap_or_default();
+    let block_jobs = jobs.iter().any(|job| matches!(job.every, scheduler::Every::Blocks(_)));
+    if rpc.disk_cache.is_some() || rpc.indexer.is_some() || tip_cached || !watch_currencies.is_empty() || !watch_notarizations.is_empty() || exporting || block_jobs || !alert_rules.identities.is_empty() || !rpc.tip.max_age.is_zero() || rpc.registrations.enabled || rpc.offers.enabled || rpc.timelocks.enabled || rpc.trust.has_raters() {
+        tokio::spawn(tip::follow(rpc.clone(), tip_interval));
+    }
+    if !watch_currencies.is_empty() {
+        let threshold = settings.get::<f64>("currency_change_threshold").unwrap_or(0.01);
+        tokio::spawn(currency_watch::watch(rpc.clone(), watch_currencies, threshold, tip_interval));
+    }
+    if rpc.indexer.is_some() {
+        tokio::spawn(indexer::follow(rpc.clone(), settings.get::<u64>("index_start_height").ok(), tip_interval));
+    }
+    if !watch_notarizations.is_empty() {
+        tokio::spawn(notarization::watch(rpc.clone(), watch_notarizations, tip_interval));
+    }
+    if rpc.registrations.enabled {
+        tokio::spawn(registrations::watch(rpc.clone(), tip_interval));
+    }
+    if rpc.offers.enabled {
+        tokio::spawn(offers::watch(rpc.clone(), tip_interval));
+    }
+    if rpc.timelocks.enabled {
+        tokio::spawn(timelocks::watch(rpc.clone(), tip_interval));
+    }
+    if !jobs.is_empty() {
+        tokio::spawn(scheduler::run(rpc.clone(), jobs));
+    }
+    if rpc.usage.is_some() {
+        tokio::spawn(usage::run(rpc.clone(), Duration::from_secs(settings.get::<u64>("usage_flush_interval").unwrap_or(60))));
+    }
+    if rpc.alerts.has_notifiers() {
+        tokio::spawn(alerts::watch(rpc.clone(), alert_rules, Duration::from_secs(settings.get::<u64>("alert_check_interval").unwrap_or(10))));
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Ok(grpc_port) = settings.get::<u16>("grpc_port") {
+        let grpc_addr = settings.get_str("grpc_addr").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let grpc_addr = listener::addresses(&grpc_addr, grpc_port).expect("Invalid grpc_addr")[0];
+        let grpc_profile = listener::Profile {
+            name: "grpc".to_string(),
+            access: Access {
+                scope: Scope::parse(&settings.get_str("grpc_access").unwrap_or_else(|_| "readonly".to_string())).expect("Unknown grpc_access"),
+                mining: settings.get::<bool>("grpc_mining").unwrap_or(false),
+                shielded: settings.get::<bool>("grpc_shielded").unwrap_or(false),
+                registration: settings.get::<bool>("grpc_registration").unwrap_or(false),
+                priority: Priority::Interactive,
+                roles: 0,
+                debug: false,
+                needs_captcha: false,
+                principal: None,
+            },
+            api_keys: settings.get::<Vec<String>>("grpc_api_keys").unwrap_or_default().into_iter().collect(),
+        };
+        if grpc_profile.access.scope != Scope::ReadOnly && grpc_profile.api_keys.is_empty() {
+            panic!("grpc_access other than readonly needs grpc_api_keys");
+        }
+        tokio::spawn(grpc::serve(rpc.clone(), grpc_addr, grpc_profile));
+    }
+
+    if let Ok(admin_port) = settings.get::<u16>("admin_port") {
+        if rpc.admin_token.is_none() {
+            panic!("admin_port needs an admin_token");
+        }
+        let admin_addr = settings.get_str("admin_addr").unwrap_or_else(|_| "127.0.0.1".to_string());
+        for admin_addr in listener::addresses(&admin_addr, admin_port).expect("Invalid admin_addr") {
+            let listener = match listener::bind(admin_addr).and_then(|listener| listener.into_std()) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("admin server error on {}: {}", admin_addr, e);
+                    return;
+                },
+            };
+            let admin_addr = listener.local_addr().unwrap_or(admin_addr);
+            eprintln!("admin listening on {}", admin_addr);
+            let rpc = rpc.clone();
+            let make_admin_svc = make_service_fn(move |_conn| {
+                let rpc = rpc.clone();
+                async {
+                    Ok::<_, hyper::Error>(service_fn(move |req| admin::handle_admin(req, rpc.clone())))
+                }
+            });
+            let server = match Server::from_tcp(listener) {
+                Ok(server) => server,
+                Err(e) => {
+                    eprintln!("admin server error on {}: {}", admin_addr, e);
+                    return;
+                },
+            };
+            tokio::spawn(async move {
+                if let Err(e) = server.serve(make_admin_svc).await {
+                    eprintln!("admin server error: {}", e);
+                }
+            });
+        }
+    }
+
+    let mempool_interval = settings.get::<u64>("mempool_sample_interval").unwrap_or(30);
+    if mempool_interval > 0 {
+        tokio::spawn(mempool::sample(rpc.clone(), Duration::from_secs(mempool_interval)));
+    }
+
+    let preconnected = rpc.upstream.preconnect().await;
+    if preconnected > 0 {
+        eprintln!("{} upstream connections open", preconnected);
+    }
+    let connections = rpc.clone();
+    tokio::spawn(async move { connections.upstream.maintain().await });
+
+    let warm_calls = settings.get::<Vec<Value>>("warm").unwrap_or_default();
+    let warm_tip_block = settings.get::<bool>("warm_tip_block").unwrap_or(false);
+    if !warm_calls.is_empty() || warm_tip_block {
+        let timeout = Duration::from_secs(settings.get::<u64>("warm_timeout").unwrap_or(10));
+        warm::warm(&rpc, warm_calls, warm_tip_block, timeout).await;
+    }
+
+    let trusted_proxies = Arc::new(TrustedProxies::parse(&settings.get::<Vec<String>>("trusted_proxies").unwrap_or_default())
+        .expect("Invalid trusted_proxies"));
+    let proxy_protocol = settings.get::<bool>("proxy_protocol").unwrap_or(false);
+    if proxy_protocol && settings.get::<Vec<String>>("trusted_proxies").unwrap_or_default().is_empty() {
+        panic!("proxy_protocol needs trusted_proxies");
+    }
+    let conn_opts = Arc::new(ConnOptions {
+        tcp_nodelay: settings.get::<bool>("tcp_nodelay").unwrap_or(true),
+        tcp_keepalive: settings.get::<u64>("tcp_keepalive").ok().map(Duration::from_secs),
+        http_keepalive: settings.get::<bool>("http_keepalive").unwrap_or(true),
+        keepalive_timeout: Some(Duration::from_secs(settings.get::<u64>("keepalive_timeout").unwrap_or(60))).filter(|t| !t.is_zero()),
+        max_header_size: settings.get::<usize>("max_header_size").ok().map(|size| size.max(8192)),
+        header_read_timeout: Some(Duration::from_secs(settings.get::<u64>("header_read_timeout").unwrap_or(10))).filter(|t| !t.is_zero()),
+        proxy_protocol: Some(trusted_proxies.clone()).filter(|_| proxy_protocol),
+        phases: phases.clone(),
+    });
+
+    let main_profile = listener::Profile {
+        name: "server".to_string(),
+        access: Access {
+            scope: Scope::parse(&settings.get_str("server_access").unwrap_or_else(|_| "standard".to_string())).expect("Unknown server_access"),
+            mining: settings.get::<bool>("server_mining").unwrap_or(false),
+            shielded: settings.get::<bool>("server_shielded").unwrap_or(false),
+            registration: settings.get::<bool>("server_registration").unwrap_or(false),
+            priority: Priority::parse(&settings.get_str("server_priority").unwrap_or_else(|_| "interactive".to_string())).expect("Unknown server_priority"),
+            roles: 0,
+            debug: false,
+            needs_captcha: false,
+            principal: None,
+        },
+        api_keys: settings.get::<Vec<String>>("server_api_keys").unwrap_or_default().into_iter().collect(),
+    };
+    let mut listeners = vec![(addrs, main_profile)];
+    listeners.extend(listener::load(settings.get::<Vec<HashMap<String, Value>>>("listeners").unwrap_or_default()).expect("Invalid listener"));
+    let mut servers = tokio::task::JoinSet::new();
+    // A hostname is bound on each address it resolves to, all with its profile.
+    let listeners = listeners.into_iter().flat_map(|(addrs, profile)| {
+        let profile = Arc::new(profile);
+        addrs.into_iter().map(move |addr| (addr, profile.clone()))
+    });
+    for (addr, profile) in listeners {
+        let listener = match listener::bind(addr) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("server error on {}: {}", addr, e);
+                return;
+            },
+        };
+        // With port 0 the OS picked the port; it is logged and listed in the
+        // admin /stats so orchestrators and tests can find it.
+        let bound = listener.local_addr().unwrap_or(addr);
+        eprintln!("listening on {}", bound);
+        rpc.listening.lock().unwrap().push(bound);
+        let (rpc, trusted_proxies, profile) = (rpc.clone(), trusted_proxies.clone(), profile);
+        servers.spawn(listener::serve(listener, conn_opts.clone(), move |mut req: Request<Body>, remote| {
+            // Handlers that need the client's address find it in the extensions,
+            // both the connecting one and the client behind any trusted proxies.
+            let client = trusted_proxies.resolve(remote.ip(), req.headers());
+            req.extensions_mut().insert(remote);
+            req.extensions_mut().insert(client);
+            let (rpc, profile) = (rpc.clone(), profile.clone());
+            async move {
+                let mut response = handle_req(req, rpc.clone(), profile).await?;
+                rpc.branding.add_headers(&mut response);
+                Ok(response)
+            }
+        }));
     }
+    servers.join_next().await;
 }
@@ -0,0 +1,189 @@
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::VerusRPC;
+use crate::limiter::Priority;
+use crate::fees::{self, FeeRules};
+
+const SATS_PER_COIN: f64 = 100_000_000.0;
+
+// How many fee rates of recently mined transactions are kept.
+const MINED_WINDOW: usize = 1000;
+
+// Most new transactions fetched and streamed per sample.
+const MAX_PUBLISHED_PER_SAMPLE: usize = 500;
+
+// Lower bounds of the fee rate buckets, in sats per byte. The last bucket is open ended.
+const RATE_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+// Latest fee histogram built from a verbose getrawmempool sample, and the fee
+// recommendation derived from it. Transactions that were in the previous
+// sample but are gone from this one are taken as mined, and their fee rates
+// feed the recommendation.
+pub struct MempoolMonitor {
+    block_max_bytes: u64,
+    rules: FeeRules,
+    snapshot: Mutex<Option<Value>>,
+    recommendation: Mutex<Option<Value>>,
+    previous: Mutex<HashMap<String, f64>>,
+    mined_rates: Mutex<VecDeque<f64>>,
+}
+
+impl MempoolMonitor {
+    pub fn new(block_max_bytes: u64, rules: FeeRules) -> MempoolMonitor {
+        MempoolMonitor {
+            block_max_bytes,
+            rules,
+            snapshot: Mutex::new(None),
+            recommendation: Mutex::new(None),
+            previous: Mutex::new(HashMap::new()),
+            mined_rates: Mutex::new(VecDeque::with_capacity(MINED_WINDOW)),
+        }
+    }
+
+    pub fn snapshot(&self) -> Option<Value> {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    pub fn recommendation(&self) -> Option<Value> {
+        self.recommendation.lock().unwrap().clone()
+    }
+
+    // Rebuilds the snapshot and returns the txids that weren't in the previous sample.
+    fn update(&self, txs: &Map<String, Value>) -> Vec<String> {
+        let mut current = HashMap::with_capacity(txs.len());
+        let mut rates = Vec::with_capacity(txs.len());
+        let mut buckets = vec![(0u64, 0u64); RATE_BUCKETS.len()];
+        let mut total_bytes = 0u64;
+        let mut total_fees = 0.0;
+        for (txid, tx) in txs {
+            let size = tx["size"].as_u64().unwrap_or(0);
+            let fee = tx["fee"].as_f64().unwrap_or(0.0);
+            if size == 0 {
+                continue;
+            }
+            let rate = fee * SATS_PER_COIN / size as f64;
+            let bucket = RATE_BUCKETS.iter().rposition(|min| rate >= *min).unwrap_or(0);
+            buckets[bucket].0 += 1;
+            buckets[bucket].1 += size;
+            total_bytes += size;
+            total_fees += fee;
+            rates.push(rate);
+            current.insert(txid.clone(), rate);
+        }
+
+        let previous = std::mem::replace(&mut *self.previous.lock().unwrap(), current);
+        let arrived = txs.keys().filter(|txid| !previous.contains_key(*txid)).cloned().collect();
+        let mut mined_rates = self.mined_rates.lock().unwrap();
+        for (txid, rate) in previous {
+            if !txs.contains_key(&txid) {
+                if mined_rates.len() == MINED_WINDOW {
+                    mined_rates.pop_front();
+                }
+                mined_rates.push_back(rate);
+            }
+        }
+        drop(mined_rates);
+        rates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let histogram: Vec<Value> = buckets.iter().enumerate().map(|(i, (count, bytes))| json!({
+            "min_rate": RATE_BUCKETS[i],
+            "max_rate": RATE_BUCKETS.get(i + 1),
+            "count": count,
+            "bytes": bytes,
+        })).collect();
+
+        // How many full blocks it would take to clear what is waiting now.
+        let blocks_to_clear = total_bytes as f64 / self.block_max_bytes as f64;
+        let level = if blocks_to_clear < 0.5 {
+            "low"
+        } else if blocks_to_clear < 2.0 {
+            "medium"
+        } else {
+            "high"
+        };
+
+        let updated = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        *self.snapshot.lock().unwrap() = Some(json!({
+            "updated": updated,
+            "tx_count": rates.len(),
+            "total_bytes": total_bytes,
+            "total_fees": total_fees,
+            "median_rate": rates.get(rates.len() / 2),
+            "histogram": histogram,
+            "congestion": { "blocks_to_clear": blocks_to_clear, "level": level },
+        }));
+        arrived
+    }
+
+    fn recommend(&self, estimates: [Option<f64>; 3]) {
+        let snapshot = match self.snapshot() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        let mined_rates: Vec<f64> = self.mined_rates.lock().unwrap().iter().copied().collect();
+        *self.recommendation.lock().unwrap() = Some(fees::recommend(estimates, &mined_rates, &snapshot, &self.rules));
+    }
+}
+
+// Addresses, currencies and per-currency output totals of a verbose
+// transaction, which is what streaming clients filter on. The chain's own coin
+// is totalled as "native".
+fn summarize(txid: &str, entry: &Value, tx: &Value) -> Value {
+    let mut addresses = BTreeSet::new();
+    let mut totals: BTreeMap<String, f64> = BTreeMap::new();
+    for output in tx["vout"].as_array().into_iter().flatten() {
+        let script = &output["scriptPubKey"];
+        addresses.extend(script["addresses"].as_array().into_iter().flatten().filter_map(Value::as_str));
+        if let Some(value) = output["value"].as_f64().filter(|value| *value > 0.0) {
+            *totals.entry("native".to_string()).or_default() += value;
+        }
+        for (currency, value) in script["reserveoutput"]["currencyvalues"].as_object().into_iter().flatten() {
+            *totals.entry(currency.clone()).or_default() += value.as_f64().unwrap_or(0.0);
+        }
+    }
+    json!({
+        "txid": txid,
+        "size": entry["size"],
+        "fee": entry["fee"],
+        "addresses": addresses,
+        "currencies": totals.keys().collect::<Vec<_>>(),
+        "totals": totals,
+    })
+}
+
+// Publishes the transactions that arrived since the last sample, fetching each
+// one only while someone is subscribed.
+async fn publish_arrivals(rpc: &VerusRPC, txs: &Map<String, Value>, arrived: Vec<String>) {
+    for txid in arrived.into_iter().take(MAX_PUBLISHED_PER_SAMPLE) {
+        if !rpc.events.has_subscribers() {
+            return;
+        }
+        if let Ok(tx) = rpc.upstream.call_as("getrawtransaction", &[json!(txid), json!(1)], Priority::Background).await {
+            rpc.events.publish("mempool_tx", summarize(&txid, &txs[&txid], &tx));
+        }
+    }
+}
+
+// estimatefee answers -1 when it doesn't have enough data.
+async fn estimate_fee(rpc: &VerusRPC, blocks: u64) -> Option<f64> {
+    rpc.upstream.call_as("estimatefee", &[json!(blocks)], Priority::Background).await.ok()?.as_f64().filter(|fee| *fee > 0.0)
+}
+
+pub async fn sample(rpc: Arc<VerusRPC>, interval: Duration) {
+    loop {
+        match rpc.upstream.call_as("getrawmempool", &[json!(true)], Priority::Background).await {
+            Ok(Value::Object(txs)) => {
+                let arrived = rpc.mempool.update(&txs);
+                publish_arrivals(&rpc, &txs, arrived).await;
+                let estimates = [estimate_fee(&rpc, 25).await, estimate_fee(&rpc, 6).await, estimate_fee(&rpc, 2).await];
+                rpc.mempool.recommend(estimates);
+            },
+            Ok(_) => eprintln!("mempool sample: unexpected getrawmempool reply"),
+            Err(e) => eprintln!("mempool sample failed: {}", e.message),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
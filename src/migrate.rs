@@ -0,0 +1,119 @@
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use crate::indexer::unix_time;
+
+// Callers told apart per legacy behaviour; calls from any more are counted
+// under "(other)".
+const MAX_CALLERS: usize = 1000;
+// Callers listed per behaviour in /stats; /deprecations lists them all.
+const TOP_CALLERS: usize = 10;
+
+// Who still relies on one legacy behaviour: calls per caller, with when each
+// last did.
+#[derive(Default)]
+struct Usage {
+    calls: u64,
+    callers: HashMap<String, (u64, i64)>,
+}
+
+// Rewrites requests that use old method names or param forms into their
+// current form before they are handled, returning a deprecation warning for the
+// client when something was rewritten (unless `warn` is off). Every rewrite is
+// counted per behaviour and caller, so a behaviour can be removed once nobody
+// uses it.
+pub struct Migrations {
+    // Old method name to the current one.
+    aliases: HashMap<String, String>,
+    warn: bool,
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl Migrations {
+    pub fn new(aliases: HashMap<String, String>, warn: bool) -> Migrations {
+        Migrations { aliases, warn, usage: Mutex::new(HashMap::new()) }
+    }
+
+    // `caller` is the API key subject, session or client address the
+    // request's rates are counted by.
+    pub fn apply(&self, req_body: &mut Value, caller: &str) -> Option<String> {
+        let mut warnings = Vec::new();
+
+        if let Some(current) = req_body["method"].as_str().and_then(|method| self.aliases.get(method)) {
+            let old = req_body["method"].as_str().unwrap_or_default();
+            self.record(&format!("alias:{}", old), caller);
+            warnings.push(format!("{} is deprecated, use {}", old, current));
+            req_body["method"] = Value::String(current.clone());
+        }
+
+        // getblock in JS used to allow the height to be passed as a number and
+        // the former JS rpc server wouldn't care, while the daemon wants a string.
+        if req_body["method"] == "getblock" {
+            if let Some(height) = req_body["params"].get(0).and_then(Value::as_i64) {
+                self.record("getblock-numeric-height", caller);
+                warnings.push("getblock with a numeric height is deprecated, pass it as a string".to_string());
+                req_body["params"][0] = Value::String(height.to_string());
+            }
+        }
+
+        if warnings.is_empty() || !self.warn {
+            None
+        } else {
+            Some(warnings.join("; "))
+        }
+    }
+
+    fn record(&self, behaviour: &str, caller: &str) {
+        let mut usage = self.usage.lock().unwrap();
+        let usage = match usage.get_mut(behaviour) {
+            Some(usage) => usage,
+            None => usage.entry(behaviour.to_string()).or_default(),
+        };
+        usage.calls += 1;
+        let caller = match usage.callers.contains_key(caller) || usage.callers.len() < MAX_CALLERS {
+            true => caller,
+            false => "(other)",
+        };
+        let entry = usage.callers.entry(caller.to_string()).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 = unix_time();
+    }
+
+    // Per behaviour, its calls and its callers, busiest first: the first
+    // `top` of them, or all.
+    fn report(&self, top: Option<usize>) -> Value {
+        let usage = self.usage.lock().unwrap();
+        let behaviours: serde_json::Map<String, Value> = usage.iter()
+            .map(|(behaviour, usage)| {
+                let mut callers: Vec<(&String, &(u64, i64))> = usage.callers.iter().collect();
+                callers.sort_by_key(|(_, (calls, _))| std::cmp::Reverse(*calls));
+                let listed: Vec<Value> = callers.iter()
+                    .take(top.unwrap_or(usize::MAX))
+                    .map(|(caller, (calls, last_seen))| json!({ "caller": caller, "calls": calls, "last_seen": last_seen }))
+                    .collect();
+                (behaviour.clone(), json!({ "calls": usage.calls, "callers": callers.len(), "by_caller": listed }))
+            })
+            .collect();
+        Value::Object(behaviours)
+    }
+
+    pub fn summary(&self) -> Value {
+        self.report(Some(TOP_CALLERS))
+    }
+
+    pub fn callers(&self) -> Value {
+        self.report(None)
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        let usage = self.usage.lock().unwrap();
+        let mut behaviours: Vec<_> = usage.iter().collect();
+        behaviours.sort_by(|a, b| a.0.cmp(b.0));
+        writeln!(out, "# TYPE deprecated_calls_total counter").unwrap();
+        for (behaviour, usage) in behaviours {
+            writeln!(out, "deprecated_calls_total{{behaviour=\"{}\"}} {}", behaviour, usage.calls).unwrap();
+        }
+    }
+}
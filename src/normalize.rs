@@ -0,0 +1,89 @@
+use serde_json::{Value, json};
+
+// Values the daemon assumes when trailing params are omitted. Positions that
+// have no default are null; filling stops at the first of those.
+fn defaults(method: &str) -> Vec<Value> {
+    match method {
+        "getblock" => vec![Value::Null, json!(true)],
+        "getrawtransaction" => vec![Value::Null, json!(0)],
+        "gettxout" => vec![Value::Null, Value::Null, json!(true)],
+        "decoderawtransaction" | "decodescript" => vec![Value::Null, json!(false)],
+        "verifymessage" | "verifyhash" => vec![Value::Null, Value::Null, Value::Null, json!(false)],
+        _ => vec![],
+    }
+}
+
+const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// Transparent and identity addresses are case sensitive and must be left alone.
+fn is_address(s: &str) -> bool {
+    s.len() == 34 && (s.starts_with('R') || s.starts_with('i')) && s.chars().all(|c| BASE58.contains(c))
+}
+
+fn is_hex_hash(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Identity names are case insensitive and resolve the same with or without the
+// trailing `@`.
+fn identity_name(name: &mut String) {
+    if is_address(name) {
+        return;
+    }
+    *name = name.to_lowercase();
+    if !name.ends_with('@') {
+        name.push('@');
+    }
+}
+
+fn currency_name(name: &mut String) {
+    if !is_address(name) {
+        *name = name.to_lowercase();
+    }
+}
+
+fn lowercase_hashes(value: &mut Value) {
+    match value {
+        Value::String(s) if is_hex_hash(s) => s.make_ascii_lowercase(),
+        Value::Array(items) => items.iter_mut().for_each(lowercase_hashes),
+        Value::Object(map) => map.values_mut().for_each(lowercase_hashes),
+        _ => {},
+    }
+}
+
+// Cache key for a call. Requests that the daemon would answer identically map
+// to the same key: omitted trailing params are filled with their defaults,
+// identity and currency names are case folded and block/tx hashes lowercased.
+// Object keys come out sorted since serde_json maps are ordered.
+pub fn cache_key(method: &str, params: &[Value]) -> String {
+    let mut params = params.to_vec();
+    for default in defaults(method).into_iter().skip(params.len()) {
+        if default.is_null() {
+            break;
+        }
+        params.push(default);
+    }
+
+    match (method, params.get_mut(0)) {
+        ("getidentity", Some(Value::String(name))) => identity_name(name),
+        ("getcurrency" | "getcurrencystate" | "getinitialcurrencystate" | "getlaunchinfo", Some(Value::String(name))) => currency_name(name),
+        _ => {},
+    }
+    // A signed message is hashed as given, so its case matters.
+    if !matches!(method, "verifymessage" | "verifysignature") {
+        params.iter_mut().for_each(lowercase_hashes);
+    }
+
+    format!("{}:{}", method, Value::Array(params))
+}
+
+// Whether a call to a method annotated invalidate-on-block depends on the tip
+// with these params. Signature checks only do when made against the identity's
+// latest state, rather than its state when it signed.
+pub fn is_tip_dependent(method: &str, params: &[Value]) -> bool {
+    match method {
+        "verifymessage" | "verifyhash" => params.get(3) == Some(&json!(true)),
+        "verifysignature" => params.first().is_some_and(|options| options["checklatest"] == json!(true)),
+        _ => true,
+    }
+}
@@ -0,0 +1,121 @@
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::VerusRPC;
+use crate::limiter::Priority;
+
+// Where a system's notarizations stand, as last checked.
+struct Status {
+    confirmed_txid: String,
+    // Height of the block that holds the last confirmed notarization.
+    confirmed_at: Option<u64>,
+    summary: Value,
+    stalled: bool,
+}
+
+// Notarization freshness per watched cross-chain system, served at
+// GET /notarizations.
+pub struct NotarizationMonitor {
+    stall_blocks: u64,
+    statuses: Mutex<BTreeMap<String, Status>>,
+}
+
+impl NotarizationMonitor {
+    pub fn new(stall_blocks: u64) -> NotarizationMonitor {
+        NotarizationMonitor { stall_blocks, statuses: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn summary(&self) -> Value {
+        let statuses = self.statuses.lock().unwrap();
+        Value::Object(statuses.iter().map(|(system, status)| (system.clone(), status.summary.clone())).collect())
+    }
+}
+
+// How many notarizations on the best fork follow the last confirmed one.
+fn pending(data: &Value) -> usize {
+    let confirmed = data["lastconfirmed"].as_i64().unwrap_or(-1);
+    let best = data["bestchain"].as_u64().unwrap_or(0) as usize;
+    let fork = data["forks"][best].as_array().map(Vec::as_slice).unwrap_or_default();
+    match fork.iter().position(|index| index.as_i64() == Some(confirmed)) {
+        Some(position) => fork.len() - position - 1,
+        None => fork.len(),
+    }
+}
+
+async fn check(rpc: &VerusRPC, system: &str, tip: u64) {
+    let data = match rpc.upstream.call_as("getnotarizationdata", &[json!(system)], Priority::Background).await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("notarization monitor: getnotarizationdata {} failed: {}", system, e.message);
+            return;
+        },
+    };
+    let confirmed = data["lastconfirmed"].as_u64().and_then(|index| data["notarizations"].get(index as usize));
+    let confirmed_txid = confirmed.and_then(|entry| entry["txid"].as_str()).unwrap_or_default().to_string();
+
+    // The confirming block only has to be looked up when the confirmed notarization changes.
+    let known = rpc.notarizations.statuses.lock().unwrap().get(system)
+        .filter(|status| status.confirmed_txid == confirmed_txid)
+        .map(|status| status.confirmed_at);
+    let confirmed_at = match known {
+        Some(confirmed_at) => confirmed_at,
+        None if confirmed_txid.is_empty() => None,
+        None => rpc.upstream.call_as("getrawtransaction", &[json!(confirmed_txid), json!(1)], Priority::Background).await.ok()
+            .and_then(|tx| tx["height"].as_u64()),
+    };
+
+    let notarization = confirmed.map(|entry| &entry["notarization"]);
+    let proof_root = notarization
+        .and_then(|notarization| notarization["proofroots"].as_array())
+        .and_then(|roots| roots.iter().find(|root| root["systemid"].as_str().is_some_and(|id| id.eq_ignore_ascii_case(system))).or(roots.first()));
+    let blocks_since = confirmed_at.map(|height| tip.saturating_sub(height));
+    let monitor = &rpc.notarizations;
+    let stalled = monitor.stall_blocks > 0 && blocks_since.is_none_or(|blocks| blocks >= monitor.stall_blocks);
+    let summary = json!({
+        "height": tip,
+        "confirmed": {
+            "txid": confirmed.map(|entry| &entry["txid"]),
+            "block": confirmed_at,
+            "notarizationheight": notarization.map(|notarization| &notarization["notarizationheight"]),
+            "proofroot": proof_root,
+        },
+        "blocks_since_confirmed": blocks_since,
+        "pending": pending(&data),
+        "forks": data["forks"].as_array().map_or(0, Vec::len),
+        "stalled": stalled,
+    });
+
+    let was_stalled = {
+        let mut statuses = monitor.statuses.lock().unwrap();
+        let was_stalled = statuses.get(system).is_some_and(|status| status.stalled);
+        statuses.insert(system.to_string(), Status { confirmed_txid, confirmed_at, summary: summary.clone(), stalled });
+        was_stalled
+    };
+    if stalled && !was_stalled {
+        match blocks_since {
+            Some(blocks) => eprintln!("notarization monitor: no confirmed notarization of {} for {} blocks", system, blocks),
+            None => eprintln!("notarization monitor: no confirmed notarization of {} found", system),
+        }
+        rpc.events.publish("notarization_stalled", json!({ "system": system, "status": summary }));
+    } else if !stalled && was_stalled {
+        rpc.events.publish("notarization_recovered", json!({ "system": system, "status": summary }));
+    }
+}
+
+// Checks the notarization data of each system whenever the tip moves.
+pub async fn watch(rpc: Arc<VerusRPC>, systems: Vec<String>, interval: Duration) {
+    let mut last_height = None;
+    loop {
+        tokio::time::sleep(interval).await;
+        let height = match rpc.tip.height() {
+            Some(height) if Some(height) != last_height => height,
+            _ => continue,
+        };
+        last_height = Some(height);
+        for system in &systems {
+            check(&rpc, system, height).await;
+        }
+    }
+}
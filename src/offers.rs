@@ -0,0 +1,296 @@
+use hyper::{Body, Request, Response, StatusCode};
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+use crate::events;
+use crate::hash;
+use crate::indexer::unix_time;
+use crate::limiter::Priority;
+use crate::rest::{self, json_response};
+
+// The output of an offer transaction that holds the offer; taking or closing
+// the offer spends it.
+const OFFER_OUTPUT: i64 = 0;
+// Offers are forgotten this long after they last changed, once finished.
+const KEEP_FINISHED_SECS: i64 = 86_400;
+// At most this many offers are tracked; the oldest finished ones go first.
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Open,
+    // The offer output was spent. Closing an offer spends it too, so an offer
+    // its maker closed also ends up here.
+    Accepted,
+    // Past its expiry height without being spent.
+    Expired,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Open => "open",
+            Status::Accepted => "accepted",
+            Status::Expired => "expired",
+        }
+    }
+}
+
+struct Offer {
+    status: Status,
+    expiry_height: Option<u64>,
+    // The transaction that spent the offer output, and its height.
+    spent_by: Option<String>,
+    spent_height: Option<u64>,
+    updated: i64,
+}
+
+impl Offer {
+    fn to_json(&self, txid: &str) -> Value {
+        json!({
+            "txid": txid,
+            "status": self.status.name(),
+            "expiry_height": self.expiry_height,
+            "spent_by": { "txid": self.spent_by, "height": self.spent_height },
+            "updated": self.updated,
+        })
+    }
+
+    fn set(&mut self, status: Status) {
+        self.status = status;
+        self.updated = unix_time();
+    }
+}
+
+// A field of a getoffers entry, which may be on the entry or on its "offer".
+fn field<'a>(entry: &'a Value, key: &str) -> &'a Value {
+    match &entry[key] {
+        Value::Null => &entry["offer"][key],
+        value => value,
+    }
+}
+
+// Marketplace offers seen through the proxy: those listed by GET /offers and
+// those prepared with makeoffer, keyed by the offer txid. An offer is open until
+// its offer output is spent (accepted) or the chain passes its expiry height
+// (expired). With the indexer on, spends are picked up as blocks are indexed;
+// otherwise open offers are checked with gettxout and getspentinfo at every
+// new block.
+pub struct OfferTracker {
+    pub enabled: bool,
+    offers: Mutex<HashMap<String, Offer>>,
+}
+
+impl OfferTracker {
+    pub fn new(enabled: bool) -> OfferTracker {
+        OfferTracker { enabled, offers: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn tracks(&self, method: &str) -> bool {
+        self.enabled && method == "makeoffer"
+    }
+
+    fn status(&self, txid: &str) -> Option<Value> {
+        self.offers.lock().unwrap().get(txid).map(|offer| offer.to_json(txid))
+    }
+
+    // Starts tracking an offer unless it already is.
+    fn track(&self, txid: &str, expiry_height: Option<u64>, tip: Option<u64>) {
+        let mut offers = self.offers.lock().unwrap();
+        if let Some(offer) = offers.get_mut(txid) {
+            offer.expiry_height = offer.expiry_height.or(expiry_height);
+            return;
+        }
+        let now = unix_time();
+        offers.retain(|_, offer| offer.status == Status::Open || offer.updated + KEEP_FINISHED_SECS > now);
+        if offers.len() >= MAX_TRACKED {
+            let oldest = offers.iter()
+                .filter(|(_, offer)| offer.status != Status::Open)
+                .min_by_key(|(_, offer)| offer.updated)
+                .map(|(txid, _)| txid.clone());
+            match oldest {
+                Some(oldest) => offers.remove(&oldest),
+                None => return,
+            };
+        }
+        let status = match (expiry_height, tip) {
+            (Some(expiry), Some(tip)) if tip > expiry => Status::Expired,
+            _ => Status::Open,
+        };
+        offers.insert(txid.to_string(), Offer { status, expiry_height, spent_by: None, spent_height: None, updated: now });
+    }
+
+    fn spent(&self, txid: &str, spent_by: Option<String>, height: Option<u64>) {
+        if let Some(offer) = self.offers.lock().unwrap().get_mut(txid).filter(|offer| offer.status != Status::Accepted) {
+            offer.spent_by = spent_by;
+            offer.spent_height = height;
+            offer.set(Status::Accepted);
+        }
+    }
+
+    // Records the offer a makeoffer call prepared. makeoffer is only allowed
+    // with returntx, so the reply is the offer transaction for the client to
+    // broadcast; the offer is tracked under that transaction's txid.
+    pub fn observe(&self, params: &[Value], result: &Result<Value, RpcError>, tip: Option<u64>) {
+        let raw = match result {
+            Ok(result) => result["hex"].as_str().or_else(|| result.as_str()).and_then(|raw| hex::decode(raw).ok()),
+            Err(_) => None,
+        };
+        if let Some(raw) = raw {
+            let expiry = params.get(1).and_then(|offer| offer["expiryheight"].as_u64());
+            self.track(&hash::txid(&raw), expiry, tip);
+        }
+    }
+
+    // Marks the tracked offers whose output a newly indexed block spends.
+    pub fn index_block(&self, height: u64, block: &Value) {
+        if !self.enabled {
+            return;
+        }
+        for tx in block["tx"].as_array().into_iter().flatten() {
+            for input in tx["vin"].as_array().into_iter().flatten() {
+                if let (Some(txid), Some(OFFER_OUTPUT)) = (input["txid"].as_str(), input["vout"].as_i64()) {
+                    if self.offers.lock().unwrap().contains_key(txid) {
+                        self.spent(txid, tx["txid"].as_str().map(str::to_string), Some(height));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Whether the offer output of `txid` is spent, and by which transaction at
+// what height when the daemon's spent index knows. None if the daemon doesn't
+// know the transaction or can't be asked.
+async fn offer_spent(rpc: &VerusRPC, txid: &str) -> Option<Option<(Option<String>, Option<u64>)>> {
+    let unspent = rpc.upstream.call_as("gettxout", &[json!(txid), json!(OFFER_OUTPUT), json!(true)], Priority::Background).await.ok()?;
+    if !unspent.is_null() {
+        return Some(None);
+    }
+    match rpc.upstream.call_as("getspentinfo", &[json!({"txid": txid, "index": OFFER_OUTPUT})], Priority::Background).await {
+        Ok(spent) => Some(Some((spent["txid"].as_str().map(str::to_string), spent["height"].as_u64()))),
+        // No spent index: make sure the transaction exists at all.
+        Err(_) => match rpc.upstream.call_as("getrawtransaction", &[json!(txid), json!(1)], Priority::Background).await {
+            Ok(_) => Some(Some((None, None))),
+            Err(_) => None,
+        },
+    }
+}
+
+async fn check(rpc: &VerusRPC, tip: u64) {
+    let tracker = &rpc.offers;
+    let open: Vec<(String, Option<u64>)> = tracker.offers.lock().unwrap().iter()
+        .filter(|(_, offer)| offer.status == Status::Open)
+        .map(|(txid, offer)| (txid.clone(), offer.expiry_height))
+        .collect();
+    for (txid, expiry) in open {
+        if rpc.indexer.is_none() {
+            if let Some(Some((spent_by, height))) = offer_spent(rpc, &txid).await {
+                tracker.spent(&txid, spent_by, height);
+                continue;
+            }
+        }
+        if expiry.is_some_and(|expiry| tip > expiry) {
+            if let Some(offer) = tracker.offers.lock().unwrap().get_mut(&txid).filter(|offer| offer.status == Status::Open) {
+                offer.set(Status::Expired);
+            }
+        }
+    }
+}
+
+// Checks the open offers whenever the tip moves.
+pub async fn watch(rpc: Arc<VerusRPC>, interval: Duration) {
+    let mut last_height = None;
+    loop {
+        tokio::time::sleep(interval).await;
+        let height = match rpc.tip.height() {
+            Some(height) if Some(height) != last_height => height,
+            _ => continue,
+        };
+        last_height = Some(height);
+        check(&rpc, height).await;
+    }
+}
+
+// GET /offers/<currency or identity>[?iscurrency=true][&for=<currency or
+// identity>] lists the open offers for or in a currency or identity, as
+// getoffers does, optionally only those involving a second one, flattened
+// into one list with each offer's txid, expiry and status.
+// GET /offers/tx/<txid> reports a tracked offer, starting to track it if it
+// isn't yet.
+pub async fn handle(path: &str, req: &Request<Body>, rpc: &Arc<VerusRPC>, access: Access) -> Response<Body> {
+    let path = path.trim_start_matches("/offers/");
+    if let Some(txid) = path.strip_prefix("tx/") {
+        return offer(txid, rpc).await;
+    }
+    let query = events::query(req.uri());
+    let is_currency = query.get("iscurrency").is_some_and(|value| value == "true" || value == "1");
+    let pair = match query.get("for") {
+        Some(other) => match rest::resolve(rpc, "getcurrency", other, access).await {
+            Ok(other) => Some(other),
+            Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+        },
+        None => None,
+    };
+    let listed = match rpc.handle_call_as("getoffers".to_string(), vec![json!(path), json!(is_currency), json!(false)], access).await {
+        Ok(listed) => listed,
+        Err(e) => return json_response(StatusCode::BAD_GATEWAY, json!({"error": e.message})),
+    };
+    let tip = rpc.tip.height();
+    let mut offers = Vec::new();
+    // Offers come grouped under keys naming both sides of the trade.
+    for (group, entries) in listed.as_object().into_iter().flatten() {
+        if pair.as_ref().is_some_and(|pair| !group.contains(pair.as_str())) {
+            continue;
+        }
+        for entry in entries.as_array().into_iter().flatten() {
+            let txid = field(entry, "txid").as_str();
+            let expiry = field(entry, "blockexpiry").as_u64().or_else(|| field(entry, "expiryheight").as_u64());
+            let tracked = txid.and_then(|txid| {
+                if rpc.offers.enabled {
+                    rpc.offers.track(txid, expiry, tip);
+                }
+                rpc.offers.status(txid)
+            });
+            let status = match (&tracked, expiry, tip) {
+                (Some(tracked), _, _) => tracked["status"].clone(),
+                (None, Some(expiry), Some(tip)) if tip > expiry => json!(Status::Expired.name()),
+                _ => json!(Status::Open.name()),
+            };
+            offers.push(json!({
+                "pair": group,
+                "txid": txid,
+                "expiry_height": expiry,
+                "status": status,
+                "offer": entry,
+            }));
+        }
+    }
+    json_response(StatusCode::OK, json!({"id": path, "iscurrency": is_currency, "for": pair, "height": tip, "offers": offers}))
+}
+
+async fn offer(txid: &str, rpc: &Arc<VerusRPC>) -> Response<Body> {
+    if !rpc.offers.enabled {
+        return json_response(StatusCode::NOT_FOUND, json!({"error": "Offer tracking is not enabled"}));
+    }
+    if let Some(status) = rpc.offers.status(txid) {
+        return json_response(StatusCode::OK, status);
+    }
+    let expiry = match rpc.upstream.call_as("getrawtransaction", &[json!(txid), json!(1)], Priority::Background).await {
+        Ok(tx) => tx["expiryheight"].as_u64().filter(|expiry| *expiry > 0),
+        Err(_) => return json_response(StatusCode::NOT_FOUND, json!({"error": "No offer with that txid is known"})),
+    };
+    rpc.offers.track(txid, expiry, rpc.tip.height());
+    if let Some(Some((spent_by, height))) = offer_spent(rpc, txid).await {
+        rpc.offers.spent(txid, spent_by, height);
+    }
+    match rpc.offers.status(txid) {
+        Some(status) => json_response(StatusCode::OK, status),
+        None => json_response(StatusCode::SERVICE_UNAVAILABLE, json!({"error": "Too many offers are tracked"})),
+    }
+}
@@ -0,0 +1,287 @@
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{Map, Value, json};
+
+use crate::VerusRPC;
+use crate::allowlist::{Access, Rule};
+use crate::rest::json_response;
+
+// Where a parameter goes, with its JSON Schema type and whether it must be given.
+#[derive(Clone, Copy)]
+struct Param {
+    name: &'static str,
+    path: bool,
+    kind: &'static str,
+    required: bool,
+}
+
+const fn path(name: &'static str) -> Param {
+    Param { name, path: true, kind: "string", required: true }
+}
+
+const fn query(name: &'static str, kind: &'static str) -> Param {
+    Param { name, path: false, kind, required: false }
+}
+
+// What a route needs switched on to be served at all.
+#[derive(Clone, Copy, PartialEq)]
+enum Needs {
+    Nothing,
+    Indexer,
+    History,
+    Faucet,
+}
+
+struct Route {
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    params: &'static [Param],
+    needs: Needs,
+}
+
+const PAGE: [Param; 2] = [query("start", "integer"), query("count", "integer")];
+const STREAM: &[Param] = &[
+    path("stream"), query("address", "string"), query("currency", "string"), query("min_value", "number"),
+    query("since", "integer"), query("api_key", "string"), query("identity", "string"), query("timestamp", "integer"),
+    query("signature", "string"),
+];
+
+// The GET routes `rest::route` serves, in its order. Keep the two in step.
+const ROUTES: &[Route] = &[
+    Route { path: "/mempool/fees", tag: "chain", summary: "Fee estimates from the sampled mempool", params: &[], needs: Needs::Nothing },
+    Route { path: "/readyz", tag: "status", summary: "Whether the proxy and its daemon are ready to serve", params: &[], needs: Needs::Nothing },
+    Route { path: "/notarizations", tag: "chain", summary: "Notarization freshness per watched cross-chain system", params: &[], needs: Needs::Nothing },
+    Route { path: "/stats/supply", tag: "stats", summary: "Coin supply, staking share, block reward and inflation", params: &[], needs: Needs::Nothing },
+    Route { path: "/stats/network", tag: "stats", summary: "Network summary and how it moved over the last day", params: &[], needs: Needs::Nothing },
+    Route { path: "/stats/reorgs", tag: "stats", summary: "Reorgs seen since startup, newest first", params: &[], needs: Needs::Nothing },
+    Route { path: "/maintenance", tag: "status", summary: "Open and upcoming maintenance windows", params: &[], needs: Needs::Nothing },
+    Route { path: "/docs", tag: "docs", summary: "The methods this endpoint allows, with their param types", params: &PAGE, needs: Needs::Nothing },
+    Route { path: "/docs/{method}", tag: "docs", summary: "The daemon's help for an allowed method, parsed", params: &[path("method")], needs: Needs::Nothing },
+    Route { path: "/openapi.json", tag: "docs", summary: "This document", params: &[], needs: Needs::Nothing },
+    Route { path: "/registrations/{txid}", tag: "identity", summary: "Progress of a tracked identity registration", params: &[path("txid")], needs: Needs::Nothing },
+    Route { path: "/timelocks", tag: "identity", summary: "Spending lock status of the watched identities", params: &[], needs: Needs::Nothing },
+    Route { path: "/timelocks/{identity}", tag: "identity", summary: "An identity's spending lock and blocks until it unlocks", params: &[path("identity")], needs: Needs::Nothing },
+    Route { path: "/trust/identity/{identity}", tag: "identity", summary: "An identity's trust score merged across the rating sources", params: &[path("identity")], needs: Needs::Nothing },
+    Route { path: "/trust/currency/{currency}", tag: "defi", summary: "A currency's trust score merged across the rating sources", params: &[path("currency")], needs: Needs::Nothing },
+    Route { path: "/converters/{from}/{to}", tag: "defi", summary: "Baskets converting between two currencies, deepest first", params: &[path("from"), path("to")], needs: Needs::Nothing },
+    Route {
+        path: "/routes/{from}/{to}", tag: "defi", summary: "Conversion routes between two currencies, best first",
+        params: &[path("from"), path("to"), query("amount", "number"), query("maxhops", "integer")], needs: Needs::Nothing,
+    },
+    Route {
+        path: "/sapling/tree/{height}", tag: "chain", summary: "The Sapling commitment tree after a block",
+        params: &[Param { name: "height", path: true, kind: "integer", required: true }, query("format", "string")], needs: Needs::Nothing,
+    },
+    Route {
+        path: "/sapling/trees", tag: "chain", summary: "Sapling commitment trees for a range of heights",
+        params: &[
+            Param { name: "from", path: false, kind: "integer", required: true },
+            Param { name: "to", path: false, kind: "integer", required: true },
+            query("format", "string"),
+        ],
+        needs: Needs::Nothing,
+    },
+    Route { path: "/offers/tx/{txid}", tag: "defi", summary: "A tracked offer and its status", params: &[path("txid")], needs: Needs::Nothing },
+    Route {
+        path: "/offers/{currency}", tag: "defi", summary: "Open offers for or in a currency or identity",
+        params: &[path("currency"), query("iscurrency", "boolean"), query("for", "string")], needs: Needs::Nothing,
+    },
+    Route { path: "/index/content/{key}", tag: "index", summary: "Identities holding content under a VDXF key", params: &[path("key"), PAGE[0], PAGE[1]], needs: Needs::Indexer },
+    Route {
+        path: "/index/identity/{identity}/content", tag: "index", summary: "Content an identity published, optionally under one key",
+        params: &[path("identity"), query("key", "string"), PAGE[0], PAGE[1]], needs: Needs::Indexer,
+    },
+    Route { path: "/identity/{identity}/history", tag: "index", summary: "Every update of an identity with the fields it changed", params: &[path("identity"), PAGE[0], PAGE[1]], needs: Needs::Indexer },
+    Route { path: "/address/{address}/balance", tag: "index", summary: "Balances of an address or identity per currency", params: &[path("address")], needs: Needs::Indexer },
+    Route { path: "/richlist", tag: "index", summary: "Largest holders of a currency", params: &[query("currency", "string"), PAGE[0], PAGE[1]], needs: Needs::Indexer },
+    Route {
+        path: "/defi/volume/{source}/{destination}", tag: "index", summary: "Daily conversion volume of a currency pair",
+        params: &[path("source"), path("destination"), query("from", "integer"), query("to", "integer")], needs: Needs::Indexer,
+    },
+    Route { path: "/history", tag: "stats", summary: "Scheduled sampling jobs", params: &[], needs: Needs::History },
+    Route {
+        path: "/history/{job}", tag: "stats", summary: "A job's samples between two unix times, oldest first",
+        params: &[path("job"), query("from", "integer"), query("to", "integer"), query("count", "integer")], needs: Needs::History,
+    },
+    Route { path: "/faucet", tag: "faucet", summary: "What the faucet gives, with a proof-of-work challenge when required", params: &[], needs: Needs::Faucet },
+    Route { path: "/events/{stream}", tag: "events", summary: "Server-sent events: mempool, currencies or reorgs", params: STREAM, needs: Needs::Nothing },
+];
+
+fn parameter(param: &Param) -> Value {
+    let mut schema = json!({ "type": param.kind });
+    if param.name == "stream" {
+        schema["enum"] = json!(["mempool", "currencies", "reorgs"]);
+    }
+    json!({
+        "name": param.name,
+        "in": if param.path { "path" } else { "query" },
+        "required": param.required,
+        "schema": schema,
+    })
+}
+
+fn get(route: &Route) -> Value {
+    let ok = match route.path {
+        "/events/{stream}" => json!({ "description": "An event stream", "content": { "text/event-stream": { "schema": { "type": "string" } } } }),
+        _ => json!({ "description": "OK", "content": { "application/json": { "schema": { "type": "object" } } } }),
+    };
+    json!({ "get": {
+        "tags": [route.tag],
+        "summary": route.summary,
+        "operationId": operation_id(route.path),
+        "parameters": route.params.iter().map(parameter).collect::<Vec<_>>(),
+        "responses": { "200": ok, "default": { "$ref": "#/components/responses/Error" } },
+    } })
+}
+
+// getStatsSupply for /stats/supply, getRoutesByFromAndTo for /routes/{from}/{to}.
+fn operation_id(path: &str) -> String {
+    let (mut words, mut variables) = (String::from("get"), Vec::new());
+    for segment in path.split(['/', '.']).filter(|segment| !segment.is_empty()) {
+        match segment.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+            Some(name) => variables.push(capitalized(name)),
+            None => words.push_str(&capitalized(segment)),
+        }
+    }
+    if !variables.is_empty() {
+        words.push_str("By");
+        words.push_str(&variables.join("And"));
+    }
+    words
+}
+
+fn capitalized(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+// The JSON Schema of an allowlist param type.
+fn schema(kind: &str) -> Value {
+    match kind {
+        "obj" => json!({ "type": "object" }),
+        "arr" => json!({ "type": "array" }),
+        "int" => json!({ "type": "integer" }),
+        "float" => json!({ "type": "number" }),
+        "bool" => json!({ "type": "boolean" }),
+        _ => json!({ "type": "string" }),
+    }
+}
+
+// A JSON-RPC request for one allowlisted method, its params typed position by
+// position and the flag param, if any, fixed to true.
+fn request(rule: &Rule) -> Value {
+    let params: Vec<Value> = rule.params.iter().enumerate()
+        .map(|(index, kind)| match rule.flag == Some(index) {
+            true => json!({ "type": "boolean", "const": true }),
+            false => schema(kind),
+        })
+        .collect();
+    // fundrawtransaction takes all four or nothing; see allowlist::is_method_allowed.
+    let min_items = match rule.method {
+        "fundrawtransaction" => rule.params.len(),
+        _ => rule.flag.map_or(0, |index| index + 1),
+    };
+    json!({
+        "type": "object",
+        "required": ["method"],
+        "properties": {
+            "jsonrpc": { "type": "string" },
+            "id": {},
+            "method": { "const": rule.method },
+            "params": { "type": "array", "prefixItems": params, "minItems": min_items, "maxItems": rule.params.len() },
+        },
+    })
+}
+
+// GET /openapi.json: an OpenAPI 3.1 document of the REST routes this
+// deployment serves and of the JSON-RPC interface, with a request schema per
+// method the caller's access allows, typed from the allowlist. Under full
+// access the method is any string.
+pub fn handle(req: &Request<Body>, rpc: &VerusRPC, access: Access) -> Response<Body> {
+    let mut paths = Map::new();
+    for route in ROUTES {
+        let served = match route.needs {
+            Needs::Nothing => true,
+            Needs::Indexer => rpc.indexer.is_some(),
+            Needs::History => rpc.history.is_some(),
+            Needs::Faucet => rpc.faucet.is_some(),
+        };
+        if served {
+            paths.insert(route.path.to_string(), get(route));
+        }
+    }
+    if let Some(Value::Object(faucet)) = paths.get_mut("/faucet") {
+        faucet.insert("post".to_string(), json!({
+            "tags": ["faucet"],
+            "summary": "Send the faucet amount to an address",
+            "operationId": "postFaucet",
+            "requestBody": { "required": true, "content": { "application/json": { "schema": {
+                "type": "object",
+                "required": ["address"],
+                "properties": { "address": { "type": "string" }, "challenge": { "type": "string" }, "nonce": { "type": "string" }, "captcha": { "type": "string" } },
+            } } } },
+            "responses": { "200": { "description": "Sent", "content": { "application/json": { "schema": { "type": "object" } } } }, "default": { "$ref": "#/components/responses/Error" } },
+        }));
+    }
+    for doc in rpc.static_docs.paths() {
+        paths.insert(doc.to_string(), json!({ "get": {
+            "tags": ["static"],
+            "summary": "A document the operator publishes",
+            "operationId": operation_id(doc),
+            "responses": { "200": { "description": "OK", "content": { "application/json": { "schema": {} } } } },
+        } }));
+    }
+
+    let mut schemas = Map::new();
+    let calls: Vec<Value> = match access.rules(|method| rpc.methods.is_write(method)) {
+        Some(rules) => rules.into_iter()
+            .map(|rule| {
+                schemas.insert(format!("{}Request", rule.method), request(rule));
+                json!({ "$ref": format!("#/components/schemas/{}Request", rule.method) })
+            })
+            .collect(),
+        None => vec![json!({
+            "type": "object",
+            "required": ["method"],
+            "properties": { "jsonrpc": { "type": "string" }, "id": {}, "method": { "type": "string" }, "params": { "type": "array" } },
+        })],
+    };
+    schemas.insert("Call".to_string(), json!({ "oneOf": calls }));
+    schemas.insert("Reply".to_string(), json!({
+        "type": "object",
+        "properties": {
+            "result": {},
+            "error": {
+                "type": ["object", "null"],
+                "properties": { "code": { "type": "integer" }, "message": { "type": "string" }, "data": {} },
+            },
+            "id": {},
+        },
+    }));
+    paths.insert("/".to_string(), json!({ "post": {
+        "tags": ["json-rpc"],
+        "summary": "Call a daemon method, or several as a batch",
+        "operationId": "call",
+        "requestBody": { "required": true, "content": { "application/json": { "schema": {
+            "oneOf": [{ "$ref": "#/components/schemas/Call" }, { "type": "array", "items": { "$ref": "#/components/schemas/Call" } }],
+        } } } },
+        "responses": { "200": { "description": "The reply, or one per call of a batch", "content": { "application/json": { "schema": {
+            "oneOf": [{ "$ref": "#/components/schemas/Reply" }, { "type": "array", "items": { "$ref": "#/components/schemas/Reply" } }],
+        } } } } },
+    } }));
+
+    let host = req.headers().get(hyper::header::HOST).and_then(|host| host.to_str().ok());
+    json_response(StatusCode::OK, json!({
+        "openapi": "3.1.0",
+        "info": rpc.branding.openapi_info(),
+        "servers": host.map(|host| vec![json!({ "url": format!("//{}", host) })]).unwrap_or_default(),
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "responses": { "Error": {
+                "description": "An error",
+                "content": { "application/json": { "schema": { "type": "object", "properties": { "error": { "type": "string" } } } } },
+            } },
+        },
+    }))
+}
@@ -0,0 +1,44 @@
+use hyper::HeaderMap;
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::annotations::MethodTable;
+use crate::listener;
+
+// Who may call write methods (those annotated as writes). Browsers
+// attach an Origin to cross-site requests, so a page on another site can't
+// get a wallet pointed at this proxy to submit transactions. Callers without
+// an Origin aren't browsers and must show an API key instead.
+pub struct OriginPolicy {
+    pub enforce: bool,
+    // Origins as browsers send them, e.g. https://wallet.example.com.
+    pub allowed: HashSet<String>,
+    pub api_keys: HashSet<String>,
+}
+
+impl OriginPolicy {
+    // Whether the request whose `headers` these are may carry write methods.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), String> {
+        if !self.enforce {
+            return Ok(());
+        }
+        match headers.get(hyper::header::ORIGIN).map(|origin| origin.to_str().unwrap_or_default()) {
+            Some(origin) if self.allowed.contains(origin.trim_end_matches('/')) => Ok(()),
+            Some(origin) => Err(format!("Origin {} may not call write methods", origin)),
+            None => match listener::bearer(headers) {
+                Some(key) if self.api_keys.contains(key) => Ok(()),
+                Some(_) => Err("Invalid API key".to_string()),
+                None => Err("Write methods need an allowed Origin or an API key".to_string()),
+            },
+        }
+    }
+}
+
+// Whether a request body (one call or a batch) calls any write method.
+pub fn has_write_method(body: &Value, methods: &MethodTable) -> bool {
+    let is_write = |entry: &Value| entry["method"].as_str().is_some_and(|method| methods.is_write(method));
+    match body {
+        Value::Array(entries) => entries.iter().any(is_write),
+        entry => is_write(entry),
+    }
+}
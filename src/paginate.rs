@@ -0,0 +1,40 @@
+use jsonrpc::error::RpcError;
+use serde_json::Value;
+
+fn invalid(message: String) -> RpcError {
+    RpcError { code: -32602, message, data: None }
+}
+
+// listcurrencies has no paging of its own, so the proxy reads `start` and
+// `count` from the query object, removes them before the call goes upstream and
+// slices the full list itself. The full list is cached like any other result,
+// so later pages don't go back to the daemon. `count` defaults to, and may not
+// exceed, `max_page_size`.
+pub struct Page {
+    pub start: usize,
+    pub count: usize,
+}
+
+pub fn take_page(params: &mut [Value], max_page_size: usize) -> Result<Page, RpcError> {
+    let query = match params.first_mut() {
+        Some(Value::Object(query)) => query,
+        _ => return Ok(Page { start: 0, count: max_page_size }),
+    };
+    let mut field = |name: &str, default: usize| match query.remove(name) {
+        None => Ok(default),
+        Some(value) => value.as_u64().map(|n| n as usize).ok_or_else(|| invalid(format!("{} must be a non-negative integer", name))),
+    };
+    let start = field("start", 0)?;
+    let count = field("count", max_page_size)?;
+    if count > max_page_size {
+        return Err(invalid(format!("count exceeds the max page size of {}", max_page_size)));
+    }
+    Ok(Page { start, count })
+}
+
+pub fn slice(result: Value, page: &Page) -> Value {
+    match result {
+        Value::Array(items) => Value::Array(items.into_iter().skip(page.start).take(page.count).collect()),
+        other => other,
+    }
+}
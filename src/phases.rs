@@ -0,0 +1,84 @@
+use serde_json::{Value, json};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// The stages a request goes through, each with its own timeout, so slowness
+// can be put down to the client (reading its headers and body), the proxy
+// (validating the call) or the daemon (the upstream call).
+#[derive(Clone, Copy)]
+pub enum Phase {
+    HeaderRead,
+    BodyRead,
+    Validation,
+    Upstream,
+}
+
+const PHASES: [Phase; 4] = [Phase::HeaderRead, Phase::BodyRead, Phase::Validation, Phase::Upstream];
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::HeaderRead => "header_read",
+            Phase::BodyRead => "body_read",
+            Phase::Validation => "validation",
+            Phase::Upstream => "upstream",
+        }
+    }
+}
+
+#[derive(Default)]
+struct PhaseStats {
+    count: AtomicU64,
+    micros: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+// The time spent in each phase and how often it ran out. Header reads are
+// timed for a connection's first request only, since later ones on a
+// keep-alive connection start whenever the client gets round to them.
+#[derive(Default)]
+pub struct Phases {
+    stats: [PhaseStats; 4],
+}
+
+impl Phases {
+    pub fn record(&self, phase: Phase, elapsed: Duration) {
+        let stats = &self.stats[phase as usize];
+        stats.count.fetch_add(1, Ordering::Relaxed);
+        stats.micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn timed_out(&self, phase: Phase) {
+        self.stats[phase as usize].timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> Value {
+        let mut summary = json!({});
+        for phase in PHASES {
+            let stats = &self.stats[phase as usize];
+            let (count, micros) = (stats.count.load(Ordering::Relaxed), stats.micros.load(Ordering::Relaxed));
+            summary[phase.name()] = json!({
+                "count": count,
+                "mean_ms": if count > 0 { Some(micros as f64 / count as f64 / 1000.0) } else { None },
+                "timeouts": stats.timeouts.load(Ordering::Relaxed),
+            });
+        }
+        summary
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        writeln!(out, "# TYPE request_phase_total counter").unwrap();
+        for phase in PHASES {
+            writeln!(out, "request_phase_total{{phase=\"{}\"}} {}", phase.name(), self.stats[phase as usize].count.load(Ordering::Relaxed)).unwrap();
+        }
+        writeln!(out, "# TYPE request_phase_seconds_total counter").unwrap();
+        for phase in PHASES {
+            writeln!(out, "request_phase_seconds_total{{phase=\"{}\"}} {}", phase.name(), self.stats[phase as usize].micros.load(Ordering::Relaxed) as f64 / 1e6).unwrap();
+        }
+        writeln!(out, "# TYPE request_phase_timeouts_total counter").unwrap();
+        for phase in PHASES {
+            writeln!(out, "request_phase_timeouts_total{{phase=\"{}\"}} {}", phase.name(), self.stats[phase as usize].timeouts.load(Ordering::Relaxed)).unwrap();
+        }
+    }
+}
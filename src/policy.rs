@@ -0,0 +1,111 @@
+use jsonrpc::error::RpcError;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// Operator guardrails for sendcurrency and registernamecommitment, the allowed
+// methods that can move funds.
+pub struct SendPolicy {
+    // Highest total of any one currency across a transaction's outputs; 0 means no limit.
+    pub max_amount: f64,
+    // Currencies that may be sent or converted to, lowercased. Empty allows all.
+    // Outputs without a currency send the native coin, which is always allowed.
+    pub currencies: Vec<String>,
+    // Addresses and identities that may not be sent from, to or refunded to.
+    pub denied_addresses: Vec<String>,
+    // Only allow building a transaction template for the client to review and
+    // sign, never sending from the daemon's wallet directly. The allowlist
+    // already holds standard access to templates; this holds full access too.
+    pub require_template: bool,
+    // Addresses and identities registernamecommitment may name as its source
+    // of funds. Empty allows none, leaving the choice to the daemon's wallet.
+    pub registration_funding: Vec<String>,
+}
+
+fn rejected(reason: String) -> RpcError {
+    RpcError { code: -8, message: format!("Rejected by policy: {}", reason), data: None }
+}
+
+fn amount(value: &Value) -> f64 {
+    match value {
+        Value::String(s) => s.parse().unwrap_or(0.0),
+        value => value.as_f64().unwrap_or(0.0),
+    }
+}
+
+// The total of each currency across a sendcurrency's outputs; the native coin
+// is "".
+pub fn output_totals(params: &[Value]) -> HashMap<&str, f64> {
+    let mut totals: HashMap<&str, f64> = HashMap::new();
+    for output in params.get(1).and_then(Value::as_array).into_iter().flatten() {
+        *totals.entry(output["currency"].as_str().unwrap_or("")).or_default() += amount(&output["amount"]);
+    }
+    totals
+}
+
+impl SendPolicy {
+    // Identity names are case insensitive, addresses are not.
+    fn is_denied(&self, address: &str) -> bool {
+        self.denied_addresses.iter().any(|denied| {
+            if denied.contains('@') {
+                denied.eq_ignore_ascii_case(address)
+            } else {
+                denied == address
+            }
+        })
+    }
+
+    fn is_allowed_currency(&self, currency: &str) -> bool {
+        self.currencies.is_empty() || self.currencies.contains(&currency.to_lowercase())
+    }
+
+    // Only the funding addresses the operator lists, and none that is denied.
+    fn check_registration(&self, params: &[Value]) -> Result<(), RpcError> {
+        let source = match params.get(4).and_then(Value::as_str) {
+            Some(source) if !source.is_empty() => source,
+            _ => return Ok(()),
+        };
+        let listed = self.registration_funding.iter().any(|funding| match funding.contains('@') {
+            true => funding.eq_ignore_ascii_case(source),
+            false => funding == source,
+        });
+        if !listed || self.is_denied(source) {
+            return Err(rejected(format!("source of funds {} is not allowed", source)));
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, method: &str, params: &[Value]) -> Result<(), RpcError> {
+        if method == "registernamecommitment" {
+            return self.check_registration(params);
+        }
+        if method != "sendcurrency" {
+            return Ok(());
+        }
+        if self.require_template && params.get(4).and_then(Value::as_bool) != Some(true) {
+            return Err(rejected("returntxtemplate must be true".into()));
+        }
+        if let Some(from) = params.first().and_then(Value::as_str).filter(|from| self.is_denied(from)) {
+            return Err(rejected(format!("address {} is denied", from)));
+        }
+
+        for output in params.get(1).and_then(Value::as_array).into_iter().flatten() {
+            for field in ["address", "refundto"] {
+                if let Some(address) = output[field].as_str().filter(|address| self.is_denied(address)) {
+                    return Err(rejected(format!("address {} is denied", address)));
+                }
+            }
+            for field in ["currency", "convertto"] {
+                if let Some(currency) = output[field].as_str().filter(|currency| !self.is_allowed_currency(currency)) {
+                    return Err(rejected(format!("currency {} is not allowed", currency)));
+                }
+            }
+        }
+        if self.max_amount > 0.0 {
+            if let Some((currency, total)) = output_totals(params).into_iter().find(|(_, total)| *total > self.max_amount) {
+                let currency = if currency.is_empty() { "the native coin" } else { currency };
+                return Err(rejected(format!("{} of {} exceeds the maximum of {}", total, currency, self.max_amount)));
+            }
+        }
+        Ok(())
+    }
+}
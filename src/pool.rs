@@ -0,0 +1,90 @@
+use hyper::body::Bytes;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Reusable byte buffers for reading request bodies and serializing responses.
+// Buffers that grew past `max_buffer_size` (large raw transactions, big
+// getaddressdeltas replies) are dropped instead of being kept around.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    max_buffer_size: usize,
+    reused: AtomicU64,
+    allocated: AtomicU64,
+    discarded: AtomicU64,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize, max_buffer_size: usize) -> BufferPool {
+        BufferPool {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+            max_buffer_size,
+            reused: AtomicU64::new(0),
+            allocated: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self) -> Vec<u8> {
+        match self.buffers.lock().unwrap().pop() {
+            Some(buf) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                buf
+            },
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                Vec::new()
+            },
+        }
+    }
+
+    pub fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() <= self.max_buffer_size {
+            let mut buffers = self.buffers.lock().unwrap();
+            if buffers.len() < self.max_buffers {
+                buf.clear();
+                buffers.push(buf);
+                return;
+            }
+        }
+        self.discarded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Hands `buf` to a response body without copying it; it comes back to
+    // the pool once the body has been sent and dropped.
+    pub fn into_bytes(self: &Arc<Self>, buf: Vec<u8>) -> Bytes {
+        Bytes::from_owner(Pooled { buf, pool: self.clone() })
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        let pooled = self.buffers.lock().unwrap().len();
+        writeln!(out, "# TYPE buffer_pool_available gauge").unwrap();
+        writeln!(out, "buffer_pool_available {}", pooled).unwrap();
+        writeln!(out, "# TYPE buffer_pool_reused_total counter").unwrap();
+        writeln!(out, "buffer_pool_reused_total {}", self.reused.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "# TYPE buffer_pool_allocated_total counter").unwrap();
+        writeln!(out, "buffer_pool_allocated_total {}", self.allocated.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "# TYPE buffer_pool_discarded_total counter").unwrap();
+        writeln!(out, "buffer_pool_discarded_total {}", self.discarded.load(Ordering::Relaxed)).unwrap();
+    }
+}
+
+// A buffer lent out by `into_bytes`, returned to its pool when dropped.
+struct Pooled {
+    buf: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl AsRef<[u8]> for Pooled {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buf));
+    }
+}
@@ -0,0 +1,78 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+
+fn invalid(message: &str) -> RpcError {
+    RpcError { code: -32602, message: message.into(), data: None }
+}
+
+// verifyproofroots [{"proofroots": [...], "lastconfirmed", "currencies"}]
+// checks the proof roots another chain claims for this one with
+// getbestproofroot and reports, root by root, whether this chain agrees: each
+// claimed root with whether it is valid here and whether it is the best one,
+// the local latest proof root, and a summary a bridge monitor can alert on
+// (any disagreement, or this chain being ahead of everything claimed).
+pub async fn verify(rpc: &Arc<VerusRPC>, params: Vec<Value>, access: Access) -> Result<Value, RpcError> {
+    let request = match params.as_slice() {
+        [request @ Value::Object(_)] => request.clone(),
+        _ => return Err(invalid("Invalid params parameter")),
+    };
+    let claimed = match request["proofroots"].as_array() {
+        Some(claimed) if !claimed.is_empty() => claimed.clone(),
+        _ => return Err(invalid("proofroots must be a non-empty array")),
+    };
+    let local = rpc.handle_call_as("getbestproofroot".to_string(), vec![request], access).await?;
+
+    let valid: Vec<u64> = local["validindexes"].as_array().into_iter().flatten().filter_map(Value::as_u64).collect();
+    let best = local["bestindex"].as_i64().filter(|best| *best >= 0).map(|best| best as u64);
+    let latest = &local["latestproofroot"];
+    let roots: Vec<Value> = claimed.iter().enumerate().map(|(index, root)| {
+        let index = index as u64;
+        let agrees = valid.contains(&index);
+        let mut report = json!({
+            "index": index,
+            "height": root["height"],
+            "systemid": root["systemid"],
+            "stateroot": root["stateroot"],
+            "blockhash": root["blockhash"],
+            "agrees": agrees,
+            "best": best == Some(index),
+        });
+        // A root at the local latest height must match it exactly.
+        if root["height"] == latest["height"] && !latest.is_null() {
+            let differs: Vec<&str> = ["stateroot", "blockhash", "power"].iter()
+                .copied()
+                .filter(|field| !root[*field].is_null() && root[*field] != latest[*field])
+                .collect();
+            report["differs_from_latest"] = json!(differs);
+        }
+        report
+    }).collect();
+
+    let disagreements = roots.iter().filter(|root| root["agrees"] == false).count();
+    let highest_claimed = claimed.iter().filter_map(|root| root["height"].as_u64()).max();
+    let local_ahead = match (latest["height"].as_u64(), highest_claimed) {
+        (Some(local), Some(claimed)) => local > claimed,
+        _ => false,
+    };
+    Ok(json!({
+        "agrees": disagreements == 0,
+        "summary": {
+            "claimed": roots.len(),
+            "agreements": roots.len() - disagreements,
+            "disagreements": disagreements,
+            "best_index": best,
+            "highest_claimed_height": highest_claimed,
+            "local_latest_height": latest["height"],
+            "local_ahead": local_ahead,
+        },
+        "roots": roots,
+        "local": {
+            "latestproofroot": latest,
+            "laststableproofroot": local["laststableproofroot"],
+        },
+    }))
+}
@@ -0,0 +1,84 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+// A v1 header is one line of at most 107 bytes, CRLF included.
+const V1_MAX_LENGTH: usize = 107;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Reads the PROXY protocol header (v1 or v2) a load balancer sends ahead of
+// the client's bytes, and returns the client's address. `None` means the
+// header carries no address (a health check, or an unknown family) and the
+// connection is the load balancer's own. Reads exactly the header, so the
+// stream is left at the start of the HTTP request.
+pub async fn read_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    // Both versions are at least 12 bytes long ("PROXY UNKNOWN\r\n" is 15).
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if &start == V2_SIGNATURE {
+        return read_v2(stream).await;
+    }
+    if start.starts_with(b"PROXY ") {
+        return read_v1(stream, &start).await;
+    }
+    Err(invalid("missing PROXY protocol header"))
+}
+
+async fn read_v1(stream: &mut TcpStream, start: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LENGTH {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("PROXY v1 header is not text"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source.parse().map_err(|_| invalid("bad PROXY v1 source address"))?;
+            let port: u16 = source_port.parse().map_err(|_| invalid("bad PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        },
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+async fn read_v2(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let version_command = stream.read_u8().await?;
+    let family = stream.read_u8().await?;
+    let length = stream.read_u16().await? as usize;
+    let mut addresses = vec![0u8; length];
+    stream.read_exact(&mut addresses).await?;
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL: the load balancer's own connection, e.g. a health check.
+        0 => return Ok(None),
+        1 => {},
+        _ => return Err(invalid("unknown PROXY v2 command")),
+    }
+    // Source address, destination address, source port, destination port;
+    // anything after them is TLVs, which aren't needed.
+    match family >> 4 {
+        1 if length >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([addresses[8], addresses[9]]))))
+        },
+        2 if length >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), u16::from_be_bytes([addresses[32], addresses[33]]))))
+        },
+        1 | 2 => Err(invalid("truncated PROXY v2 addresses")),
+        // Unix sockets and unspecified families have no client IP to use.
+        _ => Ok(None),
+    }
+}
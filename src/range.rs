@@ -0,0 +1,60 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+
+// Largest span a single enumeration call may cover; 0 means no limit.
+pub struct RangeLimits {
+    // In blocks, for getexports, getimports and getaddressdeltas.
+    pub max_blocks: u64,
+    // In seconds, for getblockhashes, which takes timestamps.
+    pub max_seconds: u64,
+}
+
+fn too_large(unit: &str, requested: u64, max: u64) -> RpcError {
+    let data = json!({ "unit": unit, "requested": requested, "max_range": max });
+    RpcError {
+        code: -32602,
+        message: format!("Range too large: {} {} requested, max {}; split the request into smaller ranges", requested, unit, max),
+        data: serde_json::value::to_raw_value(&data).ok(),
+    }
+}
+
+fn open_ended() -> RpcError {
+    RpcError { code: -32602, message: "Range needs an explicit end height until the chain tip is known".into(), data: None }
+}
+
+impl RangeLimits {
+    // Heights default to the whole chain when omitted, so a missing end is
+    // taken to be the tip.
+    fn check_blocks(&self, start: Option<u64>, end: Option<u64>, tip: Option<u64>) -> Result<(), RpcError> {
+        if self.max_blocks == 0 {
+            return Ok(());
+        }
+        let end = match end.filter(|end| *end > 0).or(tip) {
+            Some(end) => end,
+            None => return Err(open_ended()),
+        };
+        let requested = end.saturating_sub(start.unwrap_or(0));
+        if requested > self.max_blocks {
+            return Err(too_large("blocks", requested, self.max_blocks));
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, method: &str, params: &[Value], tip: Option<u64>) -> Result<(), RpcError> {
+        let param = |i: usize| params.get(i).and_then(Value::as_u64);
+        match method {
+            "getblockhashes" => match (param(0), param(1)) {
+                (Some(high), Some(low)) if self.max_seconds > 0 && high.saturating_sub(low) > self.max_seconds => {
+                    Err(too_large("seconds", high - low, self.max_seconds))
+                },
+                _ => Ok(()),
+            },
+            "getexports" | "getimports" => self.check_blocks(param(1), param(2), tip),
+            "getaddressdeltas" => {
+                let options = params.first().unwrap_or(&Value::Null);
+                self.check_blocks(options["start"].as_u64(), options["end"].as_u64(), tip)
+            },
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use serde_json::{Value, json};
+
+use crate::hash;
+
+// Keys whose values are never logged: signatures, keys, the private parts of
+// identities and the salt of a name commitment, which would reveal the name
+// before it is registered.
+const SENSITIVE_KEYS: &[&str] = &[
+    "signature", "signatures", "privatekey", "privkey", "wif", "seed", "spendingkey", "extendedkey",
+    "contentmap", "contentmultimap", "privateaddress", "hex", "salt",
+];
+
+// Methods whose first param is a raw transaction.
+const RAW_TRANSACTION_PARAM: &[&str] = &["sendrawtransaction", "signrawtransaction", "decoderawtransaction"];
+
+// Shorter runs are left alone, so txids, block hashes and addresses stay
+// readable.
+const MIN_TOKEN: usize = 80;
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=')
+}
+
+// Masks long hex and base64 runs in free text, such as an error message that
+// echoes what it was given.
+pub fn text(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find(is_token_char) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_token_char(c)).unwrap_or(rest.len());
+        match end >= MIN_TOKEN {
+            true => out.push_str(&format!("[redacted {} chars]", end)),
+            false => out.push_str(&rest[..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// A raw transaction, reduced to its size and txid (the double SHA-256 of the
+// serialized transaction, in the daemon's byte order).
+fn raw_transaction(value: &Value) -> Value {
+    match value.as_str().and_then(|raw| hex::decode(raw).ok()) {
+        Some(bytes) => json!(format!("[redacted transaction, {} bytes, txid {}]", bytes.len(), hash::txid(&bytes))),
+        None => mask(value),
+    }
+}
+
+fn mask(value: &Value) -> Value {
+    match value {
+        Value::String(s) if hex::decode(s).is_ok() => json!(format!("[redacted {} bytes]", s.len() / 2)),
+        Value::String(s) => json!(format!("[redacted {} chars]", s.len())),
+        Value::Null => Value::Null,
+        _ => json!("[redacted]"),
+    }
+}
+
+// Masks sensitive fields and long encoded strings anywhere in a value.
+pub fn value(value: &Value) -> Value {
+    match value {
+        Value::Object(entries) => Value::Object(entries.iter()
+            .map(|(key, entry)| {
+                let entry = match SENSITIVE_KEYS.contains(&key.to_ascii_lowercase().as_str()) {
+                    true => mask(entry),
+                    false => self::value(entry),
+                };
+                (key.clone(), entry)
+            })
+            .collect()),
+        Value::Array(entries) => Value::Array(entries.iter().map(self::value).collect()),
+        Value::String(s) => json!(text(s)),
+        other => other.clone(),
+    }
+}
+
+// A call's params as they may be logged: raw transactions keep their txid,
+// everything else goes through `value`.
+pub fn params(method: &str, params: &Value) -> Value {
+    match (RAW_TRANSACTION_PARAM.contains(&method), params) {
+        (true, Value::Array(params)) => Value::Array(params.iter()
+            .enumerate()
+            .map(|(i, param)| if i == 0 { raw_transaction(param) } else { value(param) })
+            .collect()),
+        _ => value(params),
+    }
+}
+
+// A call's result as it may be logged.
+pub fn result(method: &str, result: &Value) -> Value {
+    match method {
+        "createrawtransaction" => raw_transaction(result),
+        "signrawtransaction" if result.is_object() => {
+            let mut redacted = value(result);
+            redacted["hex"] = raw_transaction(&result["hex"]);
+            redacted
+        },
+        _ => value(result),
+    }
+}
@@ -0,0 +1,265 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::VerusRPC;
+use crate::hash;
+use crate::indexer::unix_time;
+use crate::limiter::Priority;
+
+// Operations are forgotten this long after they last changed, once finished.
+const KEEP_FINISHED_SECS: i64 = 86_400;
+// At most this many operations are tracked; the oldest finished ones go first.
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    // The name commitment was sent; registeridentity hasn't been called yet.
+    Committed,
+    // registeridentity returned its transaction (or sent it); it isn't mined
+    // yet.
+    Registered,
+    Confirmed,
+    // registeridentity was refused, or its transaction never got mined.
+    Failed,
+    // The commitment was never used.
+    Expired,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Committed => "committed",
+            Status::Registered => "registered",
+            Status::Confirmed => "confirmed",
+            Status::Failed => "failed",
+            Status::Expired => "expired",
+        }
+    }
+
+    fn is_finished(self) -> bool {
+        matches!(self, Status::Confirmed | Status::Failed | Status::Expired)
+    }
+}
+
+// One name registration, keyed by its commitment txid.
+struct Registration {
+    name: Value,
+    status: Status,
+    // Tip height when the proxy first saw the operation.
+    started_at: Option<u64>,
+    commitment_height: Option<u64>,
+    registration_txid: Option<String>,
+    registration_height: Option<u64>,
+    error: Option<String>,
+    updated: i64,
+}
+
+impl Registration {
+    fn committed(reservation: &Value, tip: Option<u64>) -> Registration {
+        Registration {
+            name: reservation["name"].clone(),
+            status: Status::Committed,
+            started_at: tip,
+            commitment_height: None,
+            registration_txid: None,
+            registration_height: None,
+            error: None,
+            updated: unix_time(),
+        }
+    }
+
+    fn to_json(&self, id: &str) -> Value {
+        json!({
+            "id": id,
+            "name": self.name,
+            "status": self.status.name(),
+            "commitment": { "txid": id, "height": self.commitment_height },
+            "registration": { "txid": self.registration_txid, "height": self.registration_height },
+            "error": self.error,
+            "updated": self.updated,
+        })
+    }
+
+    fn set(&mut self, status: Status) {
+        self.status = status;
+        self.updated = unix_time();
+    }
+}
+
+// Two-step identity registrations made through the proxy, from the name
+// commitment (registernamecommitment) to the registeridentity that uses it
+// and the block that confirms it. The commitment txid is the operation id,
+// since registeridentity names it too; GET /registrations/{txid} reports
+// where the operation stands. Unfinished operations are checked at every new
+// block and given up on after `expiry_blocks`.
+pub struct RegistrationTracker {
+    pub enabled: bool,
+    expiry_blocks: u64,
+    registrations: Mutex<HashMap<String, Registration>>,
+}
+
+impl RegistrationTracker {
+    pub fn new(enabled: bool, expiry_blocks: u64) -> RegistrationTracker {
+        RegistrationTracker { enabled, expiry_blocks, registrations: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn tracks(&self, method: &str) -> bool {
+        self.enabled && matches!(method, "registernamecommitment" | "registeridentity")
+    }
+
+    pub fn status(&self, id: &str) -> Option<Value> {
+        self.registrations.lock().unwrap().get(id).map(|registration| registration.to_json(id))
+    }
+
+    fn insert(registrations: &mut HashMap<String, Registration>, id: String, registration: Registration) {
+        let now = unix_time();
+        registrations.retain(|_, registration| !registration.status.is_finished() || registration.updated + KEEP_FINISHED_SECS > now);
+        if registrations.len() >= MAX_TRACKED {
+            let oldest = registrations.iter()
+                .filter(|(_, registration)| registration.status.is_finished())
+                .min_by_key(|(_, registration)| registration.updated)
+                .map(|(id, _)| id.clone());
+            match oldest {
+                Some(oldest) => registrations.remove(&oldest),
+                None => return,
+            };
+        }
+        registrations.insert(id, registration);
+    }
+
+    // Refuses a registeridentity that can't use the commitment it names: one
+    // that was already used or has expired, or that reserved another name.
+    // Commitments made elsewhere aren't known and are left to the daemon.
+    pub fn check(&self, params: &[Value]) -> Result<(), RpcError> {
+        let request = params.first().cloned().unwrap_or_default();
+        let registrations = self.registrations.lock().unwrap();
+        let registration = match request["txid"].as_str().and_then(|id| registrations.get(id)) {
+            Some(registration) => registration,
+            None => return Ok(()),
+        };
+        let refused = |reason: &str| RpcError { code: -8, message: format!("Rejected by policy: {}", reason), data: None };
+        match registration.status {
+            Status::Registered | Status::Confirmed => return Err(refused("the name commitment was already used")),
+            Status::Expired => return Err(refused("the name commitment has expired")),
+            Status::Committed | Status::Failed => {},
+        }
+        let name = |value: &Value| value.as_str().map(str::to_lowercase);
+        match (name(&registration.name), name(&request["namereservation"]["name"])) {
+            (Some(committed), Some(named)) if committed != named => Err(refused("the name differs from the one committed to")),
+            _ => Ok(()),
+        }
+    }
+
+    // Records what a tracked call did.
+    pub fn observe(&self, method: &str, params: &[Value], result: &Result<Value, RpcError>, tip: Option<u64>) {
+        let mut registrations = self.registrations.lock().unwrap();
+        match (method, result) {
+            ("registernamecommitment", Ok(result)) => {
+                if let Some(txid) = result["txid"].as_str() {
+                    let registration = Registration::committed(&result["namereservation"], tip);
+                    RegistrationTracker::insert(&mut registrations, txid.to_string(), registration);
+                }
+            },
+            ("registeridentity", result) => {
+                let request = params.first().cloned().unwrap_or_default();
+                let id = match request["txid"].as_str() {
+                    Some(id) => id.to_string(),
+                    None => return,
+                };
+                // Commitments made elsewhere start being tracked here.
+                if !registrations.contains_key(&id) {
+                    RegistrationTracker::insert(&mut registrations, id.clone(), Registration::committed(&request["namereservation"], tip));
+                }
+                if let Some(registration) = registrations.get_mut(&id) {
+                    match result {
+                        Ok(result) => {
+                            // With returntx the reply is the signed transaction,
+                            // which the client broadcasts itself.
+                            registration.registration_txid = result.as_str().map(|result| match hex::decode(result) {
+                                Ok(raw) if result.len() > 64 => hash::txid(&raw),
+                                _ => result.to_string(),
+                            });
+                            registration.started_at = tip;
+                            registration.error = None;
+                            registration.set(Status::Registered);
+                        },
+                        Err(e) => {
+                            registration.error = Some(e.message.clone());
+                            registration.set(Status::Failed);
+                        },
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+// The height of the block holding `txid`, None while it is in the mempool,
+// or Err if the daemon doesn't know it.
+async fn mined_at(rpc: &VerusRPC, txid: &str) -> Result<Option<u64>, ()> {
+    match rpc.upstream.call_as("getrawtransaction", &[json!(txid), json!(1)], Priority::Background).await {
+        Ok(tx) => Ok(tx["height"].as_u64().filter(|_| tx["confirmations"].as_u64().unwrap_or(0) > 0)),
+        Err(e) if e.code == -5 => Err(()),
+        // The daemon being unreachable says nothing about the transaction.
+        Err(_) => Ok(None),
+    }
+}
+
+async fn check(rpc: &VerusRPC, tip: u64) {
+    let tracker = &rpc.registrations;
+    let open: Vec<(String, Status, Option<String>, Option<u64>)> = tracker.registrations.lock().unwrap().iter()
+        .filter(|(_, registration)| !registration.status.is_finished())
+        .map(|(id, registration)| (id.clone(), registration.status, registration.registration_txid.clone(), registration.started_at))
+        .collect();
+    for (id, status, registration_txid, started_at) in open {
+        let overdue = started_at.is_some_and(|started| tip >= started + tracker.expiry_blocks);
+        let commitment_height = mined_at(rpc, &id).await.ok().flatten();
+        let registration_height = match &registration_txid {
+            Some(txid) => mined_at(rpc, txid).await,
+            None => Ok(None),
+        };
+        let mut registrations = tracker.registrations.lock().unwrap();
+        let registration = match registrations.get_mut(&id) {
+            Some(registration) if registration.status == status => registration,
+            // Changed by a call while this was checking.
+            _ => continue,
+        };
+        if commitment_height.is_some() {
+            registration.commitment_height = commitment_height;
+        }
+        match (status, registration_height) {
+            (Status::Registered, Ok(Some(height))) => {
+                registration.registration_height = Some(height);
+                registration.set(Status::Confirmed);
+            },
+            (Status::Registered, Err(())) if overdue => {
+                registration.error = Some("The registration transaction was dropped".to_string());
+                registration.set(Status::Failed);
+            },
+            (Status::Registered, _) if overdue => {
+                registration.error = Some(format!("Not mined within {} blocks", tracker.expiry_blocks));
+                registration.set(Status::Failed);
+            },
+            (Status::Committed, _) if overdue => registration.set(Status::Expired),
+            _ => {},
+        }
+    }
+}
+
+// Checks the unfinished registrations whenever the tip moves.
+pub async fn watch(rpc: Arc<VerusRPC>, interval: Duration) {
+    let mut last_height = None;
+    loop {
+        tokio::time::sleep(interval).await;
+        let height = match rpc.tip.height() {
+            Some(height) if Some(height) != last_height => height,
+            _ => continue,
+        };
+        last_height = Some(height);
+        check(&rpc, height).await;
+    }
+}
@@ -0,0 +1,166 @@
+use hyper::{Body, Response, StatusCode};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::VerusRPC;
+use crate::indexer::unix_time;
+use crate::rest::json_response;
+
+// Reorgs kept for /stats/reorgs.
+const KEPT_REORGS: usize = 100;
+
+// The hashes of the last `window` blocks by height, as the daemon reported
+// them, so the proxy notices when its best chain replaces blocks it has seen.
+pub struct ReorgDetector {
+    window: u64,
+    hashes: Mutex<BTreeMap<u64, String>>,
+    reorgs: Mutex<VecDeque<Value>>,
+    detected: AtomicU64,
+}
+
+impl ReorgDetector {
+    pub fn new(window: u64) -> ReorgDetector {
+        ReorgDetector { window, hashes: Mutex::new(BTreeMap::new()), reorgs: Mutex::new(VecDeque::new()), detected: AtomicU64::new(0) }
+    }
+
+    pub fn summary(&self) -> Value {
+        let reorgs: Vec<Value> = self.reorgs.lock().unwrap().iter().rev().cloned().collect();
+        let deepest = reorgs.iter().filter_map(|reorg| reorg["depth"].as_u64()).max();
+        json!({
+            "window": self.window,
+            "tracked": self.hashes.lock().unwrap().len(),
+            "detected": self.detected.load(Ordering::Relaxed),
+            "deepest": deepest,
+            "reorgs": reorgs,
+        })
+    }
+}
+
+async fn block_hash(rpc: &VerusRPC, height: u64) -> Option<String> {
+    rpc.upstream.call("getblockhash", &[json!(height)]).await.ok()?.as_str().map(str::to_string)
+}
+
+// Compares the chain up to `height` with the hashes seen before. When blocks
+// were replaced, walks back to the last block both chains share and returns
+// the reorg; the hashes are brought up to date either way. None also when the
+// daemon couldn't be asked, so the next poll tries again.
+async fn compare(rpc: &VerusRPC, height: u64) -> Option<Value> {
+    let detector = &rpc.reorgs;
+    let tip_hash = block_hash(rpc, height).await?;
+    let known = detector.hashes.lock().unwrap().clone();
+    if known.get(&height) == Some(&tip_hash) {
+        return None;
+    }
+    let (old_height, old_hash) = match known.last_key_value() {
+        Some((old_height, old_hash)) => (*old_height, old_hash.clone()),
+        None => {
+            detector.hashes.lock().unwrap().insert(height, tip_hash);
+            return None;
+        },
+    };
+
+    // Heights the current chain has a different block at, from the highest
+    // both have down to where they agree.
+    let mut current = vec![(height, tip_hash.clone())];
+    let mut fork = None;
+    for (seen_height, seen_hash) in known.range(..=height).rev() {
+        let hash = match *seen_height == height {
+            true => tip_hash.clone(),
+            false => block_hash(rpc, *seen_height).await?,
+        };
+        if hash == *seen_hash {
+            fork = Some(*seen_height);
+            break;
+        }
+        current.push((*seen_height, hash));
+    }
+    let lowest = *known.keys().next().unwrap();
+    // New blocks on top of the chain the proxy saw are filled in, up to the window.
+    let first_new = (old_height + 1).max((height + 1).saturating_sub(detector.window));
+    for new_height in first_new..height {
+        current.push((new_height, block_hash(rpc, new_height).await?));
+    }
+
+    let mut hashes = detector.hashes.lock().unwrap();
+    hashes.retain(|seen, _| *seen < height);
+    hashes.extend(current);
+    let keep_from = (height + 1).saturating_sub(detector.window);
+    hashes.retain(|seen, _| *seen >= keep_from);
+    drop(hashes);
+
+    let fork_height = match fork {
+        Some(fork) if fork >= old_height => return None,
+        Some(fork) => fork,
+        None if height < lowest => height.saturating_sub(1),
+        None => lowest.saturating_sub(1),
+    };
+    Some(json!({
+        "time": unix_time(),
+        "depth": old_height - fork_height,
+        "fork_height": fork_height,
+        // The fork is below the blocks the proxy kept, so it may be deeper.
+        "beyond_window": fork.is_none(),
+        "old_tip": { "height": old_height, "hash": old_hash },
+        "new_tip": { "height": height, "hash": tip_hash },
+    }))
+}
+
+// Drops what the replaced blocks may have left behind: cached replies about
+// them, disk cache entries and Sapling tree states that were taken as final,
+// and the index when it had reached them (it keeps no undo data, so it is
+// rebuilt from its first block).
+async fn flush(rpc: &VerusRPC, from: u64, old_height: u64) -> Value {
+    let cached = rpc.cache.flush_from(from);
+    let disk = match &rpc.disk_cache {
+        Some(disk) if from + disk.depth <= old_height => disk.flush_from(from),
+        _ => 0,
+    };
+    let sapling = rpc.sapling_trees.flush_from(from);
+    let mut index_reset = false;
+    if let Some(indexer) = &rpc.indexer {
+        if indexer.store.height().await.ok().flatten().is_some_and(|indexed| indexed >= from) {
+            let start = indexer.store.start_height().await.ok().flatten().unwrap_or(0);
+            match indexer.store.reset(start).await {
+                Ok(()) => {
+                    eprintln!("reorg below indexed block {}: index cleared, rebuilding from block {}", from, start);
+                    index_reset = true;
+                },
+                Err(e) => eprintln!("reorg: failed to reset the index: {}", e),
+            }
+        }
+    }
+    json!({ "cache": cached, "disk_cache": disk, "sapling_trees": sapling, "index_reset": index_reset })
+}
+
+// Called by the tip follower on every poll: publishes a `reorg` event for
+// each reorg found and flushes what it affected.
+pub async fn check(rpc: &VerusRPC, height: u64) {
+    if rpc.reorgs.window == 0 {
+        return;
+    }
+    let mut reorg = match compare(rpc, height).await {
+        Some(reorg) => reorg,
+        None => return,
+    };
+    let from = reorg["fork_height"].as_u64().unwrap_or_default() + 1;
+    let old_height = reorg["old_tip"]["height"].as_u64().unwrap_or_default();
+    reorg["flushed"] = flush(rpc, from, old_height).await;
+    eprintln!("reorg of depth {} from block {}", reorg["depth"], from);
+    rpc.reorgs.detected.fetch_add(1, Ordering::Relaxed);
+    let mut reorgs = rpc.reorgs.reorgs.lock().unwrap();
+    if reorgs.len() == KEPT_REORGS {
+        reorgs.pop_front();
+    }
+    reorgs.push_back(reorg.clone());
+    drop(reorgs);
+    rpc.events.publish("reorg", reorg);
+}
+
+// GET /stats/reorgs: the reorgs seen since startup, newest first, each with
+// its depth, the last block both chains share, the old and new tips and what
+// was flushed.
+pub fn handle(rpc: &VerusRPC) -> Response<Body> {
+    json_response(StatusCode::OK, rpc.reorgs.summary())
+}
@@ -0,0 +1,215 @@
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+use crate::events;
+use crate::indexer;
+use crate::listener::Profile;
+
+pub fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// GET endpoints served next to the JSON-RPC interface on the public listener.
+// Anything not matched here falls through to the JSON-RPC handler.
+pub async fn route(req: &Request<Body>, rpc: &Arc<VerusRPC>, access: Access, profile: &Profile) -> Option<Response<Body>> {
+    if req.method() != Method::GET {
+        return None;
+    }
+    match req.uri().path() {
+        "/mempool/fees" => Some(match rpc.mempool.snapshot() {
+            Some(snapshot) => json_response(StatusCode::OK, snapshot),
+            None => json_response(StatusCode::SERVICE_UNAVAILABLE, json!({"error": "Mempool has not been sampled yet"})),
+        }),
+        "/readyz" => Some(readyz(rpc)),
+        "/notarizations" => Some(json_response(StatusCode::OK, rpc.notarizations.summary())),
+        "/stats/supply" => Some(crate::chain_stats::handle_supply(rpc).await),
+        "/stats/network" => Some(crate::chain_stats::handle_network(rpc).await),
+        "/stats/reorgs" => Some(crate::reorg::handle(rpc)),
+        "/openapi.json" => Some(crate::openapi::handle(req, rpc, access)),
+        "/maintenance" => Some(json_response(StatusCode::OK, rpc.runtime.maintenance())),
+        path if path == "/docs" || path.starts_with("/docs/") => Some(crate::docs::handle(path, req, rpc, access).await),
+        path if path.starts_with("/registrations/") => Some(match rpc.registrations.status(path.trim_start_matches("/registrations/")) {
+            Some(status) => json_response(StatusCode::OK, status),
+            None => json_response(StatusCode::NOT_FOUND, json!({"error": "No registration with that commitment txid is tracked"})),
+        }),
+        path if path == "/timelocks" || path.starts_with("/timelocks/") => Some(crate::timelocks::handle(path, rpc).await),
+        path if path.starts_with("/trust/") => Some(crate::trust::handle(path, rpc, access).await),
+        path if path.starts_with("/converters/") => Some(crate::converters::handle(path, rpc, access).await),
+        path if path.starts_with("/routes/") => Some(crate::routes::handle(path, req, rpc, access).await),
+        path if path.starts_with("/sapling/") => Some(crate::sapling::handle(path, req, rpc, access).await),
+        path if path.starts_with("/offers/") => Some(crate::offers::handle(path, req, rpc, access).await),
+        path if path.starts_with("/index/") => Some(index(path, req, rpc, access).await),
+        "/richlist" => Some(index("/index/richlist", req, rpc, access).await),
+        path if path == "/defi/volume" || path.starts_with("/defi/volume/") => {
+            Some(index(&format!("/index/{}", path.trim_start_matches("/defi/")), req, rpc, access).await)
+        },
+        path if path.starts_with("/identity/") && path.ends_with("/history") => {
+            let identity = path.trim_start_matches("/identity/").trim_end_matches("/history");
+            Some(index(&format!("/index/identity/{}/history", identity), req, rpc, access).await)
+        },
+        path if path.starts_with("/address/") && path.ends_with("/balance") => {
+            let address = path.trim_start_matches("/address/").trim_end_matches("/balance");
+            Some(index(&format!("/index/balance/{}", address), req, rpc, access).await)
+        },
+        path if path == "/history" || path.starts_with("/history/") => Some(history(path, req, rpc)),
+        path if path.starts_with("/events/") => Some(crate::sse::handle(req, rpc, profile).await),
+        _ => rpc.static_docs.handle(req),
+    }
+}
+
+// Readiness for load balancers and orchestrators: 503 with the reasons when
+// the daemon is still warming up, has stopped answering or the chain tip has
+// stalled, so traffic isn't sent to a proxy serving stale data.
+fn readyz(rpc: &VerusRPC) -> Response<Body> {
+    let warmup = rpc.upstream.warmup();
+    let wrong_chain = rpc.upstream.chain_mismatch().map(|reason| format!("The daemon is on the wrong chain: {}", reason));
+    let reasons: Vec<String> = wrong_chain.into_iter()
+        .chain(rpc.upstream.unreachable_reason())
+        .chain(warmup.unready_reason())
+        .chain(rpc.tip.unready_reasons())
+        .collect();
+    let body = json!({
+        "ready": reasons.is_empty(),
+        "upstream": rpc.upstream.health(),
+        "height": rpc.tip.height(),
+        "tip_age": rpc.tip.age(),
+        "progress": warmup.progress,
+        "reasons": reasons,
+    });
+    match reasons.is_empty() {
+        true => json_response(StatusCode::OK, body),
+        false => json_response(StatusCode::SERVICE_UNAVAILABLE, body),
+    }
+}
+
+// Whether `id` is already an i-address rather than a name to look up.
+pub fn is_id(id: &str) -> bool {
+    id.len() == 34 && id.starts_with('i') && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+// Turns a VDXF key name (e.g. vrsc::profile.name), identity name or currency
+// name into its i-address through the daemon; the lookups are cached.
+pub async fn resolve(rpc: &Arc<VerusRPC>, method: &str, id: &str, access: Access) -> Result<String, String> {
+    if is_id(id) {
+        return Ok(id.to_string());
+    }
+    let result = rpc.handle_call_as(method.to_string(), vec![json!(id)], access).await.map_err(|e| e.message)?;
+    let address = match method {
+        "getvdxfid" => &result["vdxfid"],
+        "getcurrency" => &result["currencyid"],
+        _ => &result["identity"]["identityaddress"],
+    };
+    address.as_str().map(str::to_string).ok_or_else(|| format!("Cannot resolve {}", id))
+}
+
+// GET /index/content/<vdxf key> lists the identities whose content has the key;
+// GET /index/identity/<identity>/content[?key=] is the history of an
+// identity's content, and /identity/<identity>/history lists every update of
+// an identity with the fields it changed. Address balances are served as /address/<address>/balance
+// and the largest holders of a currency as /richlist[?currency=]. Lists page
+// with ?start= and ?count=. /defi/volume[/<source>[/<destination>]][?from=&to=]
+// is the daily conversion volume per currency pair between two unix times
+// (the last 30 days by default), in units of the source currency.
+async fn index(path: &str, req: &Request<Body>, rpc: &Arc<VerusRPC>, access: Access) -> Response<Body> {
+    let indexer = match &rpc.indexer {
+        Some(indexer) => indexer,
+        None => return json_response(StatusCode::NOT_FOUND, json!({"error": "Indexing is not enabled"})),
+    };
+    let query = events::query(req.uri());
+    let start = query.get("start").and_then(|start| start.parse().ok()).unwrap_or(0);
+    let count = query.get("count").and_then(|count| count.parse().ok()).unwrap_or(indexer::MAX_PAGE).min(indexer::MAX_PAGE);
+    let parts: Vec<&str> = path.trim_start_matches("/index/").split('/').collect();
+    let result = match parts.as_slice() {
+        ["content", key] => match resolve(rpc, "getvdxfid", key, access).await {
+            Ok(key) => indexer.store.identities_with_key(&key, start, count).await.map(|identities| json!({"vdxfkey": key, "identities": identities})),
+            Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+        },
+        ["identity", identity, "content"] => {
+            let key = match query.get("key") {
+                Some(key) => match resolve(rpc, "getvdxfid", key, access).await {
+                    Ok(key) => Some(key),
+                    Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+                },
+                None => None,
+            };
+            match resolve(rpc, "getidentity", identity, access).await {
+                Ok(identity) => indexer.store.content_history(&identity, key.as_deref(), start, count).await.map(|history| json!({"identity": identity, "history": history})),
+                Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+            }
+        },
+        ["identity", identity, "history"] => match resolve(rpc, "getidentity", identity, access).await {
+            Ok(identity) => indexer.store.identity_history(&identity, start, count).await.map(|history| json!({"identity": identity, "history": history})),
+            Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+        },
+        ["balance", address] => {
+            let address = if address.ends_with('@') {
+                match resolve(rpc, "getidentity", address, access).await {
+                    Ok(address) => address,
+                    Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+                }
+            } else {
+                address.to_string()
+            };
+            indexer.store.balance(&address).await.map(|balances| json!({"address": address, "balances": balances}))
+        },
+        ["richlist"] => {
+            let currency = query.get("currency").map_or("native", String::as_str);
+            indexer.store.rich_list(currency, start, count).await.map(|holders| json!({"currency": currency, "holders": holders}))
+        },
+        ["volume", pair @ ..] if pair.len() <= 2 => {
+            let mut currencies = Vec::with_capacity(pair.len());
+            for currency in pair {
+                match resolve(rpc, "getcurrency", currency, access).await {
+                    Ok(currency) => currencies.push(currency),
+                    Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+                }
+            }
+            let to = query.get("to").and_then(|to| to.parse::<i64>().ok()).unwrap_or_else(indexer::unix_time);
+            let from = query.get("from").and_then(|from| from.parse::<i64>().ok()).unwrap_or(to - 30 * 86_400);
+            let (source, destination) = (currencies.first().map(String::as_str), currencies.get(1).map(String::as_str));
+            indexer.store.conversion_volume(source, destination, from / 86_400, to / 86_400).await
+                .map(|volume| json!({"source": source, "destination": destination, "from": from, "to": to, "volume": volume}))
+        },
+        _ => return json_response(StatusCode::NOT_FOUND, json!({"error": "Unknown index endpoint"})),
+    };
+    match result {
+        Ok(mut body) => {
+            body["indexed_height"] = json!(indexer.store.height().await.unwrap_or(None));
+            // Balances only add up when every block since genesis was indexed.
+            body["complete"] = json!(indexer.store.start_height().await.unwrap_or(None).is_some_and(|start| start <= 1));
+            json_response(StatusCode::OK, body)
+        },
+        Err(message) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": message})),
+    }
+}
+
+// GET /history lists the scheduled jobs; GET /history/<job>[?from=&to=&count=]
+// returns a job's samples between two unix times, oldest first.
+fn history(path: &str, req: &Request<Body>, rpc: &Arc<VerusRPC>) -> Response<Body> {
+    let history = match &rpc.history {
+        Some(history) => history,
+        None => return json_response(StatusCode::NOT_FOUND, json!({"error": "No jobs are scheduled"})),
+    };
+    let result = match path.trim_start_matches("/history").trim_start_matches('/') {
+        "" => history.jobs().map(|jobs| json!({"jobs": jobs})),
+        job if history.has_job(job) => {
+            let query = events::query(req.uri());
+            let from = query.get("from").and_then(|from| from.parse().ok());
+            let to = query.get("to").and_then(|to| to.parse().ok());
+            let count = query.get("count").and_then(|count| count.parse().ok()).unwrap_or(indexer::MAX_PAGE);
+            history.samples(job, from, to, count).map(|samples| json!({"job": job, "samples": samples}))
+        },
+        job => return json_response(StatusCode::NOT_FOUND, json!({"error": format!("Unknown job {}", job)})),
+    };
+    match result {
+        Ok(body) => json_response(StatusCode::OK, body),
+        Err(e) => json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+    }
+}
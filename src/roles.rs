@@ -0,0 +1,157 @@
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::annotations::Annotations;
+use crate::indexer::unix_time;
+use crate::limiter::Priority;
+
+// Roles are at most this many, so a request's roles fit in a bitmask.
+const MAX_ROLES: usize = 64;
+
+// A named set of methods, with the priority and rate its members get.
+struct Role {
+    name: String,
+    // Method groups: "read" (anything not annotated as a write), "write"
+    // (anything that is), "*" (everything) or a group(name) annotation.
+    groups: HashSet<String>,
+    methods: HashSet<String>,
+    priority: Option<Priority>,
+    // Requests per minute for each principal with the role, 0 for no limit.
+    rate_per_minute: u64,
+}
+
+impl Role {
+    fn permits(&self, method: &str, annotations: &Annotations) -> bool {
+        self.methods.contains(method)
+            || self.groups.contains("*")
+            || self.groups.contains(if annotations.write { "write" } else { "read" })
+            || annotations.groups.iter().any(|group| self.groups.contains(group))
+    }
+}
+
+// Roles from the [roles] table, and who holds them: API keys and VerusIDs
+// listed under a role, sessions whose token names it, and everyone else
+// with `default_role`, if set. Requests holding roles may only call methods
+// one of their roles covers, on top of what the listener allows. Roles are
+// kept as bitmasks of their index, so `Access` stays `Copy`.
+pub struct Roles {
+    roles: Vec<Role>,
+    keys: HashMap<String, u64>,
+    identities: HashMap<String, u64>,
+    default: u64,
+    // The current minute, and requests so far in it per principal.
+    window: Mutex<(i64, HashMap<String, u64>)>,
+}
+
+fn strings(entry: &HashMap<String, Value>, field: &str) -> Result<Vec<String>, String> {
+    match entry.get(field) {
+        Some(Value::Array(values)) => values.iter().map(|value| value.as_str().map(str::to_string).ok_or(format!("{} must be strings", field))).collect(),
+        Some(_) => Err(format!("{} must be an array", field)),
+        None => Ok(Vec::new()),
+    }
+}
+
+impl Roles {
+    pub fn load(table: HashMap<String, HashMap<String, Value>>, default: Option<String>) -> Result<Roles, String> {
+        if table.len() > MAX_ROLES {
+            return Err(format!("At most {} roles", MAX_ROLES));
+        }
+        let mut roles = Roles { roles: Vec::new(), keys: HashMap::new(), identities: HashMap::new(), default: 0, window: Mutex::new((0, HashMap::new())) };
+        let mut names: Vec<_> = table.keys().cloned().collect();
+        names.sort();
+        for (index, name) in names.into_iter().enumerate() {
+            let entry = &table[&name];
+            let bit = 1u64 << index;
+            let error = |e: String| format!("role {}: {}", name, e);
+            let priority = match entry.get("priority") {
+                Some(priority) => Some(priority.as_str().and_then(Priority::parse).ok_or_else(|| error(format!("unknown priority {}", priority)))?),
+                None => None,
+            };
+            let rate_per_minute = match entry.get("rate_per_minute") {
+                Some(rate) => rate.as_u64().ok_or_else(|| error("rate_per_minute must be a number".to_string()))?,
+                None => 0,
+            };
+            for key in strings(entry, "keys").map_err(error)? {
+                *roles.keys.entry(key).or_default() |= bit;
+            }
+            for identity in strings(entry, "identities").map_err(error)? {
+                *roles.identities.entry(identity.to_lowercase()).or_default() |= bit;
+            }
+            roles.roles.push(Role {
+                groups: strings(entry, "groups").map_err(error)?.into_iter().collect(),
+                methods: strings(entry, "methods").map_err(error)?.into_iter().collect(),
+                name,
+                priority,
+                rate_per_minute,
+            });
+        }
+        if let Some(default) = default {
+            roles.default = roles.named(std::slice::from_ref(&default));
+            if roles.default == 0 {
+                return Err(format!("default_role {} is not a role", default));
+            }
+        }
+        Ok(roles)
+    }
+
+    fn each(&self, roles: u64) -> impl Iterator<Item = &Role> {
+        self.roles.iter().enumerate().filter(move |(index, _)| roles & (1 << index) != 0).map(|(_, role)| role)
+    }
+
+    pub fn of_key(&self, key: &str) -> u64 {
+        self.keys.get(key).copied().unwrap_or(0)
+    }
+
+    pub fn of_identity(&self, identity: &str) -> u64 {
+        self.identities.get(&identity.to_lowercase()).copied().unwrap_or(0)
+    }
+
+    // The roles of a request that holds none of its own.
+    pub fn default(&self) -> u64 {
+        self.default
+    }
+
+    pub fn names(&self, roles: u64) -> Vec<String> {
+        self.each(roles).map(|role| role.name.clone()).collect()
+    }
+
+    // The roles with these names; unknown names are ignored.
+    pub fn named(&self, names: &[String]) -> u64 {
+        self.roles.iter().enumerate()
+            .filter(|(_, role)| names.contains(&role.name))
+            .fold(0, |roles, (index, _)| roles | 1 << index)
+    }
+
+    // Whether requests holding `roles` may call `method`. Holding no roles
+    // leaves it to the listener.
+    pub fn permits(&self, roles: u64, method: &str, annotations: &Annotations) -> bool {
+        roles == 0 || self.each(roles).any(|role| role.permits(method, annotations))
+    }
+
+    // The most urgent priority any of the roles gives.
+    pub fn priority(&self, roles: u64) -> Option<Priority> {
+        self.each(roles).filter_map(|role| role.priority).min()
+    }
+
+    // Counts `calls` calls by `principal` against the most generous rate of
+    // its roles, and says how many seconds to wait if they would go over.
+    pub fn admit(&self, roles: u64, principal: &str, calls: u64) -> Result<(), u64> {
+        let rates: Vec<u64> = self.each(roles).map(|role| role.rate_per_minute).collect();
+        let limit = match rates.iter().all(|rate| *rate > 0) {
+            true if !rates.is_empty() => *rates.iter().max().unwrap(),
+            _ => return Ok(()),
+        };
+        let now = unix_time();
+        let mut window = self.window.lock().unwrap();
+        if window.0 != now / 60 {
+            *window = (now / 60, HashMap::new());
+        }
+        let count = window.1.entry(principal.to_string()).or_default();
+        if *count + calls > limit {
+            return Err((60 - now % 60) as u64);
+        }
+        *count += calls;
+        Ok(())
+    }
+}
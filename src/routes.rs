@@ -0,0 +1,170 @@
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+use crate::converters::Basket;
+use crate::events;
+use crate::fees::{CONVERSION_FEE_RATE, RESERVE_TO_RESERVE_FEE_RATE};
+use crate::limiter::Priority;
+use crate::rest::{self, json_response};
+
+// The fractional option bit of a currency definition.
+const FRACTIONAL: u64 = 0x01;
+// Most routes returned, best first.
+const MAX_ROUTES: usize = 10;
+// Partial paths explored before the search gives up on finding more.
+const MAX_EXPLORED: usize = 20_000;
+
+// One conversion of a route: `from` to `to` through a basket.
+#[derive(Clone)]
+struct Hop<'a> {
+    basket: &'a Basket,
+    from: &'a str,
+    to: &'a str,
+}
+
+// The fractional baskets of this chain with their latest state, from the full
+// listcurrencies list (cached, so planning doesn't go to the daemon each time).
+async fn baskets(rpc: &Arc<VerusRPC>) -> Result<Vec<Basket>, String> {
+    let listed = rpc.call("listcurrencies".to_string(), vec![], Priority::Background).await.map_err(|e| e.message)?;
+    Ok(listed.as_array().into_iter().flatten()
+        .filter(|currency| currency["currencydefinition"]["options"].as_u64().is_some_and(|options| options & FRACTIONAL != 0))
+        .filter_map(|currency| {
+            let definition = &currency["currencydefinition"];
+            let name = match &definition["fullyqualifiedname"] {
+                Value::Null => definition["name"].clone(),
+                name => name.clone(),
+            };
+            Some(Basket::from_state(definition["currencyid"].as_str()?, name, &currency["bestcurrencystate"]))
+        })
+        .filter(|basket| !basket.reserves.is_empty() && basket.supply > 0.0)
+        .collect())
+}
+
+// What converting `amount` through one basket yields, after the conversion fee
+// and the price moving along the basket's bonding curve. Baskets convert a
+// whole block's conversions at one price, so this is an estimate.
+fn convert(basket: &Basket, from: &str, to: &str, amount: f64) -> Option<f64> {
+    let buy = |currency: &str, amount: f64| {
+        let reserve = basket.reserve(currency)?;
+        match reserve.weight > 0.0 && reserve.reserves > 0.0 {
+            true => Some(basket.supply * ((1.0 + amount / reserve.reserves).powf(reserve.weight) - 1.0)),
+            false => basket.rate(currency, &basket.id).map(|rate| amount * rate),
+        }
+    };
+    let sell = |reserve: &str, units: f64, supply: f64| {
+        let reserve = basket.reserve(reserve)?;
+        match reserve.weight > 0.0 && units < supply {
+            true => Some(reserve.reserves * (1.0 - (1.0 - units / supply).powf(1.0 / reserve.weight))),
+            false => None,
+        }
+    };
+    match (from == basket.id, to == basket.id) {
+        (false, true) => buy(from, amount * (1.0 - CONVERSION_FEE_RATE)),
+        (true, false) => sell(to, amount * (1.0 - CONVERSION_FEE_RATE), basket.supply),
+        (false, false) => {
+            let units = buy(from, amount * (1.0 - RESERVE_TO_RESERVE_FEE_RATE))?;
+            sell(to, units, basket.supply + units)
+        },
+        (true, true) => None,
+    }
+}
+
+// Every route from `from` to `to` of at most `max_hops` conversions that
+// doesn't pass through a currency or basket twice.
+fn search<'a>(baskets: &'a [Basket], from: &'a str, to: &str, max_hops: usize) -> Vec<Vec<Hop<'a>>> {
+    let mut routes = Vec::new();
+    let mut stack: Vec<Vec<Hop<'a>>> = vec![Vec::new()];
+    let mut explored = 0;
+    while let Some(path) = stack.pop() {
+        explored += 1;
+        if explored > MAX_EXPLORED {
+            break;
+        }
+        let at = path.last().map_or(from, |hop| hop.to);
+        if path.len() >= max_hops {
+            continue;
+        }
+        for basket in baskets.iter().filter(|basket| basket.converts(at)) {
+            if path.iter().any(|hop| hop.basket.id == basket.id) {
+                continue;
+            }
+            let sides = std::iter::once(basket.id.as_str()).chain(basket.reserves.iter().map(|(id, _)| id.as_str()));
+            for next in sides {
+                if next == at || next == from || path.iter().any(|hop| hop.to == next) {
+                    continue;
+                }
+                let mut extended = path.clone();
+                extended.push(Hop { basket, from: at, to: next });
+                match next == to {
+                    true => routes.push(extended),
+                    false => stack.push(extended),
+                }
+            }
+        }
+    }
+    routes
+}
+
+// GET /routes/<from>/<to>[?amount=1][&maxhops=] plans conversions between two
+// currencies through the chain's fractional baskets: every route of up to
+// maxhops conversions (route_max_hops at most), each with its hops and the
+// estimated amount out after conversion fees and price impact, best first.
+pub async fn handle(path: &str, req: &Request<Body>, rpc: &Arc<VerusRPC>, access: Access) -> Response<Body> {
+    let (from, to) = match path.trim_start_matches("/routes/").split_once('/') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() && !to.contains('/') => (from, to),
+        _ => return json_response(StatusCode::NOT_FOUND, json!({"error": "Use /routes/<from>/<to>"})),
+    };
+    let query = events::query(req.uri());
+    let amount = match query.get("amount").map(|amount| amount.parse::<f64>()) {
+        None => 1.0,
+        Some(Ok(amount)) if amount > 0.0 && amount.is_finite() => amount,
+        Some(_) => return json_response(StatusCode::BAD_REQUEST, json!({"error": "amount must be a positive number"})),
+    };
+    let max_hops = match query.get("maxhops").map(|hops| hops.parse::<usize>()) {
+        None => rpc.route_max_hops,
+        Some(Ok(hops)) if hops > 0 && hops <= rpc.route_max_hops => hops,
+        Some(_) => return json_response(StatusCode::BAD_REQUEST, json!({"error": format!("maxhops must be between 1 and {}", rpc.route_max_hops)})),
+    };
+    let (from, to) = match (rest::resolve(rpc, "getcurrency", from, access).await, rest::resolve(rpc, "getcurrency", to, access).await) {
+        (Ok(from), Ok(to)) if from != to => (from, to),
+        (Ok(_), Ok(_)) => return json_response(StatusCode::BAD_REQUEST, json!({"error": "from and to are the same currency"})),
+        (Err(message), _) | (_, Err(message)) => return json_response(StatusCode::BAD_REQUEST, json!({"error": message})),
+    };
+    let baskets = match baskets(rpc).await {
+        Ok(baskets) => baskets,
+        Err(message) => return json_response(StatusCode::BAD_GATEWAY, json!({"error": message})),
+    };
+    let mut routes: Vec<(f64, Value)> = search(&baskets, &from, &to, max_hops).into_iter()
+        .filter_map(|route| {
+            let mut out = amount;
+            let mut hops = Vec::with_capacity(route.len());
+            for hop in &route {
+                let input = out;
+                out = convert(hop.basket, hop.from, hop.to, input).filter(|out| *out > 0.0)?;
+                hops.push(json!({
+                    "from": hop.from,
+                    "to": hop.to,
+                    "via": hop.basket.id,
+                    "via_name": hop.basket.name,
+                    "amount_in": input,
+                    "estimated_out": out,
+                }));
+            }
+            Some((out, json!({ "estimated_out": out, "hops": hops })))
+        })
+        .collect();
+    routes.sort_by(|a, b| b.0.total_cmp(&a.0));
+    routes.truncate(MAX_ROUTES);
+    let routes: Vec<Value> = routes.into_iter().map(|(_, route)| route).collect();
+    json_response(StatusCode::OK, json!({
+        "from": from,
+        "to": to,
+        "amount": amount,
+        "height": rpc.tip.height(),
+        "baskets": baskets.len(),
+        "routes": routes,
+    }))
+}
@@ -0,0 +1,441 @@
+use hyper::{Body, Method, Request, Response, StatusCode};
+use jsonrpc::error::RpcError;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use crate::VerusRPC;
+use crate::annotations::Annotations;
+use crate::client_ip::Cidr;
+use crate::indexer::unix_time;
+use crate::rest::json_response;
+use crate::session::{key_subject, random_hex};
+
+// An address or block banned from the public listeners, until `until` if set.
+struct Ban {
+    network: Cidr,
+    spec: String,
+    reason: String,
+    until: Option<i64>,
+}
+
+impl Ban {
+    fn is_active(&self, now: i64) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "ip": self.spec, "reason": self.reason, "until": self.until })
+    }
+}
+
+// A maintenance window: from `start` until `end`, calls to methods in any of
+// `groups` fail with `message` and the advertised end time. Groups are named
+// as in roles: "*", "write", "read", a method group or a method.
+struct Window {
+    id: String,
+    groups: Vec<String>,
+    start: i64,
+    end: i64,
+    message: String,
+}
+
+impl Window {
+    fn covers(&self, method: &str, annotations: &Annotations) -> bool {
+        self.groups.iter().any(|group| {
+            group == "*"
+                || group == method
+                || group == if annotations.write { "write" } else { "read" }
+                || annotations.groups.contains(group)
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "id": self.id, "groups": self.groups, "start": self.start, "end": self.end, "message": self.message })
+    }
+
+    fn from_json(entry: &Value) -> Option<Window> {
+        Some(Window {
+            id: entry["id"].as_str()?.to_string(),
+            groups: serde_json::from_value(entry["groups"].clone()).ok()?,
+            start: entry["start"].as_i64()?,
+            end: entry["end"].as_i64()?,
+            message: entry["message"].as_str().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+// An API key created through the admin listener. Only its hash is kept; the
+// key itself is shown once, when it is created.
+#[derive(Clone)]
+pub struct ManagedKey {
+    hash: String,
+    label: String,
+    created: i64,
+    pub roles: Vec<String>,
+    // Passes enforce_origin, like the write_api_keys.
+    pub write: bool,
+    pub mining: bool,
+}
+
+impl ManagedKey {
+    fn to_json(&self, id: &str) -> Value {
+        json!({
+            "id": id,
+            "hash": self.hash,
+            "label": self.label,
+            "created": self.created,
+            "roles": self.roles,
+            "write": self.write,
+            "mining": self.mining,
+        })
+    }
+
+    fn from_json(entry: &Value) -> Option<(String, ManagedKey)> {
+        Some((entry["id"].as_str()?.to_string(), ManagedKey {
+            hash: entry["hash"].as_str()?.to_string(),
+            label: entry["label"].as_str().unwrap_or_default().to_string(),
+            created: entry["created"].as_i64().unwrap_or_default(),
+            roles: serde_json::from_value(entry["roles"].clone()).unwrap_or_default(),
+            write: entry["write"].as_bool().unwrap_or(false),
+            mining: entry["mining"].as_bool().unwrap_or(false),
+        }))
+    }
+}
+
+fn key_hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[derive(Default)]
+struct State {
+    bans: Vec<Ban>,
+    // By id, the key's `key_subject`.
+    keys: HashMap<String, ManagedKey>,
+    // Requests per minute, by key subject.
+    limits: HashMap<String, u64>,
+    maintenance: Vec<Window>,
+    // The current minute, and requests so far in it per limited key.
+    window: (i64, HashMap<String, u64>),
+}
+
+impl State {
+    fn to_json(&self) -> Value {
+        let now = unix_time();
+        json!({
+            "bans": self.bans.iter().filter(|ban| ban.is_active(now)).map(Ban::to_json).collect::<Vec<_>>(),
+            "keys": self.keys.iter().map(|(id, key)| key.to_json(id)).collect::<Vec<_>>(),
+            "limits": self.limits,
+            "maintenance": self.maintenance.iter().filter(|window| window.end > now).map(Window::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    fn from_json(saved: &Value) -> Result<State, String> {
+        let mut state = State::default();
+        for ban in saved["bans"].as_array().into_iter().flatten() {
+            let spec = ban["ip"].as_str().unwrap_or_default();
+            state.bans.push(Ban {
+                network: Cidr::parse(spec).ok_or_else(|| format!("Invalid ban {}", spec))?,
+                spec: spec.to_string(),
+                reason: ban["reason"].as_str().unwrap_or_default().to_string(),
+                until: ban["until"].as_i64(),
+            });
+        }
+        for key in saved["keys"].as_array().into_iter().flatten() {
+            let (id, key) = ManagedKey::from_json(key).ok_or("Invalid key entry")?;
+            state.keys.insert(id, key);
+        }
+        for (subject, limit) in saved["limits"].as_object().into_iter().flatten() {
+            state.limits.insert(subject.clone(), limit.as_u64().ok_or("Invalid limit")?);
+        }
+        for window in saved["maintenance"].as_array().into_iter().flatten() {
+            state.maintenance.push(Window::from_json(window).ok_or("Invalid maintenance window")?);
+        }
+        Ok(state)
+    }
+}
+
+// IP bans, API keys, per-key rate limits and maintenance windows that
+// operators change through the admin listener while the proxy runs. Every change is written to `path`
+// (through a temporary file, so a crash leaves the old or the new state) and
+// read back at startup.
+pub struct RuntimeAccess {
+    path: String,
+    state: Mutex<State>,
+}
+
+impl RuntimeAccess {
+    pub fn open(path: String) -> Result<RuntimeAccess, String> {
+        let state = match std::path::Path::new(&path).exists() {
+            true => {
+                let saved = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+                let saved = serde_json::from_slice(&saved).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+                State::from_json(&saved)?
+            },
+            false => State::default(),
+        };
+        Ok(RuntimeAccess { path, state: Mutex::new(state) })
+    }
+
+    fn save(&self, state: &State) -> Result<(), String> {
+        let temp = format!("{}.tmp", self.path);
+        std::fs::write(&temp, state.to_json().to_string())
+            .and_then(|_| std::fs::rename(&temp, &self.path))
+            .map_err(|e| format!("Failed to save {}: {}", self.path, e))
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let state = self.state.lock().unwrap();
+        let now = unix_time();
+        state.bans.iter().any(|ban| ban.is_active(now) && ban.network.contains(ip))
+    }
+
+    // The managed key a client presented, if it is one.
+    pub fn key(&self, key: &str) -> Option<ManagedKey> {
+        let state = self.state.lock().unwrap();
+        state.keys.get(&key_subject(key)).filter(|managed| managed.hash == key_hash(key)).cloned()
+    }
+
+    // Counts `calls` calls against the rate limit of the key (or session) with
+    // this subject, and says how many seconds to wait if they would go over.
+    pub fn admit(&self, subject: &str, calls: u64) -> Result<(), u64> {
+        let mut state = self.state.lock().unwrap();
+        let limit = match state.limits.get(subject) {
+            Some(limit) => *limit,
+            None => return Ok(()),
+        };
+        let now = unix_time();
+        if state.window.0 != now / 60 {
+            state.window = (now / 60, HashMap::new());
+        }
+        let count = state.window.1.entry(subject.to_string()).or_default();
+        if *count + calls > limit {
+            return Err((60 - now % 60) as u64);
+        }
+        *count += calls;
+        Ok(())
+    }
+
+    // Applies a change and saves it, or leaves everything as it was.
+    fn change<T>(&self, f: impl FnOnce(&mut State) -> Result<T, String>) -> Result<T, String> {
+        let mut state = self.state.lock().unwrap();
+        let saved = state.to_json();
+        let result = f(&mut state)?;
+        if let Err(e) = self.save(&state) {
+            *state = State::from_json(&saved).unwrap_or_default();
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    fn ban(&self, body: &Value) -> Result<Value, String> {
+        let spec = body["ip"].as_str().ok_or("Give ip, an address or CIDR block")?.trim().to_string();
+        let network = Cidr::parse(&spec).ok_or_else(|| format!("Invalid address {}", spec))?;
+        let until = body["ttl"].as_i64().map(|ttl| unix_time() + ttl);
+        let ban = Ban { network, spec: spec.clone(), reason: body["reason"].as_str().unwrap_or_default().to_string(), until };
+        let banned = ban.to_json();
+        self.change(|state| {
+            let now = unix_time();
+            state.bans.retain(|ban| ban.spec != spec && ban.is_active(now));
+            state.bans.push(ban);
+            Ok(banned)
+        })
+    }
+
+    fn unban(&self, spec: &str) -> Result<bool, String> {
+        self.change(|state| {
+            let before = state.bans.len();
+            state.bans.retain(|ban| ban.spec != spec);
+            Ok(state.bans.len() < before)
+        })
+    }
+
+    // Creates a key and returns it, the only time it is shown.
+    fn create_key(&self, body: &Value) -> Result<Value, String> {
+        let roles = match &body["roles"] {
+            Value::Null => Vec::new(),
+            roles => serde_json::from_value(roles.clone()).map_err(|_| "roles must be an array of role names")?,
+        };
+        let key = format!("vk{}", random_hex(24));
+        let id = key_subject(&key);
+        let managed = ManagedKey {
+            hash: key_hash(&key),
+            label: body["label"].as_str().unwrap_or_default().to_string(),
+            created: unix_time(),
+            roles,
+            write: body["write"].as_bool().unwrap_or(false),
+            mining: body["mining"].as_bool().unwrap_or(false),
+        };
+        let mut created = managed.to_json(&id);
+        created["key"] = json!(key);
+        let rate = body["rate_per_minute"].as_u64();
+        self.change(|state| {
+            state.keys.insert(id.clone(), managed);
+            if let Some(rate) = rate {
+                state.limits.insert(id, rate);
+            }
+            Ok(created)
+        })
+    }
+
+    fn revoke_key(&self, id: &str) -> Result<bool, String> {
+        self.change(|state| {
+            state.limits.remove(id);
+            Ok(state.keys.remove(id).is_some())
+        })
+    }
+
+    // Fails calls to `method` while a maintenance window covering it is open,
+    // saying why and until when.
+    pub fn check_maintenance(&self, method: &str, annotations: &Annotations) -> Result<(), RpcError> {
+        let now = unix_time();
+        let state = self.state.lock().unwrap();
+        let window = match state.maintenance.iter().filter(|window| window.start <= now && now < window.end && window.covers(method, annotations)).max_by_key(|window| window.end) {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        let message = match window.message.is_empty() {
+            true => "Unavailable during maintenance".to_string(),
+            false => window.message.clone(),
+        };
+        Err(RpcError {
+            code: -32005,
+            message: format!("{} (until {})", message, window.end),
+            data: Some(serde_json::value::to_raw_value(&json!({ "until": window.end, "retry_after": window.end - now, "groups": window.groups })).unwrap()),
+        })
+    }
+
+    // The open and upcoming windows, without their ids, for clients.
+    pub fn maintenance(&self) -> Value {
+        let now = unix_time();
+        let state = self.state.lock().unwrap();
+        let windows: Vec<Value> = state.maintenance.iter()
+            .filter(|window| window.end > now)
+            .map(|window| json!({ "groups": window.groups, "start": window.start, "end": window.end, "message": window.message, "active": window.start <= now }))
+            .collect();
+        json!({ "time": now, "windows": windows })
+    }
+
+    fn schedule_maintenance(&self, body: &Value) -> Result<Value, String> {
+        let groups: Vec<String> = match &body["groups"] {
+            Value::Array(groups) if !groups.is_empty() => serde_json::from_value(Value::Array(groups.clone())).map_err(|_| "groups must be an array of strings")?,
+            _ => return Err("Give groups, e.g. [\"write\"]".to_string()),
+        };
+        let now = unix_time();
+        let start = body["start"].as_i64().unwrap_or(now);
+        let end = match (body["end"].as_i64(), body["duration"].as_i64()) {
+            (Some(end), None) => end,
+            (None, Some(duration)) => start + duration,
+            _ => return Err("Give end (a unix time) or duration (seconds)".to_string()),
+        };
+        if end <= start.max(now) {
+            return Err("The window must end after it starts, in the future".to_string());
+        }
+        let window = Window { id: random_hex(8), groups, start, end, message: body["message"].as_str().unwrap_or_default().to_string() };
+        let scheduled = window.to_json();
+        self.change(|state| {
+            state.maintenance.retain(|window| window.end > now);
+            state.maintenance.push(window);
+            Ok(scheduled)
+        })
+    }
+
+    fn cancel_maintenance(&self, id: &str) -> Result<bool, String> {
+        self.change(|state| {
+            let before = state.maintenance.len();
+            state.maintenance.retain(|window| window.id != id);
+            Ok(state.maintenance.len() < before)
+        })
+    }
+
+    fn set_limit(&self, subject: String, rate: Option<u64>) -> Result<(), String> {
+        self.change(|state| {
+            match rate {
+                Some(rate) => state.limits.insert(subject, rate),
+                None => state.limits.remove(&subject),
+            };
+            Ok(())
+        })
+    }
+}
+
+fn query_param(req: &Request<Body>, name: &str) -> Option<String> {
+    req.uri().query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+// The key subject an admin request is about: `id` (from GET /keys, or a
+// session subject) or the `api_key` itself.
+fn subject(id: Option<String>, key: Option<String>) -> Option<String> {
+    id.or_else(|| key.map(|key| key_subject(&key))).filter(|subject| !subject.is_empty())
+}
+
+// Admin listener endpoints:
+//   GET /bans, POST /bans {"ip", "reason", "ttl"}, DELETE /bans?ip= (or
+//     {"ip"}, for blocks)
+//   GET /keys, POST /keys {"label", "roles", "write", "mining",
+//     "rate_per_minute"}, DELETE /keys?id= (also ends its sessions)
+//   GET /limits, PUT /limits {"id" or "api_key", "rate_per_minute"},
+//     DELETE /limits?id= or ?api_key=
+//   GET /maintenance, POST /maintenance {"groups", "start", "end" or
+//     "duration", "message"}, DELETE /maintenance?id=
+pub async fn handle(req: Request<Body>, rpc: &VerusRPC) -> Result<Response<Body>, hyper::Error> {
+    let runtime = &rpc.runtime;
+    let path = req.uri().path().to_string();
+    let method = req.method().clone();
+    let (id, key, ip) = (query_param(&req, "id"), query_param(&req, "api_key"), query_param(&req, "ip"));
+    let (parts, body) = req.into_parts();
+    let mut whole_body = Vec::new();
+    if let Err(response) = crate::read_body(rpc, &parts.headers, body, &mut whole_body).await? {
+        return Ok(response);
+    }
+    let body = whole_body;
+    let body: Value = match body.is_empty() {
+        true => Value::Object(Map::new()),
+        false => match crate::json::from_slice(&body) {
+            Some(body @ Value::Object(_)) => body,
+            _ => return Ok(json_response(StatusCode::BAD_REQUEST, json!({"error": "Body must be a JSON object"}))),
+        },
+    };
+    let result = match (&method, path.as_str()) {
+        (&Method::GET, _) => Ok(runtime.state.lock().unwrap().to_json()[&path[1..]].clone()),
+        (&Method::POST, "/bans") => runtime.ban(&body),
+        (&Method::DELETE, "/bans") => match ip.as_deref().or_else(|| body["ip"].as_str()) {
+            Some(ip) => runtime.unban(ip).map(|removed| json!({ "removed": removed })),
+            None => Err("Give ?ip= or {\"ip\"}".to_string()),
+        },
+        (&Method::POST, "/keys") => runtime.create_key(&body),
+        (&Method::DELETE, "/keys") => match id {
+            Some(id) => runtime.revoke_key(&id).map(|removed| {
+                rpc.sessions.revoke_subject(&id);
+                json!({ "removed": removed })
+            }),
+            None => Err("Give ?id=".to_string()),
+        },
+        (&Method::PUT, "/limits") => {
+            let subject = subject(body["id"].as_str().map(str::to_string), body["api_key"].as_str().map(str::to_string));
+            match (subject, body["rate_per_minute"].as_u64()) {
+                (Some(subject), Some(rate)) => runtime.set_limit(subject.clone(), Some(rate)).map(|_| json!({ "id": subject, "rate_per_minute": rate })),
+                _ => Err("Give id or api_key, and rate_per_minute".to_string()),
+            }
+        },
+        (&Method::DELETE, "/limits") => match subject(id, key) {
+            Some(subject) => runtime.set_limit(subject.clone(), None).map(|_| json!({ "id": subject, "rate_per_minute": null })),
+            None => Err("Give ?id= or ?api_key=".to_string()),
+        },
+        (&Method::POST, "/maintenance") => runtime.schedule_maintenance(&body),
+        (&Method::DELETE, "/maintenance") => match id {
+            Some(id) => runtime.cancel_maintenance(&id).map(|removed| json!({ "removed": removed })),
+            None => Err("Give ?id=".to_string()),
+        },
+        _ => return Ok(json_response(StatusCode::METHOD_NOT_ALLOWED, json!({"error": "Method not allowed"}))),
+    };
+    Ok(match result {
+        Ok(reply) => json_response(StatusCode::OK, reply),
+        Err(e) => json_response(StatusCode::BAD_REQUEST, json!({ "error": e })),
+    })
+}
@@ -0,0 +1,94 @@
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::indexer::unix_time;
+use crate::redact;
+
+// The most of an unparseable body kept in a sample.
+const MALFORMED_PREFIX: usize = 4096;
+
+// Writes a percentage of requests, per method, to a JSON lines file, for
+// building benchmark scenarios from real traffic and for chasing rare
+// malformed clients. Each line is a JSON-RPC request that can be posted back
+// as it is (params masked as `redact` masks them, so raw transactions keep only
+// their txid), with the time it came in; a body that didn't parse is kept as
+// `malformed`, its first 4 KiB with long encoded runs masked. Sampling stops
+// once the file reaches `max_bytes`.
+pub struct Sampler {
+    // The file and how large it is.
+    file: Mutex<(File, u64)>,
+    max_bytes: u64,
+    percent: f64,
+    method_percent: HashMap<String, f64>,
+}
+
+// A random number in [0, 100).
+fn roll() -> f64 {
+    let mut random = [0u8; 4];
+    match getrandom::getrandom(&mut random) {
+        Ok(()) => u32::from_le_bytes(random) as f64 / (u32::MAX as f64 + 1.0) * 100.0,
+        Err(_) => 100.0,
+    }
+}
+
+impl Sampler {
+    pub fn open(path: &str, percent: f64, method_percent: HashMap<String, f64>, max_bytes: u64) -> Result<Sampler, String> {
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        let size = file.metadata().map_err(|e| format!("Failed to open {}: {}", path, e))?.len();
+        Ok(Sampler { file: Mutex::new((file, size)), max_bytes, percent, method_percent })
+    }
+
+    fn chosen(&self, method: &str) -> bool {
+        let percent = self.method_percent.get(method).copied().unwrap_or(self.percent);
+        percent > 0.0 && roll() < percent
+    }
+
+    fn write(&self, sample: Value) {
+        let mut line = sample.to_string();
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        let (file, size) = &mut *file;
+        if *size + line.len() as u64 > self.max_bytes {
+            return;
+        }
+        match file.write_all(line.as_bytes()) {
+            Ok(()) => *size += line.len() as u64,
+            Err(e) => eprintln!("sampler: failed to write: {}", e),
+        }
+    }
+
+    // Samples a request body: each call of a batch on its own, by its method's
+    // percentage, and a body that isn't JSON-RPC at all by the default one.
+    pub fn offer(&self, body: Option<&Value>, raw: &[u8]) {
+        let calls: Vec<&Value> = match body {
+            Some(Value::Array(calls)) => calls.iter().collect(),
+            Some(call) => vec![call],
+            None => {
+                if self.chosen("") {
+                    let prefix = String::from_utf8_lossy(&raw[..raw.len().min(MALFORMED_PREFIX)]);
+                    self.write(json!({ "time": unix_time(), "size": raw.len(), "malformed": redact::text(&prefix) }));
+                }
+                return;
+            },
+        };
+        for call in calls {
+            let method = call["method"].as_str().unwrap_or_default();
+            if !self.chosen(method) {
+                continue;
+            }
+            self.write(match call["method"].is_string() {
+                true => json!({
+                    "jsonrpc": "1.0",
+                    "id": call["id"],
+                    "method": method,
+                    "params": redact::params(method, &call["params"]),
+                    "time": unix_time(),
+                }),
+                false => json!({ "time": unix_time(), "malformed": redact::value(call) }),
+            });
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+use crate::events;
+use crate::rest::json_response;
+
+// Heights at least this far below the tip are taken as final, so their tree
+// states are kept.
+const FINAL_DEPTH: u64 = 10;
+// Most tree states one range request returns.
+const MAX_RANGE: u64 = 100;
+
+// The Sapling note commitment tree at a height, as getsaplingtree returns it,
+// cached for final heights so clients syncing shielded state height by height
+// don't each go to the daemon for it.
+pub struct SaplingTrees {
+    capacity: usize,
+    trees: Mutex<(HashMap<u64, Value>, VecDeque<u64>)>,
+}
+
+impl SaplingTrees {
+    pub fn new(capacity: usize) -> SaplingTrees {
+        SaplingTrees { capacity, trees: Mutex::new((HashMap::new(), VecDeque::new())) }
+    }
+
+    fn get(&self, height: u64) -> Option<Value> {
+        self.trees.lock().unwrap().0.get(&height).cloned()
+    }
+
+    fn insert(&self, height: u64, tree: Value) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut trees = self.trees.lock().unwrap();
+        let (by_height, order) = &mut *trees;
+        if by_height.insert(height, tree).is_none() {
+            order.push_back(height);
+        }
+        while order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                by_height.remove(&oldest);
+            }
+        }
+    }
+
+    // Drops the states at or above `height`, after a reorg replaced them.
+    pub fn flush_from(&self, height: u64) -> usize {
+        let mut trees = self.trees.lock().unwrap();
+        let (by_height, order) = &mut *trees;
+        order.retain(|cached| *cached < height);
+        let before = by_height.len();
+        by_height.retain(|cached, _| *cached < height);
+        before - by_height.len()
+    }
+}
+
+// One optional node of a serialized tree, moving `rest` past it.
+fn node(rest: &mut &[u8]) -> Option<Option<String>> {
+    let (present, tail) = rest.split_first()?;
+    *rest = tail;
+    match present {
+        0 => Some(None),
+        1 if rest.len() >= 32 => {
+            let (node, tail) = rest.split_at(32);
+            *rest = tail;
+            Some(Some(hex::encode(node)))
+        },
+        _ => None,
+    }
+}
+
+// Reads a serialized CommitmentTree: an optional left and right leaf, then a
+// CompactSize count of optional parent nodes, each optional node being a
+// presence byte followed by 32 bytes. Returns the nodes (in the tree's byte
+// order, hex) and the number of leaves the tree holds, which is what a client
+// needs to start witnessing notes from this state.
+fn frontier(tree: &[u8]) -> Option<Value> {
+    let mut rest = tree;
+    let left = node(&mut rest)?;
+    let right = node(&mut rest)?;
+    let (count, tail) = match rest.split_first()? {
+        (count @ 0..=0xfc, tail) => (*count as usize, tail),
+        (0xfd, tail) if tail.len() >= 2 => (u16::from_le_bytes([tail[0], tail[1]]) as usize, &tail[2..]),
+        _ => return None,
+    };
+    // A Sapling tree is 32 levels deep.
+    if count > 31 {
+        return None;
+    }
+    rest = tail;
+    let mut parents = Vec::with_capacity(count);
+    for _ in 0..count {
+        parents.push(node(&mut rest)?);
+    }
+    if !rest.is_empty() {
+        return None;
+    }
+    let size = left.is_some() as u64 + right.is_some() as u64
+        + parents.iter().enumerate().filter(|(_, parent)| parent.is_some()).map(|(depth, _)| 2u64 << depth).sum::<u64>();
+    Some(json!({ "left": left, "right": right, "parents": parents, "size": size }))
+}
+
+// The serialized tree of a state.
+fn tree_hex(state: &Value) -> Option<&str> {
+    state["tree"].as_str().or_else(|| state["sapling"]["commitments"]["finalState"].as_str())
+}
+
+async fn fetch(rpc: &Arc<VerusRPC>, height: u64, access: Access) -> Result<Value, String> {
+    if let Some(tree) = rpc.sapling_trees.get(height) {
+        return Ok(tree);
+    }
+    // The daemon answers with a list of states, or one.
+    let result = rpc.handle_call_as("getsaplingtree".to_string(), vec![json!(height)], access).await.map_err(|e| e.message)?;
+    let state = match result {
+        Value::Array(mut states) if !states.is_empty() => states.swap_remove(0),
+        state => state,
+    };
+    if rpc.tip.height().is_some_and(|tip| height + FINAL_DEPTH <= tip) {
+        rpc.sapling_trees.insert(height, state.clone());
+    }
+    Ok(state)
+}
+
+// The state in the requested format: "hex" keeps only the serialized tree,
+// "frontier" only the decoded nodes, and by default both are given.
+fn formatted(mut state: Value, format: &str) -> Value {
+    let decoded = tree_hex(&state).and_then(|tree| hex::decode(tree).ok()).and_then(|tree| frontier(&tree));
+    if format != "hex" {
+        state["frontier"] = decoded.unwrap_or(Value::Null);
+    }
+    if format == "frontier" {
+        if let Some(state) = state.as_object_mut() {
+            state.remove("tree");
+            state.remove("sapling");
+        }
+    }
+    state
+}
+
+// GET /sapling/tree/<height>[?format=hex|frontier] is the Sapling commitment
+// tree after a block; GET /sapling/trees?from=&to=[&format=] returns the
+// states for a range of heights, at most MAX_RANGE at a time, oldest first.
+pub async fn handle(path: &str, req: &Request<Body>, rpc: &Arc<VerusRPC>, access: Access) -> Response<Body> {
+    let query = events::query(req.uri());
+    let format = query.get("format").map_or("", String::as_str).to_string();
+    if !matches!(format.as_str(), "" | "hex" | "frontier") {
+        return json_response(StatusCode::BAD_REQUEST, json!({"error": "format must be hex or frontier"}));
+    }
+    if let Some(height) = path.strip_prefix("/sapling/tree/") {
+        let height = match height.parse::<u64>() {
+            Ok(height) => height,
+            Err(_) => return json_response(StatusCode::BAD_REQUEST, json!({"error": "Invalid height"})),
+        };
+        return match fetch(rpc, height, access).await {
+            Ok(state) => json_response(StatusCode::OK, formatted(state, &format)),
+            Err(message) => json_response(StatusCode::BAD_GATEWAY, json!({"error": message})),
+        };
+    }
+    if path != "/sapling/trees" {
+        return json_response(StatusCode::NOT_FOUND, json!({"error": "Unknown sapling endpoint"}));
+    }
+    let (from, to) = match (query.get("from").and_then(|from| from.parse::<u64>().ok()), query.get("to").and_then(|to| to.parse::<u64>().ok())) {
+        (Some(from), Some(to)) if from <= to => (from, to),
+        _ => return json_response(StatusCode::BAD_REQUEST, json!({"error": "from and to must be heights, from <= to"})),
+    };
+    if to - from >= MAX_RANGE {
+        return json_response(StatusCode::BAD_REQUEST, json!({"error": format!("At most {} heights at a time", MAX_RANGE)}));
+    }
+    let limit = Arc::new(Semaphore::new(rpc.batch.concurrency.max(1)));
+    let mut calls = JoinSet::new();
+    for height in from..=to {
+        let (rpc, limit) = (rpc.clone(), limit.clone());
+        calls.spawn(async move {
+            let _permit = limit.acquire_owned().await.unwrap();
+            (height, fetch(&rpc, height, access).await)
+        });
+    }
+    let mut states = Vec::with_capacity((to - from + 1) as usize);
+    while let Some(call) = calls.join_next().await {
+        match call.unwrap() {
+            (height, Ok(state)) => states.push((height, state)),
+            (height, Err(message)) => return json_response(StatusCode::BAD_GATEWAY, json!({"error": format!("Block {}: {}", height, message)})),
+        }
+    }
+    states.sort_by_key(|(height, _)| *height);
+    let trees: Vec<Value> = states.into_iter().map(|(_, state)| formatted(state, &format)).collect();
+    json_response(StatusCode::OK, json!({"from": from, "to": to, "trees": trees}))
+}
@@ -0,0 +1,198 @@
+use rusqlite::{Connection, params};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::VerusRPC;
+use crate::chain_stats::{self, NETWORK_SUMMARY};
+use crate::limiter::Priority;
+use crate::indexer::{self, MAX_PAGE};
+
+// When a job runs: every so many seconds, or every so many new blocks.
+pub enum Every {
+    Seconds(u64),
+    Blocks(u64),
+}
+
+// An RPC call sampled into the history, from a `schedule` entry.
+pub struct Job {
+    pub name: String,
+    pub method: String,
+    pub params: Vec<Value>,
+    pub every: Every,
+}
+
+// Parses an interval such as 90, "90s", "15m", "6h" or "1d" into seconds.
+fn seconds(every: &Value) -> Option<u64> {
+    if let Some(seconds) = every.as_u64() {
+        return Some(seconds);
+    }
+    let every = every.as_str()?.trim();
+    let (number, unit) = every.split_at(every.find(|c: char| !c.is_ascii_digit()).unwrap_or(every.len()));
+    let number: u64 = number.parse().ok()?;
+    match unit {
+        "" | "s" => Some(number),
+        "m" => Some(number * 60),
+        "h" => Some(number * 3600),
+        "d" => Some(number * 86_400),
+        _ => None,
+    }
+}
+
+pub fn load(entries: Vec<HashMap<String, Value>>) -> Result<Vec<Job>, String> {
+    let mut jobs: Vec<Job> = Vec::new();
+    for entry in entries {
+        let name = entry.get("name").and_then(Value::as_str).ok_or("Schedule entry without a name")?.to_string();
+        if jobs.iter().any(|job| job.name == name) {
+            return Err(format!("Schedule entry {} is defined twice", name));
+        }
+        let method = entry.get("method").and_then(Value::as_str).ok_or_else(|| format!("Schedule entry {} has no method", name))?.to_string();
+        let params = match entry.get("params") {
+            Some(Value::Array(params)) => params.clone(),
+            Some(_) => return Err(format!("params of schedule entry {} must be an array", name)),
+            None => Vec::new(),
+        };
+        let every = match (entry.get("every"), entry.get("every_blocks").and_then(Value::as_u64)) {
+            (Some(every), None) => Every::Seconds(seconds(every).filter(|seconds| *seconds > 0).ok_or_else(|| format!("Invalid interval for schedule entry {}", name))?),
+            (None, Some(blocks)) if blocks > 0 => Every::Blocks(blocks),
+            _ => return Err(format!("Schedule entry {} needs either every or every_blocks", name)),
+        };
+        jobs.push(Job { name, method, params, every });
+    }
+    Ok(jobs)
+}
+
+// SQLite store of the samples taken by scheduled jobs, kept for `retention`
+// and served at GET /history.
+pub struct History {
+    conn: Mutex<Connection>,
+    retention: Duration,
+    // Jobs by name with their method, for listing.
+    jobs: Vec<(String, String)>,
+}
+
+impl History {
+    pub fn open(path: &str, retention: Duration, jobs: &[Job]) -> rusqlite::Result<History> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS samples (
+                 job TEXT NOT NULL,
+                 time INTEGER NOT NULL,
+                 height INTEGER,
+                 value TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS samples_job_time ON samples (job, time);",
+        )?;
+        let jobs = jobs.iter().map(|job| (job.name.clone(), job.method.clone())).collect();
+        Ok(History { conn: Mutex::new(conn), retention, jobs })
+    }
+
+    fn record(&self, job: &str, height: Option<u64>, value: &Value) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO samples (job, time, height, value) VALUES (?1, ?2, ?3, ?4)",
+            params![job, indexer::unix_time(), height.map(|height| height as i64), value.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn prune(&self) -> rusqlite::Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM samples WHERE time < ?1", params![indexer::unix_time() - self.retention.as_secs() as i64])
+    }
+
+    // The configured jobs with how many samples each has and when it last ran.
+    pub fn jobs(&self) -> rusqlite::Result<Vec<Value>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare("SELECT COUNT(*), MAX(time) FROM samples WHERE job = ?1")?;
+        let mut jobs = Vec::new();
+        for (name, method) in &self.jobs {
+            let (samples, last): (i64, Option<i64>) = query.query_row(params![name], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            jobs.push(json!({ "name": name, "method": method, "samples": samples, "last": last }));
+        }
+        Ok(jobs)
+    }
+
+    pub fn has_job(&self, job: &str) -> bool {
+        self.jobs.iter().any(|(name, _)| name == job)
+    }
+
+    // The first job running `method`.
+    pub fn job_for(&self, method: &str) -> Option<&str> {
+        self.jobs.iter().find(|(_, job_method)| job_method == method).map(|(name, _)| name.as_str())
+    }
+
+    // The latest `count` samples of a job between `from` and `to` (unix
+    // times), oldest first for charting.
+    pub fn samples(&self, job: &str, from: Option<i64>, to: Option<i64>, count: u64) -> rusqlite::Result<Vec<Value>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT time, height, value FROM samples
+             WHERE job = ?1 AND time >= ?2 AND time <= ?3
+             ORDER BY time DESC, rowid DESC LIMIT ?4",
+        )?;
+        let rows = query.query_map(params![job, from.unwrap_or(0), to.unwrap_or(i64::MAX), count.min(MAX_PAGE) as i64], |row| {
+            Ok(json!({
+                "time": row.get::<_, i64>(0)?,
+                "height": row.get::<_, Option<i64>>(1)?,
+                "value": serde_json::from_str::<Value>(&row.get::<_, String>(2)?).unwrap_or(Value::Null),
+            }))
+        })?;
+        let mut samples = rows.collect::<rusqlite::Result<Vec<Value>>>()?;
+        samples.reverse();
+        Ok(samples)
+    }
+}
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Runs each job when it is due and stores its result. Calls go straight to
+// the daemon so samples are never served from the cache; networksummary is
+// worked out by the proxy.
+pub async fn run(rpc: Arc<VerusRPC>, jobs: Vec<Job>) {
+    let history = match &rpc.history {
+        Some(history) => history,
+        None => return,
+    };
+    let mut last_run: Vec<Option<(Instant, Option<u64>)>> = jobs.iter().map(|_| None).collect();
+    let mut pruned = Instant::now();
+    loop {
+        let height = rpc.tip.height();
+        for (job, last) in jobs.iter().zip(last_run.iter_mut()) {
+            let due = match (&job.every, *last) {
+                (_, None) => true,
+                (Every::Seconds(seconds), Some((at, _))) => at.elapsed() >= Duration::from_secs(*seconds),
+                (Every::Blocks(blocks), Some((_, ran_at))) => match (height, ran_at) {
+                    (Some(height), Some(ran_at)) => height >= ran_at + blocks,
+                    (Some(_), None) => true,
+                    _ => false,
+                },
+            };
+            if !due {
+                continue;
+            }
+            *last = Some((Instant::now(), height));
+            let sampled = match job.method.as_str() {
+                NETWORK_SUMMARY => chain_stats::network_summary(&rpc).await,
+                method => rpc.upstream.call_as(method, &job.params, Priority::Background).await.map_err(|e| e.message),
+            };
+            match sampled {
+                Ok(value) => {
+                    if let Err(e) = history.record(&job.name, height, &value) {
+                        eprintln!("scheduler: failed to store {}: {}", job.name, e);
+                    }
+                },
+                Err(message) => eprintln!("scheduler: {} failed: {}", job.name, message),
+            }
+        }
+        if pruned.elapsed() >= PRUNE_INTERVAL {
+            pruned = Instant::now();
+            if let Err(e) = history.prune() {
+                eprintln!("scheduler: failed to prune history: {}", e);
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
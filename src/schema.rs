@@ -0,0 +1,192 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// Distinct divergences kept per method for /stats.
+const KEPT_DIVERGENCES: usize = 20;
+
+// The fields dApps rely on in the replies of common methods, by dotted path,
+// with the types they must have. A type is one of integer, number, string, hex,
+// bool, object, array or null, several joined with |, and a leading ? lets the
+// field be missing. The path "." is the result itself; other paths are only
+// checked on object results, since some methods answer a hex string or an
+// object depending on their params.
+const BUILT_IN: &[(&str, &[(&str, &str)])] = &[
+    ("getblockcount", &[(".", "integer")]),
+    ("getbestblockhash", &[(".", "hex")]),
+    ("getinfo", &[
+        ("version", "integer"), ("protocolversion", "integer"), ("blocks", "integer"), ("longestchain", "integer"),
+        ("connections", "integer"), ("difficulty", "number"), ("testnet", "bool"), ("name", "string"), ("chainid", "string"),
+    ]),
+    ("getblockchaininfo", &[
+        ("chain", "string"), ("blocks", "integer"), ("headers", "integer"), ("bestblockhash", "hex"),
+        ("difficulty", "number"), ("verificationprogress", "number"), ("chainwork", "hex"),
+    ]),
+    ("getblock", &[
+        ("hash", "hex"), ("height", "integer"), ("confirmations", "integer"), ("version", "integer"),
+        ("merkleroot", "hex"), ("time", "integer"), ("tx", "array"), ("previousblockhash", "?hex"),
+    ]),
+    ("getrawtransaction", &[
+        ("txid", "hex"), ("version", "integer"), ("locktime", "integer"), ("vin", "array"), ("vout", "array"),
+        ("hex", "hex"), ("blockhash", "?hex"), ("height", "?integer"), ("confirmations", "?integer"),
+    ]),
+    ("getcurrency", &[
+        ("version", "integer"), ("options", "integer"), ("name", "string"), ("currencyid", "string"),
+        ("systemid", "string"), ("startblock", "integer"), ("currencies", "?array"), ("weights", "?array"),
+    ]),
+    ("getidentity", &[
+        ("identity", "object"), ("identity.identityaddress", "string"), ("identity.name", "string"),
+        ("identity.primaryaddresses", "array"), ("identity.minimumsignatures", "integer"),
+        ("identity.flags", "integer"), ("identity.timelock", "integer"), ("status", "string"),
+        ("blockheight", "integer"), ("txid", "hex"), ("vout", "integer"),
+    ]),
+    ("getaddressbalance", &[("balance", "integer"), ("received", "integer"), ("currencybalance", "?object")]),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Off,
+    // Divergences are logged and counted, and the reply passed on.
+    Log,
+    // Divergent replies are also failed, so dApps never see a changed format.
+    Reject,
+}
+
+// Checks daemon replies against the expected shapes to catch format changes
+// after a daemon upgrade before dApps trip over them.
+pub struct SchemaCheck {
+    mode: Mode,
+    schemas: HashMap<String, Vec<(String, String)>>,
+    // Per method, how many replies diverged and the distinct divergences seen.
+    divergences: Mutex<HashMap<String, (u64, Vec<String>)>>,
+}
+
+fn has_type(value: Option<&Value>, kind: &str) -> bool {
+    let (optional, kinds) = match kind.strip_prefix('?') {
+        Some(kinds) => (true, kinds),
+        None => (false, kind),
+    };
+    let value = match value {
+        Some(value) => value,
+        None => return optional,
+    };
+    kinds.split('|').any(|kind| match kind.trim() {
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "hex" => value.as_str().is_some_and(|s| s.chars().all(|c| c.is_ascii_hexdigit())),
+        "bool" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "null" => value.is_null(),
+        _ => false,
+    })
+}
+
+fn field<'a>(result: &'a Value, path: &str) -> Option<&'a Value> {
+    match path {
+        "." => Some(result),
+        path => path.split('.').try_fold(result, |value, key| value.get(key)),
+    }
+}
+
+fn kind_of(value: Option<&Value>) -> &'static str {
+    match value {
+        None => "missing",
+        Some(Value::Null) => "null",
+        Some(Value::Bool(_)) => "bool",
+        Some(Value::Number(n)) if n.is_f64() => "number",
+        Some(Value::Number(_)) => "integer",
+        Some(Value::String(_)) => "string",
+        Some(Value::Array(_)) => "array",
+        Some(Value::Object(_)) => "object",
+    }
+}
+
+impl SchemaCheck {
+    // `mode` is off, log or reject; `schemas` replaces the built-in schema of
+    // a method (an empty one stops checking it) or adds one.
+    pub fn new(mode: &str, schemas: HashMap<String, HashMap<String, String>>) -> Result<SchemaCheck, String> {
+        let mode = match mode {
+            "off" => Mode::Off,
+            "log" => Mode::Log,
+            "reject" => Mode::Reject,
+            other => return Err(format!("Unknown schema_check {}, expected off, log or reject", other)),
+        };
+        let mut built_in: HashMap<String, Vec<(String, String)>> = BUILT_IN.iter()
+            .map(|(method, fields)| (method.to_string(), fields.iter().map(|(path, kind)| (path.to_string(), kind.to_string())).collect()))
+            .collect();
+        for (method, fields) in schemas {
+            built_in.insert(method, fields.into_iter().collect());
+        }
+        Ok(SchemaCheck { mode, schemas: built_in, divergences: Mutex::new(HashMap::new()) })
+    }
+
+    fn diverging(&self, method: &str, result: &Value) -> Vec<String> {
+        let schema = match self.schemas.get(method) {
+            Some(schema) => schema,
+            None => return Vec::new(),
+        };
+        schema.iter()
+            .filter(|(path, _)| path == "." || result.is_object())
+            .filter(|(path, kind)| !has_type(field(result, path), kind))
+            .map(|(path, kind)| format!("{} is {}, expected {}", path, kind_of(field(result, path)), kind))
+            .collect()
+    }
+
+    // Whether replies of `method` are checked.
+    pub fn covers(&self, method: &str) -> bool {
+        self.mode != Mode::Off && self.schemas.contains_key(method)
+    }
+
+    // Passes a fresh daemon reply on, noting where it diverges from the
+    // method's schema, or fails it in reject mode.
+    pub fn check(&self, method: &str, result: Value) -> Result<Value, RpcError> {
+        if self.mode == Mode::Off {
+            return Ok(result);
+        }
+        let diverging = self.diverging(method, &result);
+        if diverging.is_empty() {
+            return Ok(result);
+        }
+        {
+            let mut divergences = self.divergences.lock().unwrap();
+            let (count, seen) = divergences.entry(method.to_string()).or_default();
+            *count += 1;
+            for divergence in &diverging {
+                if !seen.contains(divergence) && seen.len() < KEPT_DIVERGENCES {
+                    eprintln!("schema: {} reply diverges: {}", method, divergence);
+                    seen.push(divergence.clone());
+                }
+            }
+        }
+        match self.mode {
+            Mode::Reject => Err(RpcError {
+                code: -32006,
+                message: "The daemon's reply is not in the expected format".into(),
+                data: Some(serde_json::value::to_raw_value(&json!({ "method": method, "divergences": diverging })).unwrap()),
+            }),
+            _ => Ok(result),
+        }
+    }
+
+    pub fn summary(&self) -> Value {
+        let divergences = self.divergences.lock().unwrap();
+        let methods: serde_json::Map<String, Value> = divergences.iter()
+            .map(|(method, (count, seen))| (method.clone(), json!({ "replies": count, "divergences": seen })))
+            .collect();
+        json!({ "enabled": self.mode != Mode::Off, "rejecting": self.mode == Mode::Reject, "diverging": methods })
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        let divergences = self.divergences.lock().unwrap();
+        let mut methods: Vec<_> = divergences.iter().collect();
+        methods.sort_by(|a, b| a.0.cmp(b.0));
+        writeln!(out, "# TYPE response_schema_divergences_total counter").unwrap();
+        for (method, (count, _)) in methods {
+            writeln!(out, "response_schema_divergences_total{{method=\"{}\"}} {}", method, count).unwrap();
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::VerusRPC;
+use crate::allowlist::{Access, Scope};
+use crate::indexer::unix_time;
+use crate::limiter::Priority;
+use crate::listener::{self, Profile};
+use crate::rest::json_response;
+
+// How long a VerusID sign-in challenge may be used for.
+const CHALLENGE_TTL_SECS: u64 = 300;
+// Session tokens start with this, so they can't be mistaken for API keys.
+const PREFIX: &str = "vs1.";
+
+// Checks a VerusID sign-in: `signature` by `identity` over
+// "<purpose>:<challenge>", the challenge being one this proxy issued on the
+// listener of `profile` and not used before (see `Sessions::challenge`).
+// Returns the identity, lowercased.
+pub async fn verify_login(rpc: &Arc<VerusRPC>, profile: &Profile, purpose: &str, identity: &str, challenge: &str, signature: &str) -> Result<String, String> {
+    rpc.sessions.take_challenge(challenge, profile)?;
+    let identity = identity.to_lowercase();
+    let message = format!("{}:{}", purpose, challenge);
+    let verified = rpc.call("verifymessage".to_string(), vec![json!(identity), json!(signature), json!(message)], Priority::Interactive).await;
+    match verified {
+        Ok(valid) if valid == true => Ok(identity),
+        Ok(_) => Err("Invalid signature".to_string()),
+        Err(e) => Err(format!("Could not verify signature: {}", e.message)),
+    }
+}
+
+// What a session may do beyond reading: call write methods (which also
+// satisfies enforce_origin), and call the mining methods.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Grant {
+    pub write: bool,
+    pub mining: bool,
+}
+
+impl Grant {
+    fn scopes(self) -> Vec<&'static str> {
+        let mut scopes = vec!["read"];
+        if self.write {
+            scopes.push("write");
+        }
+        if self.mining {
+            scopes.push("mining");
+        }
+        scopes
+    }
+
+    // The scopes asked for, as long as this grant covers them.
+    fn narrow(self, requested: &Value) -> Result<Grant, String> {
+        let requested = match requested {
+            Value::Null => return Ok(self),
+            Value::Array(scopes) => scopes,
+            _ => return Err("scope must be an array".to_string()),
+        };
+        let mut grant = Grant::default();
+        for scope in requested {
+            match scope.as_str() {
+                Some("read") => {},
+                Some("write") if self.write => grant.write = true,
+                Some("mining") if self.mining => grant.mining = true,
+                Some(scope @ ("write" | "mining")) => return Err(format!("Not allowed scope {}", scope)),
+                _ => return Err(format!("Unknown scope {}", scope)),
+            }
+        }
+        Ok(grant)
+    }
+
+    fn from_scopes(scopes: &Value) -> Grant {
+        let has = |name: &str| scopes.as_array().is_some_and(|scopes| scopes.iter().any(|scope| scope == name));
+        Grant { write: has("write"), mining: has("mining") }
+    }
+}
+
+// A request authenticated with a valid session token.
+pub struct Session {
+    pub subject: String,
+    pub grant: Grant,
+    // The roles it was signed in with.
+    pub roles: Vec<String>,
+    // When its token expires, as unix time.
+    pub expires: u64,
+}
+
+impl Session {
+    // What a request made with this session may call on a listener with
+    // `access`: never more than the listener allows, and never full access.
+    pub fn restrict(&self, access: Access) -> Access {
+        let scope = match (self.grant.write, access.scope) {
+            (true, Scope::Standard | Scope::Full) => Scope::Standard,
+            _ => Scope::ReadOnly,
+        };
+        Access { scope, mining: access.mining || self.grant.mining, ..access }
+    }
+}
+
+pub struct SessionOptions {
+    pub enabled: bool,
+    // Key tokens are signed with. A random one is made at startup if unset,
+    // which ends every session on restart.
+    pub secret: Option<String>,
+    pub ttl: Duration,
+    pub refresh_ttl: Duration,
+    // Identities allowed to sign in, lowercased; empty allows any that can sign.
+    pub identities: Vec<String>,
+}
+
+#[derive(Default)]
+struct Revoked {
+    // Token ids, with when the token would have expired anyway.
+    tokens: HashMap<String, u64>,
+    // Subjects whose tokens issued up to the given time are revoked.
+    subjects: HashMap<String, u64>,
+}
+
+// The subject of sessions signed in with `key`: a digest, so tokens don't
+// carry the key itself.
+pub fn key_subject(key: &str) -> String {
+    format!("key:{}", &hex::encode(Sha256::digest(key.as_bytes()))[..16])
+}
+
+// Short-lived signed session tokens, issued to clients that signed in with an
+// API key or a VerusID so browsers don't have to keep the long-lived secret.
+// A token is "vs1.<claims>.<HMAC-SHA256>", both base64url. Access tokens last
+// `ttl` and are sent as Authorization: Bearer <token>; refresh tokens last
+// `refresh_ttl`, are used once each, and get a new pair from /auth/refresh.
+pub struct Sessions {
+    opts: SessionOptions,
+    key: Vec<u8>,
+    revoked: Mutex<Revoked>,
+    issued: AtomicU64,
+}
+
+pub fn random_hex(bytes: usize) -> String {
+    let mut random = vec![0u8; bytes];
+    getrandom::getrandom(&mut random).expect("No source of randomness");
+    hex::encode(random)
+}
+
+impl Sessions {
+    pub fn new(opts: SessionOptions) -> Sessions {
+        let key = match &opts.secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => random_hex(32).into_bytes(),
+        };
+        Sessions { opts, key, revoked: Mutex::new(Revoked::default()), issued: AtomicU64::new(0) }
+    }
+
+    fn sign(&self, data: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn token(&self, claims: &Value) -> String {
+        let data = format!("{}{}", PREFIX, URL_SAFE_NO_PAD.encode(claims.to_string()));
+        let signature = URL_SAFE_NO_PAD.encode(self.sign(&data));
+        format!("{}.{}", data, signature)
+    }
+
+    // A new access and refresh token pair for `subject`, good on the listener
+    // named `listener`, as sent to the client.
+    fn issue(&self, subject: &str, grant: Grant, roles: &[String], listener: &str) -> Value {
+        let now = unix_time() as u64;
+        let claims = |kind: &str, ttl: Duration| json!({
+            "sub": subject,
+            "lst": listener,
+            "scope": grant.scopes(),
+            "roles": roles,
+            "kind": kind,
+            "iat": now,
+            "exp": now + ttl.as_secs(),
+            "jti": random_hex(16),
+        });
+        self.issued.fetch_add(1, Ordering::Relaxed);
+        json!({
+            "access_token": self.token(&claims("access", self.opts.ttl)),
+            "refresh_token": self.token(&claims("refresh", self.opts.refresh_ttl)),
+            "token_type": "Bearer",
+            "expires_in": self.opts.ttl.as_secs(),
+            "scope": grant.scopes(),
+            "roles": roles,
+        })
+    }
+
+    // The claims of a token of `kind` that is genuine, unexpired and not revoked.
+    fn decode(&self, token: &str, kind: &str) -> Result<Value, String> {
+        let invalid = || "Invalid session token".to_string();
+        let (data, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(data.as_bytes());
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+        let claims = data.strip_prefix(PREFIX)
+            .and_then(|claims| URL_SAFE_NO_PAD.decode(claims).ok())
+            .and_then(|claims| serde_json::from_slice::<Value>(&claims).ok())
+            .ok_or_else(invalid)?;
+        if claims["kind"] != kind {
+            return Err(format!("Not a session {} token", kind));
+        }
+        if claims["exp"].as_u64().unwrap_or(0) <= unix_time() as u64 {
+            return Err("Session expired".to_string());
+        }
+        let revoked = self.revoked.lock().unwrap();
+        let revoked_subject = claims["sub"].as_str().and_then(|subject| revoked.subjects.get(subject));
+        if claims["jti"].as_str().is_some_and(|jti| revoked.tokens.contains_key(jti))
+            || revoked_subject.is_some_and(|revoked| claims["iat"].as_u64().unwrap_or(0) <= *revoked) {
+            return Err("Session revoked".to_string());
+        }
+        Ok(claims)
+    }
+
+    // The claims of a token of `kind` issued by the listener of `profile`.
+    fn decode_for(&self, token: &str, kind: &str, profile: &Profile) -> Result<Value, String> {
+        let claims = self.decode(token, kind)?;
+        match claims["lst"].as_str() == Some(profile.name.as_str()) {
+            true => Ok(claims),
+            false => Err("The session token is for another listener".to_string()),
+        }
+    }
+
+    // Whether the token wasn't revoked already.
+    fn revoke_token(&self, claims: &Value) -> bool {
+        let now = unix_time() as u64;
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.tokens.retain(|_, expiry| *expiry > now);
+        match claims["jti"].as_str() {
+            Some(jti) => revoked.tokens.insert(jti.to_string(), claims["exp"].as_u64().unwrap_or(now)).is_none(),
+            None => false,
+        }
+    }
+
+    // A VerusID sign-in challenge for the listener of `profile`. It is signed
+    // like a token, so only used ones need remembering.
+    fn challenge(&self, profile: &Profile) -> Value {
+        let now = unix_time() as u64;
+        let claims = json!({
+            "lst": profile.name,
+            "kind": "challenge",
+            "iat": now,
+            "exp": now + CHALLENGE_TTL_SECS,
+            "jti": random_hex(16),
+        });
+        json!({"challenge": self.token(&claims), "expires_in": CHALLENGE_TTL_SECS})
+    }
+
+    // Uses up a challenge issued on the listener of `profile`.
+    fn take_challenge(&self, challenge: &str, profile: &Profile) -> Result<(), String> {
+        let invalid = || "Unknown, used or expired sign-in challenge".to_string();
+        let claims = self.decode_for(challenge, "challenge", profile).map_err(|_| invalid())?;
+        match self.revoke_token(&claims) {
+            true => Ok(()),
+            false => Err(invalid()),
+        }
+    }
+
+    // Ends every session of `subject` issued so far.
+    pub fn revoke_subject(&self, subject: &str) {
+        let now = unix_time() as u64;
+        let mut revoked = self.revoked.lock().unwrap();
+        let refresh_ttl = self.opts.refresh_ttl.as_secs();
+        revoked.subjects.retain(|_, at| *at + refresh_ttl > now);
+        revoked.subjects.insert(subject.to_string(), now);
+    }
+
+    // The session of a request carrying a session token, or why the token
+    // isn't accepted. `None` if the request doesn't carry one. Tokens are only
+    // accepted by the listener that issued them.
+    pub fn authenticate(&self, headers: &hyper::HeaderMap, profile: &Profile) -> Option<Result<Session, String>> {
+        let token = listener::bearer(headers).filter(|token| token.starts_with(PREFIX))?;
+        if !self.opts.enabled {
+            return Some(Err("Sessions are not enabled".to_string()));
+        }
+        Some(self.decode_for(token, "access", profile).map(|claims| Session {
+            subject: claims["sub"].as_str().unwrap_or_default().to_string(),
+            grant: Grant::from_scopes(&claims["scope"]),
+            roles: serde_json::from_value(claims["roles"].clone()).unwrap_or_default(),
+            expires: claims["exp"].as_u64().unwrap_or_default(),
+        }))
+    }
+
+    // Why a session token accepted earlier no longer is: it expired or was
+    // revoked since. For connections that outlive a request, like WebSockets.
+    pub fn recheck(&self, token: &str, profile: &Profile) -> Result<(), String> {
+        self.decode_for(token, "access", profile).map(|_| ())
+    }
+
+    // What a request with `headers` may do on the listener of `profile`, as
+    // handle_req works it out for requests without a session: writes where
+    // the listener allows them and the request passes enforce_origin (or
+    // carries a key created with the write grant, outside browsers), and
+    // mining where the listener allows it or with a mining key. A session
+    // signed in with the request gets no more than this.
+    fn caller_grant(rpc: &VerusRPC, profile: &Profile, headers: &hyper::HeaderMap) -> Grant {
+        let key = listener::bearer(headers);
+        let managed = key.and_then(|key| rpc.runtime.key(key));
+        let writes = match &managed {
+            Some(managed) if managed.write && !headers.contains_key(hyper::header::ORIGIN) => true,
+            _ => rpc.origins.check(headers).is_ok(),
+        };
+        Grant {
+            write: writes && profile.access.scope != Scope::ReadOnly,
+            mining: profile.access.mining
+                || key.is_some_and(|key| rpc.mining_api_keys.contains(key))
+                || managed.is_some_and(|managed| managed.mining),
+        }
+    }
+
+    // GET /auth/challenge hands out a challenge for VerusID sign-ins, here
+    // and for stream subscriptions, whether or not sessions are enabled.
+    // POST /auth/token signs in, with Authorization: Bearer <API key> or a
+    // body of {"identity", "challenge", "signature"} signed over
+    // "session:<challenge>", optionally asking for fewer scopes with
+    // {"scope": ["read", ...]}. POST /auth/refresh trades {"refresh_token"}
+    // for a new pair. POST /auth/revoke ends the session of {"token"}. The
+    // body is read like a JSON-RPC one, held to the body limits and
+    // body_read_timeout.
+    pub async fn handle(req: Request<Body>, rpc: &Arc<VerusRPC>, profile: &Profile) -> Result<Response<Body>, hyper::Error> {
+        let sessions = &rpc.sessions;
+        if req.uri().path() == "/auth/challenge" {
+            return Ok(json_response(StatusCode::OK, sessions.challenge(profile)));
+        }
+        if !sessions.opts.enabled {
+            return Ok(json_response(StatusCode::NOT_FOUND, json!({"error": "Sessions are not enabled"})));
+        }
+        if req.method() != Method::POST {
+            return Ok(json_response(StatusCode::METHOD_NOT_ALLOWED, json!({"error": "Use POST"})));
+        }
+        let path = req.uri().path().to_string();
+        let (parts, body) = req.into_parts();
+        let mut whole_body = Vec::new();
+        if let Err(response) = crate::read_body(rpc, &parts.headers, body, &mut whole_body).await? {
+            return Ok(response);
+        }
+        let body: Value = match whole_body.is_empty() {
+            true => json!({}),
+            false => match crate::json::from_slice(&whole_body) {
+                Some(body @ Value::Object(_)) => body,
+                _ => return Ok(json_response(StatusCode::BAD_REQUEST, json!({"error": "Body must be a JSON object"}))),
+            },
+        };
+        let result = match path.as_str() {
+            "/auth/token" => sessions.sign_in(rpc, profile, &parts.headers, &body).await,
+            "/auth/refresh" => match sessions.decode_for(body["refresh_token"].as_str().unwrap_or_default(), "refresh", profile) {
+                Ok(claims) if sessions.revoke_token(&claims) => {
+                    let roles: Vec<String> = serde_json::from_value(claims["roles"].clone()).unwrap_or_default();
+                    Ok(sessions.issue(claims["sub"].as_str().unwrap_or_default(), Grant::from_scopes(&claims["scope"]), &roles, &profile.name))
+                },
+                Ok(_) => Err((StatusCode::UNAUTHORIZED, "Session revoked".to_string())),
+                Err(message) => Err((StatusCode::UNAUTHORIZED, message)),
+            },
+            "/auth/revoke" => {
+                let token = body["token"].as_str().unwrap_or_default();
+                match sessions.decode(token, "access").or_else(|_| sessions.decode(token, "refresh")) {
+                    Ok(claims) => {
+                        sessions.revoke_token(&claims);
+                        Ok(json!({"revoked": true}))
+                    },
+                    Err(message) => Err((StatusCode::BAD_REQUEST, message)),
+                }
+            },
+            _ => Err((StatusCode::NOT_FOUND, "Not found".to_string())),
+        };
+        Ok(match result {
+            Ok(reply) => json_response(StatusCode::OK, reply),
+            Err((status, message)) => json_response(status, json!({"error": message})),
+        })
+    }
+
+    // Signs in a client the listener lets in: with a key the proxy knows (one
+    // of the listener's, one the config gives access to, one holding a role
+    // or one created through the admin listener; on a listener with api_keys,
+    // only the last two besides its own), or as an identity (holding a role,
+    // on a listener with api_keys).
+    async fn sign_in(&self, rpc: &Arc<VerusRPC>, profile: &Profile, headers: &hyper::HeaderMap, body: &Value) -> Result<Value, (StatusCode, String)> {
+        let unauthorized = |message: String| (StatusCode::UNAUTHORIZED, message);
+        let admitted = profile.authorize(headers);
+        let key = listener::bearer(headers).filter(|key| !key.starts_with(PREFIX));
+        let (subject, roles) = match (key, body["identity"].as_str()) {
+            (Some(key), _) => {
+                let managed = rpc.runtime.key(key);
+                let roles = rpc.roles.of_key(key) | managed.as_ref().map_or(0, |managed| rpc.roles.named(&managed.roles));
+                let known = match admitted {
+                    Ok(()) => profile.api_keys.contains(key) || rpc.knows_key(key),
+                    Err(_) => false,
+                };
+                if roles == 0 && managed.is_none() && !known {
+                    return Err(unauthorized("Invalid API key".to_string()));
+                }
+                (key_subject(key), roles)
+            },
+            (None, Some(identity)) => {
+                let (challenge, signature) = match (body["challenge"].as_str(), body["signature"].as_str()) {
+                    (Some(challenge), Some(signature)) => (challenge, signature),
+                    _ => return Err(unauthorized("Identity sign-in needs challenge and signature".to_string())),
+                };
+                if !self.opts.identities.is_empty() && !self.opts.identities.contains(&identity.to_lowercase()) {
+                    return Err(unauthorized(format!("Identity {} may not sign in", identity)));
+                }
+                let identity = verify_login(rpc, profile, "session", identity, challenge, signature).await.map_err(unauthorized)?;
+                let roles = rpc.roles.of_identity(&identity);
+                if let (0, Err(reason)) = (roles, admitted) {
+                    return Err(unauthorized(reason));
+                }
+                (format!("id:{}", identity), roles)
+            },
+            (None, None) => return Err(unauthorized("Sign in with an API key or an identity".to_string())),
+        };
+        let grant = Sessions::caller_grant(rpc, profile, headers).narrow(&body["scope"]).map_err(|message| (StatusCode::BAD_REQUEST, message))?;
+        Ok(self.issue(&subject, grant, &rpc.roles.names(roles), &profile.name))
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        let revoked = self.revoked.lock().unwrap();
+        writeln!(out, "# TYPE sessions_issued_total counter").unwrap();
+        writeln!(out, "sessions_issued_total {}", self.issued.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "# TYPE sessions_revoked gauge").unwrap();
+        writeln!(out, "sessions_revoked {}", revoked.tokens.len() + revoked.subjects.len()).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use crate::disk_cache::DiskCache;
+use crate::indexer::{self, Indexer};
+use crate::upstream::Upstream;
+
+// A snapshot is a gzipped file of JSON lines: a header, then one
+// {"table", "row"} line per row of the index tables (SNAPSHOT_TABLES) and of
+// the disk cache ("disk_cache"). Rows are plain values, so a snapshot taken
+// from one index backend can be imported into the other.
+const VERSION: u64 = 1;
+
+// Rows written to a store per transaction while importing.
+const BATCH: usize = 1000;
+
+const DISK_CACHE: &str = "disk_cache";
+
+// Rows per table, as reported when a snapshot is written or read.
+pub type Counts = BTreeMap<String, u64>;
+
+async fn block_hash(upstream: &Upstream, height: u64) -> Result<String, String> {
+    let hash = upstream.call("getblockhash", &[json!(height)]).await.map_err(|e| e.message)?;
+    hash.as_str().map(str::to_string).ok_or_else(|| "Unexpected getblockhash reply".to_string())
+}
+
+// Writes the index and the disk cache, whichever are configured, to `path`.
+// The hash of the last indexed block goes in the header so an import can
+// check it is on the same chain as its daemon.
+pub async fn export(path: &str, upstream: &Upstream, indexer: Option<&Indexer>, disk_cache: Option<&DiskCache>) -> Result<Counts, String> {
+    let height = match indexer {
+        Some(indexer) => indexer.store.height().await?,
+        None => None,
+    };
+    let hash = match height {
+        Some(height) => Some(block_hash(upstream, height).await?),
+        None => None,
+    };
+    let header = json!({
+        "snapshot": VERSION,
+        "created": indexer::unix_time(),
+        "height": height,
+        "blockhash": hash,
+    });
+
+    // Written next to the target and renamed when complete, so a failed
+    // export never leaves a truncated snapshot behind.
+    let partial = format!("{}.partial", path);
+    let file = File::create(&partial).map_err(|e| format!("Failed to create {}: {}", partial, e))?;
+    let mut writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+    let mut counts = Counts::new();
+    let mut write = |table: &str, row: Vec<Value>| -> Result<(), String> {
+        *counts.entry(table.to_string()).or_insert(0) += 1;
+        writeln!(writer, "{}", json!({ "table": table, "row": row })).map_err(|e| format!("Failed to write {}: {}", partial, e))
+    };
+    let written = async {
+        write("header", vec![header])?;
+        if let Some(indexer) = indexer {
+            indexer.store.export(&mut write).await?;
+        }
+        if let Some(disk_cache) = disk_cache {
+            disk_cache.export(&mut |row| write(DISK_CACHE, row))?;
+        }
+        Ok::<(), String>(())
+    }
+    .await;
+    counts.remove("header");
+    let finished = written.and_then(|_| {
+        let file = writer.finish().and_then(|writer| writer.into_inner().map_err(|e| e.into_error()));
+        file.and_then(|file| file.sync_all()).map_err(|e| format!("Failed to write {}: {}", partial, e))
+    });
+    match finished {
+        Ok(()) => std::fs::rename(&partial, path).map_err(|e| format!("Failed to rename {} to {}: {}", partial, path, e))?,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        },
+    }
+    Ok(counts)
+}
+
+async fn store(indexer: Option<&Indexer>, disk_cache: Option<&DiskCache>, table: &str, rows: &[Vec<Value>]) -> Result<(), String> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    match (table, indexer, disk_cache) {
+        (DISK_CACHE, _, Some(disk_cache)) => disk_cache.import(rows).map_err(|e| e.to_string()),
+        (DISK_CACHE, _, None) => Ok(()),
+        (_, Some(indexer), _) => {
+            indexer.store.set_backfilling(true).await?;
+            indexer.store.import(table, rows).await
+        },
+        (_, None, _) => Ok(()),
+    }
+}
+
+async fn read(path: &str, upstream: &Upstream, indexer: Option<&Indexer>, disk_cache: Option<&DiskCache>) -> Result<Counts, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let mut lines = BufReader::new(GzDecoder::new(file)).lines();
+    let mut next = || -> Result<Option<(String, Vec<Value>)>, String> {
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| format!("Failed to read {}: {}", path, e))?,
+            None => return Ok(None),
+        };
+        let mut entry: Value = serde_json::from_str(&line).map_err(|e| format!("Malformed snapshot line: {}", e))?;
+        match (entry["table"].as_str().map(str::to_string), entry["row"].take()) {
+            (Some(table), Value::Array(row)) => Ok(Some((table, row))),
+            _ => Err("Malformed snapshot line".to_string()),
+        }
+    };
+
+    let header = match next()? {
+        Some((table, mut row)) if table == "header" && !row.is_empty() => row.swap_remove(0),
+        _ => return Err(format!("{} is not a snapshot", path)),
+    };
+    if header["snapshot"].as_u64() != Some(VERSION) {
+        return Err(format!("Unsupported snapshot version {}", header["snapshot"]));
+    }
+    if let (Some(height), Some(hash), Some(_)) = (header["height"].as_u64(), header["blockhash"].as_str(), indexer) {
+        let ours = block_hash(upstream, height).await.map_err(|e| format!("Failed to look up block {} of the snapshot: {}", height, e))?;
+        if ours != hash {
+            return Err(format!("The snapshot's block {} is {} but the daemon's is {}; it was taken on another chain or fork", height, hash, ours));
+        }
+    }
+
+    let mut counts = Counts::new();
+    let mut cleared = false;
+    let mut batch: Vec<Vec<Value>> = Vec::new();
+    let mut batch_table = String::new();
+    while let Some((table, row)) = next()? {
+        if table != DISK_CACHE {
+            let columns = indexer::snapshot_columns(&table)?;
+            if row.len() != columns.len() {
+                return Err(format!("Malformed {} row in snapshot", table));
+            }
+            if let (false, Some(indexer)) = (cleared, indexer) {
+                indexer.store.set_backfilling(true).await?;
+                indexer.store.reset(0).await?;
+                cleared = true;
+            }
+        }
+        if table != batch_table || batch.len() >= BATCH {
+            store(indexer, disk_cache, &batch_table, &batch).await?;
+            batch.clear();
+            batch_table = table.clone();
+        }
+        *counts.entry(table).or_insert(0) += 1;
+        batch.push(row);
+    }
+    store(indexer, disk_cache, &batch_table, &batch).await?;
+    Ok(counts)
+}
+
+// Loads a snapshot into the configured index, replacing what it held, and
+// adds its disk cache entries to the configured disk cache. Parts with
+// nowhere to go are skipped. A proxy sharing the index leaves indexing to
+// the import until it is done.
+pub async fn import(path: &str, upstream: &Upstream, indexer: Option<&Indexer>, disk_cache: Option<&DiskCache>) -> Result<Counts, String> {
+    let result = read(path, upstream, indexer, disk_cache).await;
+    if let Some(indexer) = indexer {
+        let _ = indexer.store.set_backfilling(false).await;
+    }
+    result
+}
@@ -0,0 +1,87 @@
+use hyper::{Body, Request, Response, StatusCode};
+use hyper::body::Bytes;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::VerusRPC;
+use crate::events::{self, Event, Filter};
+use crate::listener::Profile;
+use crate::rest::json_response;
+use crate::subscriptions::Subscriptions;
+
+// Comment lines sent on quiet streams so proxies don't time them out.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// The sequence number goes in the id field, so browsers reconnecting on their
+// own resume from it through Last-Event-ID.
+fn message(event: &Event) -> String {
+    format!("id: {}\nevent: {}\ndata: {}\n\n", event.seq, event.kind, event.data)
+}
+
+fn lagged(missed: u64) -> String {
+    format!("event: lagged\ndata: {}\n\n", json!({ "missed": missed }))
+}
+
+// Server-sent events for GET /events/<stream>, with the same streams and
+// filters as the WebSocket endpoint.
+pub async fn handle(req: &Request<Body>, rpc: &Arc<VerusRPC>, profile: &Profile) -> Response<Body> {
+    let stream_name = req.uri().path().strip_prefix("/events/").unwrap_or_default();
+    let query = events::query(req.uri());
+    let filter = match Filter::parse(stream_name, &query) {
+        Ok(filter) => filter,
+        Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({ "error": message })),
+    };
+
+    let subscription = match Subscriptions::open(rpc, req, &query, &filter, profile).await {
+        Ok(subscription) => subscription,
+        Err((status, message)) => return json_response(status, json!({ "error": message })),
+    };
+
+    let (mut sender, body) = Body::channel();
+    let (replay, mut events) = match events::since(&query, req.headers()) {
+        Some(since) => {
+            let (replay, events) = rpc.events.subscribe_since(since);
+            (Some(replay), events)
+        },
+        None => (None, rpc.events.subscribe()),
+    };
+    tokio::spawn(async move {
+        // Frees the client's slot once the stream ends.
+        let _subscription = subscription;
+        if let Some(replay) = replay {
+            let mut chunk = String::new();
+            if replay.missed > 0 {
+                chunk.push_str(&lagged(replay.missed));
+            }
+            for event in replay.events.iter().filter(|event| filter.matches(event)) {
+                chunk.push_str(&message(event));
+            }
+            if !chunk.is_empty() && sender.send_data(Bytes::from(chunk)).await.is_err() {
+                return;
+            }
+        }
+        let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+        loop {
+            let chunk = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) if filter.matches(&event) => message(&event),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => lagged(missed),
+                    Err(RecvError::Closed) => return,
+                },
+                _ = keepalive.tick() => ":\n\n".to_string(),
+            };
+            if sender.send_data(Bytes::from(chunk)).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+        .header(hyper::header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap()
+}
@@ -0,0 +1,48 @@
+use hyper::{Body, Request, Response, StatusCode};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+// JSON documents the operator serves as configured, such as a /network-config
+// with the contract addresses and currency ids their dApp needs, so frontends
+// get their constants from the same origin as their chain calls. Keyed by
+// path; each is serialized once, with an ETag so clients can revalidate.
+pub struct StaticDocs {
+    docs: HashMap<String, (String, String)>,
+}
+
+impl StaticDocs {
+    // From the [static_json] table, whose keys are paths ("/network-config"
+    // or "network-config") and whose values are the documents.
+    pub fn new(docs: HashMap<String, Value>) -> Result<StaticDocs, String> {
+        let docs = docs.into_iter()
+            .map(|(path, doc)| {
+                let path = format!("/{}", path.trim_matches('/'));
+                if path == "/" {
+                    return Err("A static_json path can't be /".to_string());
+                }
+                let body = doc.to_string();
+                let etag = format!("\"{}\"", hex::encode(&Sha256::digest(body.as_bytes())[..16]));
+                Ok((path, (body, etag)))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(StaticDocs { docs })
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.docs.keys().map(String::as_str)
+    }
+
+    pub fn handle(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        let (body, etag) = self.docs.get(req.uri().path().trim_end_matches('/'))?;
+        let unchanged = req.headers().get(hyper::header::IF_NONE_MATCH).is_some_and(|tag| tag.as_bytes() == etag.as_bytes());
+        let response = Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .header(hyper::header::ETAG, etag.as_str())
+            .header(hyper::header::CACHE_CONTROL, "public, max-age=60");
+        Some(match unchanged {
+            true => response.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap(),
+            false => response.body(Body::from(body.clone())).unwrap(),
+        })
+    }
+}
@@ -0,0 +1,106 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::indexer::unix_time;
+use crate::redact;
+
+// How many of the latest failed calls are kept for the dashboard.
+const RECENT_ERRORS: usize = 50;
+
+#[derive(Default)]
+struct MethodStats {
+    requests: u64,
+    errors: u64,
+    micros: u64,
+}
+
+// Calls answered per method, with their errors and time taken, and the latest
+// errors. Calls to methods that don't exist are counted together so junk
+// method names can't grow the table. With `redact`, error messages are kept
+// with long hex and base64 runs masked.
+#[derive(Default)]
+pub struct RequestStats {
+    methods: Mutex<HashMap<String, MethodStats>>,
+    recent_errors: Mutex<VecDeque<Value>>,
+    redact: bool,
+}
+
+impl RequestStats {
+    pub fn new(redact: bool) -> RequestStats {
+        RequestStats { redact, ..Default::default() }
+    }
+
+    pub fn record(&self, method: &str, elapsed: Duration, result: &Result<Value, RpcError>) {
+        let method = match result {
+            Err(e) if e.code == -32601 => "(unknown)",
+            _ => method,
+        };
+        {
+            let mut methods = self.methods.lock().unwrap();
+            let stats = match methods.get_mut(method) {
+                Some(stats) => stats,
+                None => methods.entry(method.to_string()).or_default(),
+            };
+            stats.requests += 1;
+            stats.micros += elapsed.as_micros() as u64;
+            if result.is_err() {
+                stats.errors += 1;
+            }
+        }
+        if let Err(e) = result {
+            let mut recent = self.recent_errors.lock().unwrap();
+            if recent.len() == RECENT_ERRORS {
+                recent.pop_front();
+            }
+            let message = match self.redact {
+                true => redact::text(&e.message),
+                false => e.message.clone(),
+            };
+            recent.push_back(json!({ "time": unix_time(), "method": method, "code": e.code, "message": message }));
+        }
+    }
+
+    // Calls and failed calls so far, leaving out calls to unknown methods.
+    pub fn totals(&self) -> (u64, u64) {
+        let methods = self.methods.lock().unwrap();
+        methods.iter()
+            .filter(|(method, _)| method.as_str() != "(unknown)")
+            .fold((0, 0), |(requests, errors), (_, stats)| (requests + stats.requests, errors + stats.errors))
+    }
+
+    // Per method: requests, errors and mean milliseconds; and the latest errors, newest first.
+    pub fn summary(&self) -> Value {
+        let methods = self.methods.lock().unwrap();
+        let methods: serde_json::Map<String, Value> = methods
+            .iter()
+            .map(|(method, stats)| {
+                let mean_ms = stats.micros as f64 / stats.requests.max(1) as f64 / 1000.0;
+                (method.clone(), json!({ "requests": stats.requests, "errors": stats.errors, "mean_ms": mean_ms }))
+            })
+            .collect();
+        let recent: Vec<Value> = self.recent_errors.lock().unwrap().iter().rev().cloned().collect();
+        json!({ "methods": methods, "recent_errors": recent })
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        let methods = self.methods.lock().unwrap();
+        let mut methods: Vec<_> = methods.iter().collect();
+        methods.sort_by(|a, b| a.0.cmp(b.0));
+        writeln!(out, "# TYPE rpc_requests_total counter").unwrap();
+        for (method, stats) in &methods {
+            writeln!(out, "rpc_requests_total{{method=\"{}\"}} {}", method, stats.requests).unwrap();
+        }
+        writeln!(out, "# TYPE rpc_errors_total counter").unwrap();
+        for (method, stats) in &methods {
+            writeln!(out, "rpc_errors_total{{method=\"{}\"}} {}", method, stats.errors).unwrap();
+        }
+        writeln!(out, "# TYPE rpc_request_seconds_total counter").unwrap();
+        for (method, stats) in &methods {
+            writeln!(out, "rpc_request_seconds_total{{method=\"{}\"}} {}", method, stats.micros as f64 / 1e6).unwrap();
+        }
+    }
+}
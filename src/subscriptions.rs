@@ -0,0 +1,156 @@
+use hyper::{Body, Request, StatusCode};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::VerusRPC;
+use crate::client_ip::ClientIp;
+use crate::events::Filter;
+use crate::listener::Profile;
+use crate::session;
+
+pub struct SubscriptionLimits {
+    // Streams an unauthenticated client (by IP) may hold open; 0 requires auth.
+    pub free: usize,
+    // Streams an authenticated client may hold open.
+    pub max_per_client: usize,
+    // Addresses plus currencies one stream's filter may list.
+    pub max_filter_terms: usize,
+    pub api_keys: HashSet<String>,
+    // Identities allowed to sign in, lowercased; empty allows any that can sign.
+    pub identities: Vec<String>,
+}
+
+// Who holds a stream: an API key or VerusID, or the client's IP for the free tier.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Free(String),
+    Key(String),
+    Identity(String),
+}
+
+#[derive(Default)]
+struct Counts {
+    per_client: HashMap<Client, usize>,
+    free: usize,
+    authenticated: usize,
+}
+
+pub struct Subscriptions {
+    limits: SubscriptionLimits,
+    counts: Mutex<Counts>,
+    rejected: AtomicU64,
+}
+
+// Held by a stream for as long as it is open.
+pub struct Subscription {
+    rpc: Arc<VerusRPC>,
+    client: Client,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut counts = self.rpc.subscriptions.counts.lock().unwrap();
+        if let Some(count) = counts.per_client.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                counts.per_client.remove(&self.client);
+            }
+        }
+        match self.client {
+            Client::Free(_) => counts.free -= 1,
+            _ => counts.authenticated -= 1,
+        }
+    }
+}
+
+fn param<'a>(query: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    query.get(name).map(String::as_str).filter(|value| !value.is_empty())
+}
+
+impl Subscriptions {
+    pub fn new(limits: SubscriptionLimits) -> Subscriptions {
+        Subscriptions { limits, counts: Mutex::new(Counts::default()), rejected: AtomicU64::new(0) }
+    }
+
+    // An API key from `Authorization: Bearer` or `?api_key=`, or a VerusID
+    // signing in with `?identity=alice@&challenge=<challenge>&signature=...`,
+    // the signature being over "subscribe:<challenge>" (see GET
+    // /auth/challenge). Query parameters are
+    // there for browsers, which can't set headers on WebSocket or EventSource.
+    async fn authenticate(&self, req: &Request<Body>, query: &HashMap<String, String>, rpc: &Arc<VerusRPC>, profile: &Profile) -> Result<Client, String> {
+        let bearer = req.headers().get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let Some(key) = bearer.or_else(|| param(query, "api_key")) {
+            return match self.limits.api_keys.contains(key) {
+                true => Ok(Client::Key(key.to_string())),
+                false => Err("Invalid API key".to_string()),
+            };
+        }
+
+        if let Some(identity) = param(query, "identity") {
+            let (challenge, signature) = match (param(query, "challenge"), param(query, "signature")) {
+                (Some(challenge), Some(signature)) => (challenge, signature),
+                _ => return Err("Identity sign-in needs challenge and signature".to_string()),
+            };
+            if !self.limits.identities.is_empty() && !self.limits.identities.contains(&identity.to_lowercase()) {
+                return Err(format!("Identity {} may not subscribe", identity.to_lowercase()));
+            }
+            return session::verify_login(rpc, profile, "subscribe", identity, challenge, signature).await.map(Client::Identity);
+        }
+
+        let ip = req.extensions().get::<ClientIp>().map(|client| client.0.to_string()).unwrap_or_default();
+        Ok(Client::Free(ip))
+    }
+
+    // Admits a new stream with `filter`, or says why not (with the status to
+    // answer with). The returned guard frees the slot when dropped.
+    pub async fn open(rpc: &Arc<VerusRPC>, req: &Request<Body>, query: &HashMap<String, String>, filter: &Filter, profile: &Profile) -> Result<Subscription, (StatusCode, String)> {
+        let subscriptions = &rpc.subscriptions;
+        let result = subscriptions.admit(rpc, req, query, filter, profile).await;
+        if result.is_err() {
+            subscriptions.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    async fn admit(&self, rpc: &Arc<VerusRPC>, req: &Request<Body>, query: &HashMap<String, String>, filter: &Filter, profile: &Profile) -> Result<Subscription, (StatusCode, String)> {
+        let client = self.authenticate(req, query, rpc, profile).await.map_err(|message| (StatusCode::UNAUTHORIZED, message))?;
+        if filter.terms() > self.limits.max_filter_terms {
+            return Err((StatusCode::BAD_REQUEST, format!("Filter lists more than {} addresses and currencies", self.limits.max_filter_terms)));
+        }
+
+        let mut counts = self.counts.lock().unwrap();
+        let open = counts.per_client.get(&client).copied().unwrap_or(0);
+        let free = matches!(client, Client::Free(_));
+        let limit = if free { self.limits.free } else { self.limits.max_per_client };
+        if open >= limit {
+            let message = match (free, limit) {
+                (true, 0) => "Subscriptions require an API key or identity sign-in".to_string(),
+                (true, _) => format!("Free tier allows {} subscriptions; authenticate for more", limit),
+                (false, _) => format!("At most {} subscriptions per client", limit),
+            };
+            return Err((if free && limit == 0 { StatusCode::UNAUTHORIZED } else { StatusCode::TOO_MANY_REQUESTS }, message));
+        }
+        *counts.per_client.entry(client.clone()).or_default() += 1;
+        if free {
+            counts.free += 1;
+        } else {
+            counts.authenticated += 1;
+        }
+        Ok(Subscription { rpc: rpc.clone(), client })
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        let counts = self.counts.lock().unwrap();
+        writeln!(out, "# TYPE subscriptions_active gauge").unwrap();
+        writeln!(out, "subscriptions_active{{tier=\"free\"}} {}", counts.free).unwrap();
+        writeln!(out, "subscriptions_active{{tier=\"authenticated\"}} {}", counts.authenticated).unwrap();
+        writeln!(out, "# TYPE subscription_clients gauge").unwrap();
+        writeln!(out, "subscription_clients {}", counts.per_client.len()).unwrap();
+        writeln!(out, "# TYPE subscriptions_rejected_total counter").unwrap();
+        writeln!(out, "subscriptions_rejected_total {}", self.rejected.load(Ordering::Relaxed)).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use hyper::{Body, Response, StatusCode};
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::VerusRPC;
+use crate::limiter::Priority;
+use crate::rest::json_response;
+
+// Set in an identity's flags while it is locked with an unlock delay.
+const FLAG_LOCKED: u64 = 0x2;
+// The chain's target block time, for the unlock estimate.
+const BLOCK_SECS: u64 = 60;
+// At most this many identities are watched.
+const MAX_WATCHED: usize = 10_000;
+
+// Where an identity's spending lock stands at `tip`, from its getidentity
+// reply. A locked identity (setidentitytimelock with setunlockdelay) can only
+// spend `unlock_delay` blocks after an unlock is requested; an unlocking one
+// (unlockatblock, or a requested unlock) can spend from `unlock_height`.
+fn lock_status(found: &Value, tip: u64) -> Value {
+    let identity = &found["identity"];
+    let flags = identity["flags"].as_u64().unwrap_or(0);
+    let timelock = identity["timelock"].as_u64().unwrap_or(0);
+    let (status, unlock_delay, unlock_height) = match found["status"].as_str() {
+        Some(status) if status != "active" => ("revoked", None, None),
+        _ if flags & FLAG_LOCKED != 0 => ("locked", Some(timelock), None),
+        _ if timelock > tip => ("unlocking", None, Some(timelock)),
+        _ => ("spendable", None, None),
+    };
+    let blocks_remaining = unlock_height.map(|height| height - tip);
+    json!({
+        "identity": identity["identityaddress"],
+        "name": found.get("fullyqualifiedname").unwrap_or(&identity["name"]),
+        "status": status,
+        "spendable": status == "spendable",
+        "height": tip,
+        "unlock_delay": unlock_delay,
+        "unlock_height": unlock_height,
+        "blocks_remaining": blocks_remaining,
+        "eta_secs": blocks_remaining.map(|blocks| blocks * BLOCK_SECS),
+    })
+}
+
+// The spending locks of watched identities, for vault-style dApps: those in
+// timelock_identities and, with track_timelocks, those named by
+// setidentitytimelock calls made through the proxy. Each is checked at every
+// new block, GET /timelocks reports them and an `identity_spendable` event is
+// published when one that was locked becomes spendable.
+pub struct TimelockMonitor {
+    pub enabled: bool,
+    track_calls: bool,
+    // Last status per identity as configured or named, Null until checked.
+    statuses: Mutex<BTreeMap<String, Value>>,
+}
+
+impl TimelockMonitor {
+    pub fn new(identities: Vec<String>, track_calls: bool) -> TimelockMonitor {
+        TimelockMonitor {
+            enabled: !identities.is_empty() || track_calls,
+            track_calls,
+            statuses: Mutex::new(identities.into_iter().map(|identity| (identity.to_lowercase(), Value::Null)).collect()),
+        }
+    }
+
+    pub fn tracks(&self, method: &str) -> bool {
+        self.track_calls && method == "setidentitytimelock"
+    }
+
+    // Starts watching the identity a setidentitytimelock call locked or unlocked.
+    pub fn observe(&self, params: &[Value], result: &Result<Value, RpcError>) {
+        if let (Some(identity), Ok(_)) = (params.first().and_then(Value::as_str), result) {
+            let mut statuses = self.statuses.lock().unwrap();
+            if statuses.len() < MAX_WATCHED {
+                statuses.entry(identity.to_lowercase()).or_insert(Value::Null);
+            }
+        }
+    }
+
+    pub fn summary(&self) -> Value {
+        Value::Object(self.statuses.lock().unwrap().iter().map(|(identity, status)| (identity.clone(), status.clone())).collect())
+    }
+
+    fn status(&self, identity: &str) -> Option<Value> {
+        self.statuses.lock().unwrap().get(&identity.to_lowercase()).filter(|status| !status.is_null()).cloned()
+    }
+}
+
+async fn check(rpc: &VerusRPC, tip: u64) {
+    let watched: Vec<String> = rpc.timelocks.statuses.lock().unwrap().keys().cloned().collect();
+    for identity in watched {
+        let found = match rpc.upstream.call_as("getidentity", &[json!(identity)], Priority::Background).await {
+            Ok(found) if found["identity"].is_object() => found,
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("timelock monitor: getidentity {} failed: {}", identity, e.message);
+                continue;
+            },
+        };
+        let status = lock_status(&found, tip);
+        let previous = rpc.timelocks.statuses.lock().unwrap().insert(identity.clone(), status.clone());
+        // The first status seen is only taken as the baseline.
+        let was_spendable = previous.is_none_or(|previous| previous.is_null() || previous["spendable"] == json!(true));
+        if status["spendable"] == json!(true) && !was_spendable {
+            rpc.events.publish("identity_spendable", status);
+        }
+    }
+}
+
+// Checks the watched identities whenever the tip moves.
+pub async fn watch(rpc: Arc<VerusRPC>, interval: Duration) {
+    let mut last_height = None;
+    loop {
+        tokio::time::sleep(interval).await;
+        let height = match rpc.tip.height() {
+            Some(height) if Some(height) != last_height => height,
+            _ => continue,
+        };
+        last_height = Some(height);
+        check(&rpc, height).await;
+    }
+}
+
+// GET /timelocks lists the watched identities' lock statuses as of the last
+// block checked; GET /timelocks/{identity} gives one identity's, looking up
+// any that isn't watched.
+pub async fn handle(path: &str, rpc: &VerusRPC) -> Response<Body> {
+    let identity = match path.trim_start_matches("/timelocks").trim_start_matches('/') {
+        "" => return json_response(StatusCode::OK, rpc.timelocks.summary()),
+        identity => identity,
+    };
+    if let Some(status) = rpc.timelocks.status(identity) {
+        return json_response(StatusCode::OK, status);
+    }
+    let tip = match rpc.tip.height() {
+        Some(tip) => tip,
+        None => match rpc.upstream.call("getblockcount", &[]).await.map(|count| count.as_u64()) {
+            Ok(Some(tip)) => tip,
+            Ok(None) => return json_response(StatusCode::BAD_GATEWAY, json!({ "error": "The daemon gave no block count" })),
+            Err(e) => return json_response(StatusCode::BAD_GATEWAY, json!({ "error": e.message })),
+        },
+    };
+    match rpc.upstream.call("getidentity", &[json!(identity)]).await {
+        Ok(found) if found["identity"].is_object() => json_response(StatusCode::OK, lock_status(&found, tip)),
+        Ok(_) => json_response(StatusCode::BAD_GATEWAY, json!({ "error": "The daemon's getidentity reply has no identity" })),
+        Err(e) if e.code == -5 => json_response(StatusCode::NOT_FOUND, json!({ "error": e.message })),
+        Err(e) => json_response(StatusCode::BAD_GATEWAY, json!({ "error": e.message })),
+    }
+}
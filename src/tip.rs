@@ -0,0 +1,223 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::VerusRPC;
+use crate::indexer::unix_time;
+
+// How long the daemon may go without answering a tip poll before the proxy
+// stops reporting ready.
+const UNANSWERED_SECS: u64 = 60;
+// How often the daemon is asked for its height while a read waits for it to
+// reach the height the client asked for.
+const CATCH_UP_POLL: Duration = Duration::from_millis(250);
+
+// Latest block height reported by the daemon, kept current by `follow`, with
+// the timestamp of that block and when the daemon last answered. Zero means
+// not seen yet.
+pub struct ChainTip {
+    height: AtomicU64,
+    time: AtomicU64,
+    polled: AtomicU64,
+    // The height and hash of the best block, together, for consistency tokens.
+    best: Mutex<Option<(u64, String)>>,
+    // A best block older than this means the chain or the node is stuck.
+    pub max_age: Duration,
+    // How long a read asking for a min_height above the tip waits for the
+    // daemon to get there.
+    pub catch_up_wait: Duration,
+}
+
+impl ChainTip {
+    pub fn new(max_age: Duration, catch_up_wait: Duration) -> ChainTip {
+        ChainTip {
+            height: AtomicU64::new(0),
+            time: AtomicU64::new(0),
+            polled: AtomicU64::new(0),
+            best: Mutex::new(None),
+            max_age,
+            catch_up_wait,
+        }
+    }
+
+    pub fn height(&self) -> Option<u64> {
+        match self.height.load(Ordering::Relaxed) {
+            0 => None,
+            height => Some(height),
+        }
+    }
+
+    fn set(&self, height: u64) {
+        self.height.store(height, Ordering::Relaxed);
+    }
+
+    // Keeps the higher of two best blocks, so a slow tip poll finishing after
+    // `reach` moved the tip on doesn't take tokens backwards.
+    fn set_best(&self, height: u64, hash: String) {
+        let mut best = self.best.lock().unwrap();
+        if best.as_ref().is_none_or(|(best, _)| height >= *best) {
+            *best = Some((height, hash));
+        }
+    }
+
+    // The block a reply was answered at, as {"height", "hash"}; a client
+    // passes the height back as `min_height` so that no later read, whichever
+    // node answers it, comes from an older block.
+    pub fn token(&self) -> Option<Value> {
+        self.best.lock().unwrap().as_ref().map(|(height, hash)| json!({ "height": height, "hash": hash }))
+    }
+
+    // Seconds since the best block was mined, once its time is known.
+    pub fn age(&self) -> Option<u64> {
+        match self.time.load(Ordering::Relaxed) {
+            0 => None,
+            time => Some((unix_time() as u64).saturating_sub(time)),
+        }
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        !self.max_age.is_zero() && self.age().is_some_and(|age| age > self.max_age.as_secs())
+    }
+
+    // Seconds since the daemon last answered a tip poll.
+    pub fn since_polled(&self) -> Option<u64> {
+        match self.polled.load(Ordering::Relaxed) {
+            0 => None,
+            polled => Some((unix_time() as u64).saturating_sub(polled)),
+        }
+    }
+
+    // Why the proxy shouldn't take traffic, if anything: the daemon hasn't
+    // answered lately, or its best block is older than `max_age`.
+    pub fn unready_reasons(&self) -> Vec<String> {
+        let mut reasons = Vec::new();
+        match self.since_polled() {
+            None => reasons.push("The chain tip is not known yet".to_string()),
+            Some(since) if since > UNANSWERED_SECS => reasons.push(format!("The daemon has not answered for {}s", since)),
+            Some(_) => {},
+        }
+        if self.is_stalled() {
+            reasons.push(format!("The best block is {}s old", self.age().unwrap_or_default()));
+        }
+        reasons
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        writeln!(out, "# TYPE chain_tip_height gauge").unwrap();
+        writeln!(out, "chain_tip_height {}", self.height.load(Ordering::Relaxed)).unwrap();
+        if let Some(age) = self.age() {
+            writeln!(out, "# TYPE chain_tip_age_seconds gauge").unwrap();
+            writeln!(out, "chain_tip_age_seconds {}", age).unwrap();
+        }
+        writeln!(out, "# TYPE chain_tip_stalled gauge").unwrap();
+        writeln!(out, "chain_tip_stalled {}", self.is_stalled() as u8).unwrap();
+    }
+}
+
+// Blocks announced per poll at most, so a daemon that was far behind (or a
+// proxy that was paused) doesn't flood subscribers with old blocks.
+const MAX_ANNOUNCED_BLOCKS: u64 = 10;
+
+// Publishes a `block` event for a new block and an `identity_update` event for
+// every identity it creates or updates.
+async fn announce(rpc: &VerusRPC, height: u64) {
+    let block = match rpc.upstream.call("getblock", &[json!(height.to_string()), json!(2)]).await {
+        Ok(block) => block,
+        Err(e) => {
+            eprintln!("failed to fetch block {}: {}", height, e.message);
+            return;
+        },
+    };
+    let txs = block["tx"].as_array().map(Vec::as_slice).unwrap_or_default();
+    for tx in txs {
+        for output in tx["vout"].as_array().into_iter().flatten() {
+            let identity = &output["scriptPubKey"]["identityprimary"];
+            if identity.is_object() {
+                rpc.events.publish("identity_update", json!({
+                    "height": height,
+                    "txid": tx["txid"],
+                    "name": identity["name"],
+                    "identityaddress": identity["identityaddress"],
+                    "parent": identity["parent"],
+                }));
+            }
+        }
+    }
+    rpc.events.publish("block", json!({
+        "height": height,
+        "hash": block["hash"],
+        "time": block["time"],
+        "txcount": txs.len(),
+    }));
+}
+
+// Hash and timestamp of the block at `height`.
+async fn block(rpc: &VerusRPC, height: u64) -> Option<(String, u64)> {
+    let hash = rpc.upstream.call("getblockhash", &[json!(height)]).await.ok()?;
+    let header = rpc.upstream.call("getblockheader", std::slice::from_ref(&hash)).await.ok()?;
+    Some((hash.as_str()?.to_string(), header["time"].as_u64()?))
+}
+
+// Waits, up to `catch_up_wait`, for the daemon to reach `min_height`, taking
+// the tip forward as soon as it does. Fails if it doesn't, so the client (or
+// a load balancer) can try another node rather than read an older block than
+// one it has already seen.
+pub async fn reach(rpc: &VerusRPC, min_height: u64) -> Result<(), RpcError> {
+    let deadline = Instant::now() + rpc.tip.catch_up_wait;
+    let mut height = rpc.tip.height().unwrap_or_default();
+    while height < min_height {
+        if Instant::now() >= deadline {
+            return Err(RpcError {
+                code: -32007,
+                message: format!("This node is at height {}, behind the requested min_height {}", height, min_height),
+                data: Some(serde_json::value::to_raw_value(&json!({ "height": height, "min_height": min_height })).unwrap()),
+            });
+        }
+        tokio::time::sleep(CATCH_UP_POLL).await;
+        let polled = rpc.upstream.call("getblockcount", &[]).await.ok().and_then(|count| count.as_u64());
+        if let Some(polled) = polled.filter(|polled| *polled >= min_height) {
+            if let Some((hash, time)) = block(rpc, polled).await {
+                rpc.tip.set_best(polled, hash);
+                rpc.tip.time.store(time, Ordering::Relaxed);
+            }
+            rpc.tip.height.fetch_max(polled, Ordering::Relaxed);
+        }
+        height = height.max(polled.unwrap_or_default());
+    }
+    Ok(())
+}
+
+pub async fn follow(rpc: Arc<VerusRPC>, interval: Duration) {
+    // The height this loop last saw, which `reach` may have moved the tip past.
+    let mut previous = None;
+    loop {
+        if let Ok(count) = rpc.upstream.call("getblockcount", &[]).await {
+            rpc.tip.polled.store(unix_time() as u64, Ordering::Relaxed);
+            if let Some(height) = count.as_u64() {
+                rpc.tip.set(height);
+                if previous != Some(height) || rpc.tip.age().is_none() {
+                    if let Some((hash, time)) = block(&rpc, height).await {
+                        rpc.tip.set_best(height, hash);
+                        rpc.tip.time.store(time, Ordering::Relaxed);
+                    }
+                }
+                crate::reorg::check(&rpc, height).await;
+                if let Some(previous) = previous.filter(|_| rpc.events.has_subscribers()) {
+                    for height in (previous + 1).max(height.saturating_sub(MAX_ANNOUNCED_BLOCKS) + 1)..=height {
+                        announce(&rpc, height).await;
+                    }
+                }
+                previous = Some(height);
+            }
+        }
+        // Sync progress is followed until the daemon has caught up, for
+        // readiness and the warmup error.
+        if !rpc.upstream.warmup().is_done() {
+            let _ = rpc.upstream.call("getblockchaininfo", &[]).await;
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,216 @@
+use hyper::{Body, Response, StatusCode};
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+use crate::rest::{self, json_response};
+
+// Trust levels in the daemon's ratings.
+const TRUST_BLOCKED: u64 = 1;
+const TRUST_APPROVED: u64 = 2;
+// Aggregates kept for the current block, at most.
+const MAX_CACHED: usize = 10_000;
+
+// One kind of thing rated: identities or currencies, with the daemon method
+// holding the wallet's own ratings and the one resolving names.
+#[derive(Clone, Copy)]
+enum Kind {
+    Identity,
+    Currency,
+}
+
+impl Kind {
+    fn parse(kind: &str) -> Option<Kind> {
+        match kind {
+            "identity" => Some(Kind::Identity),
+            "currency" => Some(Kind::Currency),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Identity => "identity",
+            Kind::Currency => "currency",
+        }
+    }
+
+    fn trust_method(self) -> &'static str {
+        match self {
+            Kind::Identity => "getidentitytrust",
+            Kind::Currency => "getcurrencytrust",
+        }
+    }
+
+    fn lookup_method(self) -> &'static str {
+        match self {
+            Kind::Identity => "getidentity",
+            Kind::Currency => "getcurrency",
+        }
+    }
+}
+
+// A rating as a score from 0 (blocked) to 1 (approved): a number, or an
+// object with a daemon trustlevel or a numeric "rating". Unset and
+// unreadable ratings have no score.
+fn score(rating: &Value) -> Option<f64> {
+    match rating {
+        Value::Number(rating) => rating.as_f64().map(|rating| rating.clamp(0.0, 1.0)),
+        Value::Object(rating) => match (rating.get("trustlevel").and_then(Value::as_u64), rating.get("rating").and_then(Value::as_f64)) {
+            (Some(TRUST_APPROVED), _) => Some(1.0),
+            (Some(TRUST_BLOCKED), _) => Some(0.0),
+            (_, Some(rating)) => Some(rating.clamp(0.0, 1.0)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The rating of `id` a rater published: an entry of its contentmultimap under
+// the ratings key that maps the i-address to a rating, directly or inside the
+// typed wrapper the daemon shows structured data in.
+fn published_rating(identity: &Value, key: &str, id: &str) -> Option<Value> {
+    let entries = identity["identity"]["contentmultimap"][key].as_array()?;
+    entries.iter().rev().find_map(|entry| {
+        entry.get(id).or_else(|| entry.as_object()?.values().find_map(|inner| inner.get(id))).cloned()
+    })
+}
+
+// Trust ratings of identities and currencies merged across sources: the
+// daemon wallet's own (getidentitytrust, getcurrencytrust) and those each of
+// the rating identities publishes in its contentmultimap under the ratings
+// key, as {"<i-address>": rating}. Aggregates are cached until the next block.
+pub struct TrustRatings {
+    raters: Vec<String>,
+    key: String,
+    // The height the cached aggregates and rater identities are for.
+    cached: Mutex<(Option<u64>, HashMap<String, Value>)>,
+}
+
+impl TrustRatings {
+    pub fn new(raters: Vec<String>, key: String) -> TrustRatings {
+        TrustRatings { raters, key, cached: Mutex::new((None, HashMap::new())) }
+    }
+
+    // Rater identities are read again each block, so the tip is followed
+    // while there are any.
+    pub fn has_raters(&self) -> bool {
+        !self.raters.is_empty()
+    }
+
+    fn cached(&self, height: Option<u64>, key: &str) -> Option<Value> {
+        let cached = self.cached.lock().unwrap();
+        cached.1.get(key).filter(|_| height.is_some() && cached.0 == height).cloned()
+    }
+
+    fn cache(&self, height: Option<u64>, key: String, value: Value) {
+        if height.is_none() {
+            return;
+        }
+        let mut cached = self.cached.lock().unwrap();
+        if cached.0 != height || cached.1.len() >= MAX_CACHED {
+            *cached = (height, HashMap::new());
+        }
+        cached.1.insert(key, value);
+    }
+
+    // A rater's identity as of this block.
+    async fn rater(&self, rpc: &Arc<VerusRPC>, height: Option<u64>, rater: &str, access: Access) -> Result<Value, String> {
+        let key = format!("rater:{}", rater.to_lowercase());
+        if let Some(identity) = self.cached(height, &key) {
+            return Ok(identity);
+        }
+        let identity = rpc.handle_call_as("getidentity".to_string(), vec![json!(rater)], access).await.map_err(|e| e.message)?;
+        self.cache(height, key, identity.clone());
+        Ok(identity)
+    }
+
+    async fn aggregate(&self, rpc: &Arc<VerusRPC>, kind: Kind, id: &str, height: Option<u64>, access: Access) -> Result<Value, String> {
+        let mut sources = Vec::new();
+        let mut errors = Map::new();
+
+        // The wallet's identitytrustmode or currencytrustmode says whether it
+        // only uses approved ones, or any but the blocked.
+        let mut mode = Value::Null;
+        match rpc.handle_call_as(kind.trust_method().to_string(), vec![json!([id])], access).await {
+            Ok(wallet) => {
+                mode = wallet[format!("{}trustmode", kind.name())].clone();
+                if let Some(rating) = wallet["setratings"].get(id) {
+                    sources.push(json!({ "source": "wallet", "score": score(rating), "rating": rating }));
+                }
+            },
+            Err(e) => {
+                errors.insert("wallet".to_string(), json!(e.message));
+            },
+        }
+
+        let key = match self.raters.is_empty() {
+            true => String::new(),
+            false => rest::resolve(rpc, "getvdxfid", &self.key, access).await?,
+        };
+        for rater in &self.raters {
+            match self.rater(rpc, height, rater, access).await {
+                Ok(identity) => if let Some(rating) = published_rating(&identity, &key, id) {
+                    sources.push(json!({
+                        "source": "rater",
+                        "rater": rater,
+                        "identity": identity["identity"]["identityaddress"],
+                        "score": score(&rating),
+                        "rating": rating,
+                    }));
+                },
+                Err(message) => {
+                    errors.insert(rater.clone(), json!(message));
+                },
+            }
+        }
+
+        let scores: Vec<f64> = sources.iter().filter_map(|source| source["score"].as_f64()).collect();
+        let aggregate = match scores.is_empty() {
+            true => None,
+            false => Some(scores.iter().sum::<f64>() / scores.len() as f64),
+        };
+        Ok(json!({
+            "kind": kind.name(),
+            "id": id,
+            "score": aggregate,
+            "ratings": scores.len(),
+            "blocked": scores.contains(&0.0),
+            "wallet_trust_mode": mode,
+            "sources": sources,
+            "errors": errors,
+            "height": height,
+        }))
+    }
+}
+
+// GET /trust/identity/{identity} and GET /trust/currency/{currency}: the
+// aggregate trust score of an identity or currency, 0 to 1 (null when no
+// source rates it), with each source's rating it was computed from.
+pub async fn handle(path: &str, rpc: &Arc<VerusRPC>, access: Access) -> Response<Body> {
+    let (kind, target) = match path.trim_start_matches("/trust/").split_once('/') {
+        Some((kind, target)) if !target.is_empty() => match Kind::parse(kind) {
+            Some(kind) => (kind, target),
+            None => return json_response(StatusCode::NOT_FOUND, json!({ "error": "Use /trust/identity/{identity} or /trust/currency/{currency}" })),
+        },
+        _ => return json_response(StatusCode::NOT_FOUND, json!({ "error": "Use /trust/identity/{identity} or /trust/currency/{currency}" })),
+    };
+    let id = match rest::resolve(rpc, kind.lookup_method(), target, access).await {
+        Ok(id) => id,
+        Err(message) => return json_response(StatusCode::NOT_FOUND, json!({ "error": message })),
+    };
+    let height = rpc.tip.height();
+    let key = format!("{}:{}", kind.name(), id);
+    if let Some(aggregate) = rpc.trust.cached(height, &key) {
+        return json_response(StatusCode::OK, aggregate);
+    }
+    match rpc.trust.aggregate(rpc, kind, &id, height, access).await {
+        Ok(aggregate) => {
+            rpc.trust.cache(height, key, aggregate.clone());
+            json_response(StatusCode::OK, aggregate)
+        },
+        Err(message) => json_response(StatusCode::BAD_GATEWAY, json!({ "error": message })),
+    }
+}
@@ -0,0 +1,200 @@
+use jsonrpc::error::RpcError;
+use serde_json::{Map, Value, json};
+use std::sync::Arc;
+
+use crate::VerusRPC;
+use crate::allowlist::Access;
+use crate::indexer::COIN;
+
+// Size estimates for a transparent transaction: the fixed part (version,
+// counts, lock time, expiry and the empty shielded parts), a signed P2PKH
+// input and a P2PKH output.
+const TX_OVERHEAD_BYTES: u64 = 30;
+const INPUT_BYTES: u64 = 148;
+const OUTPUT_BYTES: u64 = 34;
+// Change below this is left to the fee rather than creating an output that
+// costs more to spend than it holds.
+const DUST_SATS: i64 = 5_460;
+
+#[derive(Clone, Copy)]
+pub enum Strategy {
+    // Fewest inputs: the biggest outputs first.
+    Largest,
+    // Spends small outputs first, consolidating the wallet over time.
+    Smallest,
+    // The longest-held outputs first.
+    Oldest,
+}
+
+impl Strategy {
+    pub fn parse(name: &str) -> Result<Strategy, String> {
+        match name {
+            "largest-first" => Ok(Strategy::Largest),
+            "smallest-first" => Ok(Strategy::Smallest),
+            "oldest-first" => Ok(Strategy::Oldest),
+            other => Err(format!("Unknown coin selection strategy {} (largest-first, smallest-first or oldest-first)", other)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Strategy::Largest => "largest-first",
+            Strategy::Smallest => "smallest-first",
+            Strategy::Oldest => "oldest-first",
+        }
+    }
+}
+
+pub struct TxBuilder {
+    pub strategy: Strategy,
+    pub min_fee_per_kb: f64,
+    pub max_inputs: usize,
+}
+
+// One spendable output from getaddressutxos.
+struct Utxo {
+    txid: String,
+    vout: u64,
+    address: String,
+    sats: i64,
+    height: u64,
+}
+
+fn invalid(message: impl Into<String>) -> RpcError {
+    RpcError { code: -32602, message: message.into(), data: None }
+}
+
+fn to_sats(amount: f64) -> i64 {
+    (amount * COIN).round() as i64
+}
+
+fn estimated_size(inputs: usize, outputs: usize) -> u64 {
+    TX_OVERHEAD_BYTES + inputs as u64 * INPUT_BYTES + outputs as u64 * OUTPUT_BYTES
+}
+
+fn fee_for(size: u64, fee_per_kb: f64, min_fee_per_kb: f64) -> i64 {
+    to_sats((fee_per_kb * size as f64 / 1000.0).max(min_fee_per_kb))
+}
+
+// Picks inputs in strategy order until they cover the outputs and the fee for
+// a transaction of that many inputs (with a change output). Returns the inputs
+// and the fee, or how much was available.
+fn select(mut utxos: Vec<Utxo>, strategy: Strategy, target: i64, outputs: usize, fee_per_kb: f64, builder: &TxBuilder) -> Result<(Vec<Utxo>, i64), i64> {
+    match strategy {
+        Strategy::Largest => utxos.sort_by_key(|utxo| std::cmp::Reverse(utxo.sats)),
+        Strategy::Smallest => utxos.sort_by_key(|utxo| utxo.sats),
+        Strategy::Oldest => utxos.sort_by_key(|utxo| utxo.height),
+    }
+    let mut selected = Vec::new();
+    let mut total = 0;
+    for utxo in utxos.into_iter().take(builder.max_inputs) {
+        total += utxo.sats;
+        selected.push(utxo);
+        let fee = fee_for(estimated_size(selected.len(), outputs + 1), fee_per_kb, builder.min_fee_per_kb);
+        if total >= target + fee {
+            return Ok((selected, fee));
+        }
+    }
+    Err(total)
+}
+
+// buildtransaction [{"addresses": [...], "outputs": {"address": amount, ...},
+// "changeaddress", "strategy", "fee_per_kb"}] builds an unsigned transaction
+// paying the outputs from the native-coin UTXOs of the addresses: it fetches
+// them with getaddressutxos, selects inputs with the strategy (the configured
+// one by default), adds change above the dust limit and has the daemon
+// serialize it with createrawtransaction. The fee is fee_per_kb, or the
+// normal recommend_fees rate, over an estimate of the signed size. Outputs
+// that hold other currencies are never spent. The client signs and sends the
+// transaction itself; both daemon calls go through the usual checks.
+pub async fn build(rpc: &Arc<VerusRPC>, params: Vec<Value>, access: Access) -> Result<Value, RpcError> {
+    let request = match params.as_slice() {
+        [Value::Object(request)] => request,
+        _ => return Err(invalid("Invalid params parameter")),
+    };
+    let builder = &rpc.txbuilder;
+    let addresses: Vec<Value> = match request.get("addresses").and_then(Value::as_array) {
+        Some(addresses) if !addresses.is_empty() && addresses.iter().all(Value::is_string) => addresses.clone(),
+        _ => return Err(invalid("addresses must be a non-empty array of addresses")),
+    };
+    let mut outputs = Map::new();
+    let mut target = 0;
+    for (address, amount) in request.get("outputs").and_then(Value::as_object).into_iter().flatten() {
+        match amount.as_f64().map(to_sats) {
+            Some(sats) if sats > 0 => {
+                target += sats;
+                outputs.insert(address.clone(), amount.clone());
+            },
+            _ => return Err(invalid(format!("Invalid amount for {}", address))),
+        }
+    }
+    if outputs.is_empty() {
+        return Err(invalid("outputs must map at least one address to an amount"));
+    }
+    let change_address = match request.get("changeaddress") {
+        Some(Value::String(address)) => address.clone(),
+        None => addresses[0].as_str().unwrap().to_string(),
+        Some(_) => return Err(invalid("changeaddress must be an address")),
+    };
+    let strategy = match request.get("strategy") {
+        Some(Value::String(name)) => Strategy::parse(name).map_err(invalid)?,
+        None => builder.strategy,
+        Some(_) => return Err(invalid("strategy must be a string")),
+    };
+    let fee_per_kb = match request.get("fee_per_kb") {
+        Some(rate) => rate.as_f64().filter(|rate| *rate >= 0.0).ok_or_else(|| invalid("fee_per_kb must be a number"))?,
+        None => rpc.mempool.recommendation().and_then(|fees| fees["normal"].as_f64()).unwrap_or(builder.min_fee_per_kb),
+    };
+
+    let listed = rpc.handle_call_as("getaddressutxos".to_string(), vec![json!({"addresses": addresses})], access).await?;
+    let utxos: Vec<Utxo> = listed.as_array().into_iter().flatten()
+        .filter(|utxo| utxo["currencyvalues"].as_object().is_none_or(|values| values.is_empty()))
+        .filter_map(|utxo| Some(Utxo {
+            txid: utxo["txid"].as_str()?.to_string(),
+            vout: utxo["outputIndex"].as_u64()?,
+            address: utxo["address"].as_str().unwrap_or_default().to_string(),
+            sats: utxo["satoshis"].as_i64().filter(|sats| *sats > 0)?,
+            height: utxo["height"].as_u64().unwrap_or(u64::MAX),
+        }))
+        .collect();
+    let (inputs, mut fee) = match select(utxos, strategy, target, outputs.len(), fee_per_kb, builder) {
+        Ok(selected) => selected,
+        Err(available) => return Err(RpcError {
+            code: -32000,
+            message: "Insufficient funds".into(),
+            data: Some(serde_json::value::to_raw_value(&json!({
+                "needed": target as f64 / COIN,
+                "available": available as f64 / COIN,
+                "max_inputs": builder.max_inputs,
+            })).unwrap()),
+        }),
+    };
+    let total: i64 = inputs.iter().map(|utxo| utxo.sats).sum();
+    let mut change = total - target - fee;
+    if change < DUST_SATS {
+        fee += change;
+        change = 0;
+    } else if outputs.contains_key(&change_address) {
+        return Err(invalid("changeaddress must not be one of the outputs"));
+    } else {
+        outputs.insert(change_address.clone(), json!(change as f64 / COIN));
+    }
+
+    let spends: Vec<Value> = inputs.iter().map(|utxo| json!({"txid": utxo.txid, "vout": utxo.vout})).collect();
+    let hex = rpc.handle_call_as("createrawtransaction".to_string(), vec![json!(spends), Value::Object(outputs.clone())], access).await?;
+    Ok(json!({
+        "hex": hex,
+        "inputs": inputs.iter().map(|utxo| json!({
+            "txid": utxo.txid,
+            "vout": utxo.vout,
+            "address": utxo.address,
+            "amount": utxo.sats as f64 / COIN,
+        })).collect::<Vec<Value>>(),
+        "outputs": outputs,
+        "change": { "address": change_address, "amount": change as f64 / COIN },
+        "fee": fee as f64 / COIN,
+        "fee_per_kb": fee_per_kb,
+        "estimated_size": estimated_size(inputs.len(), outputs.len()),
+        "strategy": strategy.name(),
+    }))
+}
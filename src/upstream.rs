@@ -0,0 +1,635 @@
+use base64::Engine;
+use hyper::client::HttpConnector;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, Client, Method, Request, Uri};
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::chain_check::ChainExpectation;
+use crate::limiter::{AdaptiveLimiter, LimiterOptions, Priority};
+use crate::phases::{Phase, Phases};
+
+// Failed calls in a row after which the daemon is taken to be down: calls
+// fail at once, and its connections are rebuilt until it answers again.
+const DOWN_AFTER_FAILURES: u32 = 3;
+// The wait between reconnection attempts doubles from the first to the last,
+// each wait cut to a random 50-100% so proxies sharing a daemon don't all
+// retry at the same moment.
+const RECONNECT_FIRST: Duration = Duration::from_millis(250);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+// verusd's RPC_IN_WARMUP: still loading the block index, rescanning or
+// otherwise starting up.
+const WARMUP_CODE: i32 = -28;
+// How often a daemon found on the wrong chain is checked again.
+const CHAIN_RECHECK: Duration = Duration::from_secs(10);
+// verificationprogress from which the daemon counts as synced.
+const SYNCED_PROGRESS: f64 = 0.999;
+
+// What is known about the daemon starting up: its status message while it
+// answers -28, and its verificationprogress as of the last getblockchaininfo.
+#[derive(Clone, Default)]
+pub struct Warmup {
+    pub status: Option<String>,
+    pub progress: Option<f64>,
+}
+
+impl Warmup {
+    pub fn is_done(&self) -> bool {
+        self.status.is_none() && self.progress.is_some_and(|progress| progress >= SYNCED_PROGRESS)
+    }
+
+    // Why the proxy shouldn't take traffic yet, if the daemon is known to be
+    // starting up or catching up.
+    pub fn unready_reason(&self) -> Option<String> {
+        match (&self.status, self.progress) {
+            (Some(status), _) => Some(format!("The daemon is warming up: {}", status)),
+            (None, Some(progress)) if progress < SYNCED_PROGRESS => Some(format!("The daemon is syncing ({:.1}%)", progress * 100.0)),
+            _ => None,
+        }
+    }
+
+    fn error(&self) -> RpcError {
+        let data = json!({ "status": self.status, "progress": self.progress });
+        RpcError {
+            code: WARMUP_CODE,
+            message: "Daemon warming up, retry later".into(),
+            data: Some(serde_json::value::to_raw_value(&data).unwrap()),
+        }
+    }
+}
+
+pub struct UpstreamOptions {
+    pub keepalive: bool,
+    pub pool_idle_timeout: Duration,
+    pub pool_max_idle: usize,
+    pub limits: LimiterOptions,
+    // Largest reply read from the daemon, in bytes, unless `method_max_response`
+    // has a limit for the method. 0 means no limit.
+    pub max_response: usize,
+    pub method_max_response: HashMap<String, usize>,
+    pub chain: ChainExpectation,
+    // How long a call may take, from sending it to having read the reply (or,
+    // for streamed replies, its headers).
+    pub timeout: Duration,
+    pub phases: Arc<Phases>,
+    // Connections opened ahead of traffic and kept open, so the first calls
+    // after startup or a daemon restart don't wait for a connection.
+    pub warm_connections: usize,
+}
+
+// JSON-RPC client for verusd. Connections are pooled and kept alive between
+// calls unless `keepalive` is turned off, and the number of calls in flight is
+// capped by an adaptive limiter.
+pub struct Upstream {
+    // Replaced with a fresh client, and so a fresh pool, while reconnecting.
+    client: RwLock<Client<HttpConnector>>,
+    keepalive: bool,
+    pool_max_idle: usize,
+    uri: Uri,
+    auth: String,
+    nonce: AtomicU64,
+    max_response: usize,
+    method_max_response: HashMap<String, usize>,
+    warmup: Mutex<Warmup>,
+    chain: ChainExpectation,
+    // Whether the daemon has been checked against `chain` since it was last
+    // unreachable, and why it failed the check if it did.
+    chain_verified: AtomicBool,
+    chain_mismatch: Mutex<Option<(String, Instant)>>,
+    chain_checking: tokio::sync::Mutex<()>,
+    timeout: Duration,
+    phases: Arc<Phases>,
+    warm_connections: usize,
+    pool_idle_timeout: Duration,
+    // Woken when a call gets no answer, to reconnect if need be and warm the
+    // pool again once the daemon is back.
+    lost: tokio::sync::Notify,
+    preconnects: AtomicU64,
+    health: Mutex<Health>,
+    pub limiter: Arc<AdaptiveLimiter>,
+}
+
+// Whether the daemon is answering, and how reconnecting to it is going.
+#[derive(Default)]
+struct Health {
+    // Calls in a row that got no answer.
+    failures: u32,
+    down_since: Option<Instant>,
+    attempts: u32,
+    next_attempt: Option<Instant>,
+    rebuilds: u64,
+}
+
+fn build_client(keepalive: bool, pool_idle_timeout: Duration, pool_max_idle: usize) -> Client<HttpConnector> {
+    let mut connector = HttpConnector::new();
+    connector.set_nodelay(true);
+    Client::builder()
+        .pool_idle_timeout(pool_idle_timeout)
+        .pool_max_idle_per_host(if keepalive { pool_max_idle } else { 0 })
+        .build(connector)
+}
+
+// A random 50-100% of `wait`.
+fn jittered(wait: Duration) -> Duration {
+    let mut random = [0u8; 4];
+    let fraction = match getrandom::getrandom(&mut random) {
+        Ok(()) => u32::from_le_bytes(random) as f64 / u32::MAX as f64,
+        Err(_) => 1.0,
+    };
+    wait.mul_f64(0.5 + fraction / 2.0)
+}
+
+fn internal_error() -> RpcError {
+    RpcError { code: -32603, message: "Internal error".into(), data: None }
+}
+
+fn busy_error() -> RpcError {
+    RpcError { code: -32000, message: "Server busy, retry later".into(), data: None }
+}
+
+fn wrong_chain_error(reason: &str) -> RpcError {
+    RpcError {
+        code: -32002,
+        message: "Upstream daemon is on the wrong chain".into(),
+        data: Some(serde_json::value::to_raw_value(&json!({ "reason": reason })).unwrap()),
+    }
+}
+
+fn too_large_error(method: &str, limit: usize) -> RpcError {
+    RpcError {
+        code: -32001,
+        message: "Response too large, narrow your query".into(),
+        data: Some(serde_json::value::to_raw_value(&json!({ "method": method, "max_bytes": limit })).unwrap()),
+    }
+}
+
+// One daemon call made for a request, reported back to the client in debug
+// mode so it can be matched up with the proxy's and the daemon's logs.
+pub struct Trace {
+    // The JSON-RPC id the call went to the daemon with.
+    id: Value,
+    method: String,
+    node: String,
+    // Time spent waiting for the limiter, then for the daemon's reply.
+    queued: Duration,
+    latency: Duration,
+}
+
+impl Trace {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "method": self.method,
+            "node": self.node,
+            "queued_ms": self.queued.as_millis() as u64,
+            "latency_ms": self.latency.as_millis() as u64,
+        })
+    }
+}
+
+tokio::task_local! {
+    static TRACES: RefCell<Vec<Trace>>;
+}
+
+// Runs `f`, collecting the daemon calls made for it if `enabled`. Calls made
+// from tasks it spawns, like background cache refreshes, aren't included.
+pub async fn traced<F: Future>(enabled: bool, f: F) -> (F::Output, Vec<Trace>) {
+    if !enabled {
+        return (f.await, Vec::new());
+    }
+    TRACES.scope(RefCell::new(Vec::new()), async {
+        let output = f.await;
+        (output, TRACES.with(RefCell::take))
+    }).await
+}
+
+// Why a call got no JSON-RPC reply.
+enum Failure {
+    // The daemon couldn't be reached or didn't answer properly.
+    Unavailable,
+    // The reply was over the method's size limit and was dropped unread.
+    TooLarge(usize),
+}
+
+impl Upstream {
+    pub fn new(url: &str, user: &str, pass: &str, opts: UpstreamOptions) -> Result<Upstream, String> {
+        // rpc_url has always been accepted without a scheme.
+        let url = if url.contains("://") { url.to_string() } else { format!("http://{}", url) };
+        let uri = url.parse::<Uri>().map_err(|e| format!("invalid rpc_url '{}': {}", url, e))?;
+
+        let client = build_client(opts.keepalive, opts.pool_idle_timeout, opts.pool_max_idle);
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+        Ok(Upstream {
+            client: RwLock::new(client),
+            keepalive: opts.keepalive,
+            pool_max_idle: opts.pool_max_idle,
+            uri,
+            auth: format!("Basic {}", credentials),
+            nonce: AtomicU64::new(0),
+            max_response: opts.max_response,
+            method_max_response: opts.method_max_response,
+            warmup: Mutex::new(Warmup::default()),
+            chain: opts.chain,
+            chain_verified: AtomicBool::new(false),
+            chain_mismatch: Mutex::new(None),
+            chain_checking: tokio::sync::Mutex::new(()),
+            timeout: opts.timeout,
+            phases: opts.phases,
+            // Connections beyond what the pool keeps idle would be closed at once.
+            warm_connections: if opts.keepalive { opts.warm_connections.min(opts.pool_max_idle) } else { 0 },
+            pool_idle_timeout: opts.pool_idle_timeout,
+            lost: tokio::sync::Notify::new(),
+            preconnects: AtomicU64::new(0),
+            health: Mutex::new(Health::default()),
+            limiter: AdaptiveLimiter::new(opts.limits),
+        })
+    }
+
+    pub async fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        self.call_as(method, params, Priority::Interactive).await
+    }
+
+    // Makes a call, queueing for the daemon behind more urgent calls.
+    pub async fn call_as(&self, method: &str, params: &[Value], priority: Priority) -> Result<Value, RpcError> {
+        self.verify_chain().await?;
+        self.call_unverified(method, params, priority).await
+    }
+
+    async fn call_unverified(&self, method: &str, params: &[Value], priority: Priority) -> Result<Value, RpcError> {
+        self.check_reachable()?;
+        let queued = Instant::now();
+        let mut permit = self.limiter.acquire(priority).await.ok_or_else(busy_error)?;
+        let id = self.nonce.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let reply = self.send(id, method, params).await;
+        self.trace(json!(id), method, queued, started);
+        // Daemon-side RPC errors are ordinary answers, as are replies too large
+        // to pass on; only failures to get a reply at all count against the limit.
+        permit.record(matches!(reply, Err(Failure::Unavailable)));
+        let mut reply = match reply {
+            Ok(reply) => reply,
+            Err(Failure::Unavailable) => {
+                self.chain_verified.store(false, Ordering::Relaxed);
+                return Err(internal_error());
+            },
+            Err(Failure::TooLarge(limit)) => return Err(too_large_error(method, limit)),
+        };
+        let result = match reply["error"].take() {
+            Value::Null => Ok(reply["result"].take()),
+            error => Err(serde_json::from_value::<RpcError>(error).unwrap_or_else(|_| internal_error())),
+        };
+        // -28 is answered with the warmup error; any other answer means the
+        // daemon is past loading.
+        let mut warmup = self.warmup.lock().unwrap();
+        match &result {
+            Err(e) if e.code == WARMUP_CODE => {
+                warmup.status = Some(e.message.clone());
+                return Err(warmup.error());
+            },
+            Ok(info) if method == "getblockchaininfo" => {
+                warmup.status = None;
+                warmup.progress = info["verificationprogress"].as_f64().or(warmup.progress);
+            },
+            _ => warmup.status = None,
+        }
+        result
+    }
+
+    // Checks the daemon is on the expected chain before its first answer is
+    // used, and again once it has been unreachable, since it may have been
+    // replaced meanwhile. Calls wait for the check, and fail while the daemon
+    // is on the wrong chain.
+    pub async fn verify_chain(&self) -> Result<(), RpcError> {
+        if self.chain.is_empty() || self.chain_verified.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let _checking = self.chain_checking.lock().await;
+        if self.chain_verified.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Some((reason, checked)) = &*self.chain_mismatch.lock().unwrap() {
+            if checked.elapsed() < CHAIN_RECHECK {
+                return Err(wrong_chain_error(reason));
+            }
+        }
+        let info = self.call_unverified("getinfo", &[], Priority::Interactive).await?;
+        let genesis_hash = match self.chain.needs_genesis() {
+            true => Some(self.call_unverified("getblockhash", &[json!(0)], Priority::Interactive).await?),
+            false => None,
+        };
+        match self.chain.check(&info, genesis_hash.as_ref()) {
+            Ok(()) => {
+                *self.chain_mismatch.lock().unwrap() = None;
+                self.chain_verified.store(true, Ordering::Relaxed);
+                Ok(())
+            },
+            Err(reason) => {
+                let error = wrong_chain_error(&reason);
+                *self.chain_mismatch.lock().unwrap() = Some((reason, Instant::now()));
+                Err(error)
+            },
+        }
+    }
+
+    // Opens the warm connections all at once, each with a cheap authenticated
+    // call, so they are left idle in the pool. Returns how many answered.
+    pub async fn preconnect(&self) -> usize {
+        let calls = (0..self.warm_connections).map(|_| self.send(self.nonce.fetch_add(1, Ordering::Relaxed), "getblockcount", &[]));
+        let opened = futures_util::future::join_all(calls).await.into_iter().filter(Result::is_ok).count();
+        self.preconnects.fetch_add(opened as u64, Ordering::Relaxed);
+        opened
+    }
+
+    // Looks after the connections to the daemon: keeps the warm ones open,
+    // opening them again before the pool's idle timeout would close them, and
+    // when a call gets no answer, reconnects until the daemon answers again
+    // (a restart drops every pooled connection) and warms the pool.
+    pub async fn maintain(&self) {
+        let refresh = (self.pool_idle_timeout / 2).max(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(refresh), if self.warm_connections > 0 => {
+                    self.preconnect().await;
+                },
+                _ = self.lost.notified() => {
+                    self.reconnect().await;
+                    self.preconnect().await;
+                },
+            }
+        }
+    }
+
+    fn client(&self) -> Client<HttpConnector> {
+        self.client.read().unwrap().clone()
+    }
+
+    // Tries the daemon until it answers, starting over from a fresh client
+    // each time: connections left from before a daemon restart can hang
+    // rather than fail, and a new pool drops them.
+    async fn reconnect(&self) {
+        let mut wait = RECONNECT_FIRST;
+        while self.send(self.nonce.fetch_add(1, Ordering::Relaxed), "getblockcount", &[]).await.is_err() {
+            *self.client.write().unwrap() = build_client(self.keepalive, self.pool_idle_timeout, self.pool_max_idle);
+            let delay = jittered(wait);
+            {
+                let mut health = self.health.lock().unwrap();
+                health.attempts += 1;
+                health.rebuilds += 1;
+                health.next_attempt = Some(Instant::now() + delay);
+            }
+            tokio::time::sleep(delay).await;
+            wait = (wait * 2).min(RECONNECT_MAX);
+        }
+    }
+
+    fn note_failure(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.failures += 1;
+        if health.failures >= DOWN_AFTER_FAILURES && health.down_since.is_none() {
+            eprintln!("daemon unreachable after {} failed calls, reconnecting", health.failures);
+            health.down_since = Some(Instant::now());
+        }
+        drop(health);
+        self.lost.notify_one();
+    }
+
+    fn note_success(&self) {
+        let mut health = self.health.lock().unwrap();
+        if let Some(down_since) = health.down_since {
+            eprintln!("daemon reachable again after {:.1}s and {} attempts", down_since.elapsed().as_secs_f64(), health.attempts);
+        }
+        health.failures = 0;
+        health.down_since = None;
+        health.attempts = 0;
+        health.next_attempt = None;
+    }
+
+    // Fails calls at once while the daemon is down, rather than have each wait
+    // out the timeout on a daemon that isn't there.
+    fn check_reachable(&self) -> Result<(), RpcError> {
+        let health = self.health.lock().unwrap();
+        if health.down_since.is_none() {
+            return Ok(());
+        }
+        let retry_after = health.next_attempt.map(|at| at.saturating_duration_since(Instant::now()).as_secs_f64());
+        Err(RpcError {
+            code: -32603,
+            message: "Daemon unreachable, reconnecting".into(),
+            data: Some(serde_json::value::to_raw_value(&json!({ "retry_after": retry_after })).unwrap()),
+        })
+    }
+
+    // Why /readyz fails while the daemon is down.
+    pub fn unreachable_reason(&self) -> Option<String> {
+        let health = self.health.lock().unwrap();
+        let down_since = health.down_since?;
+        Some(format!("The daemon has been unreachable for {}s ({} reconnection attempts)", down_since.elapsed().as_secs(), health.attempts))
+    }
+
+    pub fn health(&self) -> Value {
+        let health = self.health.lock().unwrap();
+        json!({
+            "reachable": health.down_since.is_none(),
+            "failures": health.failures,
+            "down_for": health.down_since.map(|since| since.elapsed().as_secs()),
+            "attempts": health.attempts,
+            "next_attempt_in": health.next_attempt.map(|at| at.saturating_duration_since(Instant::now()).as_secs_f64()),
+            "rebuilds": health.rebuilds,
+        })
+    }
+
+    // Why the daemon failed the chain check, if it did.
+    pub fn chain_mismatch(&self) -> Option<String> {
+        self.chain_mismatch.lock().unwrap().as_ref().map(|(reason, _)| reason.clone())
+    }
+
+    pub fn warmup(&self) -> Warmup {
+        self.warmup.lock().unwrap().clone()
+    }
+
+    pub fn render_metrics(&self, out: &mut String) {
+        let warmup = self.warmup();
+        let health = self.health.lock().unwrap();
+        writeln!(out, "# TYPE upstream_reachable gauge").unwrap();
+        writeln!(out, "upstream_reachable {}", health.down_since.is_none() as u8).unwrap();
+        writeln!(out, "# TYPE upstream_rebuilds_total counter").unwrap();
+        writeln!(out, "upstream_rebuilds_total {}", health.rebuilds).unwrap();
+        drop(health);
+        writeln!(out, "# TYPE upstream_preconnects_total counter").unwrap();
+        writeln!(out, "upstream_preconnects_total {}", self.preconnects.load(Ordering::Relaxed)).unwrap();
+        writeln!(out, "# TYPE daemon_warming_up gauge").unwrap();
+        writeln!(out, "daemon_warming_up {}", warmup.status.is_some() as u8).unwrap();
+        if let Some(progress) = warmup.progress {
+            writeln!(out, "# TYPE daemon_verification_progress gauge").unwrap();
+            writeln!(out, "daemon_verification_progress {}", progress).unwrap();
+        }
+        self.limiter.render_metrics(out);
+    }
+
+    // Sends a call and returns the daemon's reply body unread, for the caller
+    // to pass on as it arrives. The reply goes out with `id` as its id. The
+    // call holds its limiter slot until the daemon has sent all of the reply,
+    // which is when its latency is taken. Error replies are short and read
+    // whole, so the warmup error is answered as for other calls.
+    pub async fn stream(&self, method: &str, params: &[Value], id: &Value, priority: Priority) -> Result<Body, RpcError> {
+        self.verify_chain().await?;
+        self.check_reachable()?;
+        let queued = Instant::now();
+        let mut permit = self.limiter.acquire(priority).await.ok_or_else(busy_error)?;
+        let body = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}).to_string();
+        let request = self.request(body).map_err(|_| internal_error())?;
+        let started = Instant::now();
+        let response = tokio::time::timeout(self.timeout, self.client().request(request)).await;
+        self.trace(id.clone(), method, queued, started);
+        self.phases.record(Phase::Upstream, started.elapsed());
+        let response = match response {
+            Ok(Ok(response)) => response,
+            failed => {
+                if failed.is_err() {
+                    self.phases.timed_out(Phase::Upstream);
+                }
+                permit.record(true);
+                self.chain_verified.store(false, Ordering::Relaxed);
+                self.note_failure();
+                return Err(internal_error());
+            },
+        };
+        self.note_success();
+        let limit = self.response_limit(method);
+        // verusd answers RPC errors with a non-200 status.
+        if !response.status().is_success() {
+            let reply = read_body(response.into_body(), limit).await;
+            permit.record(matches!(reply, Err(Failure::Unavailable)));
+            let reply = match reply {
+                Ok(reply) => reply,
+                Err(Failure::Unavailable) => return Err(internal_error()),
+                Err(Failure::TooLarge(limit)) => return Err(too_large_error(method, limit)),
+            };
+            let error = crate::json::from_slice(&reply).map(|mut reply: Value| reply["error"].take());
+            if let Some(error) = error.filter(|error| error["code"] == json!(WARMUP_CODE)) {
+                let mut warmup = self.warmup.lock().unwrap();
+                warmup.status = error["message"].as_str().map(str::to_string);
+                return Err(warmup.error());
+            }
+            return Ok(Body::from(reply));
+        }
+        self.warmup.lock().unwrap().status = None;
+        let body = response.into_body();
+        if limit.is_some_and(|limit| body.size_hint().lower() > limit as u64) {
+            permit.record(false);
+            return Err(too_large_error(method, limit.unwrap_or_default()));
+        }
+        // Without a length up front the size is only known while streaming,
+        // too late for an error reply, so the reply is cut off instead.
+        let limit = limit.unwrap_or(usize::MAX);
+        let chunks = futures_util::stream::unfold((body, Some(permit), 0), move |(mut body, permit, sent)| async move {
+            let mut permit = permit?;
+            match body.data().await {
+                Some(Ok(chunk)) if sent + chunk.len() <= limit => {
+                    let sent = sent + chunk.len();
+                    Some((Ok(chunk), (body, Some(permit), sent)))
+                },
+                Some(Ok(_)) => {
+                    permit.record(false);
+                    Some((Err(Box::<dyn std::error::Error + Send + Sync>::from("response too large")), (body, None, sent)))
+                },
+                Some(Err(e)) => {
+                    permit.record(true);
+                    Some((Err(e.into()), (body, None, sent)))
+                },
+                None => {
+                    permit.record(false);
+                    None
+                },
+            }
+        });
+        Ok(Body::wrap_stream(chunks))
+    }
+
+    fn trace(&self, id: Value, method: &str, queued: Instant, started: Instant) {
+        let _ = TRACES.try_with(|traces| traces.borrow_mut().push(Trace {
+            id,
+            method: method.to_string(),
+            node: self.uri.authority().map_or_else(String::new, |authority| authority.to_string()),
+            queued: started - queued,
+            latency: started.elapsed(),
+        }));
+    }
+
+    fn request(&self, body: String) -> Result<Request<Body>, hyper::http::Error> {
+        Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .header(hyper::header::AUTHORIZATION, self.auth.as_str())
+            .body(Body::from(body))
+    }
+
+    fn response_limit(&self, method: &str) -> Option<usize> {
+        let limit = self.method_max_response.get(method).copied().unwrap_or(self.max_response);
+        Some(limit).filter(|limit| *limit > 0)
+    }
+
+    async fn send(&self, id: u64, method: &str, params: &[Value]) -> Result<Value, Failure> {
+        let body = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}).to_string();
+        let request = self.request(body).map_err(|_| Failure::Unavailable)?;
+
+        let limit = self.response_limit(method);
+        let response = async {
+            let response = self.client().request(request).await.map_err(|_| Failure::Unavailable)?;
+            read_body(response.into_body(), limit).await
+        };
+        let started = Instant::now();
+        let body = tokio::time::timeout(self.timeout, response).await;
+        self.phases.record(Phase::Upstream, started.elapsed());
+        let body = match body {
+            Ok(Err(Failure::Unavailable)) => {
+                self.note_failure();
+                return Err(Failure::Unavailable);
+            },
+            Ok(body) => body?,
+            Err(_) => {
+                self.phases.timed_out(Phase::Upstream);
+                self.note_failure();
+                return Err(Failure::Unavailable);
+            },
+        };
+        self.note_success();
+
+        // verusd answers RPC errors with a non-200 status and a regular JSON-RPC
+        // body, so the status code is ignored in favour of the body.
+        match crate::json::from_slice(&body) {
+            Some(reply @ Value::Object(_)) => Ok(reply),
+            _ => Err(Failure::Unavailable),
+        }
+    }
+}
+
+// Reads a reply body, giving up as soon as it is known to be over `limit`
+// rather than buffering all of it first.
+async fn read_body(mut body: Body, limit: Option<usize>) -> Result<Bytes, Failure> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return hyper::body::to_bytes(body).await.map_err(|_| Failure::Unavailable),
+    };
+    if body.size_hint().lower() > limit as u64 {
+        return Err(Failure::TooLarge(limit));
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| Failure::Unavailable)?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(Failure::TooLarge(limit));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
@@ -0,0 +1,211 @@
+use hyper::{Body, Request, Response, StatusCode};
+use rusqlite::{Connection, params};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::VerusRPC;
+use crate::indexer::unix_time;
+use crate::rest::json_response;
+
+// Distinct (day, key, origin, method) counts held between writes; past this,
+// calls from new origins are counted under "(other)" until the next write.
+const MAX_PENDING: usize = 100_000;
+// Longest origin kept; browsers send short ones, anything longer is junk.
+const MAX_ORIGIN: usize = 100;
+
+// The UTC date of a unix time, as YYYY-MM-DD.
+pub fn day(time: i64) -> String {
+    // Howard Hinnant's civil_from_days.
+    let days = time.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+type Counts = HashMap<(String, String, String, String), u64>;
+// A day, key, origin and method with its calls.
+type Row = (String, String, String, String, i64);
+
+// Calls per day, per API key (or session subject), per browser origin and per
+// method, for cost sharing and fair use on community endpoints. Counted in
+// memory and added to a SQLite table every `flush_interval`; days older than
+// `retention_days` are dropped.
+pub struct UsageLog {
+    conn: Mutex<Connection>,
+    pending: Mutex<Counts>,
+    retention_days: i64,
+}
+
+impl UsageLog {
+    pub fn open(path: &str, retention_days: u64) -> rusqlite::Result<UsageLog> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS usage (
+                 day TEXT NOT NULL,
+                 key TEXT NOT NULL,
+                 origin TEXT NOT NULL,
+                 method TEXT NOT NULL,
+                 calls INTEGER NOT NULL,
+                 PRIMARY KEY (day, key, origin, method)
+             );",
+        )?;
+        Ok(UsageLog { conn: Mutex::new(conn), pending: Mutex::new(HashMap::new()), retention_days: retention_days as i64 })
+    }
+
+    // Counts the calls of a request (one or a batch) against `key` and
+    // `origin`, taking the methods from the request and telling from the
+    // replies which didn't exist, so junk names don't each get a row.
+    pub fn record(&self, key: Option<&str>, origin: Option<&str>, requests: &Value, replies: &Value) {
+        let calls: Vec<(&Value, &Value)> = match (requests, replies) {
+            (Value::Array(requests), Value::Array(replies)) => requests.iter().zip(replies).collect(),
+            (Value::Array(_), _) => return,
+            (request, reply) => vec![(request, reply)],
+        };
+        let today = day(unix_time());
+        let key = key.unwrap_or("(anonymous)");
+        let origin = origin.filter(|origin| origin.len() <= MAX_ORIGIN).unwrap_or("-");
+        let mut pending = self.pending.lock().unwrap();
+        for (request, reply) in calls {
+            let method = match (request["method"].as_str(), reply["error"]["code"].as_i64()) {
+                (Some(_), Some(-32601)) | (None, _) => "(unknown)",
+                (Some(method), _) => method,
+            };
+            let origin = match pending.len() < MAX_PENDING {
+                true => origin,
+                false => "(other)",
+            };
+            *pending.entry((today.clone(), key.to_string(), origin.to_string(), method.to_string())).or_insert(0) += 1;
+        }
+    }
+
+    // Adds the counts held in memory to the table and drops expired days.
+    pub fn flush(&self) -> rusqlite::Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT INTO usage (day, key, origin, method, calls) VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (day, key, origin, method) DO UPDATE SET calls = calls + excluded.calls",
+            )?;
+            for ((day, key, origin, method), calls) in pending {
+                upsert.execute(params![day, key, origin, method, calls as i64])?;
+            }
+        }
+        if self.retention_days > 0 {
+            tx.execute("DELETE FROM usage WHERE day < ?1", params![day(unix_time() - self.retention_days * 86_400)])?;
+        }
+        tx.commit()
+    }
+
+    // The rows between two days (inclusive), optionally for one key or origin.
+    fn report(&self, from: &str, to: &str, key: Option<&str>, origin: Option<&str>) -> rusqlite::Result<Vec<Row>> {
+        self.flush()?;
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT day, key, origin, method, calls FROM usage
+             WHERE day >= ?1 AND day <= ?2 AND (?3 IS NULL OR key = ?3) AND (?4 IS NULL OR origin = ?4)
+             ORDER BY day, key, origin, calls DESC",
+        )?;
+        let rows = query.query_map(params![from, to, key, origin], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))?;
+        rows.collect()
+    }
+}
+
+// Writes the counts held in memory to the table every `interval`.
+pub async fn run(rpc: Arc<VerusRPC>, interval: Duration) {
+    let usage = match &rpc.usage {
+        Some(usage) => usage,
+        None => return,
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = usage.flush() {
+            eprintln!("usage: failed to write: {}", e);
+        }
+    }
+}
+
+// A request (one call or a batch) cut down to the method of each call, which
+// is all `record` needs of it.
+pub fn methods(requests: &Value) -> Value {
+    match requests {
+        Value::Array(requests) => requests.iter().map(methods).collect(),
+        request => json!({ "method": request["method"] }),
+    }
+}
+
+// Decodes %XX escapes in a query value, such as an origin's "https%3A%2F%2F".
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], value.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            },
+            (b'+', _) => {
+                out.push(b' ');
+                i += 1;
+            },
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+// GET /usage on the admin listener: calls per day, key, origin and method
+// from ?from= to ?to= (YYYY-MM-DD, both included; today by default),
+// optionally for one ?key= or ?origin=, as JSON or, with ?format=csv, CSV.
+pub fn handle(req: &Request<Body>, rpc: &VerusRPC) -> Response<Body> {
+    let usage = match &rpc.usage {
+        Some(usage) => usage,
+        None => return json_response(StatusCode::NOT_FOUND, json!({"error": "Usage reports are off (set usage_path)"})),
+    };
+    let query = crate::events::query(req.uri());
+    let today = day(unix_time());
+    let from = query.get("from").cloned().unwrap_or_else(|| today.clone());
+    let to = query.get("to").cloned().unwrap_or(today);
+    let (key, origin) = (query.get("key").map(|key| unescape(key)), query.get("origin").map(|origin| unescape(origin)));
+    let rows = match usage.report(&from, &to, key.as_deref(), origin.as_deref()) {
+        Ok(rows) => rows,
+        Err(e) => return json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({"error": e.to_string()})),
+    };
+    if query.get("format").is_some_and(|format| format == "csv") {
+        let mut csv = String::from("day,key,origin,method,calls\n");
+        for (day, key, origin, method, calls) in rows {
+            csv.push_str(&format!("{},{},{},{},{}\n", day, csv_field(&key), csv_field(&origin), csv_field(&method), calls));
+        }
+        return Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/csv; charset=utf-8")
+            .header(hyper::header::CONTENT_DISPOSITION, format!("attachment; filename=\"usage-{}-{}.csv\"", from, to))
+            .body(Body::from(csv))
+            .unwrap();
+    }
+    let total: i64 = rows.iter().map(|row| row.4).sum();
+    let rows: Vec<Value> = rows.into_iter()
+        .map(|(day, key, origin, method, calls)| json!({ "day": day, "key": key, "origin": origin, "method": method, "calls": calls }))
+        .collect();
+    json_response(StatusCode::OK, json!({ "from": from, "to": to, "calls": total, "rows": rows }))
+}
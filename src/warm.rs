@@ -0,0 +1,41 @@
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::VerusRPC;
+
+// Runs the configured hot calls through the normal request path before the
+// listener opens, so their results are already cached when traffic arrives.
+// Gives up after `timeout` rather than holding startup on a slow daemon.
+pub async fn warm(rpc: &Arc<VerusRPC>, calls: Vec<Value>, tip_block: bool, timeout: Duration) {
+    let mut tasks = JoinSet::new();
+    for mut call in calls {
+        rpc.migrations.apply(&mut call, "warm");
+        let rpc = rpc.clone();
+        tasks.spawn(async move { rpc.handle(call).await.is_ok() });
+    }
+    if tip_block {
+        let rpc = rpc.clone();
+        tasks.spawn(async move {
+            match rpc.handle(json!({"method": "getbestblockhash", "params": []})).await {
+                Ok(hash) => rpc.handle(json!({"method": "getblock", "params": [hash]})).await.is_ok(),
+                Err(_) => false,
+            }
+        });
+    }
+
+    let total = tasks.len();
+    let mut warmed = 0;
+    let finished = tokio::time::timeout(timeout, async {
+        while let Some(ok) = tasks.join_next().await {
+            if ok.unwrap_or(false) {
+                warmed += 1;
+            }
+        }
+    }).await;
+    if finished.is_err() {
+        tasks.abort_all();
+    }
+    println!("cache warming: {}/{} calls succeeded", warmed, total);
+}
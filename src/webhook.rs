@@ -0,0 +1,64 @@
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnector;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::VerusRPC;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Webhook {
+    pub url: String,
+    // Event kinds to deliver; empty delivers all.
+    pub events: Vec<String>,
+}
+
+// POSTs every matching event as `{"seq": n, "event": kind, "data": ...}` to each webhook.
+// Deliveries are fire and forget: a slow or failing endpoint is logged and
+// doesn't hold up the others.
+pub async fn forward(rpc: Arc<VerusRPC>, webhooks: Vec<Webhook>) {
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client: Client<HttpsConnector<HttpConnector>> = Client::builder().build(https);
+    let mut events = rpc.events.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                eprintln!("webhooks fell behind, {} events not delivered", missed);
+                continue;
+            },
+            Err(RecvError::Closed) => return,
+        };
+        let body = event.to_json().to_string();
+        for webhook in webhooks.iter().filter(|webhook| webhook.events.is_empty() || webhook.events.iter().any(|kind| kind == event.kind)) {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(webhook.url.as_str())
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.clone()));
+            let request = match request {
+                Ok(request) => request,
+                Err(e) => {
+                    eprintln!("webhook {}: {}", webhook.url, e);
+                    continue;
+                },
+            };
+            let client = client.clone();
+            let url = webhook.url.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(DELIVERY_TIMEOUT, client.request(request)).await {
+                    Ok(Ok(response)) if response.status().is_success() => {},
+                    Ok(Ok(response)) => eprintln!("webhook {} answered {}", url, response.status()),
+                    Ok(Err(e)) => eprintln!("webhook {} failed: {}", url, e),
+                    Err(_) => eprintln!("webhook {} timed out", url),
+                }
+            });
+        }
+    }
+}
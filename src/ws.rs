@@ -0,0 +1,289 @@
+use futures_util::{SinkExt, StreamExt};
+use hyper::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::{Body, Request, Response, StatusCode};
+use jsonrpc::error::RpcError;
+use serde_json::{Value, json};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Semaphore, mpsc};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role, WebSocketConfig};
+
+use crate::{VerusRPC, batch, captcha, origin, reply, usage, with_warning};
+use crate::allowlist::Access;
+use crate::events::{self, Event, Filter};
+use crate::indexer::unix_time;
+use crate::listener::Profile;
+use crate::rest::json_response;
+use crate::subscriptions::Subscriptions;
+
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+// Calls one socket may have waiting on the daemon at once; more are refused
+// until some are answered.
+const MAX_IN_FLIGHT: usize = 32;
+
+// Who opened a socket, worked out from its upgrade request as for any other
+// request, so calls made over it get the same access, limits and records.
+pub struct Caller {
+    pub access: Access,
+    pub principal: String,
+    pub subject: Option<String>,
+    pub client: String,
+    pub origin: Option<String>,
+    // Whether write methods may be called, or why not.
+    pub writes: Result<(), String>,
+    // What the socket was opened with, checked again before each call: a
+    // session token (good until `expires`, on the listener of `profile`) or a
+    // key created through the admin listener.
+    pub session: Option<String>,
+    pub expires: Option<u64>,
+    pub managed_key: Option<String>,
+    pub profile: Arc<Profile>,
+}
+
+impl Caller {
+    // Why the caller may no longer use the socket: its address was banned,
+    // its session expired or was revoked, or its key was revoked.
+    fn revoked(&self, rpc: &VerusRPC) -> Option<String> {
+        if self.client.parse::<IpAddr>().is_ok_and(|ip| rpc.runtime.is_banned(ip)) {
+            return Some("Banned".to_string());
+        }
+        if let Some(Err(reason)) = self.session.as_deref().map(|token| rpc.sessions.recheck(token, &self.profile)) {
+            return Some(reason);
+        }
+        if self.managed_key.as_deref().is_some_and(|key| rpc.runtime.key(key).is_none()) {
+            return Some("The API key was revoked".to_string());
+        }
+        None
+    }
+}
+
+pub fn is_upgrade(req: &Request<Body>) -> bool {
+    req.headers().get(UPGRADE).and_then(|value| value.to_str().ok()).is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+// Upgrades GET /ws/<stream> to a WebSocket that streams the events matching
+// the filter in the query string (see `Filter`), and GET /ws to one without
+// events. Either way, JSON-RPC requests and batches sent over the socket are
+// answered on it as they complete, not necessarily in order, each reply
+// carrying its request's id.
+pub async fn handle(mut req: Request<Body>, rpc: Arc<VerusRPC>, caller: Caller) -> Response<Body> {
+    let stream_name = match req.uri().path() {
+        "/ws" | "/ws/" => None,
+        path => Some(path.strip_prefix("/ws/").unwrap_or_default()),
+    };
+    let query = events::query(req.uri());
+    let since = events::since(&query, req.headers());
+    let filter = match stream_name.map(|stream_name| Filter::parse(stream_name, &query)).transpose() {
+        Ok(filter) => filter,
+        Err(message) => return json_response(StatusCode::BAD_REQUEST, json!({ "error": message })),
+    };
+    let key = match req.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) => derive_accept_key(key.as_bytes()),
+        None => return json_response(StatusCode::BAD_REQUEST, json!({ "error": "Missing Sec-WebSocket-Key" })),
+    };
+    let subscription = match &filter {
+        Some(filter) => match Subscriptions::open(&rpc, &req, &query, filter, &caller.profile).await {
+            Ok(subscription) => Some(subscription),
+            Err((status, message)) => return json_response(status, json!({ "error": message })),
+        },
+        None => None,
+    };
+
+    let upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                // Calls are held to the same size as request bodies.
+                let config = WebSocketConfig { max_message_size: Some(rpc.body_limits.ceiling() as usize), ..Default::default() };
+                let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(config)).await;
+                stream(socket, rpc, Arc::new(caller), filter, since).await;
+                drop(subscription);
+            },
+            Err(e) => eprintln!("websocket upgrade failed: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "Upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, key)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn message(event: &Event) -> Message {
+    Message::Text(event.to_json().to_string())
+}
+
+fn lagged(missed: u64) -> Message {
+    Message::Text(json!({ "event": "lagged", "data": { "missed": missed } }).to_string())
+}
+
+async fn next_event(events: &mut Option<broadcast::Receiver<Arc<Event>>>) -> Result<Arc<Event>, RecvError> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Closes a socket whose caller may no longer use it, saying why.
+async fn close(socket: &mut WebSocketStream<hyper::upgrade::Upgraded>, reason: String) {
+    let frame = CloseFrame { code: CloseCode::Policy, reason: reason.into() };
+    let _ = socket.send(Message::Close(Some(frame))).await;
+}
+
+fn rejected(id: Value, code: i64, message: String, data: Option<Value>) -> Value {
+    let data = data.map(|data| serde_json::value::to_raw_value(&data).unwrap());
+    let mut reply = reply(Err(RpcError { code: code as i32, message, data }));
+    reply["id"] = id;
+    reply
+}
+
+// Answers one message: a JSON-RPC request or batch, admitted, checked and
+// recorded as it would be over HTTP.
+async fn call(rpc: Arc<VerusRPC>, caller: Arc<Caller>, text: String) -> Value {
+    let body: Value = match serde_json::from_str(&text) {
+        Ok(body) => body,
+        Err(_) => return rejected(Value::Null, -32700, "Parse error".into(), None),
+    };
+    let id = body["id"].clone();
+    if let Some(sampler) = &rpc.sampler {
+        sampler.offer(Some(&body), text.as_bytes());
+    }
+    if let Err((method, limit)) = rpc.body_limits.check(&body, text.len(), &rpc.methods) {
+        return rejected(id, -32600, format!("Request too large: {} takes at most {} bytes", method, limit), None);
+    }
+    let is_write = origin::has_write_method(&body, &rpc.methods);
+    if let (Err(reason), true) = (&caller.writes, is_write) {
+        return rejected(id, -8, format!("Rejected by policy: {}", reason), None);
+    }
+    // A message carrying a captcha token may call the guarded methods.
+    let mut access = caller.access;
+    if let (Some(captcha), true, Some(token)) = (&rpc.captcha, access.needs_captcha, captcha::token(None, &body)) {
+        if let Err(e) = captcha.verify(Some(token), &caller.client).await {
+            let mut reply = reply(Err(e));
+            reply["id"] = id;
+            return reply;
+        }
+        access.needs_captcha = false;
+    }
+    let calls = rpc.batch.calls(&body);
+    if let Err(retry_after) = rpc.roles.admit(caller.access.roles, &caller.principal, calls) {
+        return rejected(id, -32000, "Rate limit for your role exceeded".into(), Some(json!({ "retry_after": retry_after })));
+    }
+    if let Err(retry_after) = caller.subject.as_deref().map_or(Ok(()), |subject| rpc.runtime.admit(subject, calls)) {
+        return rejected(id, -32000, "Rate limit for your key exceeded".into(), Some(json!({ "retry_after": retry_after })));
+    }
+
+    let called = rpc.usage.as_ref().map(|_| usage::methods(&body));
+    let audited = rpc.audit.as_ref().filter(|_| is_write).map(|_| body.clone());
+    let reply = match body {
+        Value::Array(entries) => batch::handle_batch(entries, rpc.clone(), access, &caller.principal).await,
+        mut body => {
+            let warning = rpc.migrations.apply(&mut body, &caller.principal);
+            let mut reply = with_warning(rpc.answer(body, access).await, warning);
+            reply["id"] = id;
+            reply
+        },
+    };
+    if let (Some(audit), Some(requests)) = (&rpc.audit, &audited) {
+        audit.record(caller.subject.as_deref().unwrap_or_default(), &caller.client, requests, &reply, &rpc.methods);
+    }
+    if let (Some(usage), Some(called)) = (&rpc.usage, &called) {
+        usage.record(caller.subject.as_deref(), caller.origin.as_deref(), called, &reply);
+    }
+    rpc.branding.with_metadata(reply)
+}
+
+async fn stream(mut socket: WebSocketStream<hyper::upgrade::Upgraded>, rpc: Arc<VerusRPC>, caller: Arc<Caller>, filter: Option<Filter>, since: Option<u64>) {
+    let mut events = match (&filter, since) {
+        (None, _) => None,
+        (Some(filter), Some(since)) => {
+            let (replay, events) = rpc.events.subscribe_since(since);
+            if replay.missed > 0 && socket.send(lagged(replay.missed)).await.is_err() {
+                return;
+            }
+            for event in replay.events.iter().filter(|event| filter.matches(event)) {
+                if socket.send(message(event)).await.is_err() {
+                    return;
+                }
+            }
+            Some(events)
+        },
+        (Some(_), None) => Some(rpc.events.subscribe()),
+    };
+    let (replies_tx, mut replies) = mpsc::unbounded_channel::<Value>();
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    // The socket closes when its session does.
+    let session_end = async {
+        match caller.expires {
+            Some(expires) => tokio::time::sleep(Duration::from_secs(expires.saturating_sub(unix_time() as u64))).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(session_end);
+    loop {
+        tokio::select! {
+            _ = &mut session_end => return close(&mut socket, "Session expired".to_string()).await,
+            event = next_event(&mut events) => match event {
+                Ok(event) if filter.as_ref().is_some_and(|filter| filter.matches(&event)) => {
+                    if socket.send(message(&event)).await.is_err() {
+                        return;
+                    }
+                },
+                Ok(_) => {},
+                // A subscriber that falls behind is told how much it missed.
+                Err(RecvError::Lagged(missed)) => {
+                    if socket.send(lagged(missed)).await.is_err() {
+                        return;
+                    }
+                },
+                Err(RecvError::Closed) => return,
+            },
+            Some(reply) = replies.recv() => {
+                if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                    return;
+                }
+            },
+            incoming = socket.next() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(Message::Text(_))) if caller.revoked(&rpc).is_some() => {
+                    return close(&mut socket, caller.revoked(&rpc).unwrap_or_default()).await;
+                },
+                Some(Ok(Message::Text(text))) => match in_flight.clone().try_acquire_owned() {
+                    Ok(permit) => {
+                        let (rpc, caller, replies) = (rpc.clone(), caller.clone(), replies_tx.clone());
+                        tokio::spawn(async move {
+                            let _ = replies.send(call(rpc, caller, text).await);
+                            drop(permit);
+                        });
+                    },
+                    Err(_) => {
+                        let id = serde_json::from_str::<Value>(&text).map(|body| body["id"].clone()).unwrap_or_default();
+                        let message = format!("Too many calls in flight on this socket, max {}", MAX_IN_FLIGHT);
+                        if socket.send(Message::Text(rejected(id, -32000, message, None).to_string())).await.is_err() {
+                            return;
+                        }
+                    },
+                },
+                Some(Ok(_)) => {},
+            },
+            _ = ping.tick() => {
+                if let Some(reason) = caller.revoked(&rpc) {
+                    return close(&mut socket, reason).await;
+                }
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    return;
+                }
+            },
+        }
+    }
+}